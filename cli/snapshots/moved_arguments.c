@@ -0,0 +1,65 @@
+void unbox(void *_result, void *b, void *t) {
+  char _0[sizeof(_witness)];
+  char _3[sizeof(_witness)];
+  _copy_type(_3, t);
+  Box(_0, _3);
+  _Box_get_value(_result, b, ((_witness *) _0) -> extra);
+  _destroy_type(_0);
+}
+void twice(void *_result, void *f) {
+  char _0[sizeof(_witness)];
+  _Closure(_0);
+  char _2[sizeof(_witness)];
+  _ClosureEnv_1(_2);
+  int _heap__3 = ((_witness *) _2) -> size > _STACK_LIMIT;
+  void *_3 = _heap__3 ? malloc(((_witness *) _2) -> size) : alloca(((_witness *) _2) -> size);
+  signed long long _7 = 0;
+  (((_witness *) _0) -> copy)((((char *) _3) + _7), f, ((_witness *) _0) -> extra);
+  _7 += ((_witness *) _0) -> size;
+  void *_8 = malloc(sizeof(unsigned long long) + sizeof(_witness) + ((_witness *) _2) -> size);
+  *(unsigned long long *) _8 = 0ull;
+  _copy_type((((char *) _8) + sizeof(unsigned long long)), _2);
+  (((_witness *) _2) -> move)((((char *) _8) + sizeof(_witness) + sizeof(unsigned long long)), _3, ((_witness *) _2) -> extra);
+  *(void **)_result = _closure_1;
+  *((void **)_result + 1) = _8;
+  _destroy_type(_2);
+  _destroy_type(_0);
+  if (_heap__3) free(_3);
+}
+void apply(void *_result) {
+  char _1[sizeof(_witness)];
+  _Closure(_1);
+  char _2[sizeof(_witness)];
+  _ClosureEnv_2(_2);
+  int _heap__3 = ((_witness *) _2) -> size > _STACK_LIMIT;
+  void *_3 = _heap__3 ? malloc(((_witness *) _2) -> size) : alloca(((_witness *) _2) -> size);
+  signed long long _9 = 0;
+  int _heap__5 = ((_witness *) _1) -> size > _STACK_LIMIT;
+  void *_5 = _heap__5 ? malloc(((_witness *) _1) -> size) : alloca(((_witness *) _1) -> size);
+  void *_10 = malloc(sizeof(unsigned long long) + sizeof(_witness) + ((_witness *) _2) -> size);
+  *(unsigned long long *) _10 = 0ull;
+  _copy_type((((char *) _10) + sizeof(unsigned long long)), _2);
+  (((_witness *) _2) -> move)((((char *) _10) + sizeof(_witness) + sizeof(unsigned long long)), _3, ((_witness *) _2) -> extra);
+  *(void **)_5 = _closure_3;
+  *((void **)_5 + 1) = _10;
+  _destroy_type(_2);
+  int _heap_g = ((_witness *) _1) -> size > _STACK_LIMIT;
+  void *g = _heap_g ? malloc(((_witness *) _1) -> size) : alloca(((_witness *) _1) -> size);
+  twice(g, _5);
+  (((_witness *) _1) -> destroy)(_5, ((_witness *) _1) -> extra);
+  char _8[8];
+  *(double *) _8 = 2.0;
+  char *_11 = ((struct _Closure *) g) -> env;
+  _rc_use(_11, "a closure's environment");
+  char _12[sizeof(_witness)];
+  _copy_type(_12, _11 + sizeof(unsigned long long));
+  void *_13 = malloc(((_witness *) _12) -> size);
+  (((_witness *) _12) -> copy)(_13, _11 + sizeof(unsigned long long) + sizeof(_witness), ((_witness *) _12) -> extra);
+  ((void (*)(void *, void *, void *, void *)) ((struct _Closure *) g) -> function)(_result, _8, _13, _12);
+  (((_witness *) _1) -> destroy)(g, ((_witness *) _1) -> extra);
+  _destroy_type(_1);
+  free(_13);
+  if (_heap_g) free(g);
+  if (_heap__5) free(_5);
+  if (_heap__3) free(_3);
+}
@@ -0,0 +1,34 @@
+void pair(void *_result, void *fst, void *snd, void *a, void *b) {
+  char _0[sizeof(_witness)];
+  char _3[sizeof(_witness)];
+  _copy_type(_3, a);
+  char _4[sizeof(_witness)];
+  _copy_type(_4, b);
+  Pair(_0, _3, _4);
+  signed long long _5 = 0;
+  (((_witness *) a) -> copy)((((char *) _result) + _5), fst, ((_witness *) a) -> extra);
+  _5 += ((_witness *) a) -> size;
+  (((_witness *) b) -> copy)((((char *) _result) + _5), snd, ((_witness *) b) -> extra);
+  _5 += ((_witness *) b) -> size;
+  _destroy_type(_0);
+}
+void make(void *_result) {
+  char _1[sizeof(_witness)];
+  F64(_1);
+  char _2[sizeof(_witness)];
+  Bool(_2);
+  char _0[sizeof(_witness)];
+  char _14[sizeof(_witness)];
+  _copy_type(_14, _1);
+  char _15[sizeof(_witness)];
+  _copy_type(_15, _2);
+  Pair(_0, _14, _15);
+  char _7[8];
+  *(double *) _7 = 1.0;
+  char _8[8];
+  *(signed long long *) _8 = 1ll;
+  pair(_result, _7, _8, _1, _2);
+  _destroy_type(_1);
+  _destroy_type(_2);
+  _destroy_type(_0);
+}
@@ -0,0 +1,327 @@
+use core::fmt;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "usage: hoyle <command> [options]
+
+commands:
+    build <file>     compile <file> into an executable that prints the result of `main`
+    check <file>     type check <file> without generating code
+    emit-c <file>    print the c source for <file>, or write it to `-o`
+    run <file>       build <file> and run the executable
+    repl             read programs from stdin and check them as they are entered
+
+options:
+    -o, --output <path>    where to write the output of `build` or `emit-c`
+    --emit=<stages>        print the comma separated intermediate stages, out of
+                           tokens, parsed, typed, passed, sized, bridge and c
+    --cc <command>         the c compiler to build executables with, which
+                           defaults to $CC or `cc`
+    -O<level>              the c compiler's optimisation level, from 0 to 3,
+                           which defaults to 2
+    --debug-rc             check every allocation, copy and destroy in the c
+                           source, aborting on double destroys, uses after
+                           free and leaks
+    --rc-stats             print how many reference counting operations were
+                           removed by borrowing arguments and moving copies
+    --monomorphize         compile a copy of each generic function and struct
+                           for every type it's used with, instead of passing
+                           witness tables
+    --production           reject error unions that are used without checking
+                           for an error, instead of warning about them and
+                           crashing if they hold one
+    -h, --help             print this message
+";
+
+/// a stage of the pipeline whose output can be printed with `--emit`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Tokens,
+    Parsed,
+    Typed,
+    Passed,
+    Sized,
+    Bridge,
+    C,
+}
+
+pub enum Command {
+    Build {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Check {
+        input: PathBuf,
+    },
+    EmitC {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Run {
+        input: PathBuf,
+    },
+    Repl,
+    Help,
+}
+
+pub struct Args {
+    pub command: Command,
+    /// the stages to print, in pipeline order
+    pub emit: Vec<Stage>,
+    pub cc: Option<String>,
+    pub optimisation: u8,
+    pub debug_rc: bool,
+    pub rc_stats: bool,
+    pub monomorphize: bool,
+    pub production: bool,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    MissingCommand,
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    UnknownStage(String),
+    UnknownOptimisation(String),
+    MissingInput(&'static str),
+    UnexpectedArgument(String),
+    NoOutput(&'static str),
+}
+
+impl Stage {
+    const ALL: [Self; 7] = [
+        Self::Tokens,
+        Self::Parsed,
+        Self::Typed,
+        Self::Passed,
+        Self::Sized,
+        Self::Bridge,
+        Self::C,
+    ];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Tokens => "tokens",
+            Self::Parsed => "parsed",
+            Self::Typed => "typed",
+            Self::Passed => "passed",
+            Self::Sized => "sized",
+            Self::Bridge => "bridge",
+            Self::C => "c",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, Error> {
+        Self::ALL
+            .into_iter()
+            .find(|stage| stage.name() == name)
+            .ok_or_else(|| Error::UnknownStage(name.to_string()))
+    }
+}
+
+pub fn parse(arguments: impl IntoIterator<Item = String>) -> Result<Args, Error> {
+    let mut arguments = arguments.into_iter();
+    let command = arguments.next().ok_or(Error::MissingCommand)?;
+    let mut output = None;
+    let mut emit = Vec::new();
+    let mut cc = None;
+    let mut optimisation = 2;
+    let mut debug_rc = false;
+    let mut rc_stats = false;
+    let mut monomorphize = false;
+    let mut production = false;
+    let mut inputs = Vec::new();
+
+    while let Some(argument) = arguments.next() {
+        let mut value = |option: &str| {
+            arguments
+                .next()
+                .ok_or_else(|| Error::MissingValue(option.to_string()))
+        };
+        match argument.as_str() {
+            "-h" | "--help" => {
+                return Ok(Args {
+                    command: Command::Help,
+                    emit,
+                    cc,
+                    optimisation,
+                    debug_rc,
+                    rc_stats,
+                    monomorphize,
+                    production,
+                })
+            }
+            "-o" | "--output" => output = Some(PathBuf::from(value(&argument)?)),
+            "--emit" => emit_stages(&value(&argument)?, &mut emit)?,
+            "--cc" => cc = Some(value(&argument)?),
+            "--debug-rc" => debug_rc = true,
+            "--rc-stats" => rc_stats = true,
+            "--monomorphize" => monomorphize = true,
+            "--production" => production = true,
+            _ => {
+                if let Some(path) = argument.strip_prefix("--output=") {
+                    output = Some(PathBuf::from(path));
+                } else if let Some(stages) = argument.strip_prefix("--emit=") {
+                    emit_stages(stages, &mut emit)?;
+                } else if let Some(command) = argument.strip_prefix("--cc=") {
+                    cc = Some(command.to_string());
+                } else if let Some(level) = argument.strip_prefix("-O") {
+                    optimisation = match level.parse() {
+                        Ok(level @ 0..=3) => level,
+                        _ => return Err(Error::UnknownOptimisation(level.to_string())),
+                    };
+                } else if argument.starts_with('-') && argument != "-" {
+                    return Err(Error::UnknownOption(argument));
+                } else {
+                    inputs.push(PathBuf::from(argument));
+                }
+            }
+        }
+    }
+    emit.sort();
+    emit.dedup();
+
+    let command = match command.as_str() {
+        "-h" | "--help" | "help" => Command::Help,
+        "build" => Command::Build {
+            input: single_input("build", inputs)?,
+            output,
+        },
+        "emit-c" => Command::EmitC {
+            input: single_input("emit-c", inputs)?,
+            output,
+        },
+        "check" => no_output(
+            "check",
+            output,
+            Command::Check {
+                input: single_input("check", inputs)?,
+            },
+        )?,
+        "run" => no_output(
+            "run",
+            output,
+            Command::Run {
+                input: single_input("run", inputs)?,
+            },
+        )?,
+        "repl" => {
+            if let Some(input) = inputs.into_iter().next() {
+                return Err(Error::UnexpectedArgument(input.display().to_string()));
+            }
+            no_output("repl", output, Command::Repl)?
+        }
+        _ => return Err(Error::UnknownCommand(command)),
+    };
+    Ok(Args {
+        command,
+        emit,
+        cc,
+        optimisation,
+        debug_rc,
+        rc_stats,
+        monomorphize,
+        production,
+    })
+}
+
+fn emit_stages(names: &str, emit: &mut Vec<Stage>) -> Result<(), Error> {
+    for name in names.split(',') {
+        emit.push(Stage::parse(name)?);
+    }
+    Ok(())
+}
+
+fn single_input(command: &'static str, inputs: Vec<PathBuf>) -> Result<PathBuf, Error> {
+    let mut inputs = inputs.into_iter();
+    let input = inputs.next().ok_or(Error::MissingInput(command))?;
+    if let Some(extra) = inputs.next() {
+        return Err(Error::UnexpectedArgument(extra.display().to_string()));
+    }
+    Ok(input)
+}
+
+fn no_output(
+    command: &'static str,
+    output: Option<PathBuf>,
+    parsed: Command,
+) -> Result<Command, Error> {
+    if output.is_some() {
+        Err(Error::NoOutput(command))
+    } else {
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::MissingCommand => write!(f, "expected a command"),
+            Error::UnknownCommand(command) => write!(f, "unknown command `{command}`"),
+            Error::UnknownOption(option) => write!(f, "unknown option `{option}`"),
+            Error::MissingValue(option) => write!(f, "expected a value after `{option}`"),
+            Error::UnknownStage(stage) => {
+                let stages: Vec<_> = Stage::ALL.iter().map(|stage| stage.name()).collect();
+                write!(
+                    f,
+                    "unknown stage `{stage}`, expected one of {}",
+                    stages.join(", ")
+                )
+            }
+            Error::UnknownOptimisation(level) => {
+                write!(
+                    f,
+                    "unknown optimisation level `{level}`, expected 0, 1, 2 or 3"
+                )
+            }
+            Error::MissingInput(command) => write!(f, "`{command}` expects an input file"),
+            Error::UnexpectedArgument(argument) => write!(f, "unexpected argument `{argument}`"),
+            Error::NoOutput(command) => write!(f, "`{command}` doesn't take an output path"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_line(line: &str) -> Result<Args, Error> {
+        parse(line.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn emit_stages() {
+        let args =
+            parse_line("emit-c main.hoyle --emit=sized,tokens -o out.c --emit typed").unwrap();
+        assert_eq!(args.emit, vec![Stage::Tokens, Stage::Typed, Stage::Sized]);
+        let Command::EmitC { input, output } = args.command else {
+            panic!("expected emit-c");
+        };
+        assert_eq!(input, PathBuf::from("main.hoyle"));
+        assert_eq!(output, Some(PathBuf::from("out.c")));
+    }
+
+    #[test]
+    fn bad_arguments() {
+        let error = |line| parse_line(line).err().unwrap().to_string();
+        assert_eq!(
+            error("check a.hoyle -o a.c"),
+            "`check` doesn't take an output path"
+        );
+        assert_eq!(error("build"), "`build` expects an input file");
+        assert_eq!(
+            error("run a.hoyle b.hoyle"),
+            "unexpected argument `b.hoyle`"
+        );
+        assert_eq!(
+            error("check a.hoyle --emit=c,ast"),
+            "unknown stage `ast`, expected one of tokens, parsed, typed, passed, sized, bridge, c"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use core::fmt::Write;
+
+use chumsky::error::{Simple, SimpleReason};
+use lexer::Errors;
+use tree::token::Token;
+use tree::Span;
+
+/// A compiler error that can be rendered against the source text it came from.
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    pub severity: Severity,
+}
+
+/// whether a diagnostic stops compilation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Diagnostic {
+    pub fn lexer(errors: &Errors) -> Vec<Self> {
+        errors
+            .unknown_characters()
+            .iter()
+            .map(|(offset, char)| Self {
+                message: format!("unknown character `{char}`"),
+                span: Some(Span::new(*offset, offset + char.len_utf8())),
+                severity: Severity::Error,
+            })
+            .collect()
+    }
+
+    pub fn parser(errors: &[Simple<Token>]) -> Vec<Self> {
+        errors
+            .iter()
+            .map(|error| {
+                let message = match error.reason() {
+                    SimpleReason::Custom(message) => message.clone(),
+                    SimpleReason::Unclosed { delimiter, .. } => {
+                        format!("unclosed delimiter `{}`", delimiter.span.data)
+                    }
+                    SimpleReason::Unexpected => {
+                        let found = error.found().map_or(String::from("end of input"), |token| {
+                            format!("`{}`", token.span.data)
+                        });
+                        let expected: Vec<_> = error
+                            .expected()
+                            .map(|token| {
+                                token.map_or(String::from("end of input"), |token| {
+                                    format!("{:?}", token.kind)
+                                })
+                            })
+                            .collect();
+                        if expected.is_empty() {
+                            format!("unexpected {found}")
+                        } else {
+                            format!("unexpected {found}, expected {}", expected.join(" or "))
+                        }
+                    }
+                };
+                Self {
+                    message,
+                    span: Some(Span::from(error.span())),
+                    severity: Severity::Error,
+                }
+            })
+            .collect()
+    }
+
+    pub fn type_checker(error: &type_checker::env::Error) -> Self {
+        Self {
+            message: error.to_string(),
+            span: error.span(),
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(warning: &type_checker::env::Warning) -> Self {
+        Self {
+            message: warning.to_string(),
+            span: Some(warning.span),
+            severity: Severity::Warning,
+        }
+    }
+
+    pub fn monomorphize(error: &monomorphize::monomorphize::Error) -> Self {
+        Self {
+            message: error.to_string(),
+            span: Some(error.span),
+            severity: Severity::Error,
+        }
+    }
+
+    /// renders the diagnostic in the style of
+    /// ```text
+    /// error: unknown variable `y`
+    ///  --> main.hoyle:1:22
+    ///   |
+    /// 1 | func f(x: F64): F64 = y
+    ///   |                       ^
+    /// ```
+    pub fn render(&self, file: &str, source: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut output = format!("{severity}: {}\n", self.message);
+        let Some(span) = self.span else {
+            let _ = writeln!(output, " --> {file}");
+            return output;
+        };
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |newline| start + newline);
+        let line_number = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+        let line = &source[line_start..line_end];
+        let underline_end = span.end.clamp(start, line_end);
+        let underline = source[start..underline_end].chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        let _ = writeln!(output, "{gutter}--> {file}:{line_number}:{column}");
+        let _ = writeln!(output, "{gutter} |");
+        let _ = writeln!(output, "{line_number} | {line}");
+        let _ = writeln!(
+            output,
+            "{gutter} | {}{}",
+            " ".repeat(column - 1),
+            "^".repeat(underline)
+        );
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use lexer::scan_tokens;
+    use type_checker::env::Mode;
+
+    fn type_error(text: &str) -> Diagnostic {
+        let (tokens, errors) = scan_tokens(text);
+        assert!(errors.success());
+        let parsed = parser::parse(&tokens.into_iter().collect::<Vec<_>>()).unwrap();
+        let error = type_checker::infer::program(&parsed, Mode::Development)
+            .err()
+            .unwrap();
+        Diagnostic::type_checker(&error)
+    }
+
+    #[test]
+    fn unknown_variable() {
+        let text = "func f(x: F64): F64 = {\n  let a = x;\n  a + y\n}";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: unknown variable `y`\n --> test.hoyle:3:7\n  |\n3 |   a + y\n  |       ^\n"
+        );
+    }
+
+    #[test]
+    fn mismatched_call() {
+        let text = "func f(x: F64): F64 = x\nfunc g(): F64 = f(True)";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: expected type `F64`, got `Bool`\n --> test.hoyle:2:19\n  |\n2 | func g(): F64 = f(True)\n  |                   ^^^^\n"
+        );
+    }
+
+    #[test]
+    fn unknown_field() {
+        let text = "struct Point {\n  x: F64\n}\nfunc f(p: Point): F64 = p.y";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: struct `Point` has no field `y`\n --> test.hoyle:4:25\n  |\n4 | func f(p: Point): F64 = p.y\n  |                         ^^^\n"
+        );
+    }
+
+    #[test]
+    fn unimplemented_protocol() {
+        let text = "protocol Zero {\n  func zero(): Self\n}\nfunc f(): Bool = zero()";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: `Bool` does not implement `Zero`\n --> test.hoyle:4:18\n  |\n4 | func f(): Bool = zero()\n  |                  ^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_existential() {
+        let text = "protocol Zero {\n  func zero(): Self\n}\nfunc f(): any Zero = zero()";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: `any Zero` does not implement `Zero`, because `zero` must take `Self` exactly once and mention it nowhere else\n --> test.hoyle:4:22\n  |\n4 | func f(): any Zero = zero()\n  |                      ^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn non_numeric_literal() {
+        let text = "func f(): Bool = 1 + 2";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: expected `I64` or `F64`, got `Bool`\n --> test.hoyle:1:18\n  |\n1 | func f(): Bool = 1 + 2\n  |                  ^\n"
+        );
+    }
+
+    #[test]
+    fn non_equatable() {
+        let text = "struct Point {\n  x: F64\n}\nfunc f(p: Point): Bool = p == p";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: expected `I64`, `F64` or `Bool`, got `Point`\n --> test.hoyle:4:26\n  |\n4 | func f(p: Point): Bool = p == p\n  |                          ^^^^^^\n"
+        );
+    }
+
+    #[test]
+    fn non_exhaustive_case() {
+        let text = "enum Option { Some(Bool), None }\nfunc f(x: Option): F64 = case x of | Some(True) => 1 | None => 0";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert!(
+            rendered.starts_with(
+                "error: non-exhaustive patterns: `Some(False)` is not covered\n --> test.hoyle:2:26\n"
+            ),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn redundant_pattern() {
+        let text = "enum Option { Some(Bool), None }\nfunc f(x: Option): F64 = case x of | Some(_) => 1 | None => 0 | Some(True) => 2";
+        let rendered = type_error(text).render("test.hoyle", text);
+        assert!(
+            rendered.starts_with("error: unreachable pattern\n --> test.hoyle:2:65\n"),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn unknown_character() {
+        let text = "func f(): F64 = 1 $ 2";
+        let (_, errors) = scan_tokens(text);
+        let rendered = Diagnostic::lexer(&errors)[0].render("test.hoyle", text);
+        assert_eq!(
+            rendered,
+            "error: unknown character `$`\n --> test.hoyle:1:19\n  |\n1 | func f(): F64 = 1 $ 2\n  |                   ^\n"
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let text = "func f(): F64 = (1 + 2";
+        let (tokens, errors) = scan_tokens(text);
+        assert!(errors.success());
+        let errors = parser::parse(&tokens.into_iter().collect::<Vec<_>>())
+            .err()
+            .unwrap();
+        let rendered = Diagnostic::parser(&errors)[0].render("test.hoyle", text);
+        assert!(rendered.contains("--> test.hoyle:1:"), "{rendered}");
+    }
+}
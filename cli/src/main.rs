@@ -1,64 +1,183 @@
+mod args;
+mod diagnostic;
+mod native;
+mod pipeline;
 mod read;
 mod repl;
 mod test;
 
-use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::{env, fs};
 
-use bumpalo::Bump;
-use lower::lower;
-use read::test_loop;
-use sizer::sizer;
-use tree::token;
-fn main() {
-    read::event_loop("Welcome to the Hoyle repl", |tokens, errors| {
-        if errors.success() {
-            run(tokens)
+use args::{Command, Stage};
+use pipeline::Failed;
+use type_checker::env::Mode;
+
+fn main() -> ExitCode {
+    let args = match args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprint!("error: {error}\n\n{}", args::USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let compiler = native::Compiler::new(args.cc, args.optimisation);
+    let options = pipeline::Options {
+        emit: emit::Options {
+            debug_rc: args.debug_rc,
+        },
+        rc_stats: args.rc_stats,
+        monomorphize: args.monomorphize,
+        mode: if args.production {
+            Mode::Production
         } else {
-            println!("error while lexing: {:?}", errors);
-            read::ExitStatus::Error
+            Mode::Development
+        },
+    };
+    let result = match args.command {
+        Command::Help => {
+            print!("{}", args::USAGE);
+            Ok(ExitCode::SUCCESS)
         }
-    })
-    .unwrap()
+        Command::Check { input } => compile_file(&input, Stage::Typed, &args.emit, false, options)
+            .map(|_| ExitCode::SUCCESS),
+        Command::EmitC { input, output } => {
+            compile_file(&input, Stage::C, &args.emit, false, options)
+                .and_then(|c_source| {
+                    let c_source = c_source.unwrap_or_default();
+                    match output {
+                        Some(output) => write_output(&output, &c_source),
+                        None => {
+                            print!("{c_source}");
+                            Ok(())
+                        }
+                    }
+                })
+                .map(|()| ExitCode::SUCCESS)
+        }
+        Command::Build { input, output } => {
+            let output = output.unwrap_or_else(|| default_executable(&input));
+            build(&input, &output, &args.emit, options, &compiler).map(|()| ExitCode::SUCCESS)
+        }
+        Command::Run { input } => run(&input, &args.emit, options, &compiler),
+        Command::Repl => repl::run(&args.emit, options)
+            .map(|()| ExitCode::SUCCESS)
+            .map_err(|error| {
+                eprintln!("error: couldn't read from stdin: {error}");
+                Failed
+            }),
+    };
+    result.unwrap_or(ExitCode::FAILURE)
+}
+
+/// `main.hoyle` builds `main`
+fn default_executable(input: &Path) -> PathBuf {
+    let output = input.with_extension("");
+    if output == input {
+        input.with_extension("out")
+    } else {
+        output
+    }
+}
+
+/// builds `input` into the executable `output`, leaving the generated c source next to it
+fn build(
+    input: &Path,
+    output: &Path,
+    emit: &[Stage],
+    options: pipeline::Options,
+    compiler: &native::Compiler,
+) -> Result<(), Failed> {
+    let c_file = output.with_extension("c");
+    if c_file == output {
+        eprintln!(
+            "error: the executable `{}` would be overwritten by its c source",
+            output.display()
+        );
+        return Err(Failed);
+    }
+    let c_source = compile_file(input, Stage::C, emit, true, options)?.unwrap_or_default();
+    write_output(&c_file, &c_source)?;
+    compiler.compile(&c_file, output)
 }
 
-/*
-fn main() {
-    test_loop(
-        r#"
-            func id[t](x: t): t = x
-            func a(): F64 = id(3)
-        "#,
-        run,
+/// builds `input` in a temporary directory and runs it, exiting with the executable's exit code
+fn run(
+    input: &Path,
+    emit: &[Stage],
+    options: pipeline::Options,
+    compiler: &native::Compiler,
+) -> Result<ExitCode, Failed> {
+    let directory = env::temp_dir().join(format!("hoyle-{}", process::id()));
+    fs::create_dir_all(&directory).map_err(|error| {
+        eprintln!("error: couldn't create `{}`: {error}", directory.display());
+        Failed
+    })?;
+    let executable = directory.join(default_executable(Path::new(
+        input.file_name().unwrap_or_default(),
+    )));
+    if let Err(failed) = build(input, &executable, emit, options, compiler) {
+        // a c compiler error points into the c source, so the directory is only removed if nothing was written to it
+        let _ = fs::remove_dir(&directory);
+        return Err(failed);
+    }
+    let status = process::Command::new(&executable)
+        .status()
+        .map_err(|error| {
+            eprintln!("error: couldn't run `{}`: {error}", executable.display());
+            Failed
+        });
+    let _ = fs::remove_dir_all(&directory);
+    let status = status?;
+    if let Some(signal) = signal(status) {
+        eprintln!(
+            "error: `{}` was killed by signal {signal}",
+            executable.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+    Ok(status
+        .code()
+        .and_then(|code| u8::try_from(code).ok())
+        .map_or(ExitCode::FAILURE, ExitCode::from))
+}
+
+/// the signal that killed a process, which only unix reports
+#[cfg(unix)]
+fn signal(status: process::ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_: process::ExitStatus) -> Option<i32> {
+    None
+}
+
+fn compile_file(
+    input: &Path,
+    last: Stage,
+    emit: &[Stage],
+    executable: bool,
+    options: pipeline::Options,
+) -> Result<Option<String>, Failed> {
+    let text = fs::read_to_string(input).map_err(|error| {
+        eprintln!("error: couldn't read `{}`: {error}", input.display());
+        Failed
+    })?;
+    pipeline::compile(
+        &input.display().to_string(),
+        &text,
+        last,
+        emit,
+        executable,
+        options,
     )
 }
-*/
 
-fn run(tokens: token::List) -> read::ExitStatus {
-    println!("tokens: {:?}", tokens);
-    let parsed = match parser::parse(&tokens.into_iter().collect::<Vec<_>>()) {
-        Ok(p) => p,
-        Err(error) => {
-            println!("parse error: {:?}", error);
-            return read::ExitStatus::Error;
-        }
-    };
-    println!("parsed");
-    let typed = match type_checker::infer::program(&parsed) {
-        Ok(t) => t,
-        Err(error) => {
-            println!("type error: {:?}", error);
-            return read::ExitStatus::Error;
-        }
-    };
-    println!("okay");
-    let passed = type_passing::pass::program(&typed);
-    let sized = sizer::program(&passed);
-    println!("sized");
-    println!("{}", sized);
-    println!("printed size");
-    let bridged = lower::program(&sized);
-    println!("{}", bridged);
-    let c_source = emit::program(bridged);
-    fs::write("gen/out.c", c_source.to_string()).unwrap();
-    read::ExitStatus::Okay
+fn write_output(output: &Path, contents: &str) -> Result<(), Failed> {
+    fs::write(output, contents).map_err(|error| {
+        eprintln!("error: couldn't write `{}`: {error}", output.display());
+        Failed
+    })
 }
@@ -0,0 +1,52 @@
+use std::path::Path;
+use std::{env, process};
+
+use crate::pipeline::Failed;
+
+/// the system C compiler that turns emitted C into executables
+pub struct Compiler {
+    /// the compiler followed by any arguments of its own, like `gcc -m64`
+    command: Vec<String>,
+    optimisation: u8,
+}
+
+impl Compiler {
+    /// uses `command` if it was given, and otherwise `$CC` or `cc`
+    pub fn new(command: Option<String>, optimisation: u8) -> Self {
+        let command = command
+            .or_else(|| env::var("CC").ok())
+            .filter(|command| !command.trim().is_empty())
+            .unwrap_or_else(|| String::from("cc"));
+        Self {
+            command: command.split_whitespace().map(String::from).collect(),
+            optimisation,
+        }
+    }
+
+    /// compiles the C source at `c_file` into the executable `output`.
+    /// the C source came from the compiler, so the C compiler rejecting it is a bug in hoyle
+    pub fn compile(&self, c_file: &Path, output: &Path) -> Result<(), Failed> {
+        let status = process::Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg(format!("-O{}", self.optimisation))
+            .arg(c_file)
+            .arg("-o")
+            .arg(output)
+            .arg("-lm")
+            .status()
+            .map_err(|error| {
+                eprintln!("error: couldn't run `{}`: {error}", self.command[0]);
+                Failed
+            })?;
+        if status.success() {
+            Ok(())
+        } else {
+            eprintln!(
+                "internal compiler error: `{}` couldn't compile the generated c source",
+                self.command.join(" ")
+            );
+            eprintln!(" --> {}", c_file.display());
+            Err(Failed)
+        }
+    }
+}
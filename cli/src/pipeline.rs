@@ -0,0 +1,160 @@
+use core::fmt;
+
+use ::lower::{borrow, rme};
+use lexer::scan_tokens;
+use lower::lower;
+use sizer::sizer;
+use tree::typed::{self, Type};
+use type_checker::env::Mode;
+
+use crate::args::Stage;
+use crate::diagnostic::{Diagnostic, Severity};
+
+/// compilation stopped early, and its diagnostics have already been reported
+pub struct Failed;
+
+/// how to compile a program, besides which stages to print
+#[derive(Copy, Clone, Default)]
+pub struct Options {
+    pub emit: emit::Options,
+    /// print how many reference counting operations borrowing removed from the bridge program
+    pub rc_stats: bool,
+    /// give each generic function and struct an instance for every type it's used with, instead of passing witness tables
+    pub monomorphize: bool,
+    /// whether error unions can be used without checking for an error
+    pub mode: Mode,
+}
+
+/// runs the pipeline over `text` until `last` (or the last stage in `emit`, if that is later),
+/// printing the stages in `emit` along the way.
+/// returns the c source if the pipeline got that far, with an entry point that runs `main` if `executable` is set
+pub fn compile(
+    file: &str,
+    text: &str,
+    last: Stage,
+    emit: &[Stage],
+    executable: bool,
+    options: Options,
+) -> Result<Option<String>, Failed> {
+    let last = emit.iter().copied().fold(last, Stage::max);
+    let dump = |stage: Stage, output: &dyn fmt::Display| {
+        if emit.contains(&stage) {
+            println!("{output}");
+        }
+        stage < last
+    };
+
+    let (tokens, errors) = scan_tokens(text);
+    if !errors.success() {
+        report(file, text, Diagnostic::lexer(&errors));
+        return Err(Failed);
+    }
+    if !dump(Stage::Tokens, &format_args!("{tokens:?}")) {
+        return Ok(None);
+    }
+    let parsed = parser::parse(&tokens.into_iter().collect::<Vec<_>>()).map_err(|errors| {
+        report(file, text, Diagnostic::parser(&errors));
+        Failed
+    })?;
+    if !dump(Stage::Parsed, &parsed) {
+        return Ok(None);
+    }
+    let (typed, warnings) =
+        type_checker::infer::program(&parsed, options.mode).map_err(|error| {
+            report(file, text, [Diagnostic::type_checker(&error)]);
+            Failed
+        })?;
+    report(file, text, warnings.iter().map(Diagnostic::warning));
+    let typed = if options.monomorphize {
+        monomorphize::monomorphize::program(&typed).map_err(|error| {
+            report(file, text, [Diagnostic::monomorphize(&error)]);
+            Failed
+        })?
+    } else {
+        typed
+    };
+    let entry = if executable {
+        let entry = entry_point(&typed).map_err(|diagnostic| {
+            report(file, text, [diagnostic]);
+            Failed
+        })?;
+        Some(entry)
+    } else {
+        None
+    };
+    if !dump(Stage::Typed, &typed) {
+        return Ok(None);
+    }
+    let passed = type_passing::pass::program(&typed);
+    if !dump(Stage::Passed, &passed) {
+        return Ok(None);
+    }
+    let sized = sizer::program(&passed);
+    if !dump(Stage::Sized, &sized) {
+        return Ok(None);
+    }
+    let bridged = lower::program(&sized);
+    let counted = borrow::operations(&bridged);
+    let bridged = borrow::program(bridged);
+    if options.rc_stats {
+        let borrowed = borrow::operations(&bridged);
+        eprintln!(
+            "removed {} of {} reference counting operations: {} of {} copies and {} of {} destroys",
+            counted.total() - borrowed.total(),
+            counted.total(),
+            counted.copies - borrowed.copies,
+            counted.copies,
+            counted.destroys - borrowed.destroys,
+            counted.destroys,
+        );
+    }
+    let bridged = rme::program(bridged);
+    if !dump(Stage::Bridge, &bridged) {
+        return Ok(None);
+    }
+    let c_source = match entry {
+        Some(result) => emit::executable(bridged, &result, options.emit),
+        None => emit::program(bridged, options.emit),
+    }
+    .to_string();
+    dump(Stage::C, &c_source);
+    Ok(Some(c_source))
+}
+
+/// the result type of the `main` function that an executable starts from
+fn entry_point(program: &typed::Program) -> Result<Type, Diagnostic> {
+    let Some(main) = program
+        .functions
+        .iter()
+        .find(|function| function.name == "main")
+    else {
+        return Err(Diagnostic {
+            message: String::from("there is no `main` function to start from"),
+            span: None,
+            severity: Severity::Error,
+        });
+    };
+    let message = if !main.generics.is_empty() {
+        String::from("`main` can't be generic")
+    } else if !main.arguments.is_empty() {
+        String::from("`main` can't take arguments")
+    } else if !emit::printable(&main.result) {
+        format!(
+            "`main` must return `F64`, `I64` or `Bool`, not `{}`",
+            main.result
+        )
+    } else {
+        return Ok(main.result.clone());
+    };
+    Err(Diagnostic {
+        message,
+        span: Some(main.span),
+        severity: Severity::Error,
+    })
+}
+
+pub fn report(file: &str, text: &str, diagnostics: impl IntoIterator<Item = Diagnostic>) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(file, text));
+    }
+}
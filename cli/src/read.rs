@@ -30,10 +30,14 @@ fn is_balanced<'a>(tokens: impl IntoIterator<Item = Token<'a>>) -> bool {
     braces == 0 && parens == 0 && squares == 0
 }
 
-pub fn event_loop(name: &str, mut callback: impl FnMut(List, Errors) -> ExitStatus) -> Result<()> {
+pub fn event_loop(
+    name: &str,
+    mut callback: impl FnMut(&str, List, Errors) -> ExitStatus,
+) -> Result<()> {
     let mut working_line = String::new();
     let stdin = io::stdin();
 
+    println!("{name}");
     print!("> ");
     io::stdout().flush().unwrap();
     for line in stdin.lock().lines() {
@@ -43,7 +47,7 @@ pub fn event_loop(name: &str, mut callback: impl FnMut(List, Errors) -> ExitStat
 
         let (tokens, errors) = scan_tokens(&working_line);
         if line.strip_prefix("[ \t\n]*") == Some("") || line.is_empty() {
-            if let ExitStatus::Quit = callback(tokens, errors) {
+            if let ExitStatus::Quit = callback(&working_line, tokens, errors) {
                 break;
             }
 
@@ -57,8 +61,8 @@ pub fn event_loop(name: &str, mut callback: impl FnMut(List, Errors) -> ExitStat
     Ok(())
 }
 
-pub fn test_loop(text: &str, mut callback: impl FnMut(List) -> ExitStatus) {
+pub fn test_loop(text: &str, mut callback: impl FnMut(&str, List) -> ExitStatus) {
     let (tokens, errors) = scan_tokens(text);
     assert!(errors.success());
-    assert_eq!(callback(tokens), ExitStatus::Okay);
+    assert_eq!(callback(text, tokens), ExitStatus::Okay);
 }
@@ -1 +1,21 @@
+use std::io;
 
+use crate::args::Stage;
+use crate::pipeline;
+use crate::read::{self, ExitStatus};
+
+/// checks each program entered on stdin with `options`, printing the stages in `emit` (or the typed program)
+pub fn run(emit: &[Stage], options: pipeline::Options) -> io::Result<()> {
+    let emit = if emit.is_empty() {
+        &[Stage::Typed]
+    } else {
+        emit
+    };
+    read::event_loop(
+        "Welcome to the Hoyle repl",
+        |text, _, _| match pipeline::compile("<repl>", text, Stage::C, emit, false, options) {
+            Ok(_) => ExitStatus::Okay,
+            Err(pipeline::Failed) => ExitStatus::Error,
+        },
+    )
+}
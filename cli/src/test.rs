@@ -1,33 +1,100 @@
 use core::fmt;
 use std::{fs, process};
 
+use ::lower::{borrow, rme};
+use ir::bridge;
 use lexer::scan_tokens;
 use lower::lower;
+use monomorphize::monomorphize;
 use sizer::sizer;
+use tree::typed;
+use type_checker::env::Mode;
 
-fn to_c(text: &str) -> String {
+use crate::args::Stage;
+use crate::{native, pipeline};
+
+/// every program in the tests checks its own allocations, copies and destroys
+const DEBUG_RC: emit::Options = emit::Options { debug_rc: true };
+
+const PIPELINE: pipeline::Options = pipeline::Options {
+    emit: DEBUG_RC,
+    rc_stats: false,
+    monomorphize: false,
+    mode: Mode::Development,
+};
+
+fn to_bridge(text: &str) -> bridge::Program {
+    optimise(lower_text(text))
+}
+
+fn optimise(lowered: bridge::Program) -> bridge::Program {
+    let bridged = rme::program(borrow::program(lowered));
+    println!("{}", bridged);
+    bridged
+}
+
+fn type_check(text: &str) -> typed::Program {
     let (tokens, errors) = scan_tokens(text);
     assert!(errors.success());
 
     println!("tokens: {:?}", tokens);
     let parsed = parser::parse(&tokens.into_iter().collect::<Vec<_>>()).unwrap();
     println!("parsed");
-    let typed = type_checker::infer::program(&parsed).unwrap();
+    let (typed, _) = type_checker::infer::program(&parsed, Mode::Development).unwrap();
     println!("okay");
-    let passed = type_passing::pass::program(&typed);
+    typed
+}
+
+/// lowers `text` without borrowing any arguments
+fn lower_text(text: &str) -> bridge::Program {
+    lower_typed(&type_check(text))
+}
+
+fn lower_typed(typed: &typed::Program) -> bridge::Program {
+    let passed = type_passing::pass::program(typed);
     let sized = sizer::program(&passed);
     println!("sized");
     println!("{}", sized);
     println!("printed size");
-    let bridged = lower::program(&sized);
-    println!("{}", bridged);
-    let c_source = emit::program(bridged);
-    c_source.to_string()
+    lower::program(&sized)
+}
+
+/// runs `main` in the interpreter, which checks that every value is moved and destroyed properly
+fn interpret(program: &bridge::Program, main: &str) -> u64 {
+    let run = interpret::run(program, main, 8).unwrap_or_else(|error| panic!("{error}"));
+    for leak in &run.leaks {
+        println!("leak: {leak}");
+    }
+    assert!(run.leaks.is_empty(), "the program leaks");
+    u64::from_le_bytes(run.result.try_into().unwrap())
 }
 
 fn run_double_func(c_program: &str, double_func: &str, formatter: &str, cast_to: &str) -> String {
+    let output = run_c_main(
+        c_program,
+        double_func,
+        &format!(
+            r#"#include <stdio.h>
+
+int main() {{
+  {cast_to} x;
+  {double_func}(&x);
+  printf("{formatter}", ({cast_to}) x);
+}}"#
+        ),
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// compiles `c_program` along with the c source `main` that runs it, and runs the result
+fn run_c_main(c_program: &str, name: &str, main: &str) -> process::Output {
     let seed = fastrand::u64(u64::MIN..=u64::MAX);
-    let path = format!("./target/gen/{double_func}{seed}/");
+    let path = format!("./target/gen/{name}{seed}/");
     fs::DirBuilder::new().recursive(true).create(&path).unwrap();
     let prefix = fs::canonicalize(path).unwrap();
 
@@ -37,23 +104,11 @@ fn run_double_func(c_program: &str, double_func: &str, formatter: &str, cast_to:
         other
     };
     fs::write(in_working("out.c"), c_program).unwrap();
-    fs::write(
-        in_working("main.c"),
-        format!(
-            r#"#include "out.c"
-#include <stdio.h>
-
-int main() {{
-  {cast_to} x;
-  {double_func}(&x);
-  printf("{formatter}", ({cast_to}) x);
-}}"#
-        ),
-    )
-    .unwrap();
+    fs::write(in_working("main.c"), format!("#include \"out.c\"\n{main}")).unwrap();
     let result = process::Command::new("gcc")
         .current_dir(&prefix)
         .arg("main.c")
+        .arg("-lm")
         .stdout(process::Stdio::piped())
         .spawn()
         .unwrap()
@@ -64,25 +119,70 @@ int main() {{
     let child = process::Command::new("./a.out")
         .current_dir(&prefix)
         .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
         .spawn()
         .unwrap();
-    let output = child.wait_with_output().unwrap();
-    assert!(output.status.success());
-    String::from_utf8(output.stdout).unwrap()
+    child.wait_with_output().unwrap()
 }
 
+/// checks that `main` returns `expected`, both with witness tables and monomorphized
 fn run<O: OutputType + fmt::Display>(text: &str, main: &str, expected: O) {
-    let value = run_double_func(&to_c(text), main, O::formatter(), O::cast_to());
+    run_bridged(to_bridge(text), main, &expected);
+    let Ok(monomorphized) = monomorphize::program(&type_check(text)) else {
+        panic!("{text} can't be monomorphized");
+    };
+    run_bridged(optimise(lower_typed(&monomorphized)), main, &expected);
+}
+
+fn run_bridged<O: OutputType + fmt::Display>(bridged: bridge::Program, main: &str, expected: &O) {
+    let interpreted = O::decode(interpret(&bridged, main));
+    assert!(
+        expected.equals(interpreted.clone()),
+        "{main}() = {interpreted} in the interpreter, which is not {expected}",
+    );
+    let c_source = emit::program(bridged, DEBUG_RC).to_string();
+    let value = run_double_func(&c_source, main, O::formatter(), O::cast_to());
     assert!(
         expected.equals(value.clone()),
         "{main}() = {value} is not {expected}",
     )
 }
 
+/// builds `text` into an executable the way `hoyle build` does, and returns what it prints
+fn run_executable(text: &str) -> String {
+    run_executable_with(text, "gcc")
+}
+
+/// builds `text` into an executable with the C compiler `command` and runs it
+fn run_executable_with(text: &str, command: &str) -> String {
+    let Ok(Some(c_source)) = pipeline::compile("test.hoyle", text, Stage::C, &[], true, PIPELINE)
+    else {
+        panic!("couldn't compile the program");
+    };
+    let seed = fastrand::u64(u64::MIN..=u64::MAX);
+    let path = format!("./target/gen/executable{seed}/");
+    fs::DirBuilder::new().recursive(true).create(&path).unwrap();
+    let prefix = fs::canonicalize(path).unwrap();
+    let c_file = prefix.join("main.c");
+    let executable = prefix.join("main");
+    fs::write(&c_file, c_source).unwrap();
+    assert!(
+        native::Compiler::new(Some(String::from(command)), 2)
+            .compile(&c_file, &executable)
+            .is_ok(),
+        "emitted c code doesn't compile"
+    );
+    let output = process::Command::new(executable).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
 trait OutputType {
     fn equals(&self, other: String) -> bool;
     fn formatter() -> &'static str;
     fn cast_to() -> &'static str;
+    /// how the C program would print the word that the interpreter returned
+    fn decode(word: u64) -> String;
 }
 
 impl OutputType for f64 {
@@ -100,6 +200,31 @@ impl OutputType for f64 {
     fn cast_to() -> &'static str {
         "double"
     }
+
+    fn decode(word: u64) -> String {
+        f64::from_bits(word).to_string()
+    }
+}
+
+impl OutputType for i64 {
+    fn equals(&self, other: String) -> bool {
+        let other: i64 = other
+            .parse()
+            .expect(&format!("could not parse {} as integer", other));
+        other == *self
+    }
+
+    fn formatter() -> &'static str {
+        "%lld"
+    }
+
+    fn cast_to() -> &'static str {
+        "signed long long"
+    }
+
+    fn decode(word: u64) -> String {
+        (word as i64).to_string()
+    }
 }
 
 impl OutputType for bool {
@@ -121,6 +246,10 @@ impl OutputType for bool {
     fn cast_to() -> &'static str {
         "signed long long"
     }
+
+    fn decode(word: u64) -> String {
+        word.to_string()
+    }
 }
 
 #[test]
@@ -361,3 +490,1280 @@ fn nested_if_result() {
         4.,
     )
 }
+
+#[test]
+fn closure_call() {
+    run(
+        r#"
+        func id[t](x: t): t = x
+        func four(): F64 = 4
+        func five(): F64 = four() + id(1)
+        func mul_by(x: F64): (F64) -> F64 = a => a * x
+        func closure_call(): F64 = {
+            let f = mul_by(five());
+            f(3)
+        }
+        "#,
+        "closure_call",
+        15.,
+    )
+}
+
+#[test]
+fn closure_argument() {
+    run(
+        r#"
+        func apply(f: (F64) -> F64, x: F64): F64 = f(x)
+        func closure_argument(): F64 = {
+            let y = 2;
+            apply(x => x + y, 5)
+        }
+        "#,
+        "closure_argument",
+        7.,
+    )
+}
+
+#[test]
+fn captured_closure() {
+    run(
+        r#"
+        func twice(f: (F64) -> F64): (F64) -> F64 = x => f(f(x))
+        func captured_closure(): F64 = twice(x => x * 3)(2)
+        "#,
+        "captured_closure",
+        18.,
+    )
+}
+
+#[test]
+fn enum_case() {
+    run(
+        r#"
+        enum Shape {
+            Circle(F64),
+            Rectangle(F64, F64),
+            Empty,
+        }
+        func area(shape: Shape): F64 = case shape of
+            | Circle(r) => 3 * r * r
+            | Rectangle(w, h) => w * h
+            | Empty => 0
+        func enum_case(): F64 = area(Rectangle(2, 5)) + area(Circle(1)) + area(Empty)
+        "#,
+        "enum_case",
+        13.,
+    )
+}
+
+#[test]
+fn nested_patterns() {
+    run(
+        r#"
+        enum Option {
+            Some(F64),
+            None
+        }
+        enum Pair {
+            Pair(Option, Bool)
+        }
+        func pick(pair: Pair): F64 = case pair of
+            | Pair(Some(2), True) => 1
+            | Pair(Some(x), True) => x
+            | Pair(None, True) => 3
+            | Pair(_, False) => 4
+        func nested_patterns(): F64 =
+            pick(Pair(Some(2), True)) * 1000
+                + pick(Pair(Some(7), True)) * 100
+                + pick(Pair(None, True)) * 10
+                + pick(Pair(Some(5), False))
+        "#,
+        "nested_patterns",
+        1734.,
+    )
+}
+
+#[test]
+fn generic_payload() {
+    run(
+        r#"
+        struct Point {
+            x: F64
+            y: F64
+        }
+        enum Shape {
+            Dot(Point),
+            Nothing
+        }
+        func id[t](x: t): t = x
+        func generic_payload(): F64 = case id(Dot(Point { x: 3, y: 4 })) of
+            | Dot(p) => (case p of | q => 2)
+            | Nothing => 0
+        "#,
+        "generic_payload",
+        2.,
+    )
+}
+
+#[test]
+fn case_shadowing() {
+    run(
+        r#"
+        enum Option {
+            Some(F64),
+            None
+        }
+        func case_shadowing(): F64 = {
+            let x = 10;
+            let a = case Some(1) of
+                | Some(x) => x
+                | None => x;
+            let b = case None of
+                | Some(x) => x
+                | None => x;
+            a + b
+        }
+        "#,
+        "case_shadowing",
+        11.,
+    )
+}
+
+#[test]
+fn field_access() {
+    run(
+        r#"
+        struct Point {
+            x: F64
+            y: F64
+        }
+        func field_access(): F64 = {
+            let p = Point { x: 3, y: 4 };
+            p.x * 10 + p.y
+        }
+        "#,
+        "field_access",
+        34.,
+    )
+}
+
+#[test]
+fn nested_field_access() {
+    run(
+        r#"
+        struct Point {
+            x: F64
+            y: F64
+        }
+        struct Line {
+            start: Point
+            end: Point
+        }
+        func id[t](x: t): t = x
+        func length(line: Line): F64 = line.end.x - line.start.x
+        func nested_field_access(): F64 = length(id(Line {
+            start: Point { x: 1, y: 2 },
+            end: Point { x: 6, y: 8 }
+        })) + id(Line { start: Point { x: 1, y: 2 }, end: Point { x: 6, y: 8 } }).end.y
+        "#,
+        "nested_field_access",
+        13.,
+    )
+}
+
+#[test]
+fn closure_field() {
+    run(
+        r#"
+        struct Scaler {
+            factor: F64
+            scale: (F64) -> F64
+        }
+        func make(factor: F64): Scaler = Scaler { factor: factor, scale: x => x * factor }
+        func closure_field(): F64 = {
+            let s = make(3);
+            s.scale(s.factor)
+        }
+        "#,
+        "closure_field",
+        9.,
+    )
+}
+
+#[test]
+fn generic_struct() {
+    run(
+        r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func swap[a, b](p: Pair[a, b]): Pair[b, a] = Pair { fst: p.snd, snd: p.fst }
+        func pick(p: Pair[F64, Bool]): F64 = if p.snd then p.fst else 0
+        func generic_struct(): F64 = {
+            let p = swap(Pair { fst: True, snd: 21 });
+            pick(p) * 2
+        }
+        "#,
+        "generic_struct",
+        42.,
+    )
+}
+
+#[test]
+fn nested_generic_struct() {
+    run(
+        r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        struct Box[t] {
+            value: t
+        }
+        func unbox[t](b: Box[t]): t = b.value
+        func nested_generic_struct(): F64 = {
+            let nested = Box { value: Pair { fst: Box { value: 5 }, snd: x => x * 3 } };
+            let p = unbox(nested);
+            p.snd(unbox(p.fst))
+        }
+        "#,
+        "nested_generic_struct",
+        15.,
+    )
+}
+
+#[test]
+fn generic_closure_capture() {
+    run(
+        r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func pair_with[t](x: t): (F64) -> Pair[t, F64] = y => Pair { fst: x, snd: y }
+        func generic_closure_capture(): F64 = {
+            let make = pair_with(Pair { fst: 4, snd: True });
+            let p = make(6);
+            p.fst.fst + p.snd
+        }
+        "#,
+        "generic_closure_capture",
+        10.,
+    )
+}
+
+#[test]
+fn protocol_static_call() {
+    run(
+        r#"
+        protocol Measurable {
+            func measure(x: Self): F64
+        }
+        struct Point {
+            x: F64
+            y: F64
+        }
+        impl Measurable for Point {
+            func measure(p: Point): F64 = p.x + p.y
+        }
+        impl Measurable for Bool {
+            func measure(b: Bool): F64 = if b then 1 else 0
+        }
+        func protocol_static_call(): F64 = measure(Point { x: 3, y: 4 }) * 10 + measure(True)
+        "#,
+        "protocol_static_call",
+        71.,
+    )
+}
+
+#[test]
+fn bounded_generic() {
+    run(
+        r#"
+        protocol Monoid {
+            func empty(): Self
+            func combine(a: Self, b: Self): Self
+        }
+        impl Monoid for F64 {
+            func empty(): F64 = 0
+            func combine(a: F64, b: F64): F64 = a + b
+        }
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func triple[t: Monoid](x: t): t = combine(x, combine(x, combine(x, empty())))
+        func both[t: Monoid](p: Pair[t, t]): t = combine(triple(p.fst), p.snd)
+        func bounded_generic(): F64 = both(Pair { fst: 4, snd: 2 })
+        "#,
+        "bounded_generic",
+        14.,
+    )
+}
+
+#[test]
+fn dictionary_capture() {
+    run(
+        r#"
+        protocol Scalable {
+            func scale(x: Self, by: F64): Self
+        }
+        impl Scalable for F64 {
+            func scale(x: F64, by: F64): F64 = x * by
+        }
+        func scaler[t: Scalable](by: F64): (t) -> t = x => scale(x, by)
+        func apply(f: (F64) -> F64, x: F64): F64 = f(x)
+        func dictionary_capture(): F64 = apply(scaler(3), 5)
+        "#,
+        "dictionary_capture",
+        15.,
+    )
+}
+
+#[test]
+fn integer_arithmetic() {
+    run(
+        r#"
+        func square(x: I64): I64 = x * x
+        func integer_arithmetic(): I64 = square(3) * 10 + 2
+        "#,
+        "integer_arithmetic",
+        92i64,
+    )
+}
+
+#[test]
+fn literal_inference() {
+    run(
+        r#"
+        func half(x: F64): F64 = x * 0.5
+        func literal_inference(): F64 = {
+            let n = 7;
+            let x = 10;
+            to_f64(n * 2) + half(x) + 2 * half(1)
+        }
+        "#,
+        "literal_inference",
+        20.,
+    )
+}
+
+#[test]
+fn integer_overflow() {
+    run(
+        r#"
+        func integer_overflow(): I64 = 9223372036854775807 + 1
+        "#,
+        "integer_overflow",
+        i64::MIN,
+    )
+}
+
+#[test]
+fn float_to_integer() {
+    run(
+        r#"
+        func float_to_integer(): I64 = to_i64(2.9) + to_i64(100000000000000000000000.0) - 9223372036854775807
+        "#,
+        "float_to_integer",
+        2i64,
+    )
+}
+
+#[test]
+fn existential_field() {
+    run(
+        r#"
+        protocol Measurable {
+            func measure(x: Self): F64
+        }
+        struct Point {
+            x: F64
+            y: F64
+        }
+        impl Measurable for Point {
+            func measure(p: Point): F64 = p.x + p.y
+        }
+        impl Measurable for Bool {
+            func measure(b: Bool): F64 = if b then 1 else 0
+        }
+        struct Shapes {
+            first: any Measurable
+            second: any Measurable
+        }
+        func total(shapes: Shapes): F64 = measure(shapes.first) * 10 + measure(shapes.second)
+        func existential_field(): F64 = total(Shapes { first: Point { x: 3, y: 4 }, second: True })
+        "#,
+        "existential_field",
+        71.,
+    )
+}
+
+#[test]
+fn existential_integer_literal() {
+    run(
+        r#"
+        protocol Measurable {
+            func measure(x: Self): F64
+        }
+        impl Measurable for I64 {
+            func measure(n: I64): F64 = 2
+        }
+        struct Holder {
+            value: any Measurable
+        }
+        func weigh(x: any Measurable): F64 = measure(x)
+        func existential_integer_literal(): F64 = weigh(5) * 10 + measure(Holder { value: 2 }.value)
+        "#,
+        "existential_integer_literal",
+        22.,
+    )
+}
+
+#[test]
+fn existential_generic() {
+    run(
+        r#"
+        protocol Scalable {
+            func scale(by: F64, x: Self): F64
+        }
+        struct Point {
+            x: F64
+            y: F64
+        }
+        impl Scalable for F64 {
+            func scale(by: F64, x: F64): F64 = x * by
+        }
+        impl Scalable for Point {
+            func scale(by: F64, p: Point): F64 = (p.x + p.y) * by
+        }
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func twice[t: Scalable](x: t): F64 = scale(2, x)
+        func dup[t](x: t): Pair[t, t] = Pair { fst: x, snd: x }
+        func pack(x: any Scalable): any Scalable = x
+        func existential_generic(): F64 = {
+            let p = dup(pack(Point { x: 1, y: 2 }));
+            let q = Pair { fst: pack(5.0), snd: p.snd };
+            twice(p.fst) + scale(10, q.fst) + twice(q.snd)
+        }
+        "#,
+        "existential_generic",
+        62.,
+    )
+}
+
+#[test]
+fn comparison() {
+    run(
+        r#"
+        func max(a: I64, b: I64): I64 = if a >= b then a else b
+        func sign(x: F64): F64 = if x < 0 then -1 else if x > 0 then 1 else 0
+        func comparison(): I64 = {
+            let a = max(3, 7) * 100;
+            let b = to_i64(sign(-2.5) * 10);
+            let c = if 2 + 2 == 4 && 3 != 3 == False then 1 else 0;
+            a + b + c
+        }
+        "#,
+        "comparison",
+        691i64,
+    )
+}
+
+#[test]
+fn division() {
+    run(
+        r#"
+        func division(): I64 = 17 / 5 * 100 + -17 % 5 * 10 + -9223372036854775807 - 1 / -1
+        "#,
+        "division",
+        // -9223372036854775807 - (1 / -1) is -9223372036854775806
+        300 - 20 - 9223372036854775806,
+    )
+}
+
+#[test]
+fn float_division() {
+    run(
+        r#"
+        func float_division(): F64 = 7.0 / 2 + 7.5 % 2
+        "#,
+        "float_division",
+        5.,
+    )
+}
+
+#[test]
+fn short_circuit() {
+    run(
+        r#"
+        func crash(): Bool = 1 / 0 == 0
+        func short_circuit(): Bool = !(False && crash()) && (True || crash()) && !!True
+        "#,
+        "short_circuit",
+        true,
+    )
+}
+
+#[test]
+fn tail_recursion() {
+    run(
+        r#"
+        enum Step {
+            Done,
+            More(I64)
+        }
+        func step(n: I64): Step = if n == 0 then Done else More(n - 1)
+        func count(n: I64, acc: I64): I64 = case step(n) of
+            | Done => acc
+            | More(m) => {
+                let next = acc + 1;
+                count(m, next)
+            }
+        func tail_recursion(): I64 = count(1000000, 0)
+        "#,
+        "tail_recursion",
+        1000000i64,
+    )
+}
+
+#[test]
+fn mutual_tail_recursion() {
+    run(
+        r#"
+        func is_even(n: I64): Bool = if n == 0 then True else is_odd(n - 1)
+        func is_odd(n: I64): Bool = n != 0 && is_even(n - 1)
+        func parity(n: I64): Bool = if is_even(n) then False else is_odd(n)
+        func mutual_tail_recursion(): Bool = parity(1000001)
+        "#,
+        "mutual_tail_recursion",
+        true,
+    )
+}
+
+#[test]
+fn generic_tail_recursion() {
+    run(
+        r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func swap_n[t](n: I64, x: t, y: t): Pair[t, t] =
+            if n == 0 then Pair { fst: x, snd: y } else swap_n(n - 1, y, x)
+        func generic_tail_recursion(): F64 = {
+            let p = swap_n(1000001, 1.0, 2.0);
+            p.fst * 10 + p.snd
+        }
+        "#,
+        "generic_tail_recursion",
+        21.,
+    )
+}
+
+#[test]
+fn executable() {
+    let text = r#"
+        func fact(n: I64, acc: I64): I64 = if n == 0 then acc else fact(n - 1, acc * n)
+        func main(): I64 = fact(20, 1)
+    "#;
+    assert_eq!(run_executable(text), "2432902008176640000\n");
+    assert_eq!(run_executable("func main(): Bool = 1 < 2"), "True\n");
+    assert_eq!(run_executable("func main(): F64 = 5 / 2"), "2.5\n");
+}
+
+#[test]
+fn unprintable_main() {
+    let text = r#"
+        struct Point {
+            x: F64
+        }
+        func main(): Point = Point { x: 1 }
+    "#;
+    assert!(pipeline::compile("test.hoyle", text, Stage::C, &[], true, PIPELINE).is_err());
+}
+
+/// the C source of a program that makes a closure, which the tests then mistreat
+fn closure_program() -> String {
+    let text = r#"
+        func twice(f: (F64) -> F64): (F64) -> F64 = x => f(f(x))
+        func make(): (F64) -> F64 = twice(x => x * 3)
+        "#;
+    emit::program(to_bridge(text), DEBUG_RC).to_string()
+}
+
+#[test]
+fn debug_rc_double_destroy() {
+    let output = run_c_main(
+        &closure_program(),
+        "debug_rc_double_destroy",
+        r#"
+int main() {
+  struct _Closure f;
+  make(&f);
+  _destroy_closure(&f, NULL);
+  _destroy_closure(&f, NULL);
+}"#,
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("a closure's environment is destroyed after being freed"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn debug_rc_leak() {
+    let output = run_c_main(
+        &closure_program(),
+        "debug_rc_leak",
+        r#"
+int main() {
+  struct _Closure f;
+  make(&f);
+}"#,
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("the program leaks"), "{stderr}");
+}
+
+#[test]
+fn borrowing() {
+    let text = r#"
+        struct Box[t] {
+            value: t
+        }
+        func unbox[t](b: Box[t]): t = b.value
+        func borrowing(): F64 = unbox(Box { value: unbox(Box { value: 5 }) })
+        "#;
+    let lowered = lower_text(text);
+    let borrowed = to_bridge(text);
+    let unbox = borrowed
+        .functions
+        .iter()
+        .find(|function| function.name == "unbox")
+        .unwrap();
+    assert!(unbox.arguments[1..]
+        .iter()
+        .all(|argument| argument.convention == bridge::Convention::Inout));
+    let before = borrow::operations(&lowered);
+    let after = borrow::operations(&borrowed);
+    assert!(after.copies < before.copies && after.destroys < before.destroys);
+    assert_eq!(interpret(&borrowed, "borrowing"), 5f64.to_bits());
+}
+
+#[test]
+fn shared_witness_tables() {
+    let text = r#"
+        struct Box[t] {
+            value: t
+        }
+        func id[t](x: t): t = x
+        func twice[t](b: Box[t], flag: Bool): F64 = {
+            let first = id(b);
+            let second = id(b);
+            if flag then { let third = id(b); 1 } else 2
+        }
+        func shared_witness_tables(): F64 = twice(Box { value: 4 }, True)
+        "#;
+    let bridged = to_bridge(text);
+    let twice = bridged
+        .functions
+        .iter()
+        .find(|function| function.name == "twice")
+        .unwrap();
+    // every use of `Box[t]` shares one table, made before the if
+    assert_eq!(twice.to_string().matches("= Box(").count(), 1, "{twice}");
+    run(text, "shared_witness_tables", 1.);
+}
+
+#[test]
+fn shared_argument_witness_tables() {
+    let text = r#"
+        func pick(b: Bool, d: (F64) -> F64, e: (F64) -> F64): (F64) -> F64 = if b then d else e
+        func shared_argument_witness_tables(): F64 = pick(True, x => x + 1, y => y)(10)
+        "#;
+    run(text, "shared_argument_witness_tables", 11.);
+}
+
+/// compares the c functions emitted for `text` with `snapshots/{name}.c`, which `HOYLE_BLESS=1` rewrites.
+/// returns how many temporaries they declare with and without redundant move elimination
+fn snapshot(name: &str, text: &str, functions: &[&str]) -> (usize, usize) {
+    let path = format!("{}/snapshots/{name}.c", env!("CARGO_MANIFEST_DIR"));
+    let borrowed = borrow::program(lower_text(text));
+    let eliminated = rme::program(borrowed.clone());
+    let c_source = definitions(&emit::program(eliminated, DEFAULT).to_string(), functions);
+    if std::env::var_os("HOYLE_BLESS").is_some() {
+        fs::write(&path, &c_source).unwrap();
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        c_source == expected,
+        "the c source for {name} changed, run with HOYLE_BLESS=1 to update it:\n{c_source}"
+    );
+    let unoptimised = definitions(&emit::program(borrowed, DEFAULT).to_string(), functions);
+    (temporaries(&c_source), temporaries(&unoptimised))
+}
+
+const DEFAULT: emit::Options = emit::Options { debug_rc: false };
+
+/// the definitions of `functions` in `c_source`
+fn definitions(c_source: &str, functions: &[&str]) -> String {
+    let mut found = String::new();
+    for function in functions {
+        let start = c_source
+            .find(&format!("\nvoid {function}(void *_result"))
+            .unwrap_or_else(|| panic!("{function} isn't defined"));
+        let length = c_source[start..].find("\n}\n").unwrap();
+        found.push_str(&c_source[start + 1..start + length + 3]);
+    }
+    found
+}
+
+/// the variables that `c_source` declares to hold values
+fn temporaries(c_source: &str) -> usize {
+    c_source
+        .lines()
+        .map(str::trim_start)
+        .filter(|line| {
+            line.starts_with("char _")
+                || line.starts_with("void *_")
+                    && (line.contains("= malloc(") || line.contains("alloca("))
+        })
+        .count()
+}
+
+#[test]
+fn snapshot_struct_result() {
+    let (eliminated, unoptimised) = snapshot(
+        "struct_result",
+        r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func pair[a, b](fst: a, snd: b): Pair[a, b] = Pair { fst: fst, snd: snd }
+        func make(): Pair[F64, Bool] = {
+            let p = pair(1, True);
+            p
+        }
+        "#,
+        &["pair", "make"],
+    );
+    assert!(eliminated < unoptimised, "{eliminated} >= {unoptimised}");
+}
+
+#[test]
+fn snapshot_moved_arguments() {
+    let (eliminated, unoptimised) = snapshot(
+        "moved_arguments",
+        r#"
+        struct Box[t] {
+            value: t
+        }
+        func unbox[t](b: Box[t]): t = b.value
+        func twice(f: (F64) -> F64): (F64) -> F64 = x => f(f(x))
+        func apply(): F64 = {
+            let g = twice(x => x + unbox(Box { value: 1 }));
+            g(2)
+        }
+        "#,
+        &["unbox", "twice", "apply"],
+    );
+    assert!(eliminated < unoptimised, "{eliminated} >= {unoptimised}");
+}
+
+#[test]
+fn monomorphized_witnesses() {
+    let text = r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        enum Shape {
+            Circle(F64),
+            Square(Pair[F64, F64])
+        }
+        func swap[a, b](p: Pair[a, b]): Pair[b, a] = Pair { fst: p.snd, snd: p.fst }
+        func area(shape: Shape): F64 = case shape of
+            | Circle(r) => 3 * r * r
+            | Square(sides) => swap(sides).fst * sides.fst
+        func main(): F64 = area(Square(swap(Pair { fst: 2, snd: 3 }))) + swap(Pair { fst: True, snd: 1 }).fst
+    "#;
+    let Ok(monomorphized) = monomorphize::program(&type_check(text)) else {
+        panic!("the program can't be monomorphized");
+    };
+    let bridged = optimise(lower_typed(&monomorphized));
+    let names: Vec<_> = bridged
+        .functions
+        .iter()
+        .map(|function| &function.name)
+        .collect();
+    assert_eq!(names, ["area", "main", "_swap_0", "_swap_1"]);
+    fn trivial(block: &bridge::Block) -> bool {
+        block.instrs.iter().all(|instr| {
+            let branches = match &instr.value {
+                bridge::Expr::If {
+                    true_branch,
+                    false_branch,
+                    ..
+                } => trivial(true_branch) && trivial(false_branch),
+                _ => true,
+            };
+            matches!(
+                instr.target.witness.as_ref(),
+                bridge::Witness::Trivial { .. }
+            ) && branches
+        })
+    }
+    for function in &bridged.functions {
+        assert!(
+            trivial(&function.body),
+            "{} passes witness tables",
+            function.name
+        );
+    }
+    assert_eq!(interpret(&bridged, "main"), 7f64.to_bits());
+}
+
+#[test]
+fn polymorphic_recursion() {
+    let text = r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func grow[a](x: a, n: I64): I64 = if n == 0 then 0 else grow(Pair { fst: x, snd: x }, n - 1)
+        func main(): I64 = grow(1, 3)
+    "#;
+    let monomorphized = pipeline::Options {
+        monomorphize: true,
+        ..PIPELINE
+    };
+    assert!(pipeline::compile("test.hoyle", text, Stage::C, &[], true, monomorphized).is_err());
+    assert_eq!(run_executable(text), "0\n");
+}
+
+#[test]
+fn dynamic_values_on_the_heap() {
+    let text = r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func swap[a, b](p: Pair[a, b]): Pair[b, a] = {
+            let q = Pair { fst: p.snd, snd: p.fst };
+            q
+        }
+        func twice[a](x: a): Pair[a, a] = swap(swap(Pair { fst: x, snd: x }))
+        func main(): F64 = {
+            let p = twice(x => x + 1);
+            let q = swap(Pair { fst: p, snd: 3 });
+            q.snd.fst(q.fst)
+        }
+    "#;
+    assert_eq!(run_executable_with(text, "gcc -D_STACK_LIMIT=0"), "4\n");
+}
+
+#[test]
+fn dynamic_values_past_the_stack_limit() {
+    let text = r#"
+        struct Pair[a, b] {
+            fst: a
+            snd: b
+        }
+        func deep[a](x: a, n: I64): a =
+            if n == 0 then x else deep(Pair { fst: x, snd: x }, n - 1).snd
+        func main(): I64 = deep(7, 10)
+    "#;
+    assert_eq!(run_executable(text), "7\n");
+}
+
+#[test]
+fn error_union() {
+    run(
+        r#"
+        enum Problem {
+            Negative(F64),
+            Zero
+        }
+        func halve(x: F64): F64 ! Problem = if x < 0 then throw Negative(x) else if x == 0 then throw Zero else x / 2
+        func recover(x: F64): F64 = try halve(x) catch
+            | Negative(y) => 0 - y
+            | Zero => 100
+        func error_union(): F64 = recover(8) + recover(0 - 3) + recover(0)
+        "#,
+        "error_union",
+        107.,
+    )
+}
+
+#[test]
+fn generic_error_union() {
+    run(
+        r#"
+        struct Box[t] {
+            value: t
+        }
+        func attempt[t](x: t, ok: Bool): t ! I64 = if ok then x else throw 7
+        func generic_error_union(): I64 = attempt(Box { value: 2 }, True).value
+            + (try attempt(Box { value: 1 }, False) catch | n => Box { value: n }).value
+        "#,
+        "generic_error_union",
+        9,
+    )
+}
+
+#[test]
+fn unchecked_error() {
+    let text = r#"
+        func half(x: I64): I64 ! Bool = if x == 0 then throw False else x / 2
+        func unchecked(): I64 = half(0) + 1
+    "#;
+    let production = pipeline::Options {
+        mode: Mode::Production,
+        ..PIPELINE
+    };
+    assert!(pipeline::compile("test.hoyle", text, Stage::C, &[], false, production).is_err());
+    let bridged = to_bridge(text);
+    let Err(error) = interpret::run(&bridged, "unchecked", 8) else {
+        panic!("unwrapping a thrown error doesn't stop the program");
+    };
+    assert_eq!(error.to_string(), "an error was thrown and never caught");
+    let output = run_c_main(
+        &emit::program(bridged, DEBUG_RC).to_string(),
+        "unchecked_error",
+        "int main() {\n  signed long long x;\n  unchecked(&x);\n}",
+    );
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: an error was thrown and never caught\n"
+    );
+}
+
+#[test]
+fn polymorphic_variants() {
+    run(
+        r#"
+        func maybe_half(x: I64): Just(I64) | Nothing = if x == 0 then Nothing else Just(x / 2)
+        func or_else(m: Just(I64) | Nothing, fallback: I64): I64 = case m of
+            | Just(x) => x
+            | Nothing => fallback
+        func widen(m: Just(I64) | Nothing): Just(I64) | Nothing | Failed(Bool) = m
+        func count(r: Just(I64) | Nothing | Failed(Bool)): I64 = case r of
+            | Just(x) => x
+            | Nothing => 0
+            | Failed(fatal) => if fatal then 100 else 200
+        func inferred(x: I64): I64 = {
+            let m = if x == 0 then Nothing else Just(x);
+            case m of
+                | Just(y) => y
+                | Nothing => 1000
+        }
+        func polymorphic_variants(): I64 = or_else(maybe_half(8), 1) + or_else(maybe_half(0), 10)
+            + count(widen(Just(3))) + count(Failed(True)) + inferred(0) + inferred(2)
+        "#,
+        "polymorphic_variants",
+        1119,
+    )
+}
+
+#[test]
+fn generic_polymorphic_variants() {
+    run(
+        r#"
+        struct Box[t] {
+            value: t
+        }
+        func first[t](m: Just(t) | Nothing, fallback: t): t = case m of
+            | Just(x) => x
+            | Nothing => fallback
+        func generic_polymorphic_variants(): I64 = first(Just(Box { value: 5 }), Box { value: 1 }).value
+            + first(Nothing, Box { value: 20 }).value
+        "#,
+        "generic_polymorphic_variants",
+        25,
+    )
+}
+
+#[test]
+fn union_layouts_agree() {
+    let bridged = to_bridge(
+        r#"
+        func small(m: Just(I64) | Nothing): I64 = case m of
+            | Just(x) => x
+            | Nothing => 0
+        func big(m: Just(I64) | Nothing | Failed(Bool)): I64 = case m of
+            | Failed(_) => 1
+            | _ => 0
+        func main(): I64 = small(Just(1)) + big(Failed(False))
+        "#,
+    );
+    let layouts: Vec<Vec<_>> = bridged
+        .enums
+        .iter()
+        .filter(|to_check| to_check.definition.name.starts_with(typed::UNION))
+        .map(|to_check| {
+            to_check
+                .definition
+                .variants
+                .iter()
+                .map(|variant| variant.name.clone())
+                .collect()
+        })
+        .collect();
+    assert_eq!(layouts.len(), 2);
+    assert_eq!(layouts[0], layouts[1]);
+}
+
+#[test]
+fn closed_unions() {
+    for text in [
+        "func missing(m: Just(I64) | Nothing): I64 = case m of | Just(x) => x",
+        "func extra(): Just(I64) | Nothing = Other",
+        "func narrow(m: Just(I64) | Nothing): Just(I64) = m",
+    ] {
+        assert!(pipeline::compile("test.hoyle", text, Stage::C, &[], false, PIPELINE).is_err());
+    }
+}
+
+#[test]
+fn method_calls() {
+    run(
+        r#"
+        struct Counter {
+            count: I64
+            step: (I64) -> I64
+        }
+        func step(c: Counter, by: I64): Counter = Counter { count: c.count + by, step: c.step }
+        func total(c: Counter): I64 = c.count
+        func method_calls(): I64 = {
+            let c = Counter { count: 1, step: x => x * 100 };
+            let twice = (n: I64, m: I64) => n * m;
+            let quadruple = (k: I64) => k.twice(4);
+            step(c, 2).total() + c.step(3) + 5.twice(7) + 2.to_f64().to_i64() + quadruple(2)
+        }
+        "#,
+        "method_calls",
+        348,
+    )
+}
+
+#[test]
+fn method_call_errors() {
+    let error = |text: &str| {
+        let (tokens, _) = scan_tokens(text);
+        let parsed = parser::parse(&tokens.into_iter().collect::<Vec<_>>()).unwrap();
+        let Err(error) = type_checker::infer::program(&parsed, Mode::Development) else {
+            panic!("a mistaken method call type checks");
+        };
+        error.to_string()
+    };
+    let mistyped = error(
+        "func total(xs: I64, ys: I64): I64 = xs + ys
+        func main(): I64 = 1.total(True)",
+    );
+    assert!(mistyped.ends_with(", in `total(1, True)`"), "{mistyped}");
+    let unknown = error(
+        "func double(x: I64): I64 = x * 2
+        func main(): I64 = (1 + 2).double().nope(x => x + 1, True)",
+    );
+    assert_eq!(
+        unknown,
+        "unknown function `nope`, in `nope((1 + 2).double(), x => x + 1, True)`"
+    );
+}
+
+#[test]
+fn trailing_lambdas() {
+    run(
+        r#"
+        struct Pair[t] {
+            first: t
+            second: t
+        }
+        func map[t, u](pair: Pair[t], f: (t) -> u): Pair[u] = Pair { first: f(pair.first), second: f(pair.second) }
+        func fold[t](pair: Pair[t], f: (t, t) -> t): t = f(pair.first, pair.second)
+        func trailing_lambdas(): I64 = {
+            let offset = 10;
+            let pair = map(Pair { first: 1, second: 2 }) { x => x + offset };
+            pair.map() { x => x * 3 }.fold() { (a, b) => a * b } + fold(pair) { (a, b) => a - b }
+        }
+        "#,
+        "trailing_lambdas",
+        1187,
+    )
+}
+
+#[test]
+fn trailing_lambda_statements() {
+    run(
+        r#"
+        func with(x: I64, f: (I64) -> I64): I64 = f(x)
+        func trailing_lambda_statements(): I64 = {
+            let offset = 10;
+            with(3) { f =>
+                let a = f * 2;
+                let b = a + offset;
+                a * b
+            }
+        }
+        "#,
+        "trailing_lambda_statements",
+        96,
+    )
+}
+
+#[test]
+fn local_functions() {
+    run(
+        r#"
+        struct Box[t] {
+            value: t
+        }
+        func scale[t](items: Box[t], by: I64, size: (t) -> I64): I64 = {
+            func measure(item: t): I64 = size(item) * by;
+            func both[u](first: u, f: (u) -> I64): I64 = f(first) + measure(items.value);
+            both(items, x => measure(x.value))
+        }
+        func local_functions(): I64 = {
+            let offset = 100;
+            func id[t](x: t): t = x;
+            func count(n: I64): I64 = if n == 0 then offset else count(n - 1) + 1;
+            let offset = 1000;
+            func wrap[t](x: t): Box[t] = Box { value: id(x) };
+            let flag = id(True);
+            if flag then count(3) + id(offset) + wrap(wrap(7)).value.value + scale(Box { value: 2 }, 10) { x => x } else 0
+        }
+        "#,
+        "local_functions",
+        1150,
+    )
+}
+
+#[test]
+fn local_function_separators() {
+    run(
+        r#"
+        func local_function_separators(): I64 = {
+            func sq(x: I64): I64 = x * x
+            func add(a: I64, b: I64): I64 = a + b;
+            let y = 2;
+            func twice[t](x: t, f: (t) -> t): t = f(f(x))
+            add(sq(3), twice(y, z => z + 1))
+        }
+        "#,
+        "local_function_separators",
+        13,
+    )
+}
+
+#[test]
+fn local_generic_shadowing() {
+    run(
+        r#"
+        func outer[t](x: t, weigh: (t) -> F64): F64 = {
+            func inner[t](y: t, f: (t) -> F64): F64 = {
+                func twice(z: t): F64 = f(z) * 2;
+                twice(y) + weigh(x)
+            };
+            inner(True, (b: Bool) => if b then 1 else 0) + inner(3, (n: F64) => n)
+        }
+        func local_generic_shadowing(): F64 = outer(10, w => w)
+        "#,
+        "local_generic_shadowing",
+        28.,
+    )
+}
+
+#[test]
+fn bounded_local_function() {
+    run(
+        r#"
+        protocol Measurable {
+            func measure(x: Self): F64
+        }
+        impl Measurable for Bool {
+            func measure(b: Bool): F64 = if b then 1 else 0
+        }
+        func bounded_local_function(): F64 = {
+            let weight = 10;
+            func weigh[t: Measurable](x: t): F64 = measure(x) * weight;
+            weigh(True) + True.weigh()
+        }
+        "#,
+        "bounded_local_function",
+        20.,
+    )
+}
+
+#[test]
+fn recursive_types() {
+    for text in [
+        "enum List { Cons(I64, List), Nil }",
+        "struct Node { value: I64 next: Node }",
+        "struct Box[t] { value: t }
+        enum Tree { Leaf, Branch(Box[Tree], I64) }",
+        "enum Even { Zero, Next(Odd) }
+        enum Odd { One(Even) }",
+        "enum Chain { End, Link(I64 ! Chain) }",
+    ] {
+        assert!(pipeline::compile("test.hoyle", text, Stage::Typed, &[], false, PIPELINE).is_err());
+    }
+    // closures hold what they capture behind a pointer, so a struct can hold one that returns the struct
+    let text = "struct Stream { head: I64 tail: () -> Stream }";
+    assert!(pipeline::compile("test.hoyle", text, Stage::Typed, &[], false, PIPELINE).is_ok());
+}
+
+#[test]
+fn struct_pack_fields() {
+    let check = |text: &str| {
+        let (tokens, _) = scan_tokens(text);
+        let parsed = parser::parse(&tokens.into_iter().collect::<Vec<_>>()).unwrap();
+        type_checker::infer::program(&parsed, Mode::Development).map(|_| ())
+    };
+    for (text, message) in [
+        (
+            "struct P[a] { x: I64 y: a }
+            func main(): I64 = P { x: 1 }.x",
+            "struct `P` is missing field `y`",
+        ),
+        (
+            "struct P { x: I64 f: (I64) -> I64 }
+            func main(): I64 = P { x: 1 }.f(2)",
+            "struct `P` is missing field `f`",
+        ),
+        (
+            "struct P { x: I64 }
+            func main(): I64 = P { x: 1, x: 2 }.x",
+            "struct `P` is given field `x` more than once",
+        ),
+    ] {
+        let Err(error) = check(text) else {
+            panic!("{text} type checks");
+        };
+        assert_eq!(error.to_string(), message);
+    }
+}
+
+#[test]
+fn division_by_zero() {
+    let bridged = to_bridge("func divide(): I64 = 7 / 0 + 7 % 0");
+    let output = run_c_main(
+        &emit::program(bridged, DEBUG_RC).to_string(),
+        "division_by_zero",
+        "int main() {\n  signed long long x;\n  divide(&x);\n}",
+    );
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "error: an integer is divided by zero\n"
+    );
+}
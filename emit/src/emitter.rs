@@ -2,10 +2,18 @@ use core::fmt;
 
 use im::HashSet;
 use ir::{
-    bridge::{Block, Convention, Expr, Function, Instr, Program, Struct, Value, Variable, Witness},
+    bridge::{
+        tail_argument_name, Block, BuilderArgument, CallArgument, Convention, Enum, Expr, Function,
+        Implementation, Instr, Program, Struct, Value, Variable, Witness,
+    },
     name_source::NameSource,
 };
-use tree::{sized::Primitive, typed::Literal, String};
+use tree::sized::{Primitive, Protocol, RESULT};
+use tree::type_passing::{dictionary_builder_name, impl_function_name, EXISTENTIAL};
+use tree::{
+    typed::{Literal, Type},
+    String,
+};
 
 type StdString = std::string::String;
 
@@ -58,6 +66,14 @@ impl fmt::Display for Source {
 pub struct Bank {
     seen: HashSet<String>,
     to_free: Vec<String>,
+    /// the allocations of the enclosing C blocks, which a tail call has to free before it jumps out of them
+    enclosing: Vec<String>,
+    /// heap allocated variables that are resized to fit each value written to them
+    resized: HashSet<String>,
+    /// whether dynamically sized variables can go on the stack, which they can't in a C function that loops
+    stack: bool,
+    /// the variables that went on the stack unless they were too big, in which case `_heap_{name}` is set
+    stacked: HashSet<String>,
 }
 
 impl Bank {
@@ -65,8 +81,42 @@ impl Bank {
         self.seen.insert(name);
     }
 
+    /// a bank for a nested C block, which can see everything defined so far but frees its own allocations
+    pub fn scoped(&self) -> Self {
+        Self {
+            seen: self.seen.clone(),
+            to_free: Vec::new(),
+            enclosing: self
+                .enclosing
+                .iter()
+                .chain(&self.to_free)
+                .cloned()
+                .collect(),
+            resized: self.resized.clone(),
+            stack: self.stack,
+            stacked: self.stacked.clone(),
+        }
+    }
+
+    /// a bank for a function body that is only entered once per call, which can put its variables on the stack
+    pub fn stack() -> Self {
+        Self {
+            stack: true,
+            ..Self::default()
+        }
+    }
+
     pub fn define(&mut self, name: &str, witness: &Witness, source: &mut Source) {
-        if !self.seen.contains(name) {
+        if self.resized.contains(name) {
+            let size = match witness {
+                Witness::Trivial { size } => size.to_string(),
+                Witness::Dynamic { location } => {
+                    format!("((_witness *) {}) -> size", location.name)
+                }
+                Witness::Type => StdString::from("sizeof(_witness)"),
+            };
+            source.pushln(&format!("{name} = realloc({name}, {size});"));
+        } else if !self.seen.contains(name) {
             self.define_unchecked(String::from(name), witness, source);
         }
     }
@@ -74,6 +124,15 @@ impl Bank {
     fn define_unchecked(&mut self, name: String, witness: &Witness, source: &mut Source) {
         match witness {
             Witness::Trivial { size } => source.pushln(&format!("char {}[{}];", name, size)),
+            Witness::Dynamic { location } if self.stack => {
+                let size = format!("((_witness *) {}) -> size", location.name);
+                source.pushln(&format!("int _heap_{name} = {size} > _STACK_LIMIT;"));
+                source.pushln(&format!(
+                    "void *{name} = _heap_{name} ? malloc({size}) : alloca({size});"
+                ));
+                self.stacked.insert(name.clone());
+                self.defer_free(name.clone());
+            }
             Witness::Dynamic { location } => {
                 self.defer_free(name.clone());
                 source.pushln(&format!(
@@ -92,18 +151,183 @@ impl Bank {
         self.to_free.push(name);
     }
 
-    fn free_list(&self) -> impl Iterator<Item = &str> {
-        self.to_free.iter().rev().map(AsRef::as_ref)
+    /// the statements that free what this block allocated
+    fn frees(&self) -> impl Iterator<Item = StdString> + '_ {
+        self.to_free.iter().rev().map(|name| self.free(name))
     }
+
+    /// the statements that free everything allocated so far, including by the enclosing C blocks
+    fn jump_frees(&self) -> impl Iterator<Item = StdString> + '_ {
+        self.enclosing
+            .iter()
+            .chain(&self.to_free)
+            .rev()
+            .map(|name| self.free(name))
+    }
+
+    fn free(&self, name: &str) -> StdString {
+        if self.stacked.contains(name) {
+            format!("if (_heap_{name}) free({name});")
+        } else {
+            format!("free({name});")
+        }
+    }
+}
+
+/// how the emitted C should be built
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Options {
+    /// instruments every allocation, copy and destroy, so that the program aborts on a double destroy,
+    /// a use after free, or an allocation that is still alive when it exits
+    pub debug_rc: bool,
+}
+
+/// checks every allocation made by the emitted code. allocations are kept in a list along with their size and canaries,
+/// and freed allocations are poisoned and never handed out again, so that anything still pointing at them is caught
+const DEBUG_RC: &str = r#"#include <stdio.h>
+
+struct _rc_header {
+  unsigned long long canary;
+  unsigned long long size;
+  struct _rc_header *previous;
+  struct _rc_header *next;
+};
+
+#define _RC_LIVE 0x11feca7a11feca7aull
+#define _RC_FREED 0xdeadbeefdeadbeefull
+#define _RC_TAIL 0x7a1ca7a77a1ca7a7ull
+
+struct _rc_header _rc_live = {_RC_LIVE, 0, &_rc_live, &_rc_live};
+unsigned long long _rc_mallocs = 0;
+unsigned long long _rc_frees = 0;
+unsigned long long _rc_copies = 0;
+unsigned long long _rc_destroys = 0;
+
+void _rc_fail(const char *what, const char *message) {
+  fprintf(stderr, "debug-rc: %s %s\n", what, message);
+  fprintf(stderr, "debug-rc: after %llu mallocs, %llu frees, %llu copies and %llu destroys\n", _rc_mallocs, _rc_frees, _rc_copies, _rc_destroys);
+  abort();
+}
+
+// the header of a live allocation, failing with `message` if it has been freed
+struct _rc_header *_rc_check(void *pointer, const char *what, const char *message) {
+  struct _rc_header *header = (struct _rc_header *) pointer - 1;
+  unsigned long long tail;
+  if (header -> canary == _RC_FREED) {
+    _rc_fail(what, message);
+  } else if (header -> canary != _RC_LIVE) {
+    _rc_fail(what, "has a corrupted header");
+  }
+  memcpy(&tail, (char *) pointer + header -> size, sizeof(tail));
+  if (tail != _RC_TAIL) {
+    _rc_fail(what, "was written past its end");
+  }
+  return header;
+}
+
+void _rc_leaks(void) {
+  unsigned long long leaked = 0;
+  for (struct _rc_header *header = _rc_live.next; header != &_rc_live; header = header -> next) {
+    fprintf(stderr, "debug-rc: %llu bytes at %p are never freed\n", header -> size, (void *) (header + 1));
+    leaked += 1;
+  }
+  if (leaked != 0) {
+    _rc_fail("the program", "leaks");
+  }
+}
+
+void *_rc_malloc(unsigned long long size) {
+  static int registered = 0;
+  struct _rc_header *header = malloc(sizeof(struct _rc_header) + size + sizeof(unsigned long long));
+  unsigned long long tail = _RC_TAIL;
+  if (!registered) {
+    atexit(_rc_leaks);
+    registered = 1;
+  }
+  if (header == NULL) {
+    _rc_fail("an allocation", "doesn't fit in memory");
+  }
+  header -> canary = _RC_LIVE;
+  header -> size = size;
+  header -> previous = _rc_live.previous;
+  header -> next = &_rc_live;
+  _rc_live.previous -> next = header;
+  _rc_live.previous = header;
+  memcpy((char *) (header + 1) + size, &tail, sizeof(tail));
+  _rc_mallocs += 1;
+  return header + 1;
+}
+
+void _rc_free(void *pointer) {
+  struct _rc_header *header;
+  if (pointer == NULL) {
+    return;
+  }
+  header = _rc_check(pointer, "an allocation", "is freed twice");
+  header -> previous -> next = header -> next;
+  header -> next -> previous = header -> previous;
+  header -> canary = _RC_FREED;
+  memset(pointer, 0xdd, header -> size);
+  _rc_frees += 1;
+}
+
+void *_rc_realloc(void *pointer, unsigned long long size) {
+  void *resized = _rc_malloc(size);
+  if (pointer != NULL) {
+    struct _rc_header *header = _rc_check(pointer, "an allocation", "is resized after being freed");
+    memcpy(resized, pointer, header -> size < size ? header -> size : size);
+    _rc_free(pointer);
+  }
+  return resized;
+}
+
+void _rc_copy(void *pointer, const char *what) {
+  _rc_check(pointer, what, "is copied after being freed");
+  _rc_copies += 1;
+}
+
+void _rc_destroy(void *pointer, const char *what) {
+  _rc_check(pointer, what, "is destroyed after being freed");
+  _rc_destroys += 1;
+}
+
+void _rc_use(void *pointer, const char *what) {
+  _rc_check(pointer, what, "is used after being freed");
 }
 
-pub fn program(program: Program) -> Source {
+#define malloc(size) _rc_malloc(size)
+#define realloc(pointer, size) _rc_realloc(pointer, size)
+#define free(pointer) _rc_free(pointer)
+"#;
+
+/// the checks that `DEBUG_RC` makes, which do nothing otherwise
+const RELEASE_RC: &str = r"#define _rc_copy(pointer, what)
+#define _rc_destroy(pointer, what)
+#define _rc_use(pointer, what)
+";
+
+pub fn program(program: Program, options: Options) -> Source {
     let mut source = Source::default();
     source.pushln(
-        r#"#include <string.h>
+        r"#include <string.h>
+#include <stdio.h>
 #include <limits.h>
+#include <math.h>
 #include <stdlib.h>
-typedef struct _witness {
+#include <alloca.h>
+
+// dynamically sized values bigger than this go on the heap instead of the stack
+#ifndef _STACK_LIMIT
+#define _STACK_LIMIT 4096
+#endif",
+    );
+    source.pushln(if options.debug_rc {
+        DEBUG_RC
+    } else {
+        RELEASE_RC
+    });
+    source.pushln(
+        r#"typedef struct _witness {
   unsigned long long size;
   void (*move)(void *, void *, void *);
   void (*copy)(void *, void *, void *);
@@ -126,6 +350,63 @@ void F64(void *_result) {
   result -> extra = NULL;
 }
 
+void _move_I64(void *dest, void *src, void *extra) {
+  memmove(dest, src, 8);
+}
+
+void _destroy_I64(void *dest, void *extra) {}
+
+void I64(void *_result) {
+  _witness *result = _result;
+  result -> size = 8;
+  result -> move = _move_I64;
+  result -> copy = _move_I64;
+  result -> destroy = _destroy_I64;
+  result -> extra = NULL;
+}
+
+// the cast alone is undefined for NaN and out of range values, so they become 0 and the nearest bound
+signed long long _f64_to_i64(double x) {
+  if (x != x) {
+    return 0;
+  } else if (x >= 9223372036854775807.0) {
+    return LLONG_MAX;
+  } else if (x <= -9223372036854775808.0) {
+    return LLONG_MIN;
+  } else {
+    return (signed long long) x;
+  }
+}
+
+// dividing by zero stops the program, and LLONG_MIN / -1 wraps around like the rest of the integer arithmetic
+signed long long _i64_div(signed long long a, signed long long b) {
+  if (b == 0) {
+    fputs("error: an integer is divided by zero\n", stderr);
+    abort();
+  } else if (b == -1) {
+    return (signed long long) (0ull - (unsigned long long) a);
+  } else {
+    return a / b;
+  }
+}
+
+signed long long _i64_rem(signed long long a, signed long long b) {
+  if (b == 0) {
+    fputs("error: an integer is divided by zero\n", stderr);
+    abort();
+  } else if (b == -1) {
+    return 0;
+  } else {
+    return a % b;
+  }
+}
+
+// development mode unwraps error unions without checking them, so using one that holds an error stops the program
+void _crash(void) {
+  fputs("error: an error was thrown and never caught\n", stderr);
+  abort();
+}
+
 void _move_Bool(void *dest, void *src, void *extra) {
   memmove(dest, src, 8);
 }
@@ -141,15 +422,33 @@ void Bool(void *_result) {
   result -> extra = NULL;
 }
 
+void _move_Dictionary(void *dest, void *src, void *extra) {
+  memmove(dest, src, sizeof(void *));
+}
+
+void _destroy_Dictionary(void *dest, void *extra) {}
+
+void _Dictionary(void *_result) {
+  _witness *result = _result;
+  result -> size = sizeof(void *);
+  result -> move = _move_Dictionary;
+  result -> copy = _move_Dictionary;
+  result -> destroy = _destroy_Dictionary;
+  result -> extra = NULL;
+}
+
 struct _Closure {
   void *function;
   void *env;
 };
 
+void _destroy_type(void *src);
+
 void _copy_closure(void *dest, void *src, void *extra) {
   void *env = ((struct _Closure *) src) -> env;
   unsigned long long *counter = env;
   if (counter != NULL) {
+    _rc_copy(env, "a closure's environment");
     *counter += 1;
   }
   memmove(dest, src, sizeof(struct _Closure));
@@ -160,19 +459,25 @@ void _move_closure(void *dest, void *src, void *extra) {
 }
 
 void _destroy_closure(void *src, void *extra) {
-  void *env = ((struct _Closure *) src) -> env;
-  unsigned long long *counter = env;
+  char *env = ((struct _Closure *) src) -> env;
+  unsigned long long *counter = (unsigned long long *) env;
   if (counter != NULL) {
-    counter -= 1;
-    if (counter == 0) {
+    _rc_destroy(env, "a closure's environment");
+    if (*counter == 0) {
+      // the environment is laid out as [reference count, env witness table, env]
+      _witness *witness = (_witness *) (env + sizeof(unsigned long long));
+      (witness -> destroy)(env + sizeof(unsigned long long) + sizeof(_witness), witness -> extra);
+      _destroy_type(witness);
       free(env);
+    } else {
+      *counter -= 1;
     }
   }
 }
 
 void _Closure(void *_result) {
   _witness *result = _result;
-  result -> size = 8;
+  result -> size = sizeof(struct _Closure);
   result -> move = _move_closure;
   result -> copy = _copy_closure;
   result -> destroy = _destroy_closure;
@@ -188,6 +493,7 @@ void _copy_type(void *dest, void *src) {
     _witness *typ = src;
     if (typ -> extra != NULL) {
         unsigned long long *counter = typ -> extra;
+        _rc_copy(typ -> extra, "the type arguments of a witness table");
         if (*counter == ULONG_MAX) {
             exit(1);
         } else {
@@ -201,25 +507,507 @@ void _destroy_type(void *src) {
     _witness *typ = src;
     if (typ -> extra != NULL) {
         unsigned long long *counter = typ -> extra;
+        _rc_destroy(typ -> extra, "the type arguments of a witness table");
         if (*counter == 0) {
+            // extra is laid out as [reference count, number of type arguments, type arguments]
+            for (unsigned long long i = 0; i < counter[1]; i++) {
+                _destroy_type(((char *) (counter + 2)) + i * sizeof(_witness));
+            }
             free(typ -> extra);
         } else {
             *counter -= 1;
         }
     }
 }
+
+// an existential owns a boxed copy of its value, which can be any size
+struct _existential {
+  _witness witness;
+  void *dictionary;
+  void *value;
+};
+
+void _copy_existential(void *dest, void *src, void *extra) {
+  struct _existential *from = src;
+  struct _existential *to = dest;
+  _copy_type(&to -> witness, &from -> witness);
+  to -> dictionary = from -> dictionary;
+  _rc_copy(from -> value, "the boxed value of an existential");
+  to -> value = malloc(from -> witness.size);
+  (from -> witness.copy)(to -> value, from -> value, from -> witness.extra);
+}
+
+void _move_existential(void *dest, void *src, void *extra) {
+  memmove(dest, src, sizeof(struct _existential));
+}
+
+void _destroy_existential(void *src, void *extra) {
+  struct _existential *any = src;
+  _rc_destroy(any -> value, "the boxed value of an existential");
+  (any -> witness.destroy)(any -> value, any -> witness.extra);
+  free(any -> value);
+  _destroy_type(&any -> witness);
+}
+
+void _Existential(void *_result) {
+  _witness *result = _result;
+  result -> size = sizeof(struct _existential);
+  result -> move = _move_existential;
+  result -> copy = _copy_existential;
+  result -> destroy = _destroy_existential;
+  result -> extra = NULL;
+}
+
+// error unions are laid out as an 8 byte tag, which is 0 for a value and 1 for an error, followed by whichever they hold.
+// like generic structs, they keep the witness tables of the value and the error in their table's extra field
+_witness *_held_type(void *extra, void *src) {
+  return (_witness *) (((char *) extra) + 2 * sizeof(unsigned long long)) + *(signed long long *) src;
+}
+
+void _copy_result(void *dest, void *src, void *extra) {
+  _witness *held = _held_type(extra, src);
+  memmove(dest, src, 8);
+  (held -> copy)(((char *) dest) + 8, ((char *) src) + 8, held -> extra);
+}
+
+void _move_result(void *dest, void *src, void *extra) {
+  _witness *held = _held_type(extra, src);
+  memmove(dest, src, 8);
+  (held -> move)(((char *) dest) + 8, ((char *) src) + 8, held -> extra);
+}
+
+void _destroy_result(void *dest, void *extra) {
+  _witness *held = _held_type(extra, dest);
+  (held -> destroy)(((char *) dest) + 8, held -> extra);
+}
+
+void _Result(void *_result, void *value, void *error) {
+  _witness *result = _result;
+  unsigned long long value_size = ((_witness *) value) -> size;
+  unsigned long long error_size = ((_witness *) error) -> size;
+  unsigned long long *extra = malloc(2 * sizeof(unsigned long long) + 2 * sizeof(_witness));
+  extra[0] = 0;
+  extra[1] = 2;
+  _move_type(extra + 2, value);
+  _move_type(((char *) (extra + 2)) + sizeof(_witness), error);
+  result -> size = 8 + (value_size > error_size ? value_size : error_size);
+  result -> move = _move_result;
+  result -> copy = _copy_result;
+  result -> destroy = _destroy_result;
+  result -> extra = extra;
+}
 "#,
     );
 
+    // enums and structs can contain each other, so their witness table builders are declared up front
+    for (name, arguments) in program
+        .enums
+        .iter()
+        .map(|to_emit| (&to_emit.definition.name, to_emit.builder.arguments.len()))
+        .chain(
+            program
+                .structs
+                .iter()
+                .map(|to_emit| (&to_emit.definition.name, to_emit.builder.arguments.len())),
+        )
+    {
+        source.pushln(&format!(
+            "void {name}({});",
+            vec!["void *"; arguments].join(", ")
+        ));
+    }
+    // functions can call each other in any order, and dictionaries point to impl functions
+    for to_emit in &program.functions {
+        source.pushln(&format!(
+            "void {}({});",
+            to_emit.name,
+            vec!["void *"; to_emit.arguments.len()].join(", ")
+        ));
+    }
+    for to_emit in &program.protocols {
+        protocol(to_emit, &mut source);
+    }
+    for to_emit in &program.impls {
+        implementation(to_emit, &program.protocols, &mut source);
+    }
+    for to_emit in program.enums.into_iter() {
+        enumeration(to_emit, &mut source);
+    }
     for to_emit in program.structs.into_iter() {
         strukt(to_emit, &mut source);
     }
-    for to_emit in program.functions.into_iter() {
+    let (grouped, alone): (Vec<_>, Vec<_>) = tail_groups(program.functions)
+        .into_iter()
+        .partition(|(targets, _)| !targets.is_empty());
+    for (_, to_emit) in grouped {
+        group(&to_emit, &mut source);
+    }
+    for to_emit in alone.into_iter().flat_map(|(_, functions)| functions) {
         function(to_emit, &mut source);
     }
     source
 }
 
+/// splits the functions into groups that are connected by tail calls, along with the functions each group jumps to
+fn tail_groups(functions: Vec<Function>) -> Vec<(Vec<String>, Vec<Function>)> {
+    let mut groups: Vec<(Vec<String>, Vec<Function>)> = Vec::new();
+    for to_group in functions {
+        let mut targets = Vec::new();
+        jumps(&to_group.body, &mut targets);
+        // every group that jumps to this function, or that this function jumps to, joins it
+        let (connected, rest): (Vec<_>, Vec<_>) =
+            groups.into_iter().partition(|(group_targets, members)| {
+                group_targets.contains(&to_group.name)
+                    || members.iter().any(|member| targets.contains(&member.name))
+            });
+        groups = rest;
+        let mut members = Vec::new();
+        for (group_targets, group_members) in connected {
+            targets.extend(group_targets);
+            members.extend(group_members);
+        }
+        members.push(to_group);
+        groups.push((targets, members));
+    }
+    groups
+}
+
+/// the functions that `to_search` jumps to
+fn jumps(to_search: &Block, targets: &mut Vec<String>) {
+    for instr in &to_search.instrs {
+        match &instr.value {
+            Expr::TailCall { function, .. } => targets.push(function.clone()),
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                jumps(true_branch, targets);
+                jumps(false_branch, targets);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// functions that jump to each other are emitted as a single C function with a label for each body.
+/// the arguments of the body being run are pointed to by `_arguments`, which a tail call points at the storage it has moved its arguments into.
+/// each function still gets its own C function, which enters the group at the function's label
+fn group(to_emit: &[Function], source: &mut Source) {
+    let group_name = format!("_group_{}", to_emit[0].name);
+    source.pushln(&format!(
+        "void {group_name}(int _entry, void *_group_result, void **_arguments) {{"
+    ));
+    source.with_inc(2, |source| {
+        let most_arguments = to_emit
+            .iter()
+            .map(|function| function.arguments.len() - 1)
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        source.pushln(&format!("void *_jump[{most_arguments}];"));
+        let mut bank = Bank::default();
+        let mut heap = Vec::new();
+        for function in to_emit {
+            for (i, argument) in function.arguments[1..].iter().enumerate() {
+                let name = tail_argument_name(&function.name, i);
+                match argument.name.witness.as_ref() {
+                    Witness::Trivial { size } => source.pushln(&format!("char {name}[{size}];")),
+                    Witness::Type => source.pushln(&format!("char {name}[sizeof(_witness)];")),
+                    // the size of a dynamically sized argument can change from one call to the next
+                    Witness::Dynamic { .. } => {
+                        source.pushln(&format!("void *{name} = NULL;"));
+                        bank.resized.insert(name.clone());
+                        heap.push(name.clone());
+                    }
+                }
+                bank.already_defined(name);
+            }
+        }
+        source.pushln("switch (_entry) {");
+        for (i, function) in to_emit.iter().enumerate() {
+            source.pushln(&format!("  case {i}: goto {};", function.name));
+        }
+        source.pushln("}");
+        for function in to_emit {
+            source.pushln(&format!("{}: {{", function.name));
+            source.with_inc(2, |source| {
+                let mut body_bank = bank.scoped();
+                let (result, arguments) = function.arguments.split_first().unwrap();
+                source.pushln(&format!("void *{} = _group_result;", result.name.name));
+                body_bank.already_defined(result.name.name.clone());
+                for (i, argument) in arguments.iter().enumerate() {
+                    source.pushln(&format!("void *{} = _arguments[{i}];", argument.name.name));
+                    body_bank.already_defined(argument.name.name.clone());
+                }
+                block(
+                    function.body.clone(),
+                    source,
+                    &mut body_bank,
+                    &function.names,
+                );
+                for to_free in body_bank.frees() {
+                    source.pushln(&to_free);
+                }
+                source.pushln("goto _done;");
+            });
+            source.pushln("}");
+        }
+        source.pushln("_done: ;");
+        for to_free in heap {
+            source.pushln(&format!("free({to_free});"));
+        }
+    });
+    source.pushln("}");
+    source.pushln("");
+    for (i, function) in to_emit.iter().enumerate() {
+        let (result, arguments) = function.arguments.split_first().unwrap();
+        let parameters: Vec<_> = function
+            .arguments
+            .iter()
+            .map(|argument| format!("void *{}", argument.name.name))
+            .collect();
+        source.pushln(&format!(
+            "void {}({}) {{",
+            function.name,
+            parameters.join(", ")
+        ));
+        source.with_inc(2, |source| {
+            if arguments.is_empty() {
+                source.pushln(&format!("{group_name}({i}, {}, NULL);", result.name.name));
+            } else {
+                let names: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| argument.name.name.as_str())
+                    .collect();
+                source.pushln(&format!("void *_arguments[] = {{{}}};", names.join(", ")));
+                source.pushln(&format!(
+                    "{group_name}({i}, {}, _arguments);",
+                    result.name.name
+                ));
+            }
+        });
+        source.pushln("}");
+        source.pushln("");
+    }
+}
+
+/// emits the protocol's dictionary struct, which holds a pointer to each function of an impl,
+/// and a function for each protocol function that calls the implementation from the dictionary it is passed
+fn protocol(to_emit: &Protocol, source: &mut Source) {
+    let protocol_name = &to_emit.name;
+    source.pushln(&format!("struct _{protocol_name} {{"));
+    source.with_inc(2, |source| {
+        for function in &to_emit.functions {
+            source.pushln(&format!("void *{};", function.name));
+        }
+    });
+    source.pushln("};");
+    for function in &to_emit.functions {
+        // arguments are followed by the witness table of `Self` and the dictionary, like any other bound generic
+        let arguments: Vec<_> = (0..function.arguments.len())
+            .map(|i| format!("_{i}"))
+            .collect();
+        let mut parameters = vec![StdString::from("_result")];
+        parameters.extend(arguments);
+        source.pushln(&format!(
+            "void {}({}, void *Self, void *_dictionary) {{",
+            function.name,
+            parameters
+                .iter()
+                .map(|parameter| format!("void *{parameter}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        source.with_inc(2, |source| {
+            source.pushln(&format!(
+                "struct _{protocol_name} *dictionary = *(struct _{protocol_name} **) _dictionary;"
+            ));
+            source.pushln(&format!(
+                "((void (*)({})) dictionary -> {})({});",
+                vec!["void *"; parameters.len()].join(", "),
+                function.name,
+                parameters.join(", ")
+            ));
+            source.pushln("_destroy_type(Self);");
+        });
+        source.pushln("}");
+    }
+    if to_emit
+        .functions
+        .iter()
+        .all(|function| function.self_argument().is_some())
+    {
+        existential_implementation(to_emit, source);
+    }
+}
+
+/// emits `any P`'s implementation of `P`, whose functions unbox the existential passed as `Self`
+/// and call the packed value's implementation from the dictionary it was packed with
+fn existential_implementation(to_emit: &Protocol, source: &mut Source) {
+    let protocol_name = &to_emit.name;
+    for function in &to_emit.functions {
+        let self_argument = function.self_argument().unwrap();
+        let mut parameters = vec![StdString::from("_result")];
+        parameters.extend((0..function.arguments.len()).map(|i| format!("_{i}")));
+        source.pushln(&format!(
+            "void {}({}) {{",
+            impl_function_name(protocol_name, EXISTENTIAL, &function.name),
+            parameters
+                .iter()
+                .map(|parameter| format!("void *{parameter}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+        source.with_inc(2, |source| {
+            source.pushln(&format!("struct _existential *any = _{self_argument};"));
+            source.pushln(&format!(
+                "struct _{protocol_name} *dictionary = any -> dictionary;"
+            ));
+            // the implementation takes ownership of the value, but the box and the witness table are still ours
+            parameters[self_argument + 1] = StdString::from("any -> value");
+            source.pushln(r#"_rc_use(any -> value, "the boxed value of an existential");"#);
+            source.pushln(&format!(
+                "((void (*)({})) dictionary -> {})({});",
+                vec!["void *"; parameters.len()].join(", "),
+                function.name,
+                parameters.join(", ")
+            ));
+            source.pushln("free(any -> value);");
+            source.pushln("_destroy_type(&any -> witness);");
+        });
+        source.pushln("}");
+    }
+    implementation(
+        &Implementation {
+            protocol: protocol_name.clone(),
+            typ: String::from(EXISTENTIAL),
+        },
+        std::slice::from_ref(to_emit),
+        source,
+    );
+}
+
+/// emits the dictionary of an impl, and the function that passes a pointer to it around
+fn implementation(to_emit: &Implementation, protocols: &[Protocol], source: &mut Source) {
+    let protocol = protocols
+        .iter()
+        .find(|protocol| protocol.name == to_emit.protocol)
+        .expect("impl of an unknown protocol");
+    let builder = dictionary_builder_name(&to_emit.protocol, &to_emit.typ);
+    source.pushln(&format!(
+        "struct _{} {builder}_dictionary = {{",
+        protocol.name
+    ));
+    source.with_inc(2, |source| {
+        for function in &protocol.functions {
+            source.pushln(&format!(
+                ".{} = {},",
+                function.name,
+                impl_function_name(&to_emit.protocol, &to_emit.typ, &function.name)
+            ));
+        }
+    });
+    source.pushln("};");
+    source.pushln(&format!("void {builder}(void *_result) {{"));
+    source.with_inc(2, |source| {
+        source.pushln(&format!(
+            "*(struct _{} **) _result = &{builder}_dictionary;",
+            protocol.name
+        ));
+    });
+    source.pushln("}");
+}
+
+/// where the `index`th type argument of a generic struct lives, given the `extra` field of its witness table
+fn type_argument(extra: &str, index: usize) -> StdString {
+    format!("((char *) {extra}) + 2 * sizeof(unsigned long long) + {index} * sizeof(_witness)")
+}
+
+/// emits the instructions that make the witness tables of a struct's or an enum's fields, after pointing its type arguments into `extra`
+fn builder_preamble(
+    arguments: &[BuilderArgument],
+    builder_block: &Block,
+    source: &mut Source,
+    names: &NameSource,
+) -> Bank {
+    for (index, arg) in arguments.iter().skip(1).enumerate() {
+        source.pushln(&format!(
+            "char *{} = {};",
+            arg.name.name,
+            type_argument("extra", index)
+        ));
+    }
+    let mut bank = Bank::default();
+    block(builder_block.clone(), source, &mut bank, names);
+    bank
+}
+
+/// emits the instructions that point the `extra` field of the table being built at the type arguments of a builder,
+/// which the table owns from then on
+fn store_type_arguments(
+    type_arguments: &[BuilderArgument],
+    source: &mut Source,
+    names: &NameSource,
+) {
+    if type_arguments.is_empty() {
+        source.pushln("typ -> extra = NULL;");
+        return;
+    }
+    let extra = names.fresh_name();
+    source.pushln(&format!(
+        "unsigned long long *{extra} = malloc(2 * sizeof(unsigned long long) + {} * sizeof(_witness));",
+        type_arguments.len()
+    ));
+    source.pushln(&format!("{extra}[0] = 0;"));
+    source.pushln(&format!("{extra}[1] = {};", type_arguments.len()));
+    for (index, arg) in type_arguments.iter().enumerate() {
+        source.pushln(&format!(
+            "_move_type({}, {});",
+            type_argument(&extra, index),
+            arg.name.name
+        ));
+    }
+    source.pushln(&format!("typ -> extra = {extra};"));
+}
+
+/// cleans up the witness tables made by a builder block
+fn builder_postamble(builder_block: &Block, bank: Bank, source: &mut Source) {
+    for instr in &builder_block.instrs {
+        if let Witness::Type = instr.target.witness.as_ref() {
+            source.pushln(&format!("_destroy_type({});", instr.target.name));
+        }
+    }
+    for to_free in bank.frees() {
+        source.pushln(&to_free);
+    }
+}
+
+/// emits the body of a function that applies `operation` to every field of a struct, in layout order
+fn each_struct_field(
+    to_emit: &Struct,
+    source: &mut Source,
+    names: &NameSource,
+    operation: impl Fn(&str, &str) -> StdString,
+) {
+    let bank = builder_preamble(
+        &to_emit.builder.arguments,
+        &to_emit.builder.block,
+        source,
+        names,
+    );
+    let offset_var = names.fresh_name();
+    source.pushln(&format!("unsigned long long {offset_var} = 0;"));
+    for field in &to_emit.builder.fields {
+        source.pushln(&operation(&field.name, &offset_var));
+        source.pushln(&format!(
+            "{offset_var} += ((_witness *) {}) -> size;",
+            field.name
+        ));
+    }
+    builder_postamble(&to_emit.builder.block, bank, source);
+}
+
 fn copy_struct(to_emit: &Struct, source: &mut Source, names: &NameSource) {
     let struct_name = &to_emit.definition.name;
     source.pushln(&format!(
@@ -227,14 +1015,9 @@ fn copy_struct(to_emit: &Struct, source: &mut Source, names: &NameSource) {
         struct_name
     ));
     source.with_inc(2, |source| {
-        let mut bank = Bank::default();
-        block(to_emit.builder.block.clone(), source, &mut bank, names);
-        for field in &to_emit.builder.fields {
-            source.pushln(&format!(
-                "(((_witness *) {}) -> copy)(dest, src, ((_witness *) {}) -> extra);",
-                field.name, field.name
-            ))
-        }
+        each_struct_field(to_emit, source, names, |field, offset| {
+            format!("(((_witness *) {field}) -> copy)(((char *) dest) + {offset}, ((char *) src) + {offset}, ((_witness *) {field}) -> extra);")
+        });
     });
     source.pushln("}");
 }
@@ -245,14 +1028,9 @@ fn move_struct(to_emit: &Struct, source: &mut Source, names: &NameSource) {
         struct_name
     ));
     source.with_inc(2, |source| {
-        let mut bank = Bank::default();
-        block(to_emit.builder.block.clone(), source, &mut bank, names);
-        for field in &to_emit.builder.fields {
-            source.pushln(&format!(
-                "(((_witness *) {}) -> move)(dest, src, ((_witness *) {}) -> extra);",
-                field.name, field.name
-            ))
-        }
+        each_struct_field(to_emit, source, names, |field, offset| {
+            format!("(((_witness *) {field}) -> move)(((char *) dest) + {offset}, ((char *) src) + {offset}, ((_witness *) {field}) -> extra);")
+        });
     });
     source.pushln("}");
 }
@@ -263,14 +1041,9 @@ fn destroy_struct(to_emit: &Struct, source: &mut Source, names: &NameSource) {
         struct_name
     ));
     source.with_inc(2, |source| {
-        let mut bank = Bank::default();
-        block(to_emit.builder.block.clone(), source, &mut bank, names);
-        for field in &to_emit.builder.fields {
-            source.pushln(&format!(
-                "(((_witness *) {}) -> destroy)(dest, ((_witness *) {}) -> extra);",
-                field.name, field.name
-            ))
-        }
+        each_struct_field(to_emit, source, names, |field, offset| {
+            format!("(((_witness *) {field}) -> destroy)(((char *) dest) + {offset}, ((_witness *) {field}) -> extra);")
+        });
     });
     source.pushln("}");
 }
@@ -279,19 +1052,22 @@ fn get_field(to_emit: &Struct, field_index: usize, source: &mut Source, names: &
     let struct_name = &to_emit.definition.name;
     let field = &to_emit.definition.fields[field_index];
     source.pushln(&format!(
-        "void _{struct_name}_get_{}(void *dest, void *src) {{",
+        "void _{struct_name}_get_{}(void *dest, void *src, void *extra) {{",
         field.name
     ));
     source.with_inc(2, |source| {
-        let mut bank = Bank::default();
-        block(to_emit.builder.block.clone(), source, &mut bank, names);
+        let bank = builder_preamble(&to_emit.builder.arguments, &to_emit.builder.block, source, names);
         let offset_var = names.fresh_name();
         source.pushln(&format!("unsigned long long {offset_var} = 0;"));
         for field in to_emit.builder.fields.iter().take(field_index) {
-            source.pushln(&format!("{offset_var} += ((_witness *) {});", field.name));
+            source.pushln(&format!(
+                "{offset_var} += ((_witness *) {}) -> size;",
+                field.name
+            ));
         }
         let field_witness = &to_emit.builder.fields[field_index].name;
         source.pushln(&format!("(((_witness *) {field_witness}) -> copy)(dest, ((char *) src) + {offset_var}, ((_witness *) {field_witness}) -> extra);"));
+        builder_postamble(&to_emit.builder.block, bank, source);
     });
     source.pushln("}");
 }
@@ -318,13 +1094,16 @@ fn strukt(to_emit: Struct, source: &mut Source) {
     source.pushln(") {");
     source.with_inc(2, |source| {
         let mut bank = Bank::default();
-        block(to_emit.builder.block, source, &mut bank, &names);
+        block(to_emit.builder.block.clone(), source, &mut bank, &names);
 
         source.pushln(&format!("_witness *typ = _result;"));
         source.push("typ -> size = ");
+        if to_emit.builder.fields.is_empty() {
+            source.push("0");
+        }
 
         let mut first = true;
-        for field in to_emit.builder.fields {
+        for field in &to_emit.builder.fields {
             if first {
                 first = false
             } else {
@@ -336,7 +1115,147 @@ fn strukt(to_emit: Struct, source: &mut Source) {
         source.pushln(&format!("typ -> move = _move_{};", struct_name));
         source.pushln(&format!("typ -> copy = _copy_{};", struct_name));
         source.pushln(&format!("typ -> destroy = _destroy_{};", struct_name));
-        source.pushln("typ -> extra = NULL;");
+        store_type_arguments(&to_emit.builder.arguments[1..], source, &names);
+        builder_postamble(&to_emit.builder.block, bank, source);
+    });
+    source.pushln("}");
+}
+
+/// emits a function that applies `operation` to every field of the variant held in `value`.
+/// enums are laid out as an 8 byte tag followed by the fields of the current variant
+fn each_variant_field(
+    to_emit: &Enum,
+    value: &str,
+    source: &mut Source,
+    names: &NameSource,
+    operation: impl Fn(&str, &str) -> StdString,
+) {
+    let bank = builder_preamble(
+        &to_emit.builder.arguments,
+        &to_emit.builder.block,
+        source,
+        names,
+    );
+    let offset_var = names.fresh_name();
+    source.pushln(&format!("unsigned long long {offset_var};"));
+    source.pushln(&format!("switch (*(signed long long *) {value}) {{"));
+    for (index, fields) in to_emit.builder.variants.iter().enumerate() {
+        source.pushln(&format!("case {index}:"));
+        source.with_inc(2, |source| {
+            source.pushln(&format!("{offset_var} = 8;"));
+            for field in fields {
+                source.pushln(&operation(&field.name, &offset_var));
+                source.pushln(&format!(
+                    "{offset_var} += ((_witness *) {}) -> size;",
+                    field.name
+                ));
+            }
+            source.pushln("break;");
+        });
+    }
+    source.pushln("}");
+    builder_postamble(&to_emit.builder.block, bank, source);
+}
+
+fn get_variant_field(
+    to_emit: &Enum,
+    variant: usize,
+    field_index: usize,
+    source: &mut Source,
+    names: &NameSource,
+) {
+    let enum_name = &to_emit.definition.name;
+    source.pushln(&format!(
+        "void _{enum_name}_get_{variant}_{field_index}(void *dest, void *src, void *extra) {{"
+    ));
+    source.with_inc(2, |source| {
+        let bank = builder_preamble(
+            &to_emit.builder.arguments,
+            &to_emit.builder.block,
+            source,
+            names,
+        );
+        let fields = &to_emit.builder.variants[variant];
+        let offset_var = names.fresh_name();
+        source.pushln(&format!("unsigned long long {offset_var} = 8;"));
+        for field in fields.iter().take(field_index) {
+            source.pushln(&format!(
+                "{offset_var} += ((_witness *) {}) -> size;",
+                field.name
+            ));
+        }
+        let field_witness = &fields[field_index].name;
+        source.pushln(&format!("(((_witness *) {field_witness}) -> copy)(dest, ((char *) src) + {offset_var}, ((_witness *) {field_witness}) -> extra);"));
+        builder_postamble(&to_emit.builder.block, bank, source);
+    });
+    source.pushln("}");
+}
+
+fn enumeration(to_emit: Enum, source: &mut Source) {
+    let names = to_emit.builder.names.clone();
+    let enum_name = to_emit.definition.name.clone();
+    for operation in ["copy", "move"] {
+        source.pushln(&format!(
+            "void _{operation}_{enum_name}(void *dest, void *src, void *extra) {{"
+        ));
+        source.with_inc(2, |source| {
+            source.pushln("memmove(dest, src, 8);");
+            each_variant_field(&to_emit, "src", source, &names, |field, offset| {
+                format!("(((_witness *) {field}) -> {operation})(((char *) dest) + {offset}, ((char *) src) + {offset}, ((_witness *) {field}) -> extra);")
+            });
+        });
+        source.pushln("}");
+    }
+    source.pushln(&format!(
+        "void _destroy_{enum_name}(void *dest, void *extra) {{"
+    ));
+    source.with_inc(2, |source| {
+        each_variant_field(&to_emit, "dest", source, &names, |field, offset| {
+            format!("(((_witness *) {field}) -> destroy)(((char *) dest) + {offset}, ((_witness *) {field}) -> extra);")
+        });
+    });
+    source.pushln("}");
+    for (variant, fields) in to_emit.builder.variants.iter().enumerate() {
+        for field_index in 0..fields.len() {
+            get_variant_field(&to_emit, variant, field_index, source, &names);
+        }
+    }
+
+    source.push(&format!("void {}(", enum_name));
+    let mut first = true;
+    for arg in &to_emit.builder.arguments {
+        if first {
+            first = false;
+        } else {
+            source.push(", ");
+        }
+        source.push(&format!("void *{}", arg.name.name));
+    }
+    source.pushln(") {");
+    source.with_inc(2, |source| {
+        let mut bank = Bank::default();
+        block(to_emit.builder.block.clone(), source, &mut bank, &names);
+        // the payload is big enough to hold the largest variant
+        let payload = names.fresh_name();
+        source.pushln(&format!("unsigned long long {payload} = 0;"));
+        for fields in &to_emit.builder.variants {
+            let mut size = StdString::from("0");
+            for field in fields {
+                size.push_str(&format!(" + ((_witness *) {}) -> size", field.name));
+            }
+            source.pushln(&format!("if ({size} > {payload}) {{"));
+            source.with_inc(2, |source| {
+                source.pushln(&format!("{payload} = {size};"));
+            });
+            source.pushln("}");
+        }
+        source.pushln("_witness *typ = _result;");
+        source.pushln(&format!("typ -> size = 8 + {payload};"));
+        source.pushln(&format!("typ -> move = _move_{};", enum_name));
+        source.pushln(&format!("typ -> copy = _copy_{};", enum_name));
+        source.pushln(&format!("typ -> destroy = _destroy_{};", enum_name));
+        store_type_arguments(&to_emit.builder.arguments[1..], source, &names);
+        builder_postamble(&to_emit.builder.block, bank, source);
     });
     source.pushln("}");
 }
@@ -344,7 +1263,7 @@ fn strukt(to_emit: Struct, source: &mut Source) {
 fn function(to_emit: Function, source: &mut Source) {
     let names = &to_emit.names;
     source.push(&format!("void {}(", to_emit.name));
-    let mut bank = Bank::default();
+    let mut bank = Bank::stack();
 
     let mut first = true;
     for arg in to_emit.arguments {
@@ -359,8 +1278,8 @@ fn function(to_emit: Function, source: &mut Source) {
     source.pushln(") {");
     source.with_inc(2, |source| {
         block(to_emit.body, source, &mut bank, names);
-        for to_free in bank.free_list() {
-            source.pushln(&format!("free({});", to_free));
+        for to_free in bank.frees() {
+            source.pushln(&to_free);
         }
     });
     source.pushln("}");
@@ -373,6 +1292,31 @@ fn block(to_emit: Block, source: &mut Source, bank: &mut Bank, names: &NameSourc
     }
 }
 
+/// moves or copies `values` one after another into `target`, starting `offset` bytes in
+fn pack(target: &str, values: &[Value], offset: usize, source: &mut Source, names: &NameSource) {
+    let offset_name = names.fresh_name();
+    source.pushln(&format!("signed long long {} = {};", offset_name, offset));
+    for value in values {
+        shift_to(
+            &format!("(((char *) {target}) + {offset_name})"),
+            value,
+            source,
+        );
+        match value.variable().witness.as_ref() {
+            Witness::Trivial { size } => {
+                source.pushln(&format!("{} += {};", offset_name, size));
+            }
+            Witness::Dynamic { location } => {
+                source.pushln(&format!(
+                    "{} += ((_witness *) {}) -> size;",
+                    offset_name, location.name
+                ));
+            }
+            Witness::Type => unreachable!(),
+        }
+    }
+}
+
 fn shift(dest: &Variable, src: &Value, source: &mut Source) {
     shift_to(&dest.name, src, source)
 }
@@ -398,17 +1342,67 @@ fn shift_to(dest: &str, src: &Value, source: &mut Source) {
                 location.name, function, dest, src_name, location.name
             ))
         }
-        Witness::Type => source.pushln(&format!("_copy_type({}, {});", dest, src_name)),
+        Witness::Type => {
+            let function = match src {
+                Value::Move(_) => "_move_type",
+                Value::Copy(_) => "_copy_type",
+            };
+            source.pushln(&format!("{function}({dest}, {src_name});"));
+        }
     }
 }
 
+/// the C type that holds values of a numeric type, or of `Bool`
+fn number_type(typ: &Type) -> &'static str {
+    match typ.canonical() {
+        Type::Named { name, arguments } if arguments.is_empty() => match name.as_str() {
+            "F64" => "double",
+            "I64" | "Bool" => "signed long long",
+            _ => unreachable!("{typ} is not a number"),
+        },
+        _ => panic!("{typ} is not a number"),
+    }
+}
+
+/// the C arguments of a call. `in` arguments are given to the callee, so a copied one gets a fresh temporary,
+/// but a moved one is passed in the variable it's already in. the rest are passed by reference
+fn call_arguments(
+    arguments: &[CallArgument],
+    source: &mut Source,
+    bank: &mut Bank,
+    names: &NameSource,
+) -> Vec<String> {
+    arguments
+        .iter()
+        .map(|arg| {
+            let var = arg.value.variable();
+            let shared = arguments
+                .iter()
+                .filter(|other| other.value.variable() == var)
+                .count()
+                > 1;
+            match (&arg.value, arg.convention) {
+                (Value::Move(_), Convention::In) if !shared => var.name.clone(),
+                (_, Convention::In) => {
+                    let name = names.fresh_name();
+                    let dest = Variable::new(name.clone(), var.typ.clone(), *var.witness.clone());
+                    bank.define(&name, &var.witness, source);
+                    shift(&dest, &arg.value, source);
+                    name
+                }
+                _ => var.name.clone(),
+            }
+        })
+        .collect()
+}
+
 fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSource) {
     let var = &to_emit.target.name;
     bank.define(var, &to_emit.target.witness, source);
     match to_emit.value {
         Expr::Literal(to_emit) => {
             source.push(&match to_emit {
-                Literal::Float(float) => format!("*(double *) {var} = {float}"),
+                Literal::Float(float) => format!("*(double *) {var} = {float:?}"),
                 Literal::Integer(integer) => format!("*(signed long long *) {var} = {integer}ll"),
                 Literal::Boolean(boolean) => format!(
                     "*(signed long long *) {var} = {}ll",
@@ -418,68 +1412,86 @@ fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSourc
             source.pushln(";");
         }
         Expr::Primitive(primitive, args) => {
-            let type_name = {
-                match &args[0].typ {
-                    tree::typed::Type::Named { name, arguments } => {
-                        assert!(arguments.is_empty());
-                        match name.as_str() {
-                            "F64" => "double",
-                            "I64" => "signed long long",
-                            _ => unimplemented!(),
+            let line = match primitive {
+                Primitive::Add | Primitive::Sub | Primitive::Mul => {
+                    let (a, b) = (&args[0].name, &args[1].name);
+                    let operator = primitive.to_string();
+                    match number_type(&args[0].typ) {
+                        "double" => format!(
+                            "*(double *) {var} = *(double *) {a} {operator} *(double *) {b};"
+                        ),
+                        // signed overflow is undefined in C, so integer arithmetic is done unsigned to wrap around
+                        type_name => format!(
+                            "*({type_name} *) {var} = ({type_name}) (*(unsigned long long *) {a} {operator} *(unsigned long long *) {b});"
+                        ),
+                    }
+                }
+                Primitive::Div | Primitive::Rem => {
+                    let (a, b) = (&args[0].name, &args[1].name);
+                    match (number_type(&args[0].typ), primitive) {
+                        ("double", Primitive::Div) => {
+                            format!("*(double *) {var} = *(double *) {a} / *(double *) {b};")
+                        }
+                        ("double", _) => {
+                            format!("*(double *) {var} = fmod(*(double *) {a}, *(double *) {b});")
+                        }
+                        (type_name, _) => {
+                            let function = if matches!(primitive, Primitive::Div) {
+                                "_i64_div"
+                            } else {
+                                "_i64_rem"
+                            };
+                            format!(
+                                "*({type_name} *) {var} = {function}(*({type_name} *) {a}, *({type_name} *) {b});"
+                            )
                         }
                     }
-                    tree::typed::Type::Generic { .. } => panic!(),
-                    tree::typed::Type::Function { .. } => panic!(),
-                    tree::typed::Type::Unification { .. } => panic!(),
                 }
-            };
-            source.push(&format!("*({type_name} *) {var} = "));
-            match primitive {
-                Primitive::Add => {
-                    source.push(&format!(
-                        "*(({type_name} *) {a}) + *(({type_name} *) {b})",
-                        a = args[0].name,
-                        b = args[1].name,
-                    ));
+                Primitive::Eq
+                | Primitive::NotEq
+                | Primitive::Less
+                | Primitive::LessEq
+                | Primitive::Greater
+                | Primitive::GreaterEq => {
+                    let (a, b) = (&args[0].name, &args[1].name);
+                    let operator = primitive.to_string();
+                    let type_name = number_type(&args[0].typ);
+                    format!(
+                        "*(signed long long *) {var} = *({type_name} *) {a} {operator} *({type_name} *) {b};"
+                    )
                 }
-                Primitive::Sub => {
-                    source.push(&format!(
-                        "*(({type_name} *) {a}) - *(({type_name} *) {b})",
-                        a = args[0].name,
-                        b = args[1].name,
-                    ));
+                Primitive::Neg => {
+                    let a = &args[0].name;
+                    match number_type(&args[0].typ) {
+                        "double" => format!("*(double *) {var} = -*(double *) {a};"),
+                        type_name => format!(
+                            "*({type_name} *) {var} = ({type_name}) (0ull - *(unsigned long long *) {a});"
+                        ),
+                    }
                 }
-                Primitive::Mul => {
-                    source.push(&format!(
-                        "*(({type_name} *) {a}) * *(({type_name} *) {b})",
-                        a = args[0].name,
-                        b = args[1].name,
-                    ));
+                Primitive::Not => format!(
+                    "*(signed long long *) {var} = !*(signed long long *) {};",
+                    args[0].name
+                ),
+                Primitive::And | Primitive::Or => {
+                    unreachable!("{primitive} is lowered into an if so that it short-circuits")
                 }
+                Primitive::ToFloat => format!(
+                    "*(double *) {var} = (double) *(signed long long *) {};",
+                    args[0].name
+                ),
+                Primitive::ToInteger => format!(
+                    "*(signed long long *) {var} = _f64_to_i64(*(double *) {});",
+                    args[0].name
+                ),
             };
-            source.pushln(";");
+            source.pushln(&line);
         }
         Expr::CallDirect {
             function,
             arguments,
         } => {
-            let emitted_args: Vec<_> = arguments
-                .iter()
-                .map(|arg| {
-                    let var = arg.value.variable();
-                    let witness = &var.witness;
-                    let name = if arg.convention == Convention::Out {
-                        var.name.clone()
-                    } else {
-                        let name = names.fresh_name();
-                        let dest = Variable::new(name.clone(), var.typ.clone(), *witness.clone());
-                        bank.define(&name, &witness, source);
-                        shift(&dest, &arg.value, source);
-                        name
-                    };
-                    name
-                })
-                .collect();
+            let emitted_args = call_arguments(&arguments, source, bank, names);
             source.push(&format!("{}(", function));
             let mut first = true;
             for arg in emitted_args {
@@ -492,6 +1504,39 @@ fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSourc
             }
             source.pushln(");");
         }
+        Expr::CallIndirect { closure, arguments } => {
+            let emitted_args = call_arguments(&arguments, source, bank, names);
+            // the closure's allocation is laid out as [reference count, env witness table, env],
+            // and the callee owns its arguments, so it gets a fresh copy of both the env and its witness table
+            let alloc = names.fresh_name();
+            source.pushln(&format!(
+                "char *{alloc} = ((struct _Closure *) {}) -> env;",
+                closure.name
+            ));
+            source.pushln(&format!(r#"_rc_use({alloc}, "a closure's environment");"#));
+            let env_witness = names.fresh_name();
+            source.pushln(&format!("char {env_witness}[sizeof(_witness)];"));
+            source.pushln(&format!(
+                "_copy_type({env_witness}, {alloc} + sizeof(unsigned long long));"
+            ));
+            let env = names.fresh_name();
+            bank.defer_free(env.clone());
+            source.pushln(&format!(
+                "void *{env} = malloc(((_witness *) {env_witness}) -> size);"
+            ));
+            source.pushln(&format!(
+                "(((_witness *) {env_witness}) -> copy)({env}, {alloc} + sizeof(unsigned long long) + sizeof(_witness), ((_witness *) {env_witness}) -> extra);"
+            ));
+            let parameters = vec!["void *"; emitted_args.len() + 2].join(", ");
+            source.push(&format!(
+                "((void (*)({parameters})) ((struct _Closure *) {}) -> function)(",
+                closure.name
+            ));
+            for arg in emitted_args {
+                source.push(&format!("{arg}, "));
+            }
+            source.pushln(&format!("{env}, {env_witness});"));
+        }
         Expr::Value(value) => {
             shift(&to_emit.target, &value, source);
         }
@@ -504,27 +1549,66 @@ fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSourc
             Witness::Type => source.pushln(&format!("_destroy_type({});", var)),
         },
         Expr::StructPack { arguments, .. } => {
-            let offset_name = names.fresh_name();
-            source.pushln(&format!("signed long long {} = 0;", offset_name));
-            for arg in arguments {
-                shift_to(
-                    &format!("(((char *) {var}) + {offset_name})"),
-                    &arg.value,
-                    source,
-                );
-                match arg.value.variable().witness.as_ref() {
-                    Witness::Trivial { size } => {
-                        source.pushln(&format!("{} += {};", offset_name, size));
-                    }
-                    Witness::Dynamic { location } => {
-                        source.pushln(&format!(
-                            "{} += ((_witness *) {}) -> size;",
-                            offset_name, location.name
-                        ));
-                    }
-                    Witness::Type => unreachable!(),
+            let values: Vec<_> = arguments.into_iter().map(|arg| arg.value).collect();
+            pack(var, &values, 0, source, names);
+        }
+        Expr::VariantPack {
+            variant, arguments, ..
+        } => {
+            source.pushln(&format!("*(signed long long *) {var} = {variant}ll;"));
+            pack(var, &arguments, 8, source, names);
+        }
+        Expr::UnpackVariant {
+            enum_name, value, ..
+        } if enum_name == RESULT => match to_emit.target.witness.as_ref() {
+            // error unions hold their value or error right after the tag
+            Witness::Trivial { size } => source.pushln(&format!(
+                "memmove({var}, ((char *) {}) + 8, {size});",
+                value.name
+            )),
+            Witness::Dynamic { location } => source.pushln(&format!(
+                "(((_witness *) {}) -> copy)({var}, ((char *) {}) + 8, ((_witness *) {}) -> extra);",
+                location.name, value.name, location.name
+            )),
+            Witness::Type => unreachable!("error unions don't hold types"),
+        },
+        Expr::Crash => source.pushln("_crash();"),
+        Expr::UnpackVariant {
+            enum_name,
+            variant,
+            field,
+            value,
+            offset: None,
+        } => {
+            let extra = match value.witness.as_ref() {
+                Witness::Dynamic { location } => format!("((_witness *) {}) -> extra", location.name),
+                Witness::Trivial { .. } | Witness::Type => StdString::from("NULL"),
+            };
+            source.pushln(&format!(
+                "_{enum_name}_get_{variant}_{field}({var}, {}, {extra});",
+                value.name
+            ));
+        }
+        Expr::IsVariant { value, variant } => {
+            source.pushln(&format!(
+                "*(signed long long *) {var} = *(signed long long *) {} == {variant}ll;",
+                value.name
+            ));
+        }
+        Expr::IsLiteral { value, literal } => {
+            let value = &value.name;
+            source.pushln(&match literal {
+                Literal::Float(float) => {
+                    format!("*(signed long long *) {var} = *(double *) {value} == {float:?};")
                 }
-            }
+                Literal::Integer(integer) => format!(
+                    "*(signed long long *) {var} = *(signed long long *) {value} == {integer}ll;"
+                ),
+                Literal::Boolean(boolean) => format!(
+                    "*(signed long long *) {var} = *(signed long long *) {value} == {}ll;",
+                    if boolean { "1" } else { "0" }
+                ),
+            });
         }
         Expr::If {
             predicate,
@@ -535,27 +1619,72 @@ fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSourc
                 "if (*((signed long long *) {})) {{",
                 predicate.name
             ));
-            source.with_inc(2, |source| {
-                block(true_branch, source, bank, names);
-            });
-            source.pushln("} else {");
-            source.with_inc(2, |source| {
-                block(false_branch, source, bank, names);
-            });
+            // each branch is its own C block, so whatever it defines is only visible (and only freed) inside it
+            for (i, branch) in [true_branch, false_branch].into_iter().enumerate() {
+                if i != 0 {
+                    source.pushln("} else {");
+                }
+                source.with_inc(2, |source| {
+                    let mut branch_bank = bank.scoped();
+                    block(branch, source, &mut branch_bank, names);
+                    for to_free in branch_bank.frees() {
+                        source.pushln(&to_free);
+                    }
+                });
+            }
             source.pushln("}");
         }
+        Expr::Unpack {
+            value,
+            offset: Some(offset),
+            ..
+        }
+        | Expr::UnpackVariant {
+            value,
+            offset: Some(offset),
+            ..
+        } => {
+            let Witness::Trivial { size } = to_emit.target.witness.as_ref() else {
+                unreachable!("a statically laid out value has a field that isn't")
+            };
+            source.pushln(&format!(
+                "memmove({var}, ((char *) {}) + {offset}, {size});",
+                value.name
+            ));
+        }
         Expr::Unpack {
             value,
             field,
             struct_name,
-            type_arguments,
+            offset: None,
         } => {
             let struct_var = &value.name;
-            source.push(&format!("_{struct_name}_get_{field}({var}, {struct_var}"));
-            for arg in type_arguments {
-                source.push(&format!(", {}", arg.name));
+            let extra = match value.witness.as_ref() {
+                Witness::Dynamic { location } => format!("((_witness *) {}) -> extra", location.name),
+                Witness::Trivial { .. } | Witness::Type => StdString::from("NULL"),
+            };
+            source.pushln(&format!(
+                "_{struct_name}_get_{field}({var}, {struct_var}, {extra});"
+            ));
+        }
+        Expr::TypeArgument { witness, index } => {
+            source.pushln(&format!(
+                "_copy_type({var}, {});",
+                type_argument(&format!("((_witness *) {}) -> extra", witness.name), index)
+            ));
+        }
+        Expr::TailCall {
+            function,
+            arguments,
+        } => {
+            for (i, argument) in arguments.iter().enumerate() {
+                source.pushln(&format!("_jump[{i}] = {};", argument.name));
             }
-            source.pushln(");");
+            source.pushln("_arguments = _jump;");
+            for to_free in bank.jump_frees() {
+                source.pushln(&to_free);
+            }
+            source.pushln(&format!("goto {function};"));
         }
         Expr::MakeClosure {
             function,
@@ -581,5 +1710,22 @@ fn instr(to_emit: Instr, source: &mut Source, bank: &mut Bank, names: &NameSourc
             source.pushln(&format!("*(void **){var} = {function};"));
             source.pushln(&format!("*((void **){var} + 1) = {alloc};"));
         }
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => {
+            let any = format!("((struct _existential *) {var})");
+            shift_to(&format!("&{any} -> witness"), &witness, source);
+            source.pushln(&format!(
+                "{any} -> dictionary = *(void **) {};",
+                dictionary.variable().name
+            ));
+            source.pushln(&format!(
+                "{any} -> value = malloc(((_witness *) {}) -> size);",
+                witness.variable().name
+            ));
+            shift_to(&format!("{any} -> value"), &value, source);
+        }
     }
 }
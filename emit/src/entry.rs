@@ -0,0 +1,49 @@
+use ir::bridge::Program;
+use tree::typed::Type;
+
+use crate::emitter::{self, Options, Source};
+
+/// the C type that holds a result of type `typ`, and how to print it,
+/// or `None` if the entry point doesn't know how to print `typ`
+fn printer(typ: &Type) -> Option<(&'static str, &'static str)> {
+    match typ.canonical() {
+        Type::Named { name, arguments } if arguments.is_empty() => match name.as_str() {
+            "F64" => Some(("double", r#"printf("%g\n", result);"#)),
+            "I64" => Some(("signed long long", r#"printf("%lld\n", result);"#)),
+            "Bool" => Some((
+                "signed long long",
+                r#"printf("%s\n", result ? "True" : "False");"#,
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// whether a Hoyle `main` returning `typ` can be the entry point of an executable
+#[must_use]
+pub fn printable(typ: &Type) -> bool {
+    printer(typ).is_some()
+}
+
+/// the C source of an executable that runs the Hoyle `main` of `program`, which returns `result`, and prints what it returns
+#[must_use]
+pub fn executable(program: Program, result: &Type, options: Options) -> Source {
+    let (c_type, print) = printer(result).expect("main's result can't be printed");
+    let mut source = Source::default();
+    source.pushln("#include <stdio.h>");
+    // the Hoyle `main` would collide with the C one
+    source.pushln("#define main _hoyle_main");
+    source.pushln(&emitter::program(program, options).to_string());
+    source.pushln("#undef main");
+    source.pushln("");
+    source.pushln("int main(void) {");
+    source.with_inc(2, |source| {
+        source.pushln(&format!("{c_type} result;"));
+        source.pushln("_hoyle_main(&result);");
+        source.pushln(print);
+        source.pushln("return 0;");
+    });
+    source.pushln("}");
+    source
+}
@@ -2,4 +2,6 @@
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
 mod emitter;
-pub use emitter::program;
+mod entry;
+pub use emitter::{program, Options};
+pub use entry::{executable, printable};
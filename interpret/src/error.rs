@@ -0,0 +1,103 @@
+use core::fmt;
+
+use tree::String;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    UseAfterMove {
+        function: String,
+        variable: String,
+    },
+    UseAfterDestroy {
+        function: String,
+        variable: String,
+    },
+    DoubleDestroy {
+        function: String,
+        variable: String,
+    },
+    UseAfterFree {
+        allocation: String,
+    },
+    DoubleFree {
+        allocation: String,
+    },
+    OutOfBounds {
+        allocation: String,
+    },
+    NullPointer,
+    BadFunctionPointer,
+    DivisionByZero,
+    /// an error union that held an error was unwrapped
+    Thrown,
+    UnknownFunction(String),
+    UnknownVariable {
+        function: String,
+        variable: String,
+    },
+    /// the function that was run takes arguments
+    BadEntryPoint(String),
+}
+
+/// something that was still alive when the program finished
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Leak {
+    /// a variable that held a value with a destructor, and was never destroyed or moved out of
+    Variable { function: String, variable: String },
+    /// a heap allocation that was never freed
+    Allocation { allocation: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UseAfterMove { function, variable } => {
+                write!(f, "`{variable}` is used in `{function}` after being moved")
+            }
+            Self::UseAfterDestroy { function, variable } => {
+                write!(
+                    f,
+                    "`{variable}` is used in `{function}` after being destroyed"
+                )
+            }
+            Self::DoubleDestroy { function, variable } => {
+                write!(
+                    f,
+                    "`{variable}` is destroyed in `{function}` when it holds no value"
+                )
+            }
+            Self::UseAfterFree { allocation } => {
+                write!(f, "{allocation} is used after being freed")
+            }
+            Self::DoubleFree { allocation } => write!(f, "{allocation} is freed twice"),
+            Self::OutOfBounds { allocation } => write!(f, "{allocation} is accessed out of bounds"),
+            Self::NullPointer => write!(f, "a null pointer is dereferenced"),
+            Self::BadFunctionPointer => write!(f, "a value that isn't a function is called"),
+            Self::DivisionByZero => write!(f, "an integer is divided by zero"),
+            Self::Thrown => write!(f, "an error was thrown and never caught"),
+            Self::UnknownFunction(function) => write!(f, "there is no function `{function}`"),
+            Self::UnknownVariable { function, variable } => {
+                write!(
+                    f,
+                    "`{variable}` is used in `{function}` before being defined"
+                )
+            }
+            Self::BadEntryPoint(function) => {
+                write!(f, "`{function}` can't be run, as it takes arguments")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Leak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Variable { function, variable } => {
+                write!(f, "`{variable}` in `{function}` is never destroyed")
+            }
+            Self::Allocation { allocation } => write!(f, "{allocation} is never freed"),
+        }
+    }
+}
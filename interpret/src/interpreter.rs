@@ -0,0 +1,1498 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use ir::bridge::{
+    Block, BuilderArgument, CallArgument, Convention, Enum, Expr, Function, Instr, Program, Struct,
+    Value, Variable, Witness,
+};
+use tree::sized::{Literal, Primitive, Protocol, Type, RESULT};
+use tree::type_passing::{dictionary_builder_name, impl_function_name, EXISTENTIAL};
+use tree::String;
+
+use crate::error::{Error, Leak, Result};
+use crate::layout::Layout;
+use crate::memory::{
+    function_index, function_pointer, offset, Kind, Memory, Origin, Pointer, Table, NULL,
+    POINTER_SIZE, TABLE_SIZE,
+};
+
+/// the size of every builtin value that isn't a closure or an existential
+const WORD_SIZE: usize = 8;
+/// a closure is a function pointer followed by a pointer to its environment
+const CLOSURE_SIZE: usize = 2 * POINTER_SIZE;
+/// an existential is a witness table, followed by a dictionary and a pointer to the boxed value
+const EXISTENTIAL_SIZE: usize = TABLE_SIZE + 2 * POINTER_SIZE;
+/// a closure's environment is laid out as [reference count, env witness table, env]
+const ENV_HEADER: usize = WORD_SIZE + TABLE_SIZE;
+/// the extra field of a generic witness table is laid out as [reference count, number of type arguments, type arguments]
+const TYPE_ARGUMENTS_HEADER: usize = 2 * WORD_SIZE;
+
+/// what a program returned, and what it left behind
+pub struct Run {
+    pub result: Vec<u8>,
+    pub leaks: Vec<Leak>,
+}
+
+/// runs `function` of `program`, which has to take nothing but its result.
+/// the result is read back as `size` bytes, as the size of a dynamically sized result is only known to the callee
+pub fn run(program: &Program, function: &str, size: usize) -> Result<Run> {
+    let layout = Layout::new(program);
+    let mut interpreter = Interpreter::new(program, &layout);
+    let Some(Named::Function(entry)) = interpreter.named.get(function).copied() else {
+        return Err(Error::UnknownFunction(String::from(function)));
+    };
+    if entry.arguments.len() != 1 {
+        return Err(Error::BadEntryPoint(String::from(function)));
+    }
+    let result = interpreter
+        .memory
+        .allocate(size, Kind::Static, Origin::Result);
+    interpreter.call_function(entry, &[result])?;
+    let mut leaks = interpreter.leaks;
+    leaks.extend(interpreter.memory.leaks());
+    Ok(Run {
+        result: interpreter.memory.read(result, size)?.to_vec(),
+        leaks,
+    })
+}
+
+/// what a name that can be called refers to
+#[derive(Copy, Clone)]
+enum Named<'a> {
+    Function(&'a Function),
+    /// the builder of a struct's witness table
+    Struct(&'a Struct),
+    /// the builder of an enum's witness table
+    Enum(&'a Enum),
+    /// the builder of one of the witness tables that every program comes with
+    Builtin(Builtin),
+    /// the builder of an error union's witness table, which takes those of its value and its error
+    Result,
+    /// the builder of `typ`'s dictionary for `protocol`
+    Dictionary {
+        protocol: &'a Protocol,
+        typ: &'a str,
+    },
+    /// a protocol function, which calls the implementation in the dictionary it's passed
+    Dispatch {
+        index: usize,
+    },
+    /// `any P`'s implementation of a function of `P`, which unboxes its `self_argument`th argument
+    Unbox {
+        index: usize,
+        self_argument: usize,
+    },
+}
+
+#[derive(Copy, Clone)]
+enum Builtin {
+    Word,
+    Closure,
+    Existential,
+}
+
+/// what a function pointer points to
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Callable {
+    Named(String),
+    Memmove(usize),
+    DestroyNothing,
+    CopyClosure,
+    DestroyClosure,
+    CopyExistential,
+    DestroyExistential,
+    Struct(Operation, String),
+    Enum(Operation, String),
+    Result(Operation),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Operation {
+    Move,
+    Copy,
+    Destroy,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    Unwritten,
+    Live,
+    Moved,
+    Destroyed,
+}
+
+#[derive(Copy, Clone)]
+struct Slot<'a> {
+    variable: &'a str,
+    pointer: Pointer,
+    state: State,
+    /// whether the frame has to destroy or move out of the variable before it returns
+    owned: bool,
+}
+
+/// the variables of a call to a function, or of a run of a builder block
+struct Frame<'a> {
+    function: &'a str,
+    layout: &'a Layout,
+    /// the variables that have been bound so far, where `layout` puts them
+    slots: Vec<Option<Slot<'a>>>,
+    /// storage that is released when the frame returns
+    storage: Vec<Pointer>,
+    /// the slots moved out of by the current instruction, which can still be read until it finishes
+    moved: Vec<usize>,
+}
+
+enum Flow<'a> {
+    Next,
+    /// a tail call, which replaces the current frame
+    Jump {
+        function: &'a str,
+        arguments: Vec<Pointer>,
+    },
+}
+
+struct Interpreter<'a> {
+    layout: &'a Layout,
+    named: HashMap<String, Named<'a>>,
+    /// everything that a function pointer has been made for, indexed by the function pointer
+    callables: Vec<Callable>,
+    pointers: HashMap<Callable, Pointer>,
+    dictionaries: HashMap<String, Pointer>,
+    memory: Memory<'a>,
+    leaks: Vec<Leak>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(function: &'a str, layout: &'a Layout, body: &Block) -> Self {
+        Self {
+            function,
+            layout,
+            slots: vec![None; layout.size(body)],
+            storage: Vec::new(),
+            moved: Vec::new(),
+        }
+    }
+
+    fn bind(&mut self, variable: &'a Variable, pointer: Pointer, state: State, owned: bool) {
+        self.slots[self.layout.slot(variable)] = Some(Slot {
+            variable: &variable.name,
+            pointer,
+            state,
+            owned,
+        });
+    }
+
+    /// the storage of `variable`, if it has been bound
+    fn pointer(&self, variable: &Variable) -> Option<Pointer> {
+        self.slots[self.layout.slot(variable)].map(|slot| slot.pointer)
+    }
+
+    fn error(&self, variable: &Variable, make: fn(String, String) -> Error) -> Error {
+        make(String::from(self.function), variable.name.clone())
+    }
+
+    fn slot(&mut self, variable: &Variable) -> Result<&mut Slot<'a>> {
+        let function = self.function;
+        self.slots[self.layout.slot(variable)]
+            .as_mut()
+            .ok_or_else(|| Error::UnknownVariable {
+                function: String::from(function),
+                variable: variable.name.clone(),
+            })
+    }
+
+    /// the storage of a variable that is about to be read
+    fn read(&mut self, variable: &Variable) -> Result<Pointer> {
+        let slot = *self.slot(variable)?;
+        match slot.state {
+            State::Live => Ok(slot.pointer),
+            State::Moved if self.moved.contains(&self.layout.slot(variable)) => Ok(slot.pointer),
+            State::Moved => Err(
+                self.error(variable, |function, variable| Error::UseAfterMove {
+                    function,
+                    variable,
+                }),
+            ),
+            State::Destroyed => {
+                Err(
+                    self.error(variable, |function, variable| Error::UseAfterDestroy {
+                        function,
+                        variable,
+                    }),
+                )
+            }
+            State::Unwritten => {
+                Err(
+                    self.error(variable, |function, variable| Error::UnknownVariable {
+                        function,
+                        variable,
+                    }),
+                )
+            }
+        }
+    }
+
+    /// the storage of a variable that is about to be moved or copied out of.
+    /// only values that have to be destroyed are moved, as trivial ones can be copied any number of times
+    fn take(&mut self, variable: &'a Variable, moved: bool) -> Result<Pointer> {
+        let pointer = self.read(variable)?;
+        if moved && !variable.witness.is_trivial() {
+            self.slot(variable)?.state = State::Moved;
+            self.moved.push(self.layout.slot(variable));
+        }
+        Ok(pointer)
+    }
+
+    /// the storage of a variable that is about to be destroyed
+    fn destroy(&mut self, variable: &Variable) -> Result<Pointer> {
+        let slot = self.slot(variable)?;
+        if slot.state == State::Live {
+            slot.state = State::Destroyed;
+            Ok(slot.pointer)
+        } else {
+            Err(
+                self.error(variable, |function, variable| Error::DoubleDestroy {
+                    function,
+                    variable,
+                }),
+            )
+        }
+    }
+
+    fn written(&mut self, variable: &Variable) -> Result<()> {
+        self.slot(variable)?.state = State::Live;
+        Ok(())
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(program: &'a Program, layout: &'a Layout) -> Self {
+        let mut named = HashMap::new();
+        for function in &program.functions {
+            named.insert(function.name.clone(), Named::Function(function));
+        }
+        for strukt in &program.structs {
+            named.insert(strukt.definition.name.clone(), Named::Struct(strukt));
+        }
+        for enumeration in &program.enums {
+            named.insert(
+                enumeration.definition.name.clone(),
+                Named::Enum(enumeration),
+            );
+        }
+        for name in ["F64", "I64", "Bool", "_Dictionary"] {
+            named.insert(String::from(name), Named::Builtin(Builtin::Word));
+        }
+        named.insert(String::from("_Closure"), Named::Builtin(Builtin::Closure));
+        named.insert(
+            String::from("_Existential"),
+            Named::Builtin(Builtin::Existential),
+        );
+        named.insert(String::from(RESULT), Named::Result);
+        for protocol in &program.protocols {
+            for (index, function) in protocol.functions.iter().enumerate() {
+                named.insert(function.name.clone(), Named::Dispatch { index });
+            }
+            if protocol
+                .functions
+                .iter()
+                .all(|function| function.self_argument().is_some())
+            {
+                for (index, function) in protocol.functions.iter().enumerate() {
+                    named.insert(
+                        impl_function_name(&protocol.name, EXISTENTIAL, &function.name),
+                        Named::Unbox {
+                            index,
+                            self_argument: function.self_argument().unwrap(),
+                        },
+                    );
+                }
+                named.insert(
+                    dictionary_builder_name(&protocol.name, EXISTENTIAL),
+                    Named::Dictionary {
+                        protocol,
+                        typ: EXISTENTIAL,
+                    },
+                );
+            }
+        }
+        for implementation in &program.impls {
+            let protocol = program
+                .protocols
+                .iter()
+                .find(|protocol| protocol.name == implementation.protocol)
+                .expect("impl of an unknown protocol");
+            named.insert(
+                dictionary_builder_name(&implementation.protocol, &implementation.typ),
+                Named::Dictionary {
+                    protocol,
+                    typ: &implementation.typ,
+                },
+            );
+        }
+        Self {
+            layout,
+            named,
+            callables: Vec::new(),
+            pointers: HashMap::new(),
+            dictionaries: HashMap::new(),
+            memory: Memory::default(),
+            leaks: Vec::new(),
+        }
+    }
+
+    fn function_pointer(&mut self, callable: Callable) -> Pointer {
+        if let Some(pointer) = self.pointers.get(&callable) {
+            return *pointer;
+        }
+        let pointer = function_pointer(self.callables.len());
+        self.callables.push(callable.clone());
+        self.pointers.insert(callable, pointer);
+        pointer
+    }
+
+    fn lookup(&self, name: &str) -> Result<Named<'a>> {
+        self.named
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::UnknownFunction(String::from(name)))
+    }
+
+    fn strukt(&self, name: &str) -> Result<&'a Struct> {
+        match self.lookup(name)? {
+            Named::Struct(strukt) => Ok(strukt),
+            _ => Err(Error::UnknownFunction(String::from(name))),
+        }
+    }
+
+    fn enumeration(&self, name: &str) -> Result<&'a Enum> {
+        match self.lookup(name)? {
+            Named::Enum(enumeration) => Ok(enumeration),
+            _ => Err(Error::UnknownFunction(String::from(name))),
+        }
+    }
+
+    fn call(&mut self, pointer: Pointer, arguments: &[Pointer]) -> Result<()> {
+        let callable = self
+            .callables
+            .get(function_index(pointer)?)
+            .cloned()
+            .ok_or(Error::BadFunctionPointer)?;
+        match callable {
+            Callable::Named(name) => self.call_named(&name, arguments),
+            Callable::Memmove(size) => self.memory.copy(arguments[0], arguments[1], size),
+            Callable::DestroyNothing => Ok(()),
+            Callable::CopyClosure => self.copy_closure(arguments[0], arguments[1]),
+            Callable::DestroyClosure => self.destroy_closure(arguments[0]),
+            Callable::CopyExistential => self.copy_existential(arguments[0], arguments[1]),
+            Callable::DestroyExistential => self.destroy_existential(arguments[0]),
+            Callable::Struct(operation, name) => {
+                self.struct_operation(self.strukt(&name)?, operation, arguments)
+            }
+            Callable::Enum(operation, name) => {
+                self.enum_operation(self.enumeration(&name)?, operation, arguments)
+            }
+            Callable::Result(operation) => self.result_operation(operation, arguments),
+        }
+    }
+
+    fn call_named(&mut self, name: &str, arguments: &[Pointer]) -> Result<()> {
+        match self.lookup(name)? {
+            Named::Function(function) => self.call_function(function, arguments),
+            Named::Struct(strukt) => self.build_struct(strukt, arguments),
+            Named::Enum(enumeration) => self.build_enum(enumeration, arguments),
+            Named::Result => self.build_result(arguments),
+            Named::Builtin(builtin) => {
+                let table = self.builtin(builtin);
+                self.memory.write_table(arguments[0], table)
+            }
+            Named::Dictionary { protocol, typ } => {
+                let dictionary = self.dictionary(protocol, typ);
+                self.memory.write_u64(arguments[0], dictionary)
+            }
+            Named::Dispatch { index } => {
+                // the arguments are followed by the witness table of `Self` and the dictionary
+                let (arguments, bound) = arguments.split_at(arguments.len() - 2);
+                let dictionary = self.memory.read_u64(bound[1])?;
+                let function = self
+                    .memory
+                    .read_u64(offset(dictionary, index * POINTER_SIZE))?;
+                self.call(function, arguments)?;
+                self.destroy_type(bound[0])
+            }
+            Named::Unbox {
+                index,
+                self_argument,
+            } => {
+                let any = arguments[self_argument + 1];
+                let dictionary = self.memory.read_u64(offset(any, TABLE_SIZE))?;
+                let value = self
+                    .memory
+                    .read_u64(offset(any, TABLE_SIZE + POINTER_SIZE))?;
+                let function = self
+                    .memory
+                    .read_u64(offset(dictionary, index * POINTER_SIZE))?;
+                // the implementation takes ownership of the value, but the box and the witness table are still ours
+                let mut unboxed = arguments.to_vec();
+                unboxed[self_argument + 1] = value;
+                self.call(function, &unboxed)?;
+                self.memory.free(value)?;
+                self.destroy_type(any)
+            }
+        }
+    }
+
+    /// runs `function` with its result and arguments in `arguments`, following any tail calls it makes
+    fn call_function(&mut self, function: &'a Function, arguments: &[Pointer]) -> Result<()> {
+        let (result, arguments) = arguments.split_first().unwrap();
+        let mut function = function;
+        let mut arguments = arguments.to_vec();
+        // the storage that a tail call moved its arguments into, which the callee's frame releases
+        let mut carried = Vec::new();
+        loop {
+            let mut frame = Frame::new(&function.name, self.layout, &function.body);
+            frame.storage = std::mem::take(&mut carried);
+            let (result_argument, rest) = function.arguments.split_first().unwrap();
+            frame.bind(&result_argument.name, *result, State::Unwritten, false);
+            for (argument, pointer) in rest.iter().zip(&arguments) {
+                let (state, owned) = match argument.convention {
+                    Convention::In => (State::Live, !argument.name.witness.is_trivial()),
+                    Convention::Inout => (State::Live, false),
+                    Convention::Out => (State::Unwritten, false),
+                };
+                frame.bind(&argument.name, *pointer, state, owned);
+            }
+            match self.block(&mut frame, &function.body)? {
+                Flow::Next => return self.leave(frame, true),
+                Flow::Jump {
+                    function: target,
+                    arguments: jumped,
+                } => {
+                    frame.storage.retain(|pointer| !jumped.contains(pointer));
+                    self.leave(frame, true)?;
+                    function = match self.lookup(target)? {
+                        Named::Function(function) => function,
+                        _ => return Err(Error::UnknownFunction(String::from(target))),
+                    };
+                    carried.clone_from(&jumped);
+                    arguments = jumped;
+                }
+            }
+        }
+    }
+
+    /// releases the frame's storage, after checking that it didn't leak any of its variables
+    fn leave(&mut self, frame: Frame<'a>, check: bool) -> Result<()> {
+        if check {
+            let mut leaked: Vec<_> = frame
+                .slots
+                .iter()
+                .flatten()
+                .filter(|slot| slot.owned && slot.state == State::Live)
+                .map(|slot| slot.variable)
+                .collect();
+            leaked.sort_unstable();
+            self.leaks
+                .extend(leaked.into_iter().map(|variable| Leak::Variable {
+                    function: String::from(frame.function),
+                    variable: String::from(variable),
+                }));
+        }
+        for pointer in frame.storage {
+            self.memory.pop(pointer)?;
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, frame: &mut Frame<'a>, block: &'a Block) -> Result<Flow<'a>> {
+        for instr in &block.instrs {
+            let flow = self.instr(frame, instr)?;
+            frame.moved.clear();
+            if let Flow::Jump { .. } = flow {
+                return Ok(flow);
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// the storage of `variable`, which is made the first time it's written to
+    fn define(&mut self, frame: &mut Frame<'a>, variable: &'a Variable) -> Result<Pointer> {
+        if let Some(pointer) = frame.pointer(variable) {
+            return Ok(pointer);
+        }
+        let origin = Origin::Variable {
+            function: frame.function,
+            variable: &variable.name,
+        };
+        let pointer = self.allocate(frame, variable, origin)?;
+        frame.bind(
+            variable,
+            pointer,
+            State::Unwritten,
+            !variable.witness.is_trivial(),
+        );
+        Ok(pointer)
+    }
+
+    /// storage that can hold a value of `variable`'s type until the frame returns
+    fn allocate(
+        &mut self,
+        frame: &mut Frame<'a>,
+        variable: &Variable,
+        origin: Origin<'a>,
+    ) -> Result<Pointer> {
+        let size = self.size(frame, variable)?;
+        let pointer = self.memory.allocate(size, Kind::Stack, origin);
+        frame.storage.push(pointer);
+        Ok(pointer)
+    }
+
+    fn size(&self, frame: &mut Frame<'a>, variable: &Variable) -> Result<usize> {
+        match variable.witness.as_ref() {
+            Witness::Trivial { size } => Ok(*size),
+            Witness::Dynamic { location } => Ok(self.table(frame, location)?.size),
+            Witness::Type => Ok(TABLE_SIZE),
+        }
+    }
+
+    /// the witness table held by `location`
+    fn table(&self, frame: &mut Frame<'a>, location: &Variable) -> Result<Table> {
+        self.memory.read_table(frame.read(location)?)
+    }
+
+    fn tables(&self, frame: &mut Frame<'a>, locations: &[Variable]) -> Result<Vec<Table>> {
+        locations
+            .iter()
+            .map(|location| self.table(frame, location))
+            .collect()
+    }
+
+    /// moves or copies `value` into `dest`
+    fn shift(&mut self, frame: &mut Frame<'a>, dest: Pointer, value: &'a Value) -> Result<()> {
+        let moved = matches!(value, Value::Move(_));
+        let variable = value.variable();
+        match variable.witness.as_ref() {
+            Witness::Trivial { size } => {
+                let src = frame.take(variable, moved)?;
+                self.memory.copy(dest, src, *size)
+            }
+            Witness::Dynamic { location } => {
+                let table = self.table(frame, location)?;
+                let src = frame.take(variable, moved)?;
+                let function = if moved { table.mov } else { table.copy };
+                self.call(function, &[dest, src, table.extra])
+            }
+            Witness::Type => {
+                let src = frame.take(variable, moved)?;
+                if moved {
+                    self.memory.copy(dest, src, TABLE_SIZE)
+                } else {
+                    self.copy_type(dest, src)
+                }
+            }
+        }
+    }
+
+    /// moves or copies `values` one after another into `dest`, starting `at` bytes in
+    fn pack(
+        &mut self,
+        frame: &mut Frame<'a>,
+        dest: Pointer,
+        values: impl IntoIterator<Item = &'a Value>,
+        mut at: usize,
+    ) -> Result<()> {
+        for value in values {
+            let size = self.size(frame, value.variable())?;
+            self.shift(frame, offset(dest, at), value)?;
+            at += size;
+        }
+        Ok(())
+    }
+
+    fn read_word(&self, frame: &mut Frame<'a>, variable: &Variable) -> Result<u64> {
+        let pointer = frame.read(variable)?;
+        self.memory.read_u64(pointer)
+    }
+
+    /// the storage passed for each argument of a call.
+    /// `in` arguments are shifted into fresh storage, which the callee owns the value in,
+    /// and the rest are the caller's own storage, which an `inout` argument has to hold a value in already
+    fn arguments(
+        &mut self,
+        frame: &mut Frame<'a>,
+        arguments: &'a [CallArgument],
+    ) -> Result<Vec<Pointer>> {
+        let mut pointers = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let variable = argument.value.variable();
+            if argument.convention == Convention::Out {
+                pointers.push(self.define(frame, variable)?);
+            } else if argument.convention == Convention::Inout {
+                pointers.push(frame.read(variable)?);
+            } else {
+                let origin = Origin::Argument {
+                    function: frame.function,
+                };
+                let pointer = self.allocate(frame, variable, origin)?;
+                self.shift(frame, pointer, &argument.value)?;
+                pointers.push(pointer);
+            }
+        }
+        Ok(pointers)
+    }
+
+    fn outputs_written(frame: &mut Frame<'a>, arguments: &'a [CallArgument]) -> Result<()> {
+        for argument in arguments {
+            if argument.convention == Convention::Out {
+                frame.written(argument.value.variable())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn instr(&mut self, frame: &mut Frame<'a>, instr: &'a Instr) -> Result<Flow<'a>> {
+        let target = &instr.target;
+        match &instr.value {
+            Expr::Destroy => {
+                self.destroy(frame, target)?;
+                return Ok(Flow::Next);
+            }
+            Expr::TailCall {
+                function,
+                arguments,
+            } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| frame.take(argument, true))
+                    .collect::<Result<_>>()?;
+                return Ok(Flow::Jump {
+                    function,
+                    arguments,
+                });
+            }
+            _ => {}
+        }
+        let var = self.define(frame, target)?;
+        match &instr.value {
+            Expr::Literal(literal) => self.memory.write_u64(var, literal_word(literal))?,
+            Expr::Primitive(primitive, arguments) => {
+                let words = arguments
+                    .iter()
+                    .map(|argument| self.read_word(frame, argument))
+                    .collect::<Result<Vec<_>>>()?;
+                let word = primitive_word(*primitive, &arguments[0].typ, &words)?;
+                self.memory.write_u64(var, word)?;
+            }
+            Expr::CallDirect {
+                function,
+                arguments,
+            } => {
+                let pointers = self.arguments(frame, arguments)?;
+                self.call_named(function, &pointers)?;
+                Self::outputs_written(frame, arguments)?;
+            }
+            Expr::CallIndirect { closure, arguments } => {
+                self.call_indirect(frame, closure, arguments)?;
+            }
+            Expr::Value(value) => self.shift(frame, var, value)?,
+            Expr::StructPack { arguments, .. } => {
+                self.pack(frame, var, arguments.iter().map(|field| &field.value), 0)?;
+            }
+            Expr::VariantPack {
+                variant, arguments, ..
+            } => {
+                self.memory.write_u64(var, *variant as u64)?;
+                self.pack(frame, var, arguments, WORD_SIZE)?;
+            }
+            Expr::UnpackVariant {
+                enum_name, value, ..
+            } if enum_name == RESULT => {
+                // error unions hold their value or error right after the tag
+                let value = frame.read(value)?;
+                match target.witness.as_ref() {
+                    Witness::Trivial { size } => {
+                        self.memory.copy(var, offset(value, WORD_SIZE), *size)?;
+                    }
+                    Witness::Dynamic { location } => {
+                        let table = self.table(frame, location)?;
+                        self.call(table.copy, &[var, offset(value, WORD_SIZE), table.extra])?;
+                    }
+                    Witness::Type => unreachable!("error unions don't hold types"),
+                }
+            }
+            Expr::Crash => return Err(Error::Thrown),
+            Expr::UnpackVariant {
+                enum_name,
+                variant,
+                field,
+                value,
+                offset: None,
+            } => {
+                let extra = match value.witness.as_ref() {
+                    Witness::Dynamic { location } => self.table(frame, location)?.extra,
+                    Witness::Trivial { .. } | Witness::Type => NULL,
+                };
+                let value = frame.read(value)?;
+                let enumeration = self.enumeration(enum_name)?;
+                self.unpack_variant(enumeration, *variant, *field, var, value, extra)?;
+            }
+            Expr::IsVariant { value, variant } => {
+                let tag = self.read_word(frame, value)?;
+                self.memory
+                    .write_u64(var, u64::from(tag == *variant as u64))?;
+            }
+            Expr::IsLiteral { value, literal } => {
+                let word = self.read_word(frame, value)?;
+                let equal = match literal {
+                    // literals are matched exactly, like the emitted C does
+                    #[allow(clippy::float_cmp)]
+                    Literal::Float(float) => f64::from_bits(word) == *float,
+                    Literal::Integer(_) | Literal::Boolean(_) => word == literal_word(literal),
+                };
+                self.memory.write_u64(var, u64::from(equal))?;
+            }
+            Expr::If {
+                predicate,
+                true_branch,
+                false_branch,
+            } => {
+                let branch = if self.read_word(frame, predicate)? == 0 {
+                    false_branch
+                } else {
+                    true_branch
+                };
+                let flow = self.block(frame, branch)?;
+                if let Flow::Jump { .. } = flow {
+                    return Ok(flow);
+                }
+            }
+            Expr::Unpack {
+                value,
+                offset: Some(at),
+                ..
+            }
+            | Expr::UnpackVariant {
+                value,
+                offset: Some(at),
+                ..
+            } => {
+                let size = self.size(frame, target)?;
+                let value = frame.read(value)?;
+                self.memory.copy(var, offset(value, *at), size)?;
+            }
+            Expr::Unpack {
+                struct_name,
+                value,
+                field,
+                offset: None,
+            } => {
+                let extra = match value.witness.as_ref() {
+                    Witness::Dynamic { location } => self.table(frame, location)?.extra,
+                    Witness::Trivial { .. } | Witness::Type => NULL,
+                };
+                let value = frame.read(value)?;
+                let strukt = self.strukt(struct_name)?;
+                self.unpack(strukt, field, var, value, extra)?;
+            }
+            Expr::TypeArgument { witness, index } => {
+                let extra = self.table(frame, witness)?.extra;
+                self.copy_type(var, type_argument(extra, *index))?;
+            }
+            Expr::MakeClosure {
+                function,
+                env,
+                witness,
+            } => self.make_closure(frame, var, function, env, witness)?,
+            Expr::ExistentialPack {
+                value,
+                witness,
+                dictionary,
+            } => {
+                self.shift(frame, var, witness)?;
+                let dictionary = self.read_word(frame, dictionary.variable())?;
+                self.memory.write_u64(offset(var, TABLE_SIZE), dictionary)?;
+                let size = self.memory.read_table(var)?.size;
+                let boxed = self.memory.allocate(size, Kind::Heap, Origin::Existential);
+                self.memory
+                    .write_u64(offset(var, TABLE_SIZE + POINTER_SIZE), boxed)?;
+                self.shift(frame, boxed, value)?;
+            }
+            Expr::Destroy | Expr::TailCall { .. } => unreachable!(),
+        }
+        frame.written(target)?;
+        Ok(Flow::Next)
+    }
+
+    /// calls the function stored in `closure`, passing the closure's environment and its witness table after `arguments`
+    fn call_indirect(
+        &mut self,
+        frame: &mut Frame<'a>,
+        closure: &Variable,
+        arguments: &'a [CallArgument],
+    ) -> Result<()> {
+        let mut pointers = self.arguments(frame, arguments)?;
+        let closure = frame.read(closure)?;
+        let function = self.memory.read_u64(closure)?;
+        let env = self.memory.read_u64(offset(closure, POINTER_SIZE))?;
+        // the callee owns its arguments, so it gets a fresh copy of both the env and its witness table
+        let origin = Origin::Argument {
+            function: frame.function,
+        };
+        let env_witness = self.memory.allocate(TABLE_SIZE, Kind::Stack, origin);
+        frame.storage.push(env_witness);
+        self.copy_type(env_witness, offset(env, WORD_SIZE))?;
+        let table = self.memory.read_table(env_witness)?;
+        let env_copy = self.memory.allocate(table.size, Kind::Stack, origin);
+        frame.storage.push(env_copy);
+        self.call(
+            table.copy,
+            &[env_copy, offset(env, ENV_HEADER), table.extra],
+        )?;
+        pointers.extend([env_copy, env_witness]);
+        self.call(function, &pointers)?;
+        Self::outputs_written(frame, arguments)
+    }
+
+    fn make_closure(
+        &mut self,
+        frame: &mut Frame<'a>,
+        var: Pointer,
+        function: &str,
+        env: &'a Value,
+        witness: &'a Value,
+    ) -> Result<()> {
+        let size = self.table(frame, witness.variable())?.size;
+        let allocation = self.memory.allocate(
+            ENV_HEADER + size,
+            Kind::Heap,
+            Origin::Closure {
+                function: frame.function,
+            },
+        );
+        self.memory.write_u64(allocation, 0)?;
+        self.shift(frame, offset(allocation, WORD_SIZE), witness)?;
+        self.shift(frame, offset(allocation, ENV_HEADER), env)?;
+        let function = self.function_pointer(Callable::Named(String::from(function)));
+        self.memory.write_u64(var, function)?;
+        self.memory.write_u64(offset(var, POINTER_SIZE), allocation)
+    }
+
+    fn destroy(&mut self, frame: &mut Frame<'a>, variable: &Variable) -> Result<()> {
+        match variable.witness.as_ref() {
+            Witness::Trivial { .. } => Ok(()),
+            Witness::Dynamic { location } => {
+                let table = self.table(frame, location)?;
+                let pointer = frame.destroy(variable)?;
+                self.call(table.destroy, &[pointer, table.extra])
+            }
+            Witness::Type => {
+                let pointer = frame.destroy(variable)?;
+                self.destroy_type(pointer)
+            }
+        }
+    }
+
+    fn copy_type(&mut self, dest: Pointer, src: Pointer) -> Result<()> {
+        let extra = self.memory.read_table(src)?.extra;
+        if extra != NULL {
+            let count = self.memory.read_u64(extra)?;
+            self.memory.write_u64(extra, count + 1)?;
+        }
+        self.memory.copy(dest, src, TABLE_SIZE)
+    }
+
+    fn destroy_type(&mut self, pointer: Pointer) -> Result<()> {
+        let extra = self.memory.read_table(pointer)?.extra;
+        if extra == NULL {
+            return Ok(());
+        }
+        let count = self.memory.read_u64(extra)?;
+        if count == 0 {
+            let arguments = self.memory.read_u64(offset(extra, WORD_SIZE))?;
+            for index in 0..usize::try_from(arguments).unwrap() {
+                self.destroy_type(type_argument(extra, index))?;
+            }
+            self.memory.free(extra)
+        } else {
+            self.memory.write_u64(extra, count - 1)
+        }
+    }
+
+    fn copy_closure(&mut self, dest: Pointer, src: Pointer) -> Result<()> {
+        let env = self.memory.read_u64(offset(src, POINTER_SIZE))?;
+        if env != NULL {
+            let count = self.memory.read_u64(env)?;
+            self.memory.write_u64(env, count + 1)?;
+        }
+        self.memory.copy(dest, src, CLOSURE_SIZE)
+    }
+
+    /// drops a reference to the closure's environment, and destroys the environment along with the last one
+    fn destroy_closure(&mut self, pointer: Pointer) -> Result<()> {
+        let env = self.memory.read_u64(offset(pointer, POINTER_SIZE))?;
+        if env == NULL {
+            return Ok(());
+        }
+        let count = self.memory.read_u64(env)?;
+        if count == 0 {
+            let table = self.memory.read_table(offset(env, WORD_SIZE))?;
+            self.call(table.destroy, &[offset(env, ENV_HEADER), table.extra])?;
+            self.destroy_type(offset(env, WORD_SIZE))?;
+            self.memory.free(env)
+        } else {
+            self.memory.write_u64(env, count - 1)
+        }
+    }
+
+    fn copy_existential(&mut self, dest: Pointer, src: Pointer) -> Result<()> {
+        self.copy_type(dest, src)?;
+        let dictionary = self.memory.read_u64(offset(src, TABLE_SIZE))?;
+        self.memory
+            .write_u64(offset(dest, TABLE_SIZE), dictionary)?;
+        let table = self.memory.read_table(src)?;
+        let boxed = self
+            .memory
+            .allocate(table.size, Kind::Heap, Origin::Existential);
+        self.memory
+            .write_u64(offset(dest, TABLE_SIZE + POINTER_SIZE), boxed)?;
+        let value = self
+            .memory
+            .read_u64(offset(src, TABLE_SIZE + POINTER_SIZE))?;
+        self.call(table.copy, &[boxed, value, table.extra])
+    }
+
+    fn destroy_existential(&mut self, pointer: Pointer) -> Result<()> {
+        let table = self.memory.read_table(pointer)?;
+        let value = self
+            .memory
+            .read_u64(offset(pointer, TABLE_SIZE + POINTER_SIZE))?;
+        self.call(table.destroy, &[value, table.extra])?;
+        self.memory.free(value)?;
+        self.destroy_type(pointer)
+    }
+
+    fn builtin(&mut self, builtin: Builtin) -> Table {
+        let (size, copy, destroy) = match builtin {
+            Builtin::Word => (
+                WORD_SIZE,
+                Callable::Memmove(WORD_SIZE),
+                Callable::DestroyNothing,
+            ),
+            Builtin::Closure => (
+                CLOSURE_SIZE,
+                Callable::CopyClosure,
+                Callable::DestroyClosure,
+            ),
+            Builtin::Existential => (
+                EXISTENTIAL_SIZE,
+                Callable::CopyExistential,
+                Callable::DestroyExistential,
+            ),
+        };
+        Table {
+            size,
+            mov: self.function_pointer(Callable::Memmove(size)),
+            copy: self.function_pointer(copy),
+            destroy: self.function_pointer(destroy),
+            extra: NULL,
+        }
+    }
+
+    /// the dictionary of `typ`'s implementation of `protocol`, which is made the first time it's asked for
+    fn dictionary(&mut self, protocol: &'a Protocol, typ: &'a str) -> Pointer {
+        let name = dictionary_builder_name(&protocol.name, typ);
+        if let Some(dictionary) = self.dictionaries.get(&name) {
+            return *dictionary;
+        }
+        let dictionary = self.memory.allocate(
+            protocol.functions.len() * POINTER_SIZE,
+            Kind::Static,
+            Origin::Dictionary {
+                protocol: &protocol.name,
+                typ,
+            },
+        );
+        for (index, function) in protocol.functions.iter().enumerate() {
+            let implementation = impl_function_name(&protocol.name, typ, &function.name);
+            let pointer = self.function_pointer(Callable::Named(implementation));
+            self.memory
+                .write_u64(offset(dictionary, index * POINTER_SIZE), pointer)
+                .unwrap();
+        }
+        self.dictionaries.insert(name, dictionary);
+        dictionary
+    }
+
+    /// runs the block of a builder, which makes the witness tables of the type's fields
+    fn builder_block(
+        &mut self,
+        name: &'a str,
+        block: &'a Block,
+        arguments: impl IntoIterator<Item = (&'a Variable, Pointer)>,
+    ) -> Result<Frame<'a>> {
+        let mut frame = Frame::new(name, self.layout, block);
+        for (argument, pointer) in arguments {
+            frame.bind(argument, pointer, State::Live, false);
+        }
+        self.block(&mut frame, block)?;
+        Ok(frame)
+    }
+
+    /// cleans up the witness tables made by a builder block
+    fn builder_postamble(&mut self, mut frame: Frame<'a>, block: &'a Block) -> Result<()> {
+        for instr in &block.instrs {
+            if matches!(instr.target.witness.as_ref(), Witness::Type) {
+                let pointer = frame.read(&instr.target)?;
+                self.destroy_type(pointer)?;
+            }
+        }
+        self.leave(frame, false)
+    }
+
+    /// runs a struct's or an enum's builder block, with its type arguments pointing into the `extra` field of its witness table
+    fn builder_preamble(
+        &mut self,
+        name: &'a str,
+        arguments: &'a [BuilderArgument],
+        block: &'a Block,
+        extra: Pointer,
+    ) -> Result<Frame<'a>> {
+        let arguments = arguments[1..]
+            .iter()
+            .enumerate()
+            .map(|(index, argument)| (&argument.name, type_argument(extra, index)));
+        self.builder_block(name, block, arguments)
+    }
+
+    fn struct_preamble(&mut self, strukt: &'a Struct, extra: Pointer) -> Result<Frame<'a>> {
+        self.builder_preamble(
+            &strukt.definition.name,
+            &strukt.builder.arguments,
+            &strukt.builder.block,
+            extra,
+        )
+    }
+
+    fn enum_preamble(&mut self, enumeration: &'a Enum, extra: Pointer) -> Result<Frame<'a>> {
+        self.builder_preamble(
+            &enumeration.definition.name,
+            &enumeration.builder.arguments,
+            &enumeration.builder.block,
+            extra,
+        )
+    }
+
+    /// moves the type arguments of a builder into a new `extra` field for the table it builds, which owns them from then on
+    fn store_type_arguments(
+        &mut self,
+        name: &'a str,
+        type_arguments: &[Pointer],
+    ) -> Result<Pointer> {
+        if type_arguments.is_empty() {
+            return Ok(NULL);
+        }
+        let extra = self.memory.allocate(
+            TYPE_ARGUMENTS_HEADER + type_arguments.len() * TABLE_SIZE,
+            Kind::Heap,
+            Origin::TypeArguments { name },
+        );
+        self.memory.write_u64(extra, 0)?;
+        self.memory
+            .write_u64(offset(extra, WORD_SIZE), type_arguments.len() as u64)?;
+        for (index, argument) in type_arguments.iter().enumerate() {
+            self.memory
+                .copy(type_argument(extra, index), *argument, TABLE_SIZE)?;
+        }
+        Ok(extra)
+    }
+
+    fn build_struct(&mut self, strukt: &'a Struct, arguments: &[Pointer]) -> Result<()> {
+        let name = &strukt.definition.name;
+        let type_arguments = &arguments[1..];
+        let frame = self.builder_block(
+            name,
+            &strukt.builder.block,
+            strukt.builder.arguments[1..]
+                .iter()
+                .map(|argument| &argument.name)
+                .zip(type_arguments.iter().copied()),
+        )?;
+        let mut frame = frame;
+        let tables = self.tables(&mut frame, &strukt.builder.fields)?;
+        let extra = self.store_type_arguments(name, type_arguments)?;
+        let table = Table {
+            size: tables.iter().map(|table| table.size).sum(),
+            mov: self.function_pointer(Callable::Struct(Operation::Move, name.clone())),
+            copy: self.function_pointer(Callable::Struct(Operation::Copy, name.clone())),
+            destroy: self.function_pointer(Callable::Struct(Operation::Destroy, name.clone())),
+            extra,
+        };
+        self.memory.write_table(arguments[0], table)?;
+        self.builder_postamble(frame, &strukt.builder.block)
+    }
+
+    /// applies `operation` to the field at `at`, given the arguments of a move, copy or destroy function
+    fn field_operation(
+        &mut self,
+        operation: Operation,
+        table: Table,
+        arguments: &[Pointer],
+        at: usize,
+    ) -> Result<()> {
+        match operation {
+            Operation::Move => self.call(
+                table.mov,
+                &[
+                    offset(arguments[0], at),
+                    offset(arguments[1], at),
+                    table.extra,
+                ],
+            ),
+            Operation::Copy => self.call(
+                table.copy,
+                &[
+                    offset(arguments[0], at),
+                    offset(arguments[1], at),
+                    table.extra,
+                ],
+            ),
+            Operation::Destroy => {
+                self.call(table.destroy, &[offset(arguments[0], at), table.extra])
+            }
+        }
+    }
+
+    fn struct_operation(
+        &mut self,
+        strukt: &'a Struct,
+        operation: Operation,
+        arguments: &[Pointer],
+    ) -> Result<()> {
+        let extra = *arguments.last().unwrap();
+        let mut frame = self.struct_preamble(strukt, extra)?;
+        let mut at = 0;
+        for table in self.tables(&mut frame, &strukt.builder.fields)? {
+            self.field_operation(operation, table, arguments, at)?;
+            at += table.size;
+        }
+        self.builder_postamble(frame, &strukt.builder.block)
+    }
+
+    fn unpack(
+        &mut self,
+        strukt: &'a Struct,
+        field: &str,
+        dest: Pointer,
+        src: Pointer,
+        extra: Pointer,
+    ) -> Result<()> {
+        let index = strukt
+            .definition
+            .fields
+            .iter()
+            .position(|candidate| candidate.name == field)
+            .expect("unpacking a field that the struct doesn't have");
+        let mut frame = self.struct_preamble(strukt, extra)?;
+        let tables = self.tables(&mut frame, &strukt.builder.fields)?;
+        let at = tables[..index].iter().map(|table| table.size).sum();
+        let table = tables[index];
+        self.call(table.copy, &[dest, offset(src, at), table.extra])?;
+        self.builder_postamble(frame, &strukt.builder.block)
+    }
+
+    fn build_enum(&mut self, enumeration: &'a Enum, arguments: &[Pointer]) -> Result<()> {
+        let name = &enumeration.definition.name;
+        let type_arguments = &arguments[1..];
+        let mut frame = self.builder_block(
+            name,
+            &enumeration.builder.block,
+            enumeration.builder.arguments[1..]
+                .iter()
+                .map(|argument| &argument.name)
+                .zip(type_arguments.iter().copied()),
+        )?;
+        // the payload is big enough to hold the largest variant
+        let mut payload = 0;
+        for fields in &enumeration.builder.variants {
+            let size = self
+                .tables(&mut frame, fields)?
+                .iter()
+                .map(|table| table.size)
+                .sum();
+            payload = usize::max(payload, size);
+        }
+        let extra = self.store_type_arguments(name, type_arguments)?;
+        let table = Table {
+            size: WORD_SIZE + payload,
+            mov: self.function_pointer(Callable::Enum(Operation::Move, name.clone())),
+            copy: self.function_pointer(Callable::Enum(Operation::Copy, name.clone())),
+            destroy: self.function_pointer(Callable::Enum(Operation::Destroy, name.clone())),
+            extra,
+        };
+        self.memory.write_table(arguments[0], table)?;
+        self.builder_postamble(frame, &enumeration.builder.block)
+    }
+
+    /// applies `operation` to every field of the variant held by the enum, which is laid out as an 8 byte tag followed by those fields
+    fn enum_operation(
+        &mut self,
+        enumeration: &'a Enum,
+        operation: Operation,
+        arguments: &[Pointer],
+    ) -> Result<()> {
+        let value = if operation == Operation::Destroy {
+            arguments[0]
+        } else {
+            self.memory.copy(arguments[0], arguments[1], WORD_SIZE)?;
+            arguments[1]
+        };
+        let tag = self.memory.read_u64(value)?;
+        let mut frame = self.enum_preamble(enumeration, *arguments.last().unwrap())?;
+        if let Some(fields) = enumeration
+            .builder
+            .variants
+            .get(usize::try_from(tag).unwrap())
+        {
+            let mut at = WORD_SIZE;
+            for table in self.tables(&mut frame, fields)? {
+                self.field_operation(operation, table, arguments, at)?;
+                at += table.size;
+            }
+        }
+        self.builder_postamble(frame, &enumeration.builder.block)
+    }
+
+    /// error unions keep the witness tables of their value and their error in their table's `extra` field, like generic structs
+    fn build_result(&mut self, arguments: &[Pointer]) -> Result<()> {
+        let extra = self.memory.allocate(
+            TYPE_ARGUMENTS_HEADER + 2 * TABLE_SIZE,
+            Kind::Heap,
+            Origin::TypeArguments { name: RESULT },
+        );
+        self.memory.write_u64(extra, 0)?;
+        self.memory.write_u64(offset(extra, WORD_SIZE), 2)?;
+        let mut payload = 0;
+        for (index, argument) in arguments[1..].iter().enumerate() {
+            payload = usize::max(payload, self.memory.read_table(*argument)?.size);
+            self.memory
+                .copy(type_argument(extra, index), *argument, TABLE_SIZE)?;
+        }
+        let table = Table {
+            size: WORD_SIZE + payload,
+            mov: self.function_pointer(Callable::Result(Operation::Move)),
+            copy: self.function_pointer(Callable::Result(Operation::Copy)),
+            destroy: self.function_pointer(Callable::Result(Operation::Destroy)),
+            extra,
+        };
+        self.memory.write_table(arguments[0], table)
+    }
+
+    /// applies `operation` to whichever of the value and the error the error union holds, which is its tag's type argument
+    fn result_operation(&mut self, operation: Operation, arguments: &[Pointer]) -> Result<()> {
+        let extra = *arguments.last().unwrap();
+        let value = if operation == Operation::Destroy {
+            arguments[0]
+        } else {
+            self.memory.copy(arguments[0], arguments[1], WORD_SIZE)?;
+            arguments[1]
+        };
+        let tag = self.memory.read_u64(value)?;
+        let table = self
+            .memory
+            .read_table(type_argument(extra, usize::try_from(tag).unwrap()))?;
+        self.field_operation(operation, table, arguments, WORD_SIZE)
+    }
+
+    fn unpack_variant(
+        &mut self,
+        enumeration: &'a Enum,
+        variant: usize,
+        field: usize,
+        dest: Pointer,
+        src: Pointer,
+        extra: Pointer,
+    ) -> Result<()> {
+        let mut frame = self.enum_preamble(enumeration, extra)?;
+        let tables = self.tables(&mut frame, &enumeration.builder.variants[variant])?;
+        let at = WORD_SIZE
+            + tables[..field]
+                .iter()
+                .map(|table| table.size)
+                .sum::<usize>();
+        let table = tables[field];
+        self.call(table.copy, &[dest, offset(src, at), table.extra])?;
+        self.builder_postamble(frame, &enumeration.builder.block)
+    }
+}
+
+/// where the `index`th type argument of a generic witness table lives, given its `extra` field
+const fn type_argument(extra: Pointer, index: usize) -> Pointer {
+    offset(extra, TYPE_ARGUMENTS_HEADER + index * TABLE_SIZE)
+}
+
+/// how a literal is laid out in memory. `Bool`s take up a whole word, like integers
+fn literal_word(literal: &Literal) -> u64 {
+    match literal {
+        Literal::Float(float) => float.to_bits(),
+        Literal::Integer(integer) => *integer as u64,
+        Literal::Boolean(boolean) => u64::from(*boolean),
+    }
+}
+
+/// applies `primitive` to the words of its arguments, which are of type `typ`.
+/// integer arithmetic wraps around, and dividing an integer by zero stops the program
+fn primitive_word(primitive: Primitive, typ: &Type, words: &[u64]) -> Result<u64> {
+    let float = *typ.canonical() == Type::float();
+    let floats = |index: usize| f64::from_bits(words[index]);
+    let integers = |index: usize| words[index] as i64;
+    // comparisons with NaN are all false, except for `!=`
+    let compare = |holds: fn(Ordering) -> bool| {
+        let ordering = if float {
+            floats(0).partial_cmp(&floats(1))
+        } else {
+            Some(integers(0).cmp(&integers(1)))
+        };
+        u64::from(ordering.is_some_and(holds))
+    };
+    Ok(match primitive {
+        Primitive::Add if float => (floats(0) + floats(1)).to_bits(),
+        Primitive::Add => words[0].wrapping_add(words[1]),
+        Primitive::Sub if float => (floats(0) - floats(1)).to_bits(),
+        Primitive::Sub => words[0].wrapping_sub(words[1]),
+        Primitive::Mul if float => (floats(0) * floats(1)).to_bits(),
+        Primitive::Mul => words[0].wrapping_mul(words[1]),
+        Primitive::Div if float => (floats(0) / floats(1)).to_bits(),
+        Primitive::Rem if float => (floats(0) % floats(1)).to_bits(),
+        Primitive::Div | Primitive::Rem if integers(1) == 0 => return Err(Error::DivisionByZero),
+        Primitive::Div => integers(0).wrapping_div(integers(1)) as u64,
+        Primitive::Rem => integers(0).wrapping_rem(integers(1)) as u64,
+        Primitive::Eq => compare(Ordering::is_eq),
+        Primitive::NotEq => 1 - compare(Ordering::is_eq),
+        Primitive::Less => compare(Ordering::is_lt),
+        Primitive::LessEq => compare(Ordering::is_le),
+        Primitive::Greater => compare(Ordering::is_gt),
+        Primitive::GreaterEq => compare(Ordering::is_ge),
+        Primitive::Neg if float => (-floats(0)).to_bits(),
+        Primitive::Neg => 0u64.wrapping_sub(words[0]),
+        Primitive::Not => u64::from(words[0] == 0),
+        Primitive::ToFloat => (integers(0) as f64).to_bits(),
+        // `as` saturates at the bounds, and turns NaN into 0
+        Primitive::ToInteger => floats(0) as i64 as u64,
+        Primitive::And | Primitive::Or => {
+            unreachable!("{primitive} is lowered into an if so that it short-circuits")
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use ir::bridge::{
+        Argument, Block, CallArgument, Convention, Expr, Function, Instr, Program, Value, Variable,
+        Witness,
+    };
+    use tree::sized::{Literal, Type};
+    use tree::String;
+
+    use crate::error::{Error, Leak};
+
+    fn table(name: &str) -> Variable {
+        Variable::new(String::from(name), Type::typ(), Witness::Type)
+    }
+
+    /// a program whose `main` builds the witness table of `F64` in `_0`, runs `instrs`, and returns 1.5
+    fn program(instrs: Vec<Instr>) -> Program {
+        let result = Variable::new(String::from("_result"), Type::float(), Witness::trivial(8));
+        let mut body = vec![Instr::new(
+            table("_0"),
+            Expr::CallDirect {
+                function: String::from("F64"),
+                arguments: vec![CallArgument {
+                    value: Value::Copy(table("_0")),
+                    convention: Convention::Out,
+                }],
+            },
+        )];
+        body.extend(instrs);
+        body.push(Instr::new(
+            result.clone(),
+            Expr::Literal(Literal::Float(1.5)),
+        ));
+        Program {
+            structs: Vec::new(),
+            enums: Vec::new(),
+            protocols: Vec::new(),
+            impls: Vec::new(),
+            functions: vec![Function {
+                name: String::from("main"),
+                arguments: vec![Argument {
+                    name: result,
+                    convention: Convention::Out,
+                }],
+                body: Block { instrs: body },
+                names: ir::name_source::NameSource::default(),
+            }],
+        }
+    }
+
+    fn run(instrs: Vec<Instr>) -> crate::Result<(f64, Vec<Leak>)> {
+        let run = super::run(&program(instrs), "main", 8)?;
+        let result = f64::from_le_bytes(run.result.try_into().unwrap());
+        Ok((result, run.leaks))
+    }
+
+    #[test]
+    fn destroyed_once() {
+        let (result, leaks) = run(vec![
+            Instr::new(table("_1"), Expr::copy(table("_0"))),
+            Instr::new(table("_0"), Expr::Destroy),
+            Instr::new(table("_1"), Expr::Destroy),
+        ])
+        .unwrap();
+        assert!((result - 1.5).abs() < f64::EPSILON);
+        assert!(leaks.is_empty());
+    }
+
+    #[test]
+    fn leak() {
+        let (_, leaks) = run(vec![Instr::new(table("_1"), Expr::mov(table("_0")))]).unwrap();
+        assert_eq!(
+            leaks,
+            vec![Leak::Variable {
+                function: String::from("main"),
+                variable: String::from("_1"),
+            }]
+        );
+    }
+
+    #[test]
+    fn use_after_move() {
+        let error = run(vec![
+            Instr::new(table("_1"), Expr::mov(table("_0"))),
+            Instr::new(table("_2"), Expr::copy(table("_0"))),
+        ])
+        .err();
+        assert_eq!(
+            error,
+            Some(Error::UseAfterMove {
+                function: String::from("main"),
+                variable: String::from("_0"),
+            })
+        );
+    }
+
+    #[test]
+    fn double_destroy() {
+        let error = run(vec![
+            Instr::new(table("_0"), Expr::Destroy),
+            Instr::new(table("_0"), Expr::Destroy),
+        ])
+        .err();
+        assert_eq!(
+            error,
+            Some(Error::DoubleDestroy {
+                function: String::from("main"),
+                variable: String::from("_0"),
+            })
+        );
+    }
+}
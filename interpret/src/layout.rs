@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+
+use ir::bridge::{Block, Expr, Program, Value, Variable, Witness};
+
+/// hashes addresses, which only need their bits spread out
+#[derive(Default)]
+pub struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only addresses are hashed")
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        self.0 = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+type ByAddress<V> = HashMap<usize, V, BuildHasherDefault<AddressHasher>>;
+
+/// where each variable lives in the frame of the function or builder block that uses it.
+/// variables are looked up by their address in the program, so that running an instruction doesn't hash any names
+#[derive(Default)]
+pub struct Layout {
+    slots: ByAddress<usize>,
+    /// the number of slots that the frame of each body needs, by the address of the body
+    sizes: ByAddress<usize>,
+}
+
+impl Layout {
+    pub fn new(program: &Program) -> Self {
+        let mut layout = Self::default();
+        for function in &program.functions {
+            let mut names = HashMap::new();
+            for argument in &function.arguments {
+                layout.variable(&argument.name, &mut names);
+            }
+            layout.block(&function.body, &mut names);
+            layout.sizes.insert(address(&function.body), names.len());
+        }
+        for strukt in &program.structs {
+            let builder = &strukt.builder;
+            let mut names = HashMap::new();
+            for argument in &builder.arguments {
+                layout.variable(&argument.name, &mut names);
+            }
+            layout.block(&builder.block, &mut names);
+            for field in &builder.fields {
+                layout.variable(field, &mut names);
+            }
+            layout.sizes.insert(address(&builder.block), names.len());
+        }
+        for enumeration in &program.enums {
+            let builder = &enumeration.builder;
+            let mut names = HashMap::new();
+            for argument in &builder.arguments {
+                layout.variable(&argument.name, &mut names);
+            }
+            layout.block(&builder.block, &mut names);
+            for field in builder.variants.iter().flatten() {
+                layout.variable(field, &mut names);
+            }
+            layout.sizes.insert(address(&builder.block), names.len());
+        }
+        layout
+    }
+
+    /// the slot of `variable` in its frame
+    pub fn slot(&self, variable: &Variable) -> usize {
+        self.slots[&address(variable)]
+    }
+
+    /// the number of slots in the frame that runs `body`
+    pub fn size(&self, body: &Block) -> usize {
+        self.sizes[&address(body)]
+    }
+
+    fn variable<'a>(&mut self, variable: &'a Variable, names: &mut HashMap<&'a str, usize>) {
+        let next = names.len();
+        let slot = *names.entry(&variable.name).or_insert(next);
+        self.slots.insert(address(variable), slot);
+        if let Witness::Dynamic { location } = variable.witness.as_ref() {
+            self.variable(location, names);
+        }
+    }
+
+    fn values<'a>(
+        &mut self,
+        values: impl IntoIterator<Item = &'a Value>,
+        names: &mut HashMap<&'a str, usize>,
+    ) {
+        for value in values {
+            self.variable(value.variable(), names);
+        }
+    }
+
+    fn block<'a>(&mut self, block: &'a Block, names: &mut HashMap<&'a str, usize>) {
+        for instr in &block.instrs {
+            self.variable(&instr.target, names);
+            match &instr.value {
+                Expr::Literal(_) | Expr::Destroy | Expr::Crash => {}
+                Expr::Primitive(_, arguments) | Expr::TailCall { arguments, .. } => {
+                    for argument in arguments {
+                        self.variable(argument, names);
+                    }
+                }
+                Expr::CallDirect { arguments, .. } => {
+                    self.values(arguments.iter().map(|argument| &argument.value), names);
+                }
+                Expr::CallIndirect { closure, arguments } => {
+                    self.variable(closure, names);
+                    self.values(arguments.iter().map(|argument| &argument.value), names);
+                }
+                Expr::Value(value) => self.values([value], names),
+                Expr::StructPack { arguments, .. } => {
+                    self.values(arguments.iter().map(|field| &field.value), names);
+                }
+                Expr::VariantPack { arguments, .. } => self.values(arguments, names),
+                Expr::Unpack { value, .. }
+                | Expr::UnpackVariant { value, .. }
+                | Expr::IsVariant { value, .. }
+                | Expr::IsLiteral { value, .. }
+                | Expr::TypeArgument { witness: value, .. } => self.variable(value, names),
+                Expr::If {
+                    predicate,
+                    true_branch,
+                    false_branch,
+                } => {
+                    self.variable(predicate, names);
+                    self.block(true_branch, names);
+                    self.block(false_branch, names);
+                }
+                Expr::MakeClosure { env, witness, .. } => self.values([env, witness], names),
+                Expr::ExistentialPack {
+                    value,
+                    witness,
+                    dictionary,
+                } => self.values([value, witness, dictionary], names),
+            }
+        }
+    }
+}
+
+fn address<T>(value: &T) -> usize {
+    std::ptr::from_ref(value) as usize
+}
@@ -0,0 +1,19 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+#![allow(
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::too_many_lines,
+    // values are reinterpreted between integers and words the way the emitted C does
+    clippy::cast_possible_truncation,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+
+mod error;
+mod interpreter;
+mod layout;
+mod memory;
+
+pub use error::{Error, Leak, Result};
+pub use interpreter::{run, Run};
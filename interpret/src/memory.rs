@@ -0,0 +1,301 @@
+use core::fmt;
+
+use tree::String;
+
+use crate::error::{Error, Leak, Result};
+
+/// an address in the interpreter's memory, laid out as `[allocation: 23 bits, generation: 8 bits, offset: 32 bits]` below the top bit.
+/// function pointers set the top bit, and hold the index of the function instead
+pub type Pointer = u64;
+
+pub const NULL: Pointer = 0;
+pub const POINTER_SIZE: usize = 8;
+/// the size of a `_witness`: `size`, `move`, `copy`, `destroy` and `extra`
+pub const TABLE_SIZE: usize = 40;
+
+const FUNCTION_BIT: u64 = 1 << 63;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// released when the frame that made it returns
+    Stack,
+    /// released by `free`, and leaked otherwise
+    Heap,
+    /// never released
+    Static,
+}
+
+/// what an allocation was made for, so that errors can point at it
+#[derive(Copy, Clone)]
+pub enum Origin<'a> {
+    Variable {
+        function: &'a str,
+        variable: &'a str,
+    },
+    Argument {
+        function: &'a str,
+    },
+    Closure {
+        function: &'a str,
+    },
+    Existential,
+    TypeArguments {
+        name: &'a str,
+    },
+    Dictionary {
+        protocol: &'a str,
+        typ: &'a str,
+    },
+    Result,
+}
+
+struct Allocation<'a> {
+    bytes: Vec<u8>,
+    /// bumped each time the slot is reused, so that pointers into an old allocation are caught
+    generation: u8,
+    live: bool,
+    kind: Kind,
+    origin: Origin<'a>,
+}
+
+#[derive(Default)]
+pub struct Memory<'a> {
+    allocations: Vec<Allocation<'a>>,
+    unused: Vec<usize>,
+}
+
+/// a witness table, as laid out in memory
+#[derive(Copy, Clone)]
+pub struct Table {
+    pub size: usize,
+    pub mov: Pointer,
+    pub copy: Pointer,
+    pub destroy: Pointer,
+    pub extra: Pointer,
+}
+
+impl<'a> Memory<'a> {
+    pub fn allocate(&mut self, size: usize, kind: Kind, origin: Origin<'a>) -> Pointer {
+        let index = if let Some(index) = self.unused.pop() {
+            let allocation = &mut self.allocations[index];
+            allocation.bytes.clear();
+            allocation.bytes.resize(size, 0);
+            allocation.generation = allocation.generation.wrapping_add(1);
+            allocation.live = true;
+            allocation.kind = kind;
+            allocation.origin = origin;
+            index
+        } else {
+            self.allocations.push(Allocation {
+                bytes: vec![0; size],
+                generation: 0,
+                live: true,
+                kind,
+                origin,
+            });
+            self.allocations.len() - 1
+        };
+        let generation = self.allocations[index].generation;
+        ((index as u64 + 1) << 40) | (u64::from(generation) << 32)
+    }
+
+    /// releases a heap allocation, like `free` would
+    pub fn free(&mut self, pointer: Pointer) -> Result<()> {
+        if pointer == NULL {
+            return Ok(());
+        }
+        let (index, offset) = self.find(pointer)?;
+        let allocation = &self.allocations[index];
+        if !allocation.live {
+            return Err(Error::DoubleFree {
+                allocation: allocation.origin.to_string().into(),
+            });
+        }
+        assert!(
+            offset == 0 && allocation.kind == Kind::Heap,
+            "only the start of a heap allocation can be freed"
+        );
+        self.release(index);
+        Ok(())
+    }
+
+    /// releases the storage of a frame, which may already have been freed if it was on the heap
+    pub fn pop(&mut self, pointer: Pointer) -> Result<()> {
+        let (index, _) = self.find(pointer)?;
+        if self.allocations[index].live {
+            self.release(index);
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, index: usize) {
+        let allocation = &mut self.allocations[index];
+        allocation.live = false;
+        // the buffer is kept around for the next allocation that reuses the slot
+        allocation.bytes.clear();
+        self.unused.push(index);
+    }
+
+    /// the heap allocations that are still alive
+    pub fn leaks(&self) -> Vec<Leak> {
+        self.allocations
+            .iter()
+            .filter(|allocation| allocation.live && allocation.kind == Kind::Heap)
+            .map(|allocation| Leak::Allocation {
+                allocation: allocation.origin.to_string().into(),
+            })
+            .collect()
+    }
+
+    /// the allocation that `pointer` points into, and how far into it
+    fn find(&self, pointer: Pointer) -> Result<(usize, usize)> {
+        if pointer & FUNCTION_BIT != 0 {
+            return Err(Error::BadFunctionPointer);
+        }
+        // offsetting a null pointer, like a type argument of a table without any, still points into no allocation
+        let Some(index) = (pointer >> 40).checked_sub(1) else {
+            return Err(Error::NullPointer);
+        };
+        let index = usize::try_from(index).unwrap();
+        let generation = ((pointer >> 32) & 0xff) as u8;
+        let offset = usize::try_from(pointer & 0xffff_ffff).unwrap();
+        let allocation = self.allocations.get(index).ok_or(Error::NullPointer)?;
+        if allocation.generation != generation {
+            // the slot has been reused since the pointer was made
+            return Err(Error::UseAfterFree {
+                allocation: String::from("an allocation"),
+            });
+        }
+        Ok((index, offset))
+    }
+
+    pub fn read(&self, pointer: Pointer, size: usize) -> Result<&[u8]> {
+        let (index, offset) = self.find(pointer)?;
+        let allocation = &self.allocations[index];
+        if !allocation.live {
+            return Err(Error::UseAfterFree {
+                allocation: allocation.origin.to_string().into(),
+            });
+        }
+        allocation
+            .bytes
+            .get(offset..offset + size)
+            .ok_or_else(|| Error::OutOfBounds {
+                allocation: allocation.origin.to_string().into(),
+            })
+    }
+
+    pub fn write(&mut self, pointer: Pointer, bytes: &[u8]) -> Result<()> {
+        let (index, offset) = self.find(pointer)?;
+        let allocation = &mut self.allocations[index];
+        if !allocation.live {
+            return Err(Error::UseAfterFree {
+                allocation: allocation.origin.to_string().into(),
+            });
+        }
+        let origin = &allocation.origin;
+        allocation
+            .bytes
+            .get_mut(offset..offset + bytes.len())
+            .ok_or_else(|| Error::OutOfBounds {
+                allocation: origin.to_string().into(),
+            })?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// copies `size` bytes from `src` to `dest`, like `memmove` would
+    pub fn copy(&mut self, dest: Pointer, src: Pointer, size: usize) -> Result<()> {
+        // both ends are checked before anything is written
+        self.read(src, size)?;
+        self.read(dest, size)?;
+        let (from, at) = self.find(src)?;
+        let (to, to_at) = self.find(dest)?;
+        if from == to {
+            self.allocations[from]
+                .bytes
+                .copy_within(at..at + size, to_at);
+        } else {
+            let (low, high) = self.allocations.split_at_mut(from.max(to));
+            let (source, target) = if from < to {
+                (&low[from], &mut high[0])
+            } else {
+                (&high[0], &mut low[to])
+            };
+            target.bytes[to_at..to_at + size].copy_from_slice(&source.bytes[at..at + size]);
+        }
+        Ok(())
+    }
+
+    pub fn read_u64(&self, pointer: Pointer) -> Result<u64> {
+        let bytes = self.read(pointer, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn write_u64(&mut self, pointer: Pointer, value: u64) -> Result<()> {
+        self.write(pointer, &value.to_le_bytes())
+    }
+
+    pub fn read_table(&self, pointer: Pointer) -> Result<Table> {
+        Ok(Table {
+            size: usize::try_from(self.read_u64(pointer)?).unwrap(),
+            mov: self.read_u64(offset(pointer, 8))?,
+            copy: self.read_u64(offset(pointer, 16))?,
+            destroy: self.read_u64(offset(pointer, 24))?,
+            extra: self.read_u64(offset(pointer, 32))?,
+        })
+    }
+
+    pub fn write_table(&mut self, pointer: Pointer, table: Table) -> Result<()> {
+        self.write_u64(pointer, table.size as u64)?;
+        self.write_u64(offset(pointer, 8), table.mov)?;
+        self.write_u64(offset(pointer, 16), table.copy)?;
+        self.write_u64(offset(pointer, 24), table.destroy)?;
+        self.write_u64(offset(pointer, 32), table.extra)
+    }
+}
+
+/// the pointer `by` bytes past `pointer`
+pub const fn offset(pointer: Pointer, by: usize) -> Pointer {
+    pointer + by as u64
+}
+
+pub const fn function_pointer(index: usize) -> Pointer {
+    FUNCTION_BIT | index as u64
+}
+
+/// the index of the function that `pointer` points to
+pub fn function_index(pointer: Pointer) -> Result<usize> {
+    if pointer & FUNCTION_BIT == 0 {
+        Err(Error::BadFunctionPointer)
+    } else {
+        Ok(usize::try_from(pointer & !FUNCTION_BIT).unwrap())
+    }
+}
+
+impl fmt::Display for Origin<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Variable { function, variable } => {
+                write!(f, "the storage of `{variable}` in `{function}`")
+            }
+            Self::Argument { function } => {
+                write!(f, "an argument passed by `{function}`")
+            }
+            Self::Closure { function } => {
+                write!(f, "the environment of a closure made in `{function}`")
+            }
+            Self::Existential => write!(f, "the boxed value of an existential"),
+            Self::TypeArguments { name } => {
+                write!(f, "the type arguments of a witness table for `{name}`")
+            }
+            Self::Dictionary { protocol, typ } => {
+                write!(
+                    f,
+                    "the dictionary of `{typ}`'s implementation of `{protocol}`"
+                )
+            }
+            Self::Result => write!(f, "the result of the program"),
+        }
+    }
+}
@@ -9,9 +9,19 @@ use tree::String;
 #[derive(Clone)]
 pub struct Program {
     pub structs: Vec<Struct>,
+    pub enums: Vec<Enum>,
+    pub protocols: Vec<sized::Protocol>,
+    pub impls: Vec<Implementation>,
     pub functions: Vec<Function>,
 }
 
+/// a type's implementation of a protocol. its functions are lowered alongside all the others, under mangled names
+#[derive(Clone)]
+pub struct Implementation {
+    pub protocol: String,
+    pub typ: String,
+}
+
 #[derive(Clone)]
 pub struct Struct {
     pub definition: sized::Struct,
@@ -26,6 +36,21 @@ pub struct StructBuilder {
     pub names: NameSource,
 }
 
+#[derive(Clone)]
+pub struct Enum {
+    pub definition: sized::Enum,
+    pub builder: EnumBuilder,
+}
+
+#[derive(Clone)]
+pub struct EnumBuilder {
+    pub arguments: Vec<BuilderArgument>,
+    pub block: Block,
+    /// the witness tables of the fields of each variant
+    pub variants: Vec<Vec<Variable>>,
+    pub names: NameSource,
+}
+
 #[derive(Clone)]
 pub struct BuilderArgument {
     pub name: Variable,
@@ -141,17 +166,57 @@ pub enum Expr {
         function: String,
         arguments: Vec<CallArgument>,
     },
+    /// calls the function stored in a closure, passing the closure's environment and its witness table after `arguments`
+    CallIndirect {
+        /// the closure is only read from, so a `Use` isn't necessary
+        closure: Variable,
+        arguments: Vec<CallArgument>,
+    },
     Value(Value),
     Destroy,
+    /// stops the program, because an error union was unwrapped while it held an error
+    Crash,
     StructPack {
         name: String,
         arguments: Vec<PackField>,
     },
+    /// copies a field out of a struct value. generic structs keep their type arguments in the `extra` field of their witness table
     Unpack {
         struct_name: String,
         value: Variable,
         field: String,
-        type_arguments: Vec<Variable>,
+        /// where the field is, if the struct's layout is known statically, in which case it can be copied out directly
+        offset: Option<usize>,
+    },
+    /// copies the witness table of the `index`th type argument out of a generic struct's witness table
+    TypeArgument {
+        witness: Variable,
+        index: usize,
+    },
+    /// writes the tag of `variant` followed by its fields
+    VariantPack {
+        enum_name: String,
+        variant: usize,
+        arguments: Vec<Value>,
+    },
+    /// copies a field out of an enum value, which must currently hold `variant`
+    UnpackVariant {
+        enum_name: String,
+        variant: usize,
+        field: usize,
+        value: Variable,
+        /// where the field is, if the enum's layout is known statically, in which case it can be copied out directly
+        offset: Option<usize>,
+    },
+    /// produces a `Bool` that is true when the tag of `value` is `variant`
+    IsVariant {
+        value: Variable,
+        variant: usize,
+    },
+    /// produces a `Bool` that is true when `value` is equal to `literal`
+    IsLiteral {
+        value: Variable,
+        literal: Literal,
     },
     If {
         /// `predicate` is always trivially copyable, so a `Use` isn't necessary
@@ -165,6 +230,24 @@ pub enum Expr {
         // the extra witnes field is here in case `env` is a trivial struct: the existential that backs the closure still needs a witness table
         witness: Value,
     },
+    /// boxes up `value` along with its witness table and its dictionary for the existential's protocol
+    ExistentialPack {
+        value: Value,
+        witness: Value,
+        dictionary: Value,
+    },
+    /// jumps to the start of `function`, which has to be emitted in the same group as the current function.
+    /// `arguments` are moved into the callee, and its result becomes the result of the current function
+    TailCall {
+        function: String,
+        /// named by `tail_argument_name`, so that they don't clash with the arguments the callee is still using
+        arguments: Vec<Variable>,
+    },
+}
+
+/// the variable that holds the `index`th argument (after the result) passed to `function` by a tail call
+pub fn tail_argument_name(function: &str, index: usize) -> String {
+    String::from(format!("_tail_{function}_{index}"))
 }
 
 #[derive(Clone)]
@@ -194,6 +277,19 @@ impl fmt::Display for Program {
         for s in &self.structs {
             s.fmt(f)?;
         }
+        for e in &self.enums {
+            e.fmt(f)?;
+        }
+        for protocol in &self.protocols {
+            writeln!(f, "{protocol}")?;
+        }
+        for implementation in &self.impls {
+            writeln!(
+                f,
+                "impl {} for {}",
+                implementation.protocol, implementation.typ
+            )?;
+        }
         for func in &self.functions {
             func.fmt(f)?;
         }
@@ -277,8 +373,16 @@ impl fmt::Display for Expr {
                 }
                 tuple.finish()
             }
+            Expr::CallIndirect { closure, arguments } => {
+                let mut tuple = f.debug_tuple(&format!("({closure})"));
+                for arg in arguments {
+                    tuple.field(arg);
+                }
+                tuple.finish()
+            }
             Expr::Value(value) => write!(f, "{value}"),
             Expr::Destroy => write!(f, "destroy"),
+            Expr::Crash => write!(f, "crash"),
             Expr::Literal(literal) => write!(f, "{}", literal),
             Expr::StructPack { name, arguments } => {
                 let mut strukt = f.debug_struct(&name);
@@ -294,14 +398,12 @@ impl fmt::Display for Expr {
             } => {
                 write!(f, "if {predicate} then {true_branch} else {false_branch}")
             }
-            Expr::Unpack {
-                value,
-                field,
-                struct_name,
-                type_arguments,
-            } => {
+            Expr::Unpack { value, field, .. } => {
                 write!(f, "({value}).{field}")
             }
+            Expr::TypeArgument { witness, index } => {
+                write!(f, "type argument {index} of {witness}")
+            }
             Expr::MakeClosure {
                 function,
                 env,
@@ -309,6 +411,40 @@ impl fmt::Display for Expr {
             } => {
                 write!(f, "closure {function} {env} and {witness}")
             }
+            Expr::ExistentialPack {
+                value,
+                witness,
+                dictionary,
+            } => write!(f, "existential {value} using {witness} and {dictionary}"),
+            Expr::VariantPack {
+                enum_name,
+                variant,
+                arguments,
+            } => {
+                let mut tuple = f.debug_tuple(&format!("{enum_name}#{variant}"));
+                for arg in arguments {
+                    tuple.field(arg);
+                }
+                tuple.finish()
+            }
+            Expr::UnpackVariant {
+                variant,
+                field,
+                value,
+                ..
+            } => write!(f, "({value})#{variant}.{field}"),
+            Expr::IsVariant { value, variant } => write!(f, "({value}) is #{variant}"),
+            Expr::IsLiteral { value, literal } => write!(f, "({value}) is {literal}"),
+            Expr::TailCall {
+                function,
+                arguments,
+            } => {
+                let mut tuple = f.debug_tuple(&format!("jump {function}"));
+                for arg in arguments {
+                    tuple.field(arg);
+                }
+                tuple.finish()
+            }
         }
     }
 }
@@ -355,6 +491,26 @@ impl fmt::Display for StructBuilder {
     }
 }
 
+impl fmt::Display for Enum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.definition, self.builder)
+    }
+}
+
+impl fmt::Display for EnumBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut args = f.debug_tuple("");
+        for arg in &self.arguments {
+            args.field(arg);
+        }
+        args.finish()?;
+        write!(f, "{{\n{}", self.block)?;
+        write!(f, "\tyield ")?;
+        f.debug_list().entries(&self.variants).finish()?;
+        write!(f, "\n}}")
+    }
+}
+
 impl fmt::Debug for Variable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self)
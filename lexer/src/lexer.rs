@@ -15,6 +15,12 @@ impl Errors {
     pub fn success(&self) -> bool {
         self.errors.is_empty()
     }
+
+    /// the byte offset and value of every character that couldn't be lexed
+    #[must_use]
+    pub fn unknown_characters(&self) -> &[(usize, char)] {
+        &self.errors
+    }
 }
 
 #[must_use]
@@ -40,6 +46,26 @@ pub fn scan_tokens(text: &str) -> (token::List, Errors) {
             '-' => Kind::BinaryOperator(BinaryOperator::Dash),
             '*' => Kind::BinaryOperator(BinaryOperator::Star),
             '/' => Kind::BinaryOperator(BinaryOperator::Slash),
+            '%' => Kind::BinaryOperator(BinaryOperator::Percent),
+            '<' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::LessEquals)
+            }
+            '<' => Kind::BinaryOperator(BinaryOperator::Less),
+            '>' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::GreaterEquals)
+            }
+            '>' => Kind::BinaryOperator(BinaryOperator::Greater),
+            '!' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::BangEquals)
+            }
+            '!' => Kind::Bang,
+            '&' if matches!(chars.peek(), Some((_, '&'))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::DoubleAmpersand)
+            }
             ',' => Kind::Comma,
             ':' => Kind::Colon,
             ';' => Kind::Semicolon,
@@ -48,16 +74,36 @@ pub fn scan_tokens(text: &str) -> (token::List, Errors) {
                 chars.next();
                 Kind::ThickArrow
             }
+            '=' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::DoubleEquals)
+            }
             '=' => Kind::SingleEquals,
+            '|' if matches!(chars.peek(), Some((_, '|'))) => {
+                chars.next();
+                Kind::BinaryOperator(BinaryOperator::DoubleBar)
+            }
             '|' => Kind::SingleBar,
+            '_' if !matches!(chars.peek(), Some((_, next)) if next.is_alphanumeric() || *next == '_') => {
+                Kind::Underscore
+            }
             w if w.is_whitespace() => continue,
             n if n.is_numeric() => {
                 let mut end = None;
-                while let Some((idx, char)) = chars.peek() {
+                let mut seen_point = false;
+                while let Some(&(idx, char)) = chars.peek() {
                     if char.is_numeric() {
                         let _ = chars.next();
+                    } else if char == '.' && !seen_point && {
+                        // only a digit after the point makes it part of the number, rather than a field access
+                        let mut ahead = chars.clone();
+                        ahead.next();
+                        matches!(ahead.peek(), Some((_, next)) if next.is_numeric())
+                    } {
+                        seen_point = true;
+                        let _ = chars.next();
                     } else {
-                        end = Some(*idx);
+                        end = Some(idx);
                         break;
                     }
                 }
@@ -80,12 +126,20 @@ pub fn scan_tokens(text: &str) -> (token::List, Errors) {
                 let kind = match span.data {
                     "func" => Kind::Func,
                     "struct" => Kind::Struct,
+                    "enum" => Kind::Enum,
+                    "protocol" => Kind::Protocol,
+                    "impl" => Kind::Impl,
+                    "for" => Kind::For,
+                    "any" => Kind::Any,
                     "let" => Kind::Let,
                     "case" => Kind::Case,
                     "of" => Kind::Of,
                     "if" => Kind::If,
                     "then" => Kind::Then,
                     "else" => Kind::Else,
+                    "throw" => Kind::Throw,
+                    "try" => Kind::Try,
+                    "catch" => Kind::Catch,
                     "True" => Kind::True,
                     "False" => Kind::False,
                     _ => {
@@ -149,4 +203,63 @@ mod test {
             assert_eq!(token.kind, kind);
         }
     }
+
+    /// scans `text`, which has to produce exactly the tokens of `kinds`
+    fn assert_kinds(text: &str, kinds: &[Kind]) {
+        let (tokens, errors) = scan_tokens(text);
+        assert!(errors.success());
+        let found: Vec<_> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(found, kinds);
+    }
+
+    #[test]
+    fn enum_tokens() {
+        assert_kinds("enum _ |", &[Kind::Enum, Kind::Underscore, Kind::SingleBar]);
+    }
+
+    #[test]
+    fn protocol_tokens() {
+        assert_kinds(
+            "protocol impl for",
+            &[Kind::Protocol, Kind::Impl, Kind::For],
+        );
+    }
+
+    #[test]
+    fn any_token() {
+        assert_kinds("any Shape", &[Kind::Any, Kind::UpperIdentifier]);
+    }
+
+    #[test]
+    fn float_tokens() {
+        assert_kinds(
+            "1.5 2.x",
+            &[Kind::Number, Kind::Number, Kind::Dot, Kind::Identifier],
+        );
+    }
+
+    #[test]
+    fn operator_tokens() {
+        assert_kinds(
+            "% == != < <= > >= && || ! =>",
+            &[
+                Kind::BinaryOperator(BinaryOperator::Percent),
+                Kind::BinaryOperator(BinaryOperator::DoubleEquals),
+                Kind::BinaryOperator(BinaryOperator::BangEquals),
+                Kind::BinaryOperator(BinaryOperator::Less),
+                Kind::BinaryOperator(BinaryOperator::LessEquals),
+                Kind::BinaryOperator(BinaryOperator::Greater),
+                Kind::BinaryOperator(BinaryOperator::GreaterEquals),
+                Kind::BinaryOperator(BinaryOperator::DoubleAmpersand),
+                Kind::BinaryOperator(BinaryOperator::DoubleBar),
+                Kind::Bang,
+                Kind::ThickArrow,
+            ],
+        );
+    }
+
+    #[test]
+    fn error_tokens() {
+        assert_kinds("try throw catch", &[Kind::Try, Kind::Throw, Kind::Catch]);
+    }
 }
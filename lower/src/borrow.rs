@@ -0,0 +1,513 @@
+use im::{HashMap, HashSet};
+use ir::bridge::{Block, Convention, Expr, Instr, Program, Value, Variable, Witness};
+use tree::type_passing::impl_function_name;
+use tree::String;
+
+/// the reference counting operations in a program, which are the copies and destroys of values that aren't trivial.
+///
+/// `inout` and `out` arguments aren't counted as copies, since they only pass a reference
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Operations {
+    pub copies: usize,
+    pub destroys: usize,
+}
+
+impl Operations {
+    #[must_use]
+    pub const fn total(self) -> usize {
+        self.copies + self.destroys
+    }
+}
+
+#[must_use]
+pub fn operations(program: &Program) -> Operations {
+    let mut operations = Operations::default();
+    for function in &program.functions {
+        count_block(&function.body, &mut operations);
+    }
+    operations
+}
+
+fn count_block(block: &Block, operations: &mut Operations) {
+    for instr in &block.instrs {
+        if matches!(instr.value, Expr::Destroy) && !instr.target.witness.is_trivial() {
+            operations.destroys += 1;
+        }
+        if let Expr::If {
+            true_branch,
+            false_branch,
+            ..
+        } = &instr.value
+        {
+            count_block(true_branch, operations);
+            count_block(false_branch, operations);
+        }
+        for value in owned_values(&instr.value) {
+            if let Value::Copy(variable) = value {
+                if !variable.witness.is_trivial() {
+                    operations.copies += 1;
+                }
+            }
+        }
+    }
+}
+
+/// removes reference counting operations that refcounting put in conservatively.
+///
+/// it does this in two steps:
+/// - an `in` argument that the callee only reads is borrowed instead, by passing it `inout`.
+///   callers then don't have to copy it, and the callee doesn't destroy it
+/// - a copy of a variable that is destroyed afterwards, with nothing using it in between, becomes a move
+///
+/// only functions that are called directly borrow their arguments, since the signatures of closures,
+/// protocol functions and functions that tail calls jump between are fixed by how they're called
+#[must_use]
+pub fn program(mut program: Program) -> Program {
+    let borrowed = borrowed_arguments(&program);
+    for function in &mut program.functions {
+        if let Some(flags) = borrowed.get(&function.name) {
+            for (argument, borrowed) in function.arguments.iter_mut().zip(flags) {
+                if *borrowed {
+                    argument.convention = Convention::Inout;
+                    remove_destroys(&mut function.body, &argument.name);
+                }
+            }
+        }
+        lend(&mut function.body, &borrowed);
+        move_last_copies(&mut function.body);
+    }
+    program
+}
+
+/// for each function, which of its arguments (including its result) it can borrow,
+/// which are the `in` arguments that it never gives away, writes or lends to a call that can write them
+fn borrowed_arguments(program: &Program) -> HashMap<String, Vec<bool>> {
+    let mut fixed = HashSet::new();
+    for function in &program.functions {
+        fixed_signatures(&function.body, &function.name, &mut fixed);
+    }
+    for implementation in &program.impls {
+        let protocol = program
+            .protocols
+            .iter()
+            .find(|protocol| protocol.name == implementation.protocol)
+            .expect("impl of an unknown protocol");
+        for function in &protocol.functions {
+            fixed.insert(impl_function_name(
+                &implementation.protocol,
+                &implementation.typ,
+                &function.name,
+            ));
+        }
+    }
+    let mut borrowed: HashMap<String, Vec<bool>> = program
+        .functions
+        .iter()
+        .filter(|function| !fixed.contains(&function.name))
+        .map(|function| {
+            let flags = function
+                .arguments
+                .iter()
+                .map(|argument| {
+                    argument.convention == Convention::In
+                        && !argument.name.witness.is_trivial()
+                        && only_read(&function.body, &argument.name)
+                })
+                .collect();
+            (function.name.clone(), flags)
+        })
+        .collect();
+    // a borrowed argument is passed by reference, so its variable can't also be passed where the callee writes it
+    for function in &program.functions {
+        unalias(&function.body, &mut borrowed);
+    }
+    // a moved argument still has to be destroyed by the caller, so borrowing only pays off
+    // when it saves more copies and destroys than the destroys it adds after moves
+    let mut savings: HashMap<String, Vec<isize>> = program
+        .functions
+        .iter()
+        .map(|function| {
+            let destroys = function
+                .arguments
+                .iter()
+                .map(|argument| destroys(&function.body, &argument.name))
+                .collect();
+            (function.name.clone(), destroys)
+        })
+        .collect();
+    for function in &program.functions {
+        call_savings(&function.body, &mut savings);
+    }
+    for (function, flags) in borrowed.iter_mut() {
+        for (borrowed, saved) in flags.iter_mut().zip(&savings[function]) {
+            *borrowed &= *saved > 0;
+        }
+    }
+    borrowed.retain(|_, flags| flags.contains(&true));
+    borrowed
+}
+
+/// how many times `block` destroys `variable`
+fn destroys(block: &Block, variable: &Variable) -> isize {
+    block
+        .instrs
+        .iter()
+        .map(|instr| match &instr.value {
+            Expr::Destroy => isize::from(instr.target == *variable),
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => destroys(true_branch, variable) + destroys(false_branch, variable),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// adds what borrowing each argument would save at the calls in `block`: a copy it doesn't need to make,
+/// or a destroy it has to make after the callee instead of in it
+fn call_savings(block: &Block, savings: &mut HashMap<String, Vec<isize>>) {
+    for instr in &block.instrs {
+        match &instr.value {
+            Expr::CallDirect {
+                function,
+                arguments,
+            } => {
+                if let Some(saved) = savings.get_mut(function) {
+                    for (passed, saved) in arguments.iter().zip(saved) {
+                        match &passed.value {
+                            _ if passed.convention != Convention::In => {}
+                            Value::Copy(variable) if !variable.witness.is_trivial() => *saved += 1,
+                            Value::Move(variable) if !variable.witness.is_trivial() => *saved -= 1,
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                call_savings(true_branch, savings);
+                call_savings(false_branch, savings);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// adds the functions whose signatures `block` relies on beyond direct calls to `fixed`,
+/// including `function` itself if it makes a tail call
+fn fixed_signatures(block: &Block, function: &String, fixed: &mut HashSet<String>) {
+    for instr in &block.instrs {
+        match &instr.value {
+            Expr::MakeClosure { function, .. } => {
+                fixed.insert(function.clone());
+            }
+            Expr::TailCall {
+                function: target, ..
+            } => {
+                fixed.insert(target.clone());
+                fixed.insert(function.clone());
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                fixed_signatures(true_branch, function, fixed);
+                fixed_signatures(false_branch, function, fixed);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// whether `block` only copies `argument` and destroys it, without moving, writing or lending it
+fn only_read(block: &Block, argument: &Variable) -> bool {
+    block.instrs.iter().all(|instr| {
+        if instr.target == *argument {
+            return matches!(instr.value, Expr::Destroy);
+        }
+        let moved = owned_values(&instr.value)
+            .into_iter()
+            .any(|value| matches!(value, Value::Move(moved) if moved == argument));
+        if moved {
+            return false;
+        }
+        match &instr.value {
+            Expr::CallDirect { arguments, .. } | Expr::CallIndirect { arguments, .. } => {
+                arguments.iter().all(|passed| {
+                    passed.convention == Convention::In || passed.value.variable() != argument
+                })
+            }
+            Expr::TailCall { arguments, .. } => !arguments.contains(argument),
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => only_read(true_branch, argument) && only_read(false_branch, argument),
+            _ => true,
+        }
+    })
+}
+
+/// stops borrowing each argument that a call in `block` passes a variable for that it also passes `inout` or `out`
+fn unalias(block: &Block, borrowed: &mut HashMap<String, Vec<bool>>) {
+    for instr in &block.instrs {
+        match &instr.value {
+            Expr::CallDirect {
+                function,
+                arguments,
+            } => {
+                if let Some(flags) = borrowed.get_mut(function) {
+                    for (passed, borrowed) in arguments.iter().zip(flags) {
+                        let variable = passed.value.variable();
+                        *borrowed &= !arguments.iter().any(|other| {
+                            other.convention != Convention::In && other.value.variable() == variable
+                        });
+                    }
+                }
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                unalias(true_branch, borrowed);
+                unalias(false_branch, borrowed);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn remove_destroys(block: &mut Block, argument: &Variable) {
+    block
+        .instrs
+        .retain(|instr| !(instr.target == *argument && matches!(instr.value, Expr::Destroy)));
+    for instr in &mut block.instrs {
+        if let Expr::If {
+            true_branch,
+            false_branch,
+            ..
+        } = &mut instr.value
+        {
+            remove_destroys(true_branch, argument);
+            remove_destroys(false_branch, argument);
+        }
+    }
+}
+
+/// passes borrowed arguments `inout`. a moved argument is destroyed after the call, instead of by the callee
+fn lend(block: &mut Block, borrowed: &HashMap<String, Vec<bool>>) {
+    let mut instrs = Vec::with_capacity(block.instrs.len());
+    for mut instr in std::mem::take(&mut block.instrs) {
+        let mut destroys = Vec::new();
+        match &mut instr.value {
+            Expr::CallDirect {
+                function,
+                arguments,
+            } => {
+                if let Some(flags) = borrowed.get(function) {
+                    for (passed, _) in
+                        arguments
+                            .iter_mut()
+                            .zip(flags)
+                            .filter(|(passed, borrowed)| {
+                                **borrowed && passed.convention == Convention::In
+                            })
+                    {
+                        passed.convention = Convention::Inout;
+                        if let Value::Move(variable) = &passed.value {
+                            let variable = variable.clone();
+                            if !variable.witness.is_trivial() {
+                                destroys.push(Instr::new(variable.clone(), Expr::Destroy));
+                            }
+                            passed.value = Value::Copy(variable);
+                        }
+                    }
+                }
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                lend(true_branch, borrowed);
+                lend(false_branch, borrowed);
+            }
+            _ => {}
+        }
+        instrs.push(instr);
+        instrs.append(&mut destroys);
+    }
+    block.instrs = instrs;
+}
+
+/// turns a copy into a move when the next thing to mention the copied variable destroys it, and drops the destroy
+fn move_last_copies(block: &mut Block) {
+    for instr in &mut block.instrs {
+        if let Expr::If {
+            true_branch,
+            false_branch,
+            ..
+        } = &mut instr.value
+        {
+            move_last_copies(true_branch);
+            move_last_copies(false_branch);
+        }
+    }
+    let mut index = 0;
+    while index < block.instrs.len() {
+        let (instr, rest) = block.instrs[index..].split_first_mut().unwrap();
+        let mut mentioned = Vec::new();
+        mentions(instr, &mut mentioned);
+        let mut destroyed = Vec::new();
+        for value in owned_values_mut(&mut instr.value) {
+            let Value::Copy(variable) = value else {
+                continue;
+            };
+            if mentioned
+                .iter()
+                .filter(|other| **other == *variable)
+                .count()
+                != 1
+            {
+                continue;
+            }
+            let next = rest.iter().position(|later| {
+                let mut later_mentioned = Vec::new();
+                mentions(later, &mut later_mentioned);
+                later_mentioned.contains(variable)
+            });
+            if let Some(next) = next {
+                if rest[next].target == *variable && matches!(rest[next].value, Expr::Destroy) {
+                    destroyed.push(index + 1 + next);
+                    *value = Value::Move(variable.clone());
+                }
+            }
+        }
+        destroyed.sort_unstable();
+        for destroy in destroyed.into_iter().rev() {
+            block.instrs.remove(destroy);
+        }
+        index += 1;
+    }
+}
+
+/// every variable that `instr` mentions, including the witness tables it needs for them, once for each mention
+pub(crate) fn mentions(instr: &Instr, mentioned: &mut Vec<Variable>) {
+    fn mention(variable: &Variable, mentioned: &mut Vec<Variable>) {
+        mentioned.push(variable.clone());
+        if let Witness::Dynamic { location } = variable.witness.as_ref() {
+            mention(location, mentioned);
+        }
+    }
+    mention(&instr.target, mentioned);
+    match &instr.value {
+        Expr::Literal(_) | Expr::Destroy | Expr::Crash => {}
+        Expr::Primitive(_, arguments) | Expr::TailCall { arguments, .. } => {
+            for argument in arguments {
+                mention(argument, mentioned);
+            }
+        }
+        Expr::CallDirect { arguments, .. } => {
+            for argument in arguments {
+                mention(argument.value.variable(), mentioned);
+            }
+        }
+        Expr::CallIndirect { closure, arguments } => {
+            mention(closure, mentioned);
+            for argument in arguments {
+                mention(argument.value.variable(), mentioned);
+            }
+        }
+        Expr::Value(value) => mention(value.variable(), mentioned),
+        Expr::StructPack { arguments, .. } => {
+            for argument in arguments {
+                mention(argument.value.variable(), mentioned);
+            }
+        }
+        Expr::VariantPack { arguments, .. } => {
+            for argument in arguments {
+                mention(argument.variable(), mentioned);
+            }
+        }
+        Expr::Unpack { value, .. }
+        | Expr::UnpackVariant { value, .. }
+        | Expr::IsVariant { value, .. }
+        | Expr::IsLiteral { value, .. }
+        | Expr::TypeArgument { witness: value, .. } => mention(value, mentioned),
+        Expr::If {
+            predicate,
+            true_branch,
+            false_branch,
+        } => {
+            mention(predicate, mentioned);
+            for instr in true_branch.instrs.iter().chain(&false_branch.instrs) {
+                mentions(instr, mentioned);
+            }
+        }
+        Expr::MakeClosure { env, witness, .. } => {
+            mention(env.variable(), mentioned);
+            mention(witness.variable(), mentioned);
+        }
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => {
+            mention(value.variable(), mentioned);
+            mention(witness.variable(), mentioned);
+            mention(dictionary.variable(), mentioned);
+        }
+    }
+}
+
+/// the values that `value` gives ownership of to what it makes or calls
+fn owned_values(value: &Expr) -> Vec<&Value> {
+    match value {
+        Expr::Value(value) => vec![value],
+        Expr::CallDirect { arguments, .. } | Expr::CallIndirect { arguments, .. } => arguments
+            .iter()
+            .filter(|argument| argument.convention == Convention::In)
+            .map(|argument| &argument.value)
+            .collect(),
+        Expr::StructPack { arguments, .. } => {
+            arguments.iter().map(|argument| &argument.value).collect()
+        }
+        Expr::VariantPack { arguments, .. } => arguments.iter().collect(),
+        Expr::MakeClosure { env, witness, .. } => vec![env, witness],
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => vec![value, witness, dictionary],
+        _ => Vec::new(),
+    }
+}
+
+fn owned_values_mut(value: &mut Expr) -> Vec<&mut Value> {
+    match value {
+        Expr::Value(value) => vec![value],
+        Expr::CallDirect { arguments, .. } | Expr::CallIndirect { arguments, .. } => arguments
+            .iter_mut()
+            .filter(|argument| argument.convention == Convention::In)
+            .map(|argument| &mut argument.value)
+            .collect(),
+        Expr::StructPack { arguments, .. } => arguments
+            .iter_mut()
+            .map(|argument| &mut argument.value)
+            .collect(),
+        Expr::VariantPack { arguments, .. } => arguments.iter_mut().collect(),
+        Expr::MakeClosure { env, witness, .. } => vec![env, witness],
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => vec![value, witness, dictionary],
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,292 @@
+use im::{HashMap, HashSet};
+use ir::bridge::{Block, Convention, Expr, Function, Instr, Value, Variable, Witness};
+use tree::sized::Primitive;
+use tree::String;
+
+/// what an instruction computes, for the instructions that always compute the same thing from the same variables
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// a witness table made by a builder, from the tables it's passed
+    Witness {
+        builder: String,
+        arguments: Vec<String>,
+    },
+    TypeArgument {
+        witness: String,
+        index: usize,
+    },
+    Primitive {
+        primitive: Primitive,
+        arguments: Vec<String>,
+    },
+}
+
+/// merges the instructions of `function` that compute the same witness table or primitive value.
+///
+/// witness tables that are only made from its arguments are moved to its start, where every block can share them.
+/// it runs before refcounting, when every use of a witness table is still a copy.
+/// a table that is moved somewhere can't be shared, so it is left alone
+#[must_use]
+pub fn function(mut function: Function) -> Function {
+    let mut fixed = HashSet::new();
+    let mut definitions = HashMap::new();
+    scan(&function.body, &mut fixed, &mut definitions);
+    let mut eliminator = Eliminator {
+        fixed,
+        definitions,
+        entry: function
+            .arguments
+            .iter()
+            .filter(|argument| argument.convention != Convention::Out)
+            .map(|argument| argument.name.name.clone())
+            .collect(),
+        hoisted: Vec::new(),
+        everywhere: HashMap::new(),
+        renamed: HashMap::new(),
+    };
+    let body = eliminator.block(function.body, HashMap::new());
+    // the arguments are described by the tables that the body makes, which may have been merged
+    for argument in &mut function.arguments {
+        rename_variable(&mut argument.name, &eliminator.renamed);
+    }
+    let mut instrs = eliminator.hoisted;
+    instrs.extend(body.instrs);
+    function.body = Block { instrs };
+    function
+}
+
+struct Eliminator {
+    /// the variables that are moved or written by a call, which can't stand in for another
+    fixed: HashSet<String>,
+    /// how many instructions write each variable
+    definitions: HashMap<String, usize>,
+    /// the variables that are defined at the start of the function
+    entry: HashSet<String>,
+    /// the instructions moved to the start of the function
+    hoisted: Vec<Instr>,
+    /// what the hoisted instructions compute
+    everywhere: HashMap<Key, Variable>,
+    /// the variables whose instructions were merged, and the variable that they were merged into
+    renamed: HashMap<String, String>,
+}
+
+impl Eliminator {
+    /// `available` is what the instructions before the block compute, which it can reuse
+    fn block(&mut self, block: Block, mut available: HashMap<Key, Variable>) -> Block {
+        let mut instrs = Vec::with_capacity(block.instrs.len());
+        for mut instr in block.instrs {
+            self.rename(&mut instr);
+            if let Expr::If {
+                predicate,
+                true_branch,
+                false_branch,
+            } = instr.value
+            {
+                let true_branch = self.block(true_branch, available.clone());
+                let false_branch = self.block(false_branch, available.clone());
+                instrs.push(Instr::new(
+                    instr.target,
+                    Expr::If {
+                        predicate,
+                        true_branch,
+                        false_branch,
+                    },
+                ));
+                continue;
+            }
+            let Some((key, operands)) = self.key(&instr) else {
+                instrs.push(instr);
+                continue;
+            };
+            if let Some(existing) = self.everywhere.get(&key).or_else(|| available.get(&key)) {
+                self.renamed
+                    .insert(instr.target.name.clone(), existing.name.clone());
+                continue;
+            }
+            // only witness tables are worth making up front, since a primitive can fail where its block wouldn't run
+            let hoistable = !matches!(key, Key::Primitive { .. })
+                && operands.iter().all(|operand| self.entry.contains(operand));
+            if hoistable {
+                self.entry.insert(instr.target.name.clone());
+                self.everywhere.insert(key, instr.target.clone());
+                self.hoisted.push(instr);
+            } else {
+                available.insert(key, instr.target.clone());
+                instrs.push(instr);
+            }
+        }
+        Block { instrs }
+    }
+
+    /// what `instr` computes and the variables it computes it from, if another instruction could compute it instead
+    fn key(&self, instr: &Instr) -> Option<(Key, Vec<String>)> {
+        let target = &instr.target.name;
+        if self.fixed.contains(target) || self.definitions.get(target) != Some(&1) {
+            return None;
+        }
+        match &instr.value {
+            Expr::CallDirect {
+                function,
+                arguments,
+            } if matches!(instr.target.witness.as_ref(), Witness::Type) => {
+                let (result, arguments) = arguments.split_first()?;
+                let tables = arguments.iter().all(|argument| {
+                    argument.convention == Convention::In
+                        && matches!(argument.value, Value::Copy(_))
+                        && matches!(argument.value.variable().witness.as_ref(), Witness::Type)
+                });
+                if result.value.variable() != &instr.target || !tables {
+                    return None;
+                }
+                let arguments: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| argument.value.variable().name.clone())
+                    .collect();
+                let key = Key::Witness {
+                    builder: function.clone(),
+                    arguments: arguments.clone(),
+                };
+                Some((key, arguments))
+            }
+            Expr::TypeArgument { witness, index } => {
+                let key = Key::TypeArgument {
+                    witness: witness.name.clone(),
+                    index: *index,
+                };
+                Some((key, vec![witness.name.clone()]))
+            }
+            Expr::Primitive(primitive, arguments) => {
+                let arguments: Vec<_> = arguments
+                    .iter()
+                    .map(|argument| argument.name.clone())
+                    .collect();
+                let key = Key::Primitive {
+                    primitive: *primitive,
+                    arguments: arguments.clone(),
+                };
+                Some((key, arguments))
+            }
+            _ => None,
+        }
+    }
+
+    /// replaces each merged variable that `instr` mentions, outside of its branches, with the one it was merged into
+    fn rename(&self, instr: &mut Instr) {
+        let rename = |variable: &mut Variable| rename_variable(variable, &self.renamed);
+        rename(&mut instr.target);
+        match &mut instr.value {
+            Expr::Literal(_) | Expr::Destroy | Expr::Crash => {}
+            Expr::Primitive(_, arguments) | Expr::TailCall { arguments, .. } => {
+                arguments.iter_mut().for_each(rename);
+            }
+            Expr::CallDirect { arguments, .. } => {
+                for argument in arguments {
+                    rename_value(&mut argument.value, &self.renamed);
+                }
+            }
+            Expr::CallIndirect { closure, arguments } => {
+                rename(closure);
+                for argument in arguments {
+                    rename_value(&mut argument.value, &self.renamed);
+                }
+            }
+            Expr::Value(value) => rename_value(value, &self.renamed),
+            Expr::StructPack { arguments, .. } => {
+                for argument in arguments {
+                    rename_value(&mut argument.value, &self.renamed);
+                }
+            }
+            Expr::VariantPack { arguments, .. } => {
+                for argument in arguments {
+                    rename_value(argument, &self.renamed);
+                }
+            }
+            Expr::Unpack { value, .. }
+            | Expr::UnpackVariant { value, .. }
+            | Expr::IsVariant { value, .. }
+            | Expr::IsLiteral { value, .. }
+            | Expr::TypeArgument { witness: value, .. } => rename(value),
+            Expr::If { predicate, .. } => rename(predicate),
+            Expr::MakeClosure { env, witness, .. } => {
+                rename_value(env, &self.renamed);
+                rename_value(witness, &self.renamed);
+            }
+            Expr::ExistentialPack {
+                value,
+                witness,
+                dictionary,
+            } => {
+                rename_value(value, &self.renamed);
+                rename_value(witness, &self.renamed);
+                rename_value(dictionary, &self.renamed);
+            }
+        }
+    }
+}
+
+fn rename_value(value: &mut Value, renamed: &HashMap<String, String>) {
+    match value {
+        Value::Move(variable) | Value::Copy(variable) => rename_variable(variable, renamed),
+    }
+}
+
+/// renames `variable`, along with the witness table it's described by
+fn rename_variable(variable: &mut Variable, renamed: &HashMap<String, String>) {
+    if let Some(name) = renamed.get(&variable.name) {
+        variable.name = name.clone();
+    }
+    if let Witness::Dynamic { location } = variable.witness.as_mut() {
+        rename_variable(location, renamed);
+    }
+}
+
+/// finds the variables in `block` that are moved or written by a call, and counts the instructions that write each one
+fn scan(block: &Block, fixed: &mut HashSet<String>, definitions: &mut HashMap<String, usize>) {
+    for instr in &block.instrs {
+        *definitions.entry(instr.target.name.clone()).or_default() += 1;
+        match &instr.value {
+            Expr::CallDirect { arguments, .. } | Expr::CallIndirect { arguments, .. } => {
+                for argument in arguments {
+                    let variable = argument.value.variable();
+                    let written =
+                        argument.convention != Convention::In && *variable != instr.target;
+                    if written || matches!(argument.value, Value::Move(_)) {
+                        fixed.insert(variable.name.clone());
+                    }
+                }
+            }
+            Expr::TailCall { arguments, .. } => {
+                fixed.extend(arguments.iter().map(|argument| argument.name.clone()));
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                scan(true_branch, fixed, definitions);
+                scan(false_branch, fixed, definitions);
+            }
+            value => {
+                let values: Vec<&Value> = match value {
+                    Expr::Value(value) => vec![value],
+                    Expr::StructPack { arguments, .. } => {
+                        arguments.iter().map(|argument| &argument.value).collect()
+                    }
+                    Expr::VariantPack { arguments, .. } => arguments.iter().collect(),
+                    Expr::MakeClosure { env, witness, .. } => vec![env, witness],
+                    Expr::ExistentialPack {
+                        value,
+                        witness,
+                        dictionary,
+                    } => vec![value, witness, dictionary],
+                    _ => Vec::new(),
+                };
+                for value in values {
+                    if let Value::Move(variable) = value {
+                        fixed.insert(variable.name.clone());
+                    }
+                }
+            }
+        }
+    }
+}
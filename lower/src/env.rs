@@ -1,3 +1,4 @@
+use im::{HashMap, HashSet};
 use ir::bridge::{Variable, Witness};
 use ir::name_source::NameSource;
 use tree::typed::Type;
@@ -6,15 +7,37 @@ use tree::String;
 #[derive(Clone)]
 pub struct Env {
     pub name_source: NameSource,
+    /// the unique names given to variables bound by patterns
+    pub renames: HashMap<String, String>,
+    /// the functions that a tail call from the function being lowered can jump to
+    pub tail_group: HashSet<String>,
 }
 
 impl Env {
     pub fn new() -> Self {
         Self {
             name_source: NameSource::default(),
+            renames: HashMap::new(),
+            tail_group: HashSet::new(),
         }
     }
 
+    /// defines a variable bound by a pattern under a fresh name, so that it can't clash with a variable of the same name elsewhere in the function
+    pub fn define_binding(&mut self, name: String, typ: Type, witness: Witness) -> Variable {
+        let unique = String::from(format!("{name}{}", self.fresh_name()));
+        self.renames.insert(name, unique.clone());
+        self.define_variable(unique, typ, witness)
+    }
+
+    /// stops referring to a variable bound by a pattern once it has been shadowed
+    pub fn forget_binding(&mut self, name: &String) {
+        self.renames.remove(name);
+    }
+
+    pub fn rename(&self, name: &String) -> String {
+        self.renames.get(name).unwrap_or(name).clone()
+    }
+
     pub fn define_variable(&mut self, name: String, typ: Type, witness: Witness) -> Variable {
         let variable = Variable {
             name,
@@ -5,6 +5,10 @@
     clippy::option_if_let_else
 )]
 
+pub mod borrow;
+pub mod cse;
 pub mod env;
 pub mod lower;
 pub mod refcount;
+pub mod rme;
+pub mod tail;
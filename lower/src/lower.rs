@@ -1,40 +1,98 @@
 use core::fmt;
 use std::cell::RefCell;
 
+use crate::cse;
 use crate::env::Env;
 use crate::refcount::count_function;
+use crate::tail;
+use im::{HashMap, HashSet};
 use ir::bridge::{
-    Argument, Block, BuilderArgument, CallArgument, Convention, Expr, Function, Instr, PackField,
-    Program, Struct, StructBuilder, Value, Variable, Witness,
+    tail_argument_name, Argument, Block, BuilderArgument, CallArgument, Convention, Enum,
+    EnumBuilder, Expr, Function, Implementation, Instr, PackField, Program, Struct, StructBuilder,
+    Value, Variable, Witness,
 };
-use tree::sized::{self};
-use tree::type_passing::make_signature;
-use tree::typed::Type;
-use tree::String;
+use tree::sized::{self, Primitive, OK, RESULT, THROWN};
+use tree::type_passing::{impl_function_name, make_signature};
+use tree::typed::{Literal, Type};
+use tree::{Span, String};
 
 #[derive(Default)]
 struct Builder {
     functions: Vec<Function>,
     structs: Vec<Struct>,
+    enums: Vec<Enum>,
+    protocols: Vec<sized::Protocol>,
+    impls: Vec<Implementation>,
+    /// the functions that each function can jump to with a tail call
+    tail_groups: HashMap<String, HashSet<String>>,
 }
 
 impl Builder {
     pub fn build(self) -> Program {
         Program {
             structs: self.structs,
+            enums: self.enums,
+            protocols: self.protocols,
+            impls: self.impls,
             functions: self.functions,
         }
     }
+
+    /// finds the enum that declares the variant, and the variant's position in it.
+    /// the variants of a union of type `typ` are in the enum that it is laid out as instead
+    fn variant(&self, name: &String, typ: &Type) -> (String, usize) {
+        match name.as_str() {
+            OK => return (String::from(RESULT), 0),
+            THROWN => return (String::from(RESULT), 1),
+            _ => {}
+        }
+        let union = match typ.union_shape() {
+            Some(Type::Named { name, .. }) => Some(name),
+            _ => None,
+        };
+        self.enums
+            .iter()
+            .filter(|to_search| {
+                union
+                    .as_ref()
+                    .is_none_or(|union| &to_search.definition.name == union)
+            })
+            .find_map(|to_search| {
+                let definition = &to_search.definition;
+                let index = definition
+                    .variants
+                    .iter()
+                    .position(|variant| &variant.name == name)?;
+                Some((definition.name.clone(), index))
+            })
+            .expect(&format!("unknown variant {name}"))
+    }
 }
 
 pub fn program(to_lower: &sized::Program) -> Program {
     let mut builder = Builder::default();
+    for to_lower in &to_lower.enums {
+        enumeration(to_lower, &mut builder);
+    }
     for to_lower in &to_lower.structs {
         strukt(to_lower, &mut builder);
     }
+    builder.protocols = to_lower.protocols.clone();
+    for to_lower in &to_lower.impls {
+        for func in &to_lower.functions {
+            let mut func = function(func, &mut builder);
+            func.name = impl_function_name(&to_lower.protocol, &to_lower.typ, &func.name);
+            builder.functions.push(count_function(cse::function(func)));
+        }
+        builder.impls.push(Implementation {
+            protocol: to_lower.protocol.clone(),
+            typ: to_lower.typ.clone(),
+        });
+    }
+    builder.tail_groups = tail::groups(&to_lower.functions);
     for to_lower in &to_lower.functions {
         let func = function(to_lower, &mut builder);
-        builder.functions.push(count_function(func));
+        builder.functions.push(count_function(cse::function(func)));
     }
     builder.build()
 }
@@ -76,11 +134,17 @@ fn strukt(to_lower: &sized::Struct, builder: &mut Builder) {
         .collect();
 
     let block = instrs.build();
+    let mut arguments = vec![BuilderArgument {
+        name: env.define_variable(String::from("_result"), Type::typ(), Witness::Type),
+        convention: Convention::Out,
+    }];
+    // generic structs are built from the witness tables of their type arguments
+    arguments.extend(to_lower.tag.arguments.iter().map(|arg| BuilderArgument {
+        name: env.define_variable(arg.name.clone(), Type::typ(), Witness::Type),
+        convention: Convention::In,
+    }));
     let lowered_builder = StructBuilder {
-        arguments: vec![BuilderArgument {
-            name: env.define_variable(String::from("_result"), Type::typ(), Witness::Type),
-            convention: Convention::Out,
-        }],
+        arguments,
         block,
         fields,
         names: env.name_source,
@@ -92,8 +156,51 @@ fn strukt(to_lower: &sized::Struct, builder: &mut Builder) {
     });
 }
 
+fn enumeration(to_lower: &sized::Enum, builder: &mut Builder) {
+    let mut env = Env::new();
+    let instrs = BlockBuilder::new("enum");
+    let variants = to_lower
+        .tag
+        .variants
+        .iter()
+        .map(|fields| {
+            fields
+                .iter()
+                .map(|field| expr(&mut env, field, &instrs, builder))
+                .collect()
+        })
+        .collect();
+
+    let block = instrs.build();
+    let mut arguments = vec![BuilderArgument {
+        name: env.define_variable(String::from("_result"), Type::typ(), Witness::Type),
+        convention: Convention::Out,
+    }];
+    // the enums that unions are laid out as are built from the witness tables of their type arguments, like generic structs
+    arguments.extend(to_lower.tag.arguments.iter().map(|arg| BuilderArgument {
+        name: env.define_variable(arg.name.clone(), Type::typ(), Witness::Type),
+        convention: Convention::In,
+    }));
+    let lowered_builder = EnumBuilder {
+        arguments,
+        block,
+        variants,
+        names: env.name_source,
+    };
+
+    builder.enums.push(Enum {
+        definition: to_lower.clone(),
+        builder: lowered_builder,
+    });
+}
+
 fn function<'a>(to_lower: &sized::Function, builder: &mut Builder) -> Function {
     let mut env = Env::new();
+    env.tail_group = builder
+        .tail_groups
+        .get(&to_lower.name)
+        .cloned()
+        .unwrap_or_default();
     let body_builder = BlockBuilder::new("body");
     let lowered_arguments: Vec<_> = to_lower
         .arguments
@@ -108,11 +215,13 @@ fn function<'a>(to_lower: &sized::Function, builder: &mut Builder) -> Function {
             }
         })
         .collect();
-    let result = expr(&mut env, &to_lower.body, &body_builder, builder);
-    body_builder.push(Instr::new(
-        lowered_arguments[0].name.clone(),
-        Expr::mov(result.clone()),
-    ));
+    tail(
+        &mut env,
+        &to_lower.body,
+        &lowered_arguments[0].name,
+        &body_builder,
+        builder,
+    );
     let names = env.name_source.clone();
     Function {
         name: to_lower.name.clone(),
@@ -122,6 +231,115 @@ fn function<'a>(to_lower: &sized::Function, builder: &mut Builder) -> Function {
     }
 }
 
+/// lowers the expression that computes a function's result, so that calls to functions of the same tail group become jumps
+fn tail(
+    env: &mut Env,
+    to_lower: &sized::Expr,
+    result: &Variable,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) {
+    match to_lower {
+        sized::Expr::If {
+            predicate,
+            true_branch,
+            false_branch,
+            ..
+        } => {
+            let lowered_predicate = expr(env, predicate, instrs, builder);
+            let true_instrs = BlockBuilder::new("true branch");
+            tail(env, true_branch, result, &true_instrs, builder);
+            let false_instrs = BlockBuilder::new("false branch");
+            tail(env, false_branch, result, &false_instrs, builder);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate: lowered_predicate,
+                    true_branch: true_instrs.build(),
+                    false_branch: false_instrs.build(),
+                },
+            ));
+        }
+        sized::Expr::Primitive {
+            primitive: primitive @ (Primitive::And | Primitive::Or),
+            arguments,
+            ..
+        } => {
+            let lowered_first = expr(env, &arguments[0], instrs, builder);
+            let second_instrs = BlockBuilder::new("second operand");
+            tail(env, &arguments[1], result, &second_instrs, builder);
+            let (true_branch, false_branch) = short_circuit(*primitive, result, second_instrs);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate: lowered_first,
+                    true_branch: true_branch.build(),
+                    false_branch: false_branch.build(),
+                },
+            ));
+        }
+        sized::Expr::Block(to_lower) => {
+            statements(env, to_lower, instrs, builder);
+            tail(env, &to_lower.result, result, instrs, builder);
+        }
+        sized::Expr::Case {
+            scrutinee, arms, ..
+        } => {
+            let lowered_scrutinee = expr(env, scrutinee, instrs, builder);
+            case_arms(env, &lowered_scrutinee, arms, result, true, instrs, builder);
+        }
+        sized::Expr::CallDirect {
+            function,
+            arguments,
+            ..
+        } if env.tail_group.contains(function) => {
+            let lowered_arguments: Vec<_> = arguments
+                .iter()
+                .map(|to_lower| expr(env, to_lower, instrs, builder))
+                .collect();
+            // every argument is copied before any is moved into place, because the callee's arguments can be this function's own
+            let copies: Vec<_> = lowered_arguments
+                .into_iter()
+                .map(|argument| {
+                    let copy = env.fresh_variable(argument.typ.clone(), *argument.witness.clone());
+                    instrs.push(Instr::new(copy.clone(), Expr::copy(argument)));
+                    copy
+                })
+                .collect();
+            let targets: Vec<_> = copies
+                .iter()
+                .enumerate()
+                .map(|(i, copy)| {
+                    Variable::new(
+                        tail_argument_name(function, i),
+                        copy.typ.clone(),
+                        *copy.witness.clone(),
+                    )
+                })
+                .collect();
+            // the values are moved before the witness tables they might be described by get replaced
+            let (types, values): (Vec<_>, Vec<_>) = targets
+                .iter()
+                .zip(copies)
+                .partition(|(target, _)| matches!(target.witness.as_ref(), Witness::Type));
+            for (target, copy) in values.into_iter().chain(types) {
+                instrs.push(Instr::new(target.clone(), Expr::mov(copy)));
+            }
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::TailCall {
+                    function: function.clone(),
+                    arguments: targets,
+                },
+            ));
+        }
+        _ => {
+            let value = expr(env, to_lower, instrs, builder);
+            instrs.push(Instr::new(result.clone(), Expr::mov(value)));
+        }
+    }
+}
+
 pub fn expr(
     env: &mut Env,
     to_lower: &sized::Expr,
@@ -129,20 +347,22 @@ pub fn expr(
     builder: &mut Builder,
 ) -> Variable {
     match to_lower {
-        sized::Expr::Variable { name, typ } => {
+        sized::Expr::Variable { name, typ, .. } => {
             let witness = witness(env, &name.witness, instrs, builder);
-            env.define_variable(name.name.clone(), typ.clone(), witness)
+            let name = env.rename(&name.name);
+            env.define_variable(name, typ.clone(), witness)
         }
-        sized::Expr::Literal { literal } => {
+        sized::Expr::Literal { literal, typ, .. } => {
             let name = env.fresh_name();
-            let var = env.define_variable(name, literal.get_type(), Witness::trivial(8));
-            instrs.push(Instr::new(var.clone(), Expr::Literal(literal.clone())));
+            let var = env.define_variable(name, typ.clone(), Witness::trivial(8));
+            instrs.push(Instr::new(var.clone(), Expr::Literal(number(literal, typ))));
             var
         }
         sized::Expr::CallDirect {
             function,
             arguments,
             tag,
+            ..
         } => {
             let name = env.fresh_name();
             let result_witness = witness(env, &tag.witness, instrs, builder);
@@ -152,7 +372,6 @@ pub fn expr(
                 .map(|to_lower| expr(env, to_lower, instrs, builder))
                 .collect();
             lowered_arguments.insert(0, result.clone());
-            dbg!(&lowered_arguments, &tag.signature.len());
             let tagged_arguments: Vec<_> = lowered_arguments
                 .into_iter()
                 .zip(tag.signature.clone())
@@ -171,13 +390,71 @@ pub fn expr(
 
             result
         }
+        sized::Expr::CallIndirect {
+            function,
+            arguments,
+            tag,
+            ..
+        } => {
+            let closure = expr(env, function, instrs, builder);
+            let name = env.fresh_name();
+            let result_witness = witness(env, &tag.witness, instrs, builder);
+            let result = env.define_variable(name, tag.result.clone(), result_witness);
+            let mut lowered_arguments: Vec<_> = arguments
+                .iter()
+                .map(|to_lower| expr(env, to_lower, instrs, builder))
+                .collect();
+            lowered_arguments.insert(0, result.clone());
+            let tagged_arguments: Vec<_> = lowered_arguments
+                .into_iter()
+                .zip(tag.signature.clone())
+                .map(|(value, convention)| CallArgument {
+                    value: Value::Copy(value),
+                    convention,
+                })
+                .collect();
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::CallIndirect {
+                    closure,
+                    arguments: tagged_arguments,
+                },
+            ));
+
+            result
+        }
         sized::Expr::Block(to_lower) => block(env, to_lower, instrs, builder),
+        sized::Expr::Primitive {
+            primitive: primitive @ (Primitive::And | Primitive::Or),
+            arguments,
+            ..
+        } => {
+            // the second operand is only evaluated if the first one doesn't already decide the result
+            let name = env.fresh_name();
+            let result = env.define_variable(name, Type::bool(), Witness::trivial(8));
+            let lowered_first = expr(env, &arguments[0], instrs, builder);
+            let second_instrs = BlockBuilder::new("second operand");
+            let lowered_second = expr(env, &arguments[1], &second_instrs, builder);
+            second_instrs.push(Instr::new(result.clone(), Expr::copy(lowered_second)));
+            let (true_branch, false_branch) = short_circuit(*primitive, &result, second_instrs);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate: lowered_first,
+                    true_branch: true_branch.build(),
+                    false_branch: false_branch.build(),
+                },
+            ));
+            result
+        }
         sized::Expr::Primitive {
             primitive,
             arguments,
+            ..
         } => {
             let name = env.fresh_name();
-            let result = env.define_variable(name, arguments[0].get_type(), Witness::trivial(8));
+            let typ = primitive.result_type(arguments[0].get_type());
+            let result = env.define_variable(name, typ, Witness::trivial(8));
             let lowered_args = arguments
                 .iter()
                 .map(|to_lower| expr(env, to_lower, instrs, builder))
@@ -192,6 +469,7 @@ pub fn expr(
             name: struct_name,
             fields,
             tag,
+            ..
         } => {
             let name = env.fresh_name();
             let result_witness = witness(env, &tag.witness, instrs, builder);
@@ -220,6 +498,7 @@ pub fn expr(
             true_branch,
             false_branch,
             tag,
+            ..
         } => {
             let witness = witness(env, &tag.witness, instrs, builder);
             let name = env.fresh_name();
@@ -245,6 +524,7 @@ pub fn expr(
             arguments,
             body,
             tag,
+            span,
         } => {
             let env_name = {
                 // closures are compiled into a (function pointer, existential consisting of a heap allocated witness table describing an accompanying env)
@@ -288,32 +568,25 @@ pub fn expr(
                         witness: body.get_witness(),
                     },
                 );
-                let witness_arguments: Vec<_> = tag
-                    .type_captures
-                    .iter()
-                    .map(|arg| sized::Expr::Variable {
-                        name: sized::Variable {
-                            name: arg.name.clone(),
-                            witness: sized::Witness::Type,
-                        },
-                        typ: sized::Type::typ(),
-                    })
-                    .collect();
                 arguments.push(sized::Argument {
                     name: String::from("_env"),
                     typ: env_type.clone(),
                     witness: sized::Witness::Dynamic {
-                        value: Box::new(sized::Expr::CallDirect {
-                            tag: sized::Call {
-                                result: Type::typ(),
+                        value: Box::new(sized::Expr::Variable {
+                            name: sized::Variable {
+                                name: String::from("_env_witness"),
                                 witness: sized::Witness::Type,
-                                signature: make_signature(witness_arguments.len()),
                             },
-                            function: env_name.clone(),
-                            arguments: witness_arguments,
+                            typ: Type::typ(),
+                            span: *span,
                         }),
                     },
                 });
+                arguments.push(sized::Argument {
+                    name: String::from("_env_witness"),
+                    typ: Type::typ(),
+                    witness: sized::Witness::Type,
+                });
                 arguments.extend(make_args(&tag.type_captures));
                 arguments.extend(make_args(&tag.value_captures));
                 sized::Function {
@@ -322,17 +595,13 @@ pub fn expr(
                     arguments,
                     result: body.get_type(),
                     body: body.as_ref().clone(),
+                    span: *span,
                 }
             };
             let mut lowered_func = function(&mocked_function, builder);
             let real_arguments = lowered_func.arguments.len() - tag.value_captures.len();
-            let env_argument = real_arguments - tag.type_captures.len() - 1;
-            let env_witness_variable = lowered_func.arguments[env_argument]
-                .name
-                .witness
-                .as_ref()
-                .clone()
-                .unwrap_dynamic();
+            let env_argument = real_arguments - tag.type_captures.len() - 2;
+            let env_witness_variable = lowered_func.arguments[env_argument + 1].name.clone();
             let env_variable = Variable::new(
                 String::from("_env"),
                 env_type.clone(),
@@ -340,20 +609,31 @@ pub fn expr(
                     location: env_witness_variable.clone(),
                 },
             );
-            let witness_preamble = BlockBuilder::new("witness preamble");
-            let unpacking_type_args: Vec<_> = tag
+            // the type captures are stored in the env's witness table, since closure calls don't pass them
+            let type_preamble: Vec<_> = tag
                 .type_captures
                 .iter()
-                .map(|capture| {
-                    Variable::new(capture.name.clone(), capture.typ.clone(), Witness::Type)
+                .enumerate()
+                .map(|(index, capture)| {
+                    Instr::new(
+                        Variable::new(capture.name.clone(), capture.typ.clone(), Witness::Type),
+                        Expr::TypeArgument {
+                            witness: env_witness_variable.clone(),
+                            index,
+                        },
+                    )
                 })
                 .collect();
-
+            let witness_preamble = BlockBuilder::new("witness preamble");
+            // the preamble is part of the closure's function, so its names mustn't clash with the ones in the body
+            let mut closure_env = Env::new();
+            closure_env.name_source = lowered_func.names.clone();
             let value_preamble: Vec<_> = tag
                 .value_captures
                 .iter()
                 .map(|arg| {
-                    let witness = witness(env, &arg.witness, &witness_preamble, builder);
+                    let witness =
+                        witness(&mut closure_env, &arg.witness, &witness_preamble, builder);
                     let target = Variable::new(arg.name.clone(), arg.typ.clone(), witness);
                     Instr::new(
                         target,
@@ -361,7 +641,7 @@ pub fn expr(
                             value: env_variable.clone(),
                             field: arg.name.clone(),
                             struct_name: env_name.clone(),
-                            type_arguments: unpacking_type_args.clone(),
+                            offset: None,
                         },
                     )
                 })
@@ -375,8 +655,11 @@ pub fn expr(
             for (i, instr) in preamble.enumerate() {
                 lowered_func.body.instrs.insert(i + pre_preamble, instr);
             }
-            lowered_func.arguments.truncate(real_arguments);
-            lowered_func = count_function(lowered_func);
+            for (i, instr) in type_preamble.into_iter().enumerate() {
+                lowered_func.body.instrs.insert(i, instr);
+            }
+            lowered_func.arguments.truncate(env_argument + 2);
+            lowered_func = count_function(cse::function(lowered_func));
 
             let made_env_witness = env.fresh_variable(Type::typ(), Witness::Type);
             let made_env = env.fresh_variable(
@@ -415,7 +698,11 @@ pub fn expr(
                     let witness = witness(env, &capture.witness, instrs, builder);
                     PackField {
                         name: capture.name.clone(),
-                        value: Value::Copy(Variable::new(capture.name, capture.typ, witness)),
+                        value: Value::Copy(Variable::new(
+                            env.rename(&capture.name),
+                            capture.typ,
+                            witness,
+                        )),
                     }
                 })
                 .collect();
@@ -439,8 +726,382 @@ pub fn expr(
             builder.functions.push(lowered_func);
             closure_var
         }
+        sized::Expr::VariantPack {
+            name,
+            arguments,
+            tag,
+            ..
+        } => {
+            let result_witness = witness(env, &tag.witness, instrs, builder);
+            let result = env.fresh_variable(tag.result.clone(), result_witness);
+            let (enum_name, variant) = builder.variant(name, &tag.result);
+            let lowered_arguments = arguments
+                .iter()
+                .map(|arg| Value::Copy(expr(env, arg, instrs, builder)))
+                .collect();
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::VariantPack {
+                    enum_name,
+                    variant,
+                    arguments: lowered_arguments,
+                },
+            ));
+            result
+        }
+        sized::Expr::Case {
+            scrutinee,
+            arms,
+            tag,
+            ..
+        } => {
+            let lowered_scrutinee = expr(env, scrutinee, instrs, builder);
+            let witness = witness(env, &tag.witness, instrs, builder);
+            let result = env.fresh_variable(arms[0].body.get_type(), witness);
+            case_arms(
+                env,
+                &lowered_scrutinee,
+                arms,
+                &result,
+                false,
+                instrs,
+                builder,
+            );
+            result
+        }
+        sized::Expr::FieldAccess {
+            value, field, tag, ..
+        } => {
+            let lowered_value = expr(env, value, instrs, builder);
+            let Type::Named {
+                name: struct_name, ..
+            } = lowered_value.typ.canonical().clone()
+            else {
+                unreachable!("field access on a non-struct value")
+            };
+            let result_witness = witness(env, &tag.witness, instrs, builder);
+            let result = env.fresh_variable(tag.result.clone(), result_witness);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::Unpack {
+                    struct_name,
+                    value: lowered_value,
+                    field: field.clone(),
+                    offset: tag.offset,
+                },
+            ));
+            result
+        }
+        sized::Expr::Unwrap { value, tag, .. } => {
+            // an error union holds its value as the only field of its first variant
+            let lowered_value = expr(env, value, instrs, builder);
+            let is_value = env.fresh_variable(Type::bool(), Witness::trivial(8));
+            instrs.push(Instr::new(
+                is_value.clone(),
+                Expr::IsVariant {
+                    value: lowered_value.clone(),
+                    variant: 0,
+                },
+            ));
+            let result_witness = witness(env, &tag.witness, instrs, builder);
+            let result = env.fresh_variable(tag.result.clone(), result_witness);
+            let value_instrs = BlockBuilder::new("unwrapped value");
+            value_instrs.push(Instr::new(
+                result.clone(),
+                Expr::UnpackVariant {
+                    enum_name: String::from(RESULT),
+                    variant: 0,
+                    field: 0,
+                    value: lowered_value,
+                    offset: None,
+                },
+            ));
+            let error_instrs = BlockBuilder::new("unwrapped error");
+            error_instrs.push(Instr::new(result.clone(), Expr::Crash));
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate: is_value,
+                    true_branch: value_instrs.build(),
+                    false_branch: error_instrs.build(),
+                },
+            ));
+            result
+        }
+        sized::Expr::ExistentialPack { value, tag, .. } => {
+            let lowered_value = expr(env, value, instrs, builder);
+            let lowered_witness = expr(env, &tag.witness, instrs, builder);
+            let lowered_dictionary = expr(env, &tag.dictionary, instrs, builder);
+            let result_witness = witness(env, &sized::Witness::Existential, instrs, builder);
+            let result = env.fresh_variable(to_lower.get_type(), result_witness);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::ExistentialPack {
+                    value: Value::Copy(lowered_value),
+                    witness: Value::Copy(lowered_witness),
+                    dictionary: Value::Copy(lowered_dictionary),
+                },
+            ));
+            result
+        }
+    }
+}
+
+/// the branches of the `If` that `&&` or `||` becomes, given the branch that evaluates the second operand into `result`
+fn short_circuit(
+    primitive: Primitive,
+    result: &Variable,
+    second_instrs: BlockBuilder,
+) -> (BlockBuilder, BlockBuilder) {
+    let decided_instrs = BlockBuilder::new("short circuit");
+    let decided = matches!(primitive, Primitive::Or);
+    decided_instrs.push(Instr::new(
+        result.clone(),
+        Expr::Literal(Literal::Boolean(decided)),
+    ));
+    if decided {
+        (decided_instrs, second_instrs)
+    } else {
+        (second_instrs, decided_instrs)
+    }
+}
+
+/// lowers the arms of a case expression into a chain of `If`s, each of which writes its arm's value to `result`.
+/// when the case is in tail position, the arms are lowered as tail expressions themselves
+fn case_arms(
+    env: &mut Env,
+    scrutinee: &Variable,
+    arms: &[sized::Arm],
+    result: &Variable,
+    in_tail: bool,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) {
+    let (arm, rest) = arms.split_first().unwrap();
+    // the type checker has made sure the arms are exhaustive, so the last one doesn't need to be tested
+    let predicate = if rest.is_empty() {
+        None
+    } else {
+        test(env, scrutinee, &arm.pattern, instrs, builder)
+    };
+    let arm_instrs = BlockBuilder::new("matching arm");
+    let renames = env.renames.clone();
+    bind(env, scrutinee, &arm.pattern, &arm_instrs, builder);
+    if in_tail {
+        tail(env, &arm.body, result, &arm_instrs, builder);
+    } else {
+        let value = expr(env, &arm.body, &arm_instrs, builder);
+        arm_instrs.push(Instr::new(result.clone(), Expr::copy(value)));
+    }
+    env.renames = renames;
+    match predicate {
+        Some(predicate) => {
+            let rest_instrs = BlockBuilder::new("remaining arms");
+            case_arms(env, scrutinee, rest, result, in_tail, &rest_instrs, builder);
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate,
+                    true_branch: arm_instrs.build(),
+                    false_branch: rest_instrs.build(),
+                },
+            ));
+        }
+        None => {
+            for instr in arm_instrs.build().instrs {
+                instrs.push(instr);
+            }
+        }
+    }
+}
+
+/// computes a `Bool` that says whether `value` matches `pattern`, or `None` if every value does
+fn test(
+    env: &mut Env,
+    value: &Variable,
+    pattern: &sized::Pattern,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) -> Option<Variable> {
+    match pattern {
+        sized::Pattern::Wildcard { .. } | sized::Pattern::Binding { .. } => None,
+        sized::Pattern::Literal { literal, .. } => {
+            let result = env.fresh_variable(Type::bool(), Witness::trivial(8));
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::IsLiteral {
+                    value: value.clone(),
+                    literal: number(literal, &value.typ),
+                },
+            ));
+            Some(result)
+        }
+        sized::Pattern::Variant {
+            name, fields, tag, ..
+        } => {
+            let (enum_name, variant) = builder.variant(name, &tag.typ);
+            let is_variant = env.fresh_variable(Type::bool(), Witness::trivial(8));
+            instrs.push(Instr::new(
+                is_variant.clone(),
+                Expr::IsVariant {
+                    value: value.clone(),
+                    variant,
+                },
+            ));
+            let refutable: Vec<_> = fields
+                .iter()
+                .enumerate()
+                .filter(|(_, field)| {
+                    matches!(
+                        field,
+                        sized::Pattern::Literal { .. } | sized::Pattern::Variant { .. }
+                    )
+                })
+                .collect();
+            if refutable.is_empty() {
+                return Some(is_variant);
+            }
+            // the fields may only be read once the tag is known to be right
+            let result = env.fresh_variable(Type::bool(), Witness::trivial(8));
+            let field_instrs = BlockBuilder::new("variant fields");
+            let fields_match = test_fields(
+                env,
+                value,
+                (&enum_name, variant),
+                fields,
+                &refutable,
+                &field_instrs,
+                builder,
+            );
+            field_instrs.push(Instr::new(result.clone(), Expr::copy(fields_match)));
+            let other_instrs = BlockBuilder::new("other variant");
+            other_instrs.push(Instr::new(
+                result.clone(),
+                Expr::Literal(Literal::Boolean(false)),
+            ));
+            instrs.push(Instr::new(
+                result.clone(),
+                Expr::If {
+                    predicate: is_variant,
+                    true_branch: field_instrs.build(),
+                    false_branch: other_instrs.build(),
+                },
+            ));
+            Some(result)
+        }
+    }
+}
+
+/// computes whether every one of `fields` matches, only unpacking each field once the ones before it have matched
+fn test_fields(
+    env: &mut Env,
+    value: &Variable,
+    variant: (&String, usize),
+    all: &[sized::Pattern],
+    fields: &[(usize, &sized::Pattern)],
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) -> Variable {
+    let ((index, pattern), rest) = fields.split_first().unwrap();
+    let field = unpack_field(env, value, variant, all, *index, instrs, builder);
+    let matched = test(env, &field, pattern, instrs, builder).unwrap();
+    if rest.is_empty() {
+        return matched;
+    }
+    let result = env.fresh_variable(Type::bool(), Witness::trivial(8));
+    let rest_instrs = BlockBuilder::new("remaining fields");
+    let rest_match = test_fields(env, value, variant, all, rest, &rest_instrs, builder);
+    rest_instrs.push(Instr::new(result.clone(), Expr::copy(rest_match)));
+    let mismatch_instrs = BlockBuilder::new("mismatched field");
+    mismatch_instrs.push(Instr::new(
+        result.clone(),
+        Expr::Literal(Literal::Boolean(false)),
+    ));
+    instrs.push(Instr::new(
+        result.clone(),
+        Expr::If {
+            predicate: matched,
+            true_branch: rest_instrs.build(),
+            false_branch: mismatch_instrs.build(),
+        },
+    ));
+    result
+}
+
+/// defines the variables bound by `pattern`, which `value` is already known to match
+fn bind(
+    env: &mut Env,
+    value: &Variable,
+    pattern: &sized::Pattern,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) {
+    match pattern {
+        sized::Pattern::Wildcard { .. } | sized::Pattern::Literal { .. } => {}
+        sized::Pattern::Binding { name, tag, .. } => {
+            let witness = witness(env, &tag.witness, instrs, builder);
+            let target = env.define_binding(name.clone(), tag.typ.clone(), witness);
+            instrs.push(Instr::new(target, Expr::copy(value.clone())));
+        }
+        sized::Pattern::Variant {
+            name, fields, tag, ..
+        } => {
+            let (enum_name, variant) = builder.variant(name, &tag.typ);
+            for (index, field) in fields.iter().enumerate() {
+                if !field.bindings().is_empty() {
+                    let unpacked = unpack_field(
+                        env,
+                        value,
+                        (&enum_name, variant),
+                        fields,
+                        index,
+                        instrs,
+                        builder,
+                    );
+                    bind(env, &unpacked, field, instrs, builder);
+                }
+            }
+        }
     }
 }
+
+fn unpack_field(
+    env: &mut Env,
+    value: &Variable,
+    (enum_name, variant): (&String, usize),
+    fields: &[sized::Pattern],
+    field: usize,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) -> Variable {
+    // every field of an enum that is laid out statically is too, so the ones before this one say where it is
+    let offset = match value.witness.as_ref() {
+        Witness::Trivial { .. } => fields[..field]
+            .iter()
+            .map(|pattern| match pattern.tag().witness {
+                sized::Witness::Trivial { size } => Some(size),
+                _ => None,
+            })
+            .sum::<Option<usize>>()
+            .map(|size| 8 + size),
+        _ => None,
+    };
+    let tag = fields[field].tag();
+    let field_witness = witness(env, &tag.witness, instrs, builder);
+    let typ = tag.typ.clone();
+    let target = env.fresh_variable(typ, field_witness);
+    instrs.push(Instr::new(
+        target.clone(),
+        Expr::UnpackVariant {
+            enum_name: enum_name.clone(),
+            variant,
+            field,
+            value: value.clone(),
+            offset,
+        },
+    ));
+    target
+}
 fn first_non_type<'a, I: ExactSizeIterator<Item = &'a Instr>>(
     instrs: impl IntoIterator<IntoIter = I>,
 ) -> usize {
@@ -463,17 +1124,32 @@ fn block(
     instrs: &BlockBuilder,
     builder: &mut Builder,
 ) -> Variable {
+    statements(env, to_lower, instrs, builder);
+    expr(env, &to_lower.result, instrs, builder)
+}
+
+fn statements(
+    env: &mut Env,
+    to_lower: &sized::Block,
+    instrs: &BlockBuilder,
+    builder: &mut Builder,
+) {
     for stmt in &to_lower.stmts {
         match stmt {
-            sized::Statement::Let { name, typ, value } => {
+            sized::Statement::Let {
+                name, typ, value, ..
+            } => {
                 let source = expr(env, value, instrs, builder);
+                env.forget_binding(&name.name);
                 let target =
                     env.define_variable(name.name.clone(), typ.clone(), *source.witness.clone());
                 instrs.push(Instr::new(target, Expr::copy(source)));
             }
+            sized::Statement::Function(_) => {
+                unreachable!("the type checker lifts local functions to the top level")
+            }
         }
     }
-    expr(env, &to_lower.result, instrs, builder)
 }
 
 fn witness(
@@ -488,7 +1164,34 @@ fn witness(
             location: expr(env, value.as_ref(), instrs, builder),
         },
         sized::Witness::Type => Witness::Type,
-        sized::Witness::Existential => todo!(),
+        // every existential has the same layout, so its table is made by a builder without arguments
+        sized::Witness::Existential => Witness::Dynamic {
+            location: expr(
+                env,
+                &sized::Expr::CallDirect {
+                    function: String::from("_Existential"),
+                    arguments: Vec::new(),
+                    tag: sized::Call {
+                        result: Type::typ(),
+                        witness: sized::Witness::Type,
+                        signature: make_signature(0),
+                    },
+                    span: Span::default(),
+                },
+                instrs,
+                builder,
+            ),
+        },
+    }
+}
+
+/// integer literals that were inferred to be floats become float literals
+fn number(literal: &Literal, typ: &Type) -> Literal {
+    match literal {
+        Literal::Integer(integer) if *typ.canonical() == Type::float() => {
+            Literal::Float(*integer as f64)
+        }
+        _ => literal.clone(),
     }
 }
 
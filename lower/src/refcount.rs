@@ -1,17 +1,33 @@
 use im::{hashset, HashSet};
 use ir::bridge::{Block, Convention, Expr, Function, Instr, Value, Variable, Witness};
 
+/// inserts a destroy after the last use of each variable that a function owns.
+///
+/// what an argument owns depends on its convention:
+/// - `in` arguments belong to the callee, which destroys them
+/// - `inout` arguments are lent to the callee, which can replace their value but leaves them to the caller
+/// - `out` arguments are written by the callee, and belong to the caller once they are
+///
+/// and at each call, what happens to an argument's variable depends on its convention and its value:
+/// - `in copy` reads the variable, which the caller still owns
+/// - `in move` gives the variable to the callee, so the caller mustn't destroy it
+/// - `inout copy` and `inout move` both read the variable and write it back, and the caller still owns it afterwards,
+///   since the callee can't give away what it's only borrowing
+/// - `out copy` and `out move` both write the variable, since nothing is read from an `out` argument
 pub fn count_function(function: Function) -> Function {
     let args = function
         .arguments
         .iter()
         .map(|arg| arg.name.clone())
         .collect();
-    let block = count_block(
-        function.body.clone(),
-        function.body.instrs.last().unwrap().target.clone(),
-        args,
-    );
+    // the caller owns what is written to its out arguments, and what it lent through its inout arguments
+    let results = function
+        .arguments
+        .iter()
+        .filter(|arg| arg.convention != Convention::In)
+        .map(|arg| arg.name.clone())
+        .collect();
+    let block = count_block(function.body.clone(), &args, &results);
     Function {
         name: function.name,
         arguments: function.arguments,
@@ -20,40 +36,92 @@ pub fn count_function(function: Function) -> Function {
     }
 }
 
-fn count_block(mut block: Block, result: Variable, written: HashSet<Variable>) -> Block {
-    dbg!(&result);
+/// destroys each variable of `block` after its last use.
+/// `written` is what the block owns when it starts, and `live` is what is still used after it, which it must leave alone
+fn count_block(mut block: Block, written: &HashSet<Variable>, live: &HashSet<Variable>) -> Block {
     let size = block.instrs.len();
-    let uses: Vec<_> = block
+    let mut uses: Vec<_> = block
         .instrs
         .iter()
         .map(|instr| find_instr_uses(instr))
         .collect();
-    dbg!(&uses);
-    let mut read = HashSet::new();
-    let mut first_writes = vec![HashSet::new(); size];
-    first_writes[0] = uses[0].writes.clone().union(written);
+    // the variables defined before each instruction, as they were defined, since a read
+    // can give a variable a witness table that only exists in a branch
+    let mut defined = vec![written.clone(); size];
     for i in 1..size {
-        first_writes[i] = first_writes[i - 1].clone().union(uses[i].writes.clone());
+        defined[i] = defined[i - 1]
+            .clone()
+            .union(own_writes(&block.instrs[i - 1], &uses[i - 1]));
     }
-    dbg!(&first_writes);
-
-    let mut last_reads: Vec<_> = uses
-        .into_iter()
-        .zip(first_writes)
-        .rev()
-        .map(|(uses, written)| {
-            let mut first_read = HashSet::new();
-            for to_read in uses.reads {
-                if !read.contains(&to_read) && written.contains(&to_read) {
-                    first_read.insert(to_read.clone());
-                    read.insert(to_read);
+    for (i, instr) in block.instrs.iter().enumerate() {
+        if matches!(instr.value, Expr::If { .. }) {
+            // a branch destroys what it owns but doesn't use with the table it was defined with, so that table has to outlive the if
+            for variable in uses[i].reads.clone() {
+                if let Some(Witness::Dynamic { location }) = defined[i]
+                    .iter()
+                    .find(|defined| **defined == variable)
+                    .map(|defined| defined.witness.as_ref())
+                {
+                    uses[i].reads.insert(location.clone());
                 }
             }
-            first_read
+        }
+    }
+    // everything that is used after each instruction
+    let mut used_after = vec![live.clone(); size];
+    for i in (0..size - 1).rev() {
+        used_after[i] = used_after[i + 1].clone().union(uses[i + 1].mentions());
+    }
+    // what each instruction writes that is never used again, which is dead as soon as it's written
+    let mut dead: Vec<HashSet<Variable>> = block
+        .instrs
+        .iter()
+        .zip(&uses)
+        .zip(&used_after)
+        .map(|((instr, uses), used_after)| {
+            if matches!(instr.value, Expr::If { .. }) {
+                // the branches only read the target to write it
+                hashset![instr.target.clone()].relative_complement(used_after.clone())
+            } else {
+                uses.writes.clone().relative_complement(
+                    used_after
+                        .clone()
+                        .union(uses.reads.clone())
+                        .union(uses.destroys.clone()),
+                )
+            }
         })
         .collect();
-    last_reads.reverse();
-    for (i, reads) in last_reads.into_iter().enumerate().rev() {
+    // what the block owns from the start but never uses is destroyed as soon as its witness table has been made
+    let mut unused = HashSet::new();
+    for variable in written
+        .clone()
+        .relative_complement(used_after[0].clone().union(uses[0].mentions()))
+    {
+        let made = match variable.witness.as_ref() {
+            Witness::Dynamic { location } if !written.contains(location) => block
+                .instrs
+                .iter()
+                .zip(&uses)
+                .position(|(instr, uses)| own_writes(instr, uses).contains(location)),
+            _ => None,
+        };
+        match made {
+            Some(i) => {
+                dead[i].insert(variable);
+            }
+            None => {
+                unused.insert(variable);
+            }
+        }
+    }
+
+    for ((i, reads), dead) in last_reads(&block, &uses, written)
+        .into_iter()
+        .enumerate()
+        .zip(dead)
+        .rev()
+    {
         let instr = &mut block.instrs[i];
         if let Expr::If {
             true_branch,
@@ -61,19 +129,95 @@ fn count_block(mut block: Block, result: Variable, written: HashSet<Variable>) -
             ..
         } = &mut instr.value
         {
-            *true_branch = count_block(true_branch.clone(), instr.target.clone(), reads.clone());
-            *false_branch = count_block(false_branch.clone(), instr.target.clone(), reads);
+            // the branches own what was defined before the if and last read in it, but can't destroy what is used after it
+            let owned: HashSet<_> = defined[i]
+                .iter()
+                .filter(|variable| reads.contains(*variable) && !live.contains(*variable))
+                .cloned()
+                .collect();
+            let live = used_after[i].clone().update(instr.target.clone());
+            *true_branch = count_block(true_branch.clone(), &owned, &live);
+            *false_branch = count_block(false_branch.clone(), &owned, &live);
+            destroy_at(&mut block, i + 1, dead);
         } else {
-            for to_destroy in reads {
-                block
-                    .instrs
-                    .insert(i + 1, Instr::new(to_destroy, Expr::Destroy));
-            }
+            destroy_at(
+                &mut block,
+                i + 1,
+                reads.relative_complement(live.clone()).union(dead),
+            );
         }
     }
+    destroy_at(&mut block, 0, unused);
     block
 }
 
+/// what each instruction of a block reads for the last time, out of what has been written by then.
+/// an if is the last use of what its branches move as well, since the branch that doesn't move it has to destroy it
+fn last_reads(
+    block: &Block,
+    uses: &[VariableUses],
+    written: &HashSet<Variable>,
+) -> Vec<HashSet<Variable>> {
+    let mut read = HashSet::new();
+    let mut first_writes = vec![HashSet::new(); uses.len()];
+    first_writes[0] = uses[0].writes.clone().union(written.clone());
+    for i in 1..uses.len() {
+        first_writes[i] = first_writes[i - 1].clone().union(uses[i].writes.clone());
+    }
+    let mut last_reads: Vec<_> = block
+        .instrs
+        .iter()
+        .zip(uses)
+        .zip(first_writes)
+        .rev()
+        .map(|((instr, uses), written)| {
+            let to_read = if matches!(instr.value, Expr::If { .. }) {
+                uses.reads.clone().union(uses.destroys.clone())
+            } else {
+                // what is moved later can't be destroyed here
+                read.extend(uses.destroys.iter().cloned());
+                uses.reads.clone()
+            };
+            let mut first_read = HashSet::new();
+            for to_read in to_read {
+                if !read.contains(&to_read) && written.contains(&to_read) {
+                    first_read.insert(to_read.clone());
+                    read.insert(to_read);
+                }
+            }
+            first_read
+        })
+        .collect();
+    last_reads.reverse();
+    last_reads
+}
+
+/// what `instr` defines in the block it's in. whatever a branch defines is only visible inside it,
+/// so only the target of an if is written outside of it
+fn own_writes(instr: &Instr, uses: &VariableUses) -> HashSet<Variable> {
+    if matches!(instr.value, Expr::If { .. }) {
+        hashset![instr.target.clone()]
+    } else {
+        uses.writes.clone()
+    }
+}
+
+/// inserts destroys of `to_destroy` before the `at`th instruction of `block`
+fn destroy_at(block: &mut Block, at: usize, to_destroy: HashSet<Variable>) {
+    // values can't be destroyed without their witness tables, so the tables go last.
+    // each goes in order of its name, so that the same program always compiles to the same code
+    let mut to_destroy: Vec<_> = to_destroy.into_iter().collect();
+    to_destroy.sort_by(|a, b| a.name.cmp(&b.name));
+    let (types, values): (Vec<_>, Vec<_>) = to_destroy
+        .into_iter()
+        .partition(|variable| matches!(variable.witness.as_ref(), Witness::Type));
+    for to_destroy in values.into_iter().chain(types).rev() {
+        block
+            .instrs
+            .insert(at, Instr::new(to_destroy, Expr::Destroy));
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 struct VariableUses {
     writes: HashSet<Variable>,
@@ -107,6 +251,14 @@ impl VariableUses {
         self.writes.remove(variable);
     }
 
+    /// every variable that is read, destroyed or written
+    fn mentions(&self) -> HashSet<Variable> {
+        self.reads
+            .clone()
+            .union(self.destroys.clone())
+            .union(self.writes.clone())
+    }
+
     fn read_witness(&mut self, variable: &Variable) {
         if let Witness::Dynamic { location } = variable.witness.as_ref() {
             self.read(location.clone());
@@ -129,13 +281,16 @@ fn find_instr_uses(instr: &Instr) -> VariableUses {
         ..Default::default()
     };
     match &instr.value {
-        Expr::Literal(_) => {}
+        Expr::Literal(_) | Expr::Crash => {}
         Expr::Primitive(_, arguments) => {
             for arg in arguments {
                 uses.read(arg.clone());
             }
         }
-        Expr::CallDirect { arguments, .. } => {
+        Expr::CallDirect { arguments, .. } | Expr::CallIndirect { arguments, .. } => {
+            if let Expr::CallIndirect { closure, .. } = &instr.value {
+                uses.read(closure.clone());
+            }
             for arg in arguments {
                 match (arg.convention, &arg.value) {
                     (Convention::In, Value::Move(src)) => {
@@ -144,10 +299,11 @@ fn find_instr_uses(instr: &Instr) -> VariableUses {
                     (Convention::In, Value::Copy(src)) => {
                         uses.read(src.clone());
                     }
-                    (Convention::Inout, Value::Move(_)) => todo!(),
-                    (Convention::Inout, Value::Copy(_)) => todo!(),
-                    (Convention::Out, Value::Move(_)) => todo!(),
-                    (Convention::Out, Value::Copy(src)) => {
+                    (Convention::Inout, Value::Move(src) | Value::Copy(src)) => {
+                        uses.read(src.clone());
+                        uses.write(src.clone());
+                    }
+                    (Convention::Out, Value::Move(src) | Value::Copy(src)) => {
                         uses.write(src.clone());
                     }
                 }
@@ -165,6 +321,16 @@ fn find_instr_uses(instr: &Instr) -> VariableUses {
                 uses.extend(find_value_uses(&arg.value));
             }
         }
+        Expr::VariantPack { arguments, .. } => {
+            for arg in arguments {
+                uses.extend(find_value_uses(arg));
+            }
+        }
+        Expr::UnpackVariant { value, .. }
+        | Expr::IsVariant { value, .. }
+        | Expr::IsLiteral { value, .. } => {
+            uses.read(value.clone());
+        }
         Expr::If {
             predicate,
             true_branch,
@@ -179,11 +345,30 @@ fn find_instr_uses(instr: &Instr) -> VariableUses {
         Expr::Unpack { value, .. } => {
             uses.read(value.clone());
         }
+        Expr::TypeArgument { witness, .. } => {
+            uses.read(witness.clone());
+        }
         Expr::MakeClosure { env, witness, .. } => {
-            find_value_uses(env);
-            find_value_uses(witness);
+            uses.extend(find_value_uses(env));
+            uses.extend(find_value_uses(witness));
+        }
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => {
+            uses.extend(find_value_uses(value));
+            uses.extend(find_value_uses(witness));
+            uses.extend(find_value_uses(dictionary));
+        }
+        Expr::TailCall { arguments, .. } => {
+            // nothing runs after the jump, so reading the arguments' witness tables here would leave nowhere to destroy them
+            uses.destroys.extend(arguments.iter().cloned());
+            return uses;
         }
     }
+    // the target is written with its witness table
+    uses.read_witness(&instr.target);
     uses
 }
 
@@ -199,3 +384,268 @@ fn find_value_uses(value: &Value) -> VariableUses {
     }
     uses
 }
+
+#[cfg(test)]
+mod test {
+    use ir::bridge::{
+        Argument, Block, CallArgument, Convention, Expr, Function, Instr, Program, Value, Variable,
+        Witness,
+    };
+    use ir::name_source::NameSource;
+    use tree::sized::{Literal, Type};
+    use tree::String;
+
+    use super::count_function;
+    use crate::{borrow, rme};
+
+    fn table(name: &str) -> Variable {
+        Variable::new(String::from(name), Type::typ(), Witness::Type)
+    }
+
+    fn word(name: &str) -> Variable {
+        Variable::new(String::from(name), Type::integer(), Witness::trivial(8))
+    }
+
+    fn call(target: Variable, function: &str, arguments: Vec<(Value, Convention)>) -> Instr {
+        let mut arguments: Vec<_> = arguments
+            .into_iter()
+            .map(|(value, convention)| CallArgument { value, convention })
+            .collect();
+        arguments.insert(
+            0,
+            CallArgument {
+                value: Value::Copy(target.clone()),
+                convention: Convention::Out,
+            },
+        );
+        Instr::new(
+            target,
+            Expr::CallDirect {
+                function: String::from(function),
+                arguments,
+            },
+        )
+    }
+
+    fn function(name: &str, arguments: Vec<Argument>, instrs: Vec<Instr>) -> Function {
+        Function {
+            name: String::from(name),
+            arguments,
+            body: Block { instrs },
+            names: NameSource::default(),
+        }
+    }
+
+    fn argument(name: Variable, convention: Convention) -> Argument {
+        Argument { name, convention }
+    }
+
+    /// makes random blocks out of witness tables, which have to be destroyed exactly once
+    struct Generator {
+        rng: fastrand::Rng,
+        next: usize,
+    }
+
+    impl Generator {
+        fn fresh(&mut self) -> std::string::String {
+            self.next += 1;
+            format!("v{}", self.next)
+        }
+
+        fn value(&mut self, available: &mut Vec<Variable>, moved: bool) -> Value {
+            let index = self.rng.usize(..available.len());
+            if moved {
+                Value::Move(available.remove(index))
+            } else {
+                Value::Copy(available[index].clone())
+            }
+        }
+
+        /// a block that uses and adds to the tables in `available`, and ends by writing `result`
+        fn block(
+            &mut self,
+            available: &mut Vec<Variable>,
+            result: &Variable,
+            depth: usize,
+        ) -> Vec<Instr> {
+            let mut instrs = Vec::new();
+            for _ in 0..self.rng.usize(1..6) {
+                let choice = if available.is_empty() {
+                    0
+                } else {
+                    self.rng.usize(0..if depth < 2 { 6 } else { 5 })
+                };
+                match choice {
+                    0 => {
+                        let target = table(&self.fresh());
+                        let builtin = if self.rng.bool() { "F64" } else { "I64" };
+                        instrs.push(call(target.clone(), builtin, Vec::new()));
+                        available.push(target);
+                    }
+                    1 => {
+                        let target = table(&self.fresh());
+                        let moved = self.rng.bool();
+                        let value = self.value(available, moved);
+                        instrs.push(Instr::new(target.clone(), Expr::Value(value)));
+                        available.push(target);
+                    }
+                    2 => {
+                        let moved = self.rng.bool();
+                        let value = self.value(available, moved);
+                        instrs.push(call(
+                            word(&self.fresh()),
+                            "take",
+                            vec![(value, Convention::In)],
+                        ));
+                    }
+                    3 => {
+                        // an inout argument is only lent, so the caller keeps it whether it's moved or copied
+                        let lent = available[self.rng.usize(..available.len())].clone();
+                        let value = if self.rng.bool() {
+                            Value::Move(lent)
+                        } else {
+                            Value::Copy(lent)
+                        };
+                        instrs.push(call(
+                            word(&self.fresh()),
+                            "lend",
+                            vec![(value, Convention::Inout)],
+                        ));
+                    }
+                    4 => {
+                        // writing to an out argument neither reads nor destroys the variable passed for it
+                        let target = table(&self.fresh());
+                        let value = if self.rng.bool() {
+                            Value::Move(target.clone())
+                        } else {
+                            Value::Copy(target.clone())
+                        };
+                        instrs.push(Instr::new(
+                            target.clone(),
+                            Expr::CallDirect {
+                                function: String::from("I64"),
+                                arguments: vec![CallArgument {
+                                    value,
+                                    convention: Convention::Out,
+                                }],
+                            },
+                        ));
+                        available.push(target);
+                    }
+                    _ => {
+                        let predicate = word(&self.fresh());
+                        instrs.push(Instr::new(
+                            predicate.clone(),
+                            Expr::Literal(Literal::Boolean(self.rng.bool())),
+                        ));
+                        let target = table(&self.fresh());
+                        let mut true_available = available.clone();
+                        let true_branch = self.block(&mut true_available, &target, depth + 1);
+                        let mut false_available = available.clone();
+                        let false_branch = self.block(&mut false_available, &target, depth + 1);
+                        // only what both branches leave alone is still there after the if
+                        available.retain(|variable| {
+                            true_available.contains(variable) && false_available.contains(variable)
+                        });
+                        instrs.push(Instr::new(
+                            target.clone(),
+                            Expr::If {
+                                predicate,
+                                true_branch: Block {
+                                    instrs: true_branch,
+                                },
+                                false_branch: Block {
+                                    instrs: false_branch,
+                                },
+                            },
+                        ));
+                        available.push(target);
+                    }
+                }
+            }
+            instrs.push(if result.witness.is_trivial() {
+                Instr::new(result.clone(), Expr::Literal(Literal::Integer(7)))
+            } else {
+                call(result.clone(), "F64", Vec::new())
+            });
+            instrs
+        }
+    }
+
+    /// a random `main` that passes witness tables around, along with the functions it calls, before refcounting
+    fn program(seed: u64) -> Program {
+        let mut generator = Generator {
+            rng: fastrand::Rng::with_seed(seed),
+            next: 0,
+        };
+        let result = word("_result");
+        let main = generator.block(&mut Vec::new(), &result, 0);
+        Program {
+            structs: Vec::new(),
+            enums: Vec::new(),
+            protocols: Vec::new(),
+            impls: Vec::new(),
+            functions: vec![
+                function(
+                    "main",
+                    vec![argument(result.clone(), Convention::Out)],
+                    main,
+                ),
+                // takes the table, without using it
+                function(
+                    "take",
+                    vec![
+                        argument(result.clone(), Convention::Out),
+                        argument(table("a"), Convention::In),
+                    ],
+                    vec![Instr::new(
+                        result.clone(),
+                        Expr::Literal(Literal::Integer(1)),
+                    )],
+                ),
+                // replaces the table it was lent
+                function(
+                    "lend",
+                    vec![
+                        argument(result.clone(), Convention::Out),
+                        argument(table("a"), Convention::Inout),
+                    ],
+                    vec![
+                        Instr::new(table("a"), Expr::Destroy),
+                        call(table("a"), "I64", Vec::new()),
+                        Instr::new(result, Expr::Literal(Literal::Integer(2))),
+                    ],
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn destroys_everything_once() {
+        let mut removed = 0;
+        for seed in 0..1000 {
+            let mut program = program(seed);
+            program.functions = program.functions.into_iter().map(count_function).collect();
+            run(seed, &program);
+            // borrowing has to keep every value destroyed exactly once, with fewer operations
+            let counted = borrow::operations(&program).total();
+            let borrowed = borrow::program(program);
+            run(seed, &borrowed);
+            run(seed, &rme::program(borrowed.clone()));
+            let left = borrow::operations(&borrowed).total();
+            assert!(left <= counted, "seed {seed}: {borrowed}");
+            removed += counted - left;
+        }
+        assert!(removed > 0);
+    }
+
+    fn run(seed: u64, program: &Program) {
+        let run = interpret::run(program, "main", 8)
+            .unwrap_or_else(|error| panic!("seed {seed}: {error}\n{program}"));
+        assert!(
+            run.leaks.is_empty(),
+            "seed {seed}: {}\n{program}",
+            run.leaks[0]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use im::{HashMap, HashSet};
+use ir::bridge::{Block, Expr, Instr, Program, Value, Variable, Witness};
+use tree::String;
+
+use crate::borrow::mentions;
+
+/// redundant move elimination: a value that is only made to be moved into another variable is made in that variable instead.
+///
+/// values moved into `in` arguments don't need this, since a moved argument is passed in the variable it's already in
+#[must_use]
+pub fn program(mut program: Program) -> Program {
+    for function in &mut program.functions {
+        let mut counts = HashMap::new();
+        for instr in &function.body.instrs {
+            let mut mentioned = Vec::new();
+            mentions(instr, &mut mentioned);
+            for variable in mentioned {
+                *counts.entry(variable.name).or_default() += 1;
+            }
+        }
+        let mut jumped = HashSet::new();
+        jumps(&function.body, &mut jumped);
+        let mut eliminator = Eliminator { counts, jumped };
+        eliminator.block(&mut function.body);
+    }
+    program
+}
+
+struct Eliminator {
+    /// how many times each variable is mentioned in the function
+    counts: HashMap<String, usize>,
+    /// the variables passed to tail calls, which hold the arguments of the function being jumped to until then
+    jumped: HashSet<String>,
+}
+
+impl Eliminator {
+    fn block(&mut self, block: &mut Block) {
+        for instr in &mut block.instrs {
+            if let Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } = &mut instr.value
+            {
+                self.block(true_branch);
+                self.block(false_branch);
+            }
+        }
+        let mut index = 0;
+        while index < block.instrs.len() {
+            match self.source(&block.instrs, index) {
+                Some(source) => {
+                    let moved = block.instrs.remove(index);
+                    self.forget(&moved);
+                    self.forget(&block.instrs[source]);
+                    if let Expr::Value(value) = &moved.value {
+                        rename(&mut block.instrs[source], value.variable(), &moved.target);
+                    }
+                    self.remember(&block.instrs[source]);
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    /// if `instrs[index]` moves a variable that was made earlier in the block just to be moved there,
+    /// the instruction that made it
+    fn source(&self, instrs: &[Instr], index: usize) -> Option<usize> {
+        let instr = &instrs[index];
+        let Expr::Value(Value::Move(from)) = &instr.value else {
+            return None;
+        };
+        let to = &instr.target;
+        if from == to || self.jumped.contains(&to.name) || !same_witness(from, to) {
+            return None;
+        }
+        let source = (0..index)
+            .rev()
+            .find(|&earlier| count(&instrs[earlier], from) > 0)?;
+        let made = &instrs[source];
+        // the value mustn't be used anywhere else, and the destination mustn't be used until the value is moved into it
+        let only_made = made.target == *from
+            && count(made, from) + count(instr, from)
+                == self.counts.get(&from.name).copied().unwrap_or_default();
+        let untouched = instrs[source..index]
+            .iter()
+            .all(|between| count(between, to) == 0);
+        (only_made && untouched).then_some(source)
+    }
+
+    fn forget(&mut self, instr: &Instr) {
+        let mut mentioned = Vec::new();
+        mentions(instr, &mut mentioned);
+        for variable in mentioned {
+            if let Some(count) = self.counts.get_mut(&variable.name) {
+                *count -= 1;
+            }
+        }
+    }
+
+    fn remember(&mut self, instr: &Instr) {
+        let mut mentioned = Vec::new();
+        mentions(instr, &mut mentioned);
+        for variable in mentioned {
+            *self.counts.entry(variable.name).or_default() += 1;
+        }
+    }
+}
+
+/// how many times `instr` mentions `variable`
+fn count(instr: &Instr, variable: &Variable) -> usize {
+    let mut mentioned = Vec::new();
+    mentions(instr, &mut mentioned);
+    mentioned.iter().filter(|other| *other == variable).count()
+}
+
+/// whether values of `from` and `to` are laid out the same way, by the same witness table
+fn same_witness(from: &Variable, to: &Variable) -> bool {
+    match (from.witness.as_ref(), to.witness.as_ref()) {
+        (Witness::Trivial { size: from }, Witness::Trivial { size: to }) => from == to,
+        (Witness::Dynamic { location: from }, Witness::Dynamic { location: to }) => from == to,
+        (Witness::Type, Witness::Type) => true,
+        _ => false,
+    }
+}
+
+/// adds the variables that `block` passes to tail calls to `jumped`
+fn jumps(block: &Block, jumped: &mut HashSet<String>) {
+    for instr in &block.instrs {
+        match &instr.value {
+            Expr::TailCall { arguments, .. } => {
+                jumped.extend(arguments.iter().map(|argument| argument.name.clone()));
+            }
+            Expr::If {
+                true_branch,
+                false_branch,
+                ..
+            } => {
+                jumps(true_branch, jumped);
+                jumps(false_branch, jumped);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// replaces every mention of `from` in `instr`, including in its branches, with `to`
+fn rename(instr: &mut Instr, from: &Variable, to: &Variable) {
+    let mut rename = |variable: &mut Variable| {
+        if variable == from {
+            *variable = to.clone();
+        } else if let Witness::Dynamic { location } = variable.witness.as_mut() {
+            if location == from {
+                *location = to.clone();
+            }
+        }
+    };
+    each_variable(instr, &mut rename);
+}
+
+fn each_variable(instr: &mut Instr, f: &mut impl FnMut(&mut Variable)) {
+    f(&mut instr.target);
+    match &mut instr.value {
+        Expr::Literal(_) | Expr::Destroy | Expr::Crash => {}
+        Expr::Primitive(_, arguments) | Expr::TailCall { arguments, .. } => {
+            arguments.iter_mut().for_each(&mut *f);
+        }
+        Expr::CallDirect { arguments, .. } => {
+            for argument in arguments {
+                f(value_variable(&mut argument.value));
+            }
+        }
+        Expr::CallIndirect { closure, arguments } => {
+            f(closure);
+            for argument in arguments {
+                f(value_variable(&mut argument.value));
+            }
+        }
+        Expr::Value(value) => f(value_variable(value)),
+        Expr::StructPack { arguments, .. } => {
+            for argument in arguments {
+                f(value_variable(&mut argument.value));
+            }
+        }
+        Expr::VariantPack { arguments, .. } => {
+            for argument in arguments {
+                f(value_variable(argument));
+            }
+        }
+        Expr::Unpack { value, .. }
+        | Expr::UnpackVariant { value, .. }
+        | Expr::IsVariant { value, .. }
+        | Expr::IsLiteral { value, .. }
+        | Expr::TypeArgument { witness: value, .. } => f(value),
+        Expr::If {
+            predicate,
+            true_branch,
+            false_branch,
+        } => {
+            f(predicate);
+            for instr in true_branch
+                .instrs
+                .iter_mut()
+                .chain(&mut false_branch.instrs)
+            {
+                each_variable(instr, f);
+            }
+        }
+        Expr::MakeClosure { env, witness, .. } => {
+            f(value_variable(env));
+            f(value_variable(witness));
+        }
+        Expr::ExistentialPack {
+            value,
+            witness,
+            dictionary,
+        } => {
+            f(value_variable(value));
+            f(value_variable(witness));
+            f(value_variable(dictionary));
+        }
+    }
+}
+
+const fn value_variable(value: &mut Value) -> &mut Variable {
+    match value {
+        Value::Move(variable) | Value::Copy(variable) => variable,
+    }
+}
@@ -0,0 +1,106 @@
+use im::{HashMap, HashSet};
+use tree::sized::{self, Primitive};
+use tree::String;
+
+/// for each function that can reach itself through tail calls, the functions it can reach that way.
+/// a tail call to one of them never has to return, so it can be compiled into a jump
+pub fn groups(functions: &[sized::Function]) -> HashMap<String, HashSet<String>> {
+    let known: HashSet<&String> = functions.iter().map(|function| &function.name).collect();
+    let graph: HashMap<&String, Vec<&String>> = functions
+        .iter()
+        .map(|function| {
+            let mut calls = Vec::new();
+            tail_calls(&function.body, &mut calls);
+            calls.retain(|call| known.contains(call));
+            (&function.name, calls)
+        })
+        .collect();
+    let mut search = Search {
+        graph,
+        next_index: 0,
+        indices: HashMap::new(),
+        lowest: HashMap::new(),
+        stack: Vec::new(),
+        groups: HashMap::new(),
+    };
+    for function in functions {
+        if !search.indices.contains_key(&function.name) {
+            search.visit(&function.name);
+        }
+    }
+    search.groups
+}
+
+/// the functions that `to_search` calls in tail position
+fn tail_calls<'a>(to_search: &'a sized::Expr, calls: &mut Vec<&'a String>) {
+    match to_search {
+        sized::Expr::CallDirect { function, .. } => calls.push(function),
+        sized::Expr::If {
+            true_branch,
+            false_branch,
+            ..
+        } => {
+            tail_calls(true_branch, calls);
+            tail_calls(false_branch, calls);
+        }
+        // the second operand of `&&` and `||` is only evaluated when it decides the result
+        sized::Expr::Primitive {
+            primitive: Primitive::And | Primitive::Or,
+            arguments,
+            ..
+        } => tail_calls(&arguments[1], calls),
+        sized::Expr::Block(block) => tail_calls(&block.result, calls),
+        sized::Expr::Case { arms, .. } => {
+            for arm in arms {
+                tail_calls(&arm.body, calls);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// tarjan's algorithm for the strongly connected components of the tail call graph
+struct Search<'a> {
+    graph: HashMap<&'a String, Vec<&'a String>>,
+    next_index: usize,
+    indices: HashMap<&'a String, usize>,
+    /// the lowest index reachable from each function that is still on the stack
+    lowest: HashMap<&'a String, usize>,
+    stack: Vec<&'a String>,
+    groups: HashMap<String, HashSet<String>>,
+}
+
+impl<'a> Search<'a> {
+    fn visit(&mut self, function: &'a String) {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.indices.insert(function, index);
+        self.lowest.insert(function, index);
+        self.stack.push(function);
+        for callee in self.graph[function].clone() {
+            if !self.indices.contains_key(callee) {
+                self.visit(callee);
+                let lowest = self.lowest[function].min(self.lowest[callee]);
+                self.lowest.insert(function, lowest);
+            } else if self.stack.contains(&callee) {
+                let lowest = self.lowest[function].min(self.indices[callee]);
+                self.lowest.insert(function, lowest);
+            }
+        }
+        if self.lowest[function] == index {
+            let start = self
+                .stack
+                .iter()
+                .position(|&other| other == function)
+                .unwrap();
+            let members: Vec<_> = self.stack.split_off(start);
+            // a function on its own only loops if it calls itself
+            if members.len() > 1 || self.graph[function].contains(&function) {
+                let group: HashSet<String> = members.iter().map(|&member| member.clone()).collect();
+                for member in members {
+                    self.groups.insert(member.clone(), group.clone());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,568 @@
+use core::fmt;
+use std::collections::VecDeque;
+
+use im::HashMap;
+use tree::typed::*;
+use tree::{Span, String};
+
+/// how deeply a generic can be nested in the types it's filled in with before its function is assumed to
+/// call itself with ever bigger types, which would never run out of instances to make
+const MAX_DEPTH: usize = 32;
+
+/// a generic function or struct that can't be given a finite set of instances
+pub struct Error {
+    pub name: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is used with ever bigger types, so it can't be monomorphized",
+            self.name
+        )
+    }
+}
+
+/// gives every generic function and struct an instance for each list of types it is used with.
+///
+/// the rest of the pipeline then never has to pass witness tables around,
+/// and the generic originals are left out, since nothing refers to them anymore
+pub fn program(to_specialize: &Program) -> Result<Program, Error> {
+    let mut specializer = Specializer {
+        functions: to_specialize
+            .functions
+            .iter()
+            .filter(|function| !function.generics.is_empty())
+            .map(|function| (function.name.clone(), function))
+            .collect(),
+        structs: to_specialize
+            .structs
+            .iter()
+            .filter(|strukt| !strukt.generics.is_empty())
+            .map(|strukt| (strukt.name.clone(), strukt))
+            .collect(),
+        names: HashMap::new(),
+        counts: HashMap::new(),
+        depths: HashMap::new(),
+        queue: VecDeque::new(),
+        structs_made: Vec::new(),
+    };
+    let spec = HashMap::new();
+    let mut functions: Vec<_> = to_specialize
+        .functions
+        .iter()
+        .filter(|function| function.generics.is_empty())
+        .map(|function| specializer.function(function, function.name.clone(), &spec))
+        .collect::<Result<_, _>>()?;
+    let impls = to_specialize
+        .impls
+        .iter()
+        .map(|implementation| {
+            let functions = implementation
+                .functions
+                .iter()
+                .map(|function| specializer.function(function, function.name.clone(), &spec))
+                .collect::<Result<_, _>>()?;
+            Ok(Impl {
+                functions,
+                ..implementation.clone()
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let enums = to_specialize
+        .enums
+        .iter()
+        .map(|enumeration| {
+            let variants = enumeration
+                .variants
+                .iter()
+                .map(|variant| {
+                    let fields = variant
+                        .fields
+                        .iter()
+                        .map(|field| specializer.typ(field, &spec))
+                        .collect::<Result<_, _>>()?;
+                    Ok(Variant {
+                        name: variant.name.clone(),
+                        fields,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Enum {
+                variants,
+                ..enumeration.clone()
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    let mut structs: Vec<_> = to_specialize
+        .structs
+        .iter()
+        .filter(|strukt| strukt.generics.is_empty())
+        .map(|strukt| specializer.strukt(strukt, strukt.name.clone(), &spec))
+        .collect::<Result<_, _>>()?;
+    // making an instance can call for more of them, so the queue is worked through until it's empty
+    while let Some((function, name, spec)) = specializer.queue.pop_front() {
+        functions.push(specializer.function(function, name, &spec)?);
+    }
+    structs.append(&mut specializer.structs_made);
+    Ok(Program {
+        structs,
+        enums,
+        protocols: to_specialize.protocols.clone(),
+        impls,
+        functions,
+    })
+}
+
+type Spec = HashMap<String, Type>;
+
+struct Specializer<'a> {
+    functions: HashMap<String, &'a Function>,
+    structs: HashMap<String, &'a Struct>,
+    /// the name of each instance made so far, by the function or struct and the types it's filled in with
+    names: HashMap<(String, Vec<Type>), String>,
+    /// how many instances of each function or struct have been made
+    counts: HashMap<String, usize>,
+    /// how many types each struct instance's type was nested in, before it was named
+    depths: HashMap<String, usize>,
+    /// the function instances whose bodies haven't been specialized yet
+    queue: VecDeque<(&'a Function, String, Spec)>,
+    structs_made: Vec<Struct>,
+}
+
+impl Specializer<'_> {
+    fn function(
+        &mut self,
+        function: &Function,
+        name: String,
+        spec: &Spec,
+    ) -> Result<Function, Error> {
+        let arguments = function
+            .arguments
+            .iter()
+            .map(|argument| {
+                Ok(Argument {
+                    name: argument.name.clone(),
+                    typ: self.typ(&argument.typ, spec)?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Function {
+            name,
+            generics: Vec::new(),
+            arguments,
+            result: self.typ(&function.result, spec)?,
+            body: self.expr(&function.body, spec)?,
+            span: function.span,
+        })
+    }
+
+    fn strukt(&mut self, strukt: &Struct, name: String, spec: &Spec) -> Result<Struct, Error> {
+        let fields = strukt
+            .fields
+            .iter()
+            .map(|field| {
+                Ok(Field {
+                    name: field.name.clone(),
+                    typ: self.typ(&field.typ, spec)?,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Struct {
+            name,
+            generics: Vec::new(),
+            fields,
+            tag: strukt.tag,
+            span: strukt.span,
+        })
+    }
+
+    /// the name of the instance of the generic function or struct `name` for `types`, which is made if it doesn't exist yet.
+    /// instances are named after their original with a leading underscore, which no name in the source can have
+    fn instance(&mut self, name: &String, types: Vec<Type>, span: Span) -> Result<String, Error> {
+        let key = (name.clone(), types);
+        if let Some(instance) = self.names.get(&key) {
+            return Ok(instance.clone());
+        }
+        let (name, types) = key;
+        let depth = 1 + types
+            .iter()
+            .map(|typ| self.depth(typ))
+            .max()
+            .unwrap_or_default();
+        if depth > MAX_DEPTH {
+            // a struct is blamed on its definition, since types don't remember where they were written
+            let span = self.structs.get(&name).map_or(span, |strukt| strukt.span);
+            return Err(Error { name, span });
+        }
+        let count = self.counts.entry(name.clone()).or_default();
+        let instance = String::from(format!("_{name}_{count}"));
+        *count += 1;
+        self.names
+            .insert((name.clone(), types.clone()), instance.clone());
+        if let Some(function) = self.functions.get(&name).copied() {
+            let spec = generic_spec(&function.generics, types);
+            self.queue.push_back((function, instance.clone(), spec));
+        } else {
+            self.depths.insert(instance.clone(), depth);
+            let strukt = self.structs[&name];
+            let spec = generic_spec(&strukt.generics, types);
+            let made = self.strukt(strukt, instance.clone(), &spec)?;
+            self.structs_made.push(made);
+        }
+        Ok(instance)
+    }
+
+    /// how many types `typ` is nested in, counting the ones that its struct instances were made from
+    fn depth(&self, typ: &Type) -> usize {
+        let deepest = |types: &mut dyn Iterator<Item = &Type>| {
+            types.map(|typ| self.depth(typ)).max().unwrap_or_default()
+        };
+        match typ {
+            Type::Named { name, arguments } => {
+                let named = self.depths.get(name).copied().unwrap_or(1);
+                named.max(1 + deepest(&mut arguments.iter()))
+            }
+            Type::Function { arguments, result } => {
+                1 + deepest(&mut arguments.iter().chain([result.as_ref()]))
+            }
+            Type::Generic { .. } | Type::Existential { .. } => 1,
+            Type::Unification { name, value } => self.depth(Type::unwrap(name, value)),
+            Type::Union { .. } => {
+                let (variants, _) = typ.union_variants().unwrap();
+                1 + deepest(&mut variants.iter().flat_map(|variant| &variant.fields))
+            }
+        }
+    }
+
+    /// fills in the generics of `typ` and resolves its unification variables, replacing generic structs with their instances
+    fn typ(&mut self, typ: &Type, spec: &Spec) -> Result<Type, Error> {
+        Ok(match typ {
+            Type::Named { name, arguments } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.typ(argument, spec))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if arguments.is_empty() || !self.structs.contains_key(name) {
+                    Type::Named {
+                        name: name.clone(),
+                        arguments,
+                    }
+                } else {
+                    Type::Named {
+                        name: self.instance(name, arguments, Span::default())?,
+                        arguments: Vec::new(),
+                    }
+                }
+            }
+            Type::Generic { name } => spec.get(name).cloned().unwrap_or_else(|| typ.clone()),
+            Type::Function { arguments, result } => Type::Function {
+                arguments: arguments
+                    .iter()
+                    .map(|argument| self.typ(argument, spec))
+                    .collect::<Result<_, _>>()?,
+                result: Box::new(self.typ(result, spec)?),
+            },
+            Type::Unification { name, value } => self.typ(Type::unwrap(name, value), spec)?,
+            Type::Existential { .. } => typ.clone(),
+            // every union is closed by now, so its rest can be flattened into it
+            Type::Union { .. } => {
+                let (variants, _) = typ.union_variants().unwrap();
+                let variants = variants
+                    .into_iter()
+                    .map(|variant| {
+                        Ok(Variant {
+                            name: variant.name,
+                            fields: self.types(&variant.fields, spec)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Type::union(variants, None)
+            }
+        })
+    }
+
+    fn types(&mut self, types: &[Type], spec: &Spec) -> Result<Vec<Type>, Error> {
+        types.iter().map(|typ| self.typ(typ, spec)).collect()
+    }
+
+    fn expr(&mut self, expr: &Expr, spec: &Spec) -> Result<Expr, Error> {
+        Ok(match expr {
+            Expr::Variable { name, typ, span } => Expr::Variable {
+                name: name.clone(),
+                typ: self.typ(typ, spec)?,
+                span: *span,
+            },
+            Expr::Literal { literal, typ, span } => Expr::Literal {
+                literal: literal.clone(),
+                typ: self.typ(typ, spec)?,
+                span: *span,
+            },
+            Expr::CallDirect {
+                function,
+                arguments,
+                tag,
+                span,
+            } => {
+                let arguments = self.exprs(arguments, spec)?;
+                let result = self.typ(&tag.result, spec)?;
+                let generics = self.types(&tag.generics, spec)?;
+                // protocol functions aren't in `functions`, and are left for the type passer to call a concrete type's impl
+                if !generics.is_empty() && self.functions.contains_key(function) {
+                    Expr::CallDirect {
+                        function: self.instance(function, generics, *span)?,
+                        arguments,
+                        tag: Call {
+                            result,
+                            generics: Vec::new(),
+                        },
+                        span: *span,
+                    }
+                } else {
+                    Expr::CallDirect {
+                        function: function.clone(),
+                        arguments,
+                        tag: Call { result, generics },
+                        span: *span,
+                    }
+                }
+            }
+            Expr::CallIndirect {
+                function,
+                arguments,
+                tag,
+                span,
+            } => Expr::CallIndirect {
+                function: Box::new(self.expr(function, spec)?),
+                arguments: self.exprs(arguments, spec)?,
+                tag: Call {
+                    result: self.typ(&tag.result, spec)?,
+                    generics: self.types(&tag.generics, spec)?,
+                },
+                span: *span,
+            },
+            Expr::Primitive {
+                primitive,
+                arguments,
+                span,
+            } => Expr::Primitive {
+                primitive: *primitive,
+                arguments: self.exprs(arguments, spec)?,
+                span: *span,
+            },
+            Expr::Block(block) => Expr::Block(self.block(block, spec)?),
+            Expr::StructPack {
+                name,
+                fields,
+                tag,
+                span,
+            } => {
+                let fields = fields
+                    .iter()
+                    .map(|field| {
+                        Ok(PackField {
+                            name: field.name.clone(),
+                            value: self.expr(&field.value, spec)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                let result = self.typ(&tag.result, spec)?;
+                // a generic struct's type has become its instance's
+                let (name, generics) = match &result {
+                    Type::Named { name: instance, .. } if instance != name => {
+                        (instance.clone(), Vec::new())
+                    }
+                    _ => (name.clone(), self.types(&tag.generics, spec)?),
+                };
+                Expr::StructPack {
+                    name,
+                    fields,
+                    tag: StructPack { result, generics },
+                    span: *span,
+                }
+            }
+            Expr::If {
+                predicate,
+                true_branch,
+                false_branch,
+                tag,
+                span,
+            } => Expr::If {
+                predicate: Box::new(self.expr(predicate, spec)?),
+                true_branch: Box::new(self.expr(true_branch, spec)?),
+                false_branch: Box::new(self.expr(false_branch, spec)?),
+                tag: *tag,
+                span: *span,
+            },
+            Expr::Closure {
+                arguments,
+                body,
+                tag,
+                span,
+            } => Expr::Closure {
+                arguments: self.closure_arguments(arguments, spec)?,
+                body: Box::new(self.expr(body, spec)?),
+                tag: Closure {
+                    captures: self.closure_arguments(&tag.captures, spec)?,
+                    result: self.typ(&tag.result, spec)?,
+                },
+                span: *span,
+            },
+            Expr::VariantPack {
+                name,
+                arguments,
+                tag,
+                span,
+            } => Expr::VariantPack {
+                name: name.clone(),
+                arguments: self.exprs(arguments, spec)?,
+                tag: self.struct_pack(tag, spec)?,
+                span: *span,
+            },
+            Expr::Case {
+                scrutinee,
+                arms,
+                tag,
+                span,
+            } => Expr::Case {
+                scrutinee: Box::new(self.expr(scrutinee, spec)?),
+                arms: arms
+                    .iter()
+                    .map(|arm| {
+                        Ok(Arm {
+                            pattern: self.pattern(&arm.pattern, spec)?,
+                            body: self.expr(&arm.body, spec)?,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                tag: *tag,
+                span: *span,
+            },
+            Expr::FieldAccess {
+                value,
+                field,
+                tag,
+                span,
+            } => Expr::FieldAccess {
+                value: Box::new(self.expr(value, spec)?),
+                field: field.clone(),
+                tag: self.struct_pack(tag, spec)?,
+                span: *span,
+            },
+            Expr::Unwrap { value, tag, span } => Expr::Unwrap {
+                value: Box::new(self.expr(value, spec)?),
+                tag: self.struct_pack(tag, spec)?,
+                span: *span,
+            },
+            Expr::ExistentialPack {
+                value,
+                protocol,
+                tag,
+                span,
+            } => Expr::ExistentialPack {
+                value: Box::new(self.expr(value, spec)?),
+                protocol: protocol.clone(),
+                tag: *tag,
+                span: *span,
+            },
+        })
+    }
+
+    fn exprs(&mut self, exprs: &[Expr], spec: &Spec) -> Result<Vec<Expr>, Error> {
+        exprs.iter().map(|expr| self.expr(expr, spec)).collect()
+    }
+
+    fn struct_pack(&mut self, tag: &StructPack, spec: &Spec) -> Result<StructPack, Error> {
+        Ok(StructPack {
+            result: self.typ(&tag.result, spec)?,
+            generics: self.types(&tag.generics, spec)?,
+        })
+    }
+
+    fn closure_arguments(
+        &mut self,
+        arguments: &[ClosureArgument],
+        spec: &Spec,
+    ) -> Result<Vec<ClosureArgument>, Error> {
+        arguments
+            .iter()
+            .map(|argument| {
+                Ok(ClosureArgument {
+                    name: argument.name.clone(),
+                    typ: self.typ(&argument.typ, spec)?,
+                })
+            })
+            .collect()
+    }
+
+    fn block(&mut self, block: &Block, spec: &Spec) -> Result<Block, Error> {
+        let stmts = block
+            .stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Statement::Let {
+                    name,
+                    typ,
+                    value,
+                    span,
+                } => Ok(Statement::Let {
+                    name: name.clone(),
+                    typ: self.typ(typ, spec)?,
+                    value: self.expr(value, spec)?,
+                    span: *span,
+                }),
+                Statement::Function(_) => {
+                    unreachable!("the type checker lifts local functions to the top level")
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Block {
+            stmts,
+            result: Box::new(self.expr(&block.result, spec)?),
+            span: block.span,
+        })
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, spec: &Spec) -> Result<Pattern, Error> {
+        Ok(match pattern {
+            Pattern::Wildcard { tag, span } => Pattern::Wildcard {
+                tag: self.typ(tag, spec)?,
+                span: *span,
+            },
+            Pattern::Binding { name, tag, span } => Pattern::Binding {
+                name: name.clone(),
+                tag: self.typ(tag, spec)?,
+                span: *span,
+            },
+            Pattern::Literal { literal, tag, span } => Pattern::Literal {
+                literal: literal.clone(),
+                tag: self.typ(tag, spec)?,
+                span: *span,
+            },
+            Pattern::Variant {
+                name,
+                fields,
+                tag,
+                span,
+            } => Pattern::Variant {
+                name: name.clone(),
+                fields: fields
+                    .iter()
+                    .map(|field| self.pattern(field, spec))
+                    .collect::<Result<_, _>>()?,
+                tag: self.typ(tag, spec)?,
+                span: *span,
+            },
+        })
+    }
+}
+
+fn generic_spec(generics: &[Generic], types: Vec<Type>) -> Spec {
+    generics
+        .iter()
+        .map(|generic| generic.name.clone())
+        .zip(types)
+        .collect()
+}
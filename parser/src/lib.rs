@@ -9,15 +9,30 @@
 pub mod util;
 pub mod program;
 
+use std::ops::Range;
+
 use chumsky::primitive::end;
-use chumsky::{error::Simple, Parser};
+use chumsky::{error::Simple, Parser, Stream};
 use tree::parsed::*;
 use tree::token::Token;
 
 pub fn parse<'src>(tokens: &[Token<'src>]) -> Result<Program, Vec<Simple<Token<'src>>>> {
-    program::program().then_ignore(end()).parse(tokens)
+    program::program().then_ignore(end()).parse(stream(tokens))
 }
 
 pub fn parse_expr<'src>(tokens: &[Token<'src>]) -> Result<Expr, Vec<Simple<Token<'src>>>> {
-    program::expr().then_ignore(end()).parse(tokens)
+    program::expr().then_ignore(end()).parse(stream(tokens))
+}
+
+/// pairs every token with its byte range, so that parser spans (and parse errors) point into the source text
+fn stream<'a, 'src>(
+    tokens: &'a [Token<'src>],
+) -> Stream<'a, Token<'src>, Range<usize>, impl Iterator<Item = (Token<'src>, Range<usize>)> + 'a> {
+    let eoi = tokens.last().map_or(0, |token| token.span.end());
+    Stream::from_iter(
+        eoi..eoi,
+        tokens
+            .iter()
+            .map(|token| (*token, token.span.offset..token.span.end())),
+    )
 }
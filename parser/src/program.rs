@@ -1,9 +1,11 @@
+use std::iter;
+
 use chumsky::recursive::recursive;
 use chumsky::{error::Simple, primitive::filter_map, Parser};
 use tree::parsed::*;
 use tree::sized::Primitive;
 use tree::token::{BinaryOperator, Kind, Token};
-use tree::String;
+use tree::{Span, String};
 
 pub fn token<'src>(kind: Kind) -> parser!('src, Token<'src>) {
     filter_map(move |span, t: Token| {
@@ -21,6 +23,9 @@ pub fn token_text<'src>(kind: Kind) -> parser!('src, String) {
 
 pub fn program<'src>() -> parser!('src, Program) {
     struct_definition()
+        .or(enum_definition())
+        .or(protocol_definition())
+        .or(impl_definition())
         .or(function_definition())
         .repeated()
         .map(|defs| Program {
@@ -29,6 +34,13 @@ pub fn program<'src>() -> parser!('src, Program) {
                 .into_iter()
                 .flat_map(|def| def.structs)
                 .collect(),
+            enums: defs.clone().into_iter().flat_map(|def| def.enums).collect(),
+            protocols: defs
+                .clone()
+                .into_iter()
+                .flat_map(|def| def.protocols)
+                .collect(),
+            impls: defs.clone().into_iter().flat_map(|def| def.impls).collect(),
             functions: defs.into_iter().flat_map(|def| def.functions).collect(),
         })
 }
@@ -41,20 +53,67 @@ fn ident<'src>() -> parser!('src, String) {
     token_text(Kind::Identifier)
 }
 
+fn generic_list<'src>() -> parser!('src, Vec<Generic>) {
+    let bounds = token(Kind::Colon)
+        .ignore_then(named_type().separated_by(token(Kind::BinaryOperator(BinaryOperator::Cross))))
+        .or_not()
+        .map(|bounds| bounds.unwrap_or_default());
+    token(Kind::LeftSquareBracket)
+        .ignore_then(
+            ident()
+                .then(bounds)
+                .map(|(name, bounds)| Generic { name, bounds })
+                .separated_by(token(Kind::Comma)),
+        )
+        .then_ignore(token(Kind::RightSquareBracket))
+        .or_not()
+        .map(|list| list.unwrap_or(Vec::new()))
+}
+
 fn struct_definition<'src>() -> parser!('src, Program) {
     token(Kind::Struct)
         .ignore_then(named_type())
+        .then(generic_list())
         .then_ignore(token(Kind::LeftBrace))
         .then(field_definition().repeated())
         .then_ignore(token(Kind::RightBrace))
-        .map(|(name, fields)| Struct {
+        .map_with_span(|((name, generics), fields), span| Struct {
             name,
+            generics,
             fields,
-            tag: (),
+            tag: Untagged,
+            span: Span::from(span),
         })
         .map(Program::from_struct)
 }
 
+fn enum_definition<'src>() -> parser!('src, Program) {
+    let variant = named_type()
+        .then(
+            token(Kind::LeftParen)
+                .ignore_then(comma_list(typ()))
+                .then_ignore(token(Kind::RightParen))
+                .or_not(),
+        )
+        .map(|(name, fields)| Variant {
+            name,
+            fields: fields.unwrap_or_default(),
+        });
+    token(Kind::Enum)
+        .ignore_then(named_type())
+        .then_ignore(token(Kind::LeftBrace))
+        .then(comma_list(variant))
+        .then_ignore(token(Kind::Comma).or_not())
+        .then_ignore(token(Kind::RightBrace))
+        .map_with_span(|(name, variants), span| Enum {
+            name,
+            variants,
+            tag: Untagged,
+            span: Span::from(span),
+        })
+        .map(Program::from_enum)
+}
+
 fn field_definition<'src>() -> parser!('src, Field) {
     ident()
         .then_ignore(token(Kind::Colon))
@@ -64,7 +123,25 @@ fn field_definition<'src>() -> parser!('src, Field) {
 
 fn typ<'src>() -> parser!('src, Type) {
     recursive(|typ| {
-        token(Kind::LeftParen)
+        let fields = token(Kind::LeftParen)
+            .ignore_then(typ.clone().separated_by(token(Kind::Comma)))
+            .then_ignore(token(Kind::RightParen));
+        let with_fields = named_type()
+            .then(fields)
+            .map(|(name, fields)| Variant { name, fields });
+        let variant = with_fields.clone().or(named_type().map(|name| Variant {
+            name,
+            fields: Vec::new(),
+        }));
+        let more = token(Kind::SingleBar)
+            .ignore_then(variant.clone())
+            .repeated();
+        // a lone variant without fields would just be a named type, so a union needs either fields or a `|`
+        let union = with_fields
+            .then(more.clone())
+            .or(variant.then(more.at_least(1)))
+            .map(|(first, rest)| Type::union(iter::once(first).chain(rest).collect(), None));
+        let single = token(Kind::LeftParen)
             .ignore_then(typ.clone())
             .then_ignore(token(Kind::RightParen))
             .then_ignore(token(Kind::Arrow))
@@ -73,10 +150,20 @@ fn typ<'src>() -> parser!('src, Type) {
                 arguments: vec![arg],
                 result: Box::new(result),
             })
-            .or(named_type().map(|name| Type::Named {
-                name,
-                arguments: Vec::new(),
-            }))
+            .or(named_type()
+                .then(
+                    token(Kind::LeftSquareBracket)
+                        .ignore_then(typ.clone().separated_by(token(Kind::Comma)))
+                        .then_ignore(token(Kind::RightSquareBracket))
+                        .or_not(),
+                )
+                .map(|(name, arguments)| Type::Named {
+                    name,
+                    arguments: arguments.unwrap_or_default(),
+                }))
+            .or(token(Kind::Any)
+                .ignore_then(named_type())
+                .map(|protocol| Type::Existential { protocol }))
             .or(ident().map(|name| Type::Generic { name }))
             .or(token(Kind::LeftParen)
                 .ignore_then(typ.clone().separated_by(token(Kind::Comma)))
@@ -87,6 +174,16 @@ fn typ<'src>() -> parser!('src, Type) {
                     arguments,
                     result: Box::new(result),
                 }))
+            .boxed();
+        let single = union.or(single).boxed();
+        // `!` binds looser than anything else, so `(F64) -> F64 ! E` is a function that can throw
+        single
+            .clone()
+            .then(token(Kind::Bang).ignore_then(single).or_not())
+            .map(|(value, error)| match error {
+                Some(error) => Type::result(value, error),
+                None => value,
+            })
     })
 }
 
@@ -97,46 +194,97 @@ fn argument<'src>() -> parser!('src, Argument) {
         .map(|(name, typ)| Argument { name, typ })
 }
 
-fn function_definition<'src>() -> parser!('src, Program) {
-    let generic_list = token(Kind::LeftSquareBracket)
-        .ignore_then(
-            ident()
-                .map(|name| Generic { name })
-                .separated_by(token(Kind::Comma)),
-        )
-        .then_ignore(token(Kind::RightSquareBracket))
-        .or_not()
-        .map(|list| list.unwrap_or(Vec::new()));
-    let argument_list = token(Kind::LeftParen)
+fn argument_list<'src>() -> parser!('src, Vec<Argument>) {
+    token(Kind::LeftParen)
         .ignore_then(argument().separated_by(token(Kind::Comma)))
-        .then_ignore(token(Kind::RightParen));
-    token(Kind::Func)
+        .then_ignore(token(Kind::RightParen))
+}
+
+fn protocol_definition<'src>() -> parser!('src, Program) {
+    let signature = token(Kind::Func)
         .ignore_then(ident())
-        .then(generic_list)
-        .then(argument_list)
+        .then(argument_list())
         .then_ignore(token(Kind::Colon))
         .then(typ())
-        .then_ignore(token(Kind::SingleEquals))
-        .then(expr())
-        .map(|((((name, generics), arguments), result), body)| Function {
+        .map(|((name, arguments), result)| Signature {
             name,
-            generics,
-            arguments,
+            arguments: arguments.into_iter().map(|arg| arg.typ).collect(),
             result,
-            body,
+        });
+    token(Kind::Protocol)
+        .ignore_then(named_type())
+        .then_ignore(token(Kind::LeftBrace))
+        .then(signature.repeated())
+        .then_ignore(token(Kind::RightBrace))
+        .map_with_span(|(name, functions), span| Protocol {
+            name,
+            functions,
+            span: Span::from(span),
         })
-        .map(Program::from_function)
+        .map(Program::from_protocol)
+}
+
+fn impl_definition<'src>() -> parser!('src, Program) {
+    token(Kind::Impl)
+        .ignore_then(named_type())
+        .then_ignore(token(Kind::For))
+        .then(named_type())
+        .then_ignore(token(Kind::LeftBrace))
+        .then(function(expr()).repeated())
+        .then_ignore(token(Kind::RightBrace))
+        .map_with_span(|((protocol, typ), functions), span| Impl {
+            protocol,
+            typ,
+            functions,
+            span: Span::from(span),
+        })
+        .map(Program::from_impl)
+}
+
+fn function_definition<'src>() -> parser!('src, Program) {
+    function(expr()).map(Program::from_function)
+}
+
+fn function<'src>(expr: parser!('src, Expr)) -> parser!('src, Function) {
+    token(Kind::Func)
+        .ignore_then(ident())
+        .then(generic_list())
+        .then(argument_list())
+        .then_ignore(token(Kind::Colon))
+        .then(typ())
+        .then_ignore(token(Kind::SingleEquals))
+        .then(expr)
+        .map_with_span(
+            |((((name, generics), arguments), result), body), span| Function {
+                name,
+                generics,
+                arguments,
+                result,
+                body,
+                span: Span::from(span),
+            },
+        )
 }
 
 trait WithOperation<'src>: Parser<Token<'src>, Expr, Error = Simple<Token<'src>>> + Clone {
-    fn with_operation<T>(
+    /// any number of operands separated by the operators of one level of the precedence table, grouped from the left
+    fn with_operation(
         self,
-        symbol: parser!('src, T),
-        operation: impl Fn(Expr, Expr) -> Expr + Clone,
-    ) -> parser!('src, Expr) {
-        self.clone()
-            .then(symbol.ignore_then(self).repeated())
-            .foldl(operation)
+        operators: &'static [(BinaryOperator, Primitive)],
+    ) -> parser!('src, Expr)
+    where
+        Self: 'src,
+    {
+        // boxing keeps the parser's type from doubling in size with every level of the table
+        let operand = self.boxed();
+        operand
+            .clone()
+            .then(operator(operators).then(operand).repeated())
+            .foldl(|a, (primitive, b)| Expr::Primitive {
+                primitive,
+                span: a.span().merge(b.span()),
+                arguments: vec![a, b],
+            })
     }
 }
 
@@ -145,28 +293,55 @@ impl<'src, T> WithOperation<'src> for T where
 {
 }
 
+fn operator<'src>(operators: &'static [(BinaryOperator, Primitive)]) -> parser!('src, Primitive) {
+    filter_map(move |span, t: Token| {
+        operators
+            .iter()
+            .find(|(operator, _)| t.kind == Kind::BinaryOperator(*operator))
+            .map(|(_, primitive)| *primitive)
+            .ok_or_else(|| Simple::custom(span, format!("{t} is not an operator here")))
+    })
+}
+
+/// `-` and `!` in front of an operand, which bind tighter than any binary operator
+fn prefix<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    let negation = token(Kind::BinaryOperator(BinaryOperator::Dash))
+        .to(Primitive::Neg)
+        .or(token(Kind::Bang).to(Primitive::Not))
+        .map_with_span(|primitive, span| (primitive, Span::from(span)));
+    negation
+        .repeated()
+        .then(call(expr))
+        .foldr(|(primitive, span), operand| Expr::Primitive {
+            primitive,
+            span: span.merge(operand.span()),
+            arguments: vec![operand],
+        })
+}
+
 pub fn expr<'src>() -> parser!('src, Expr) {
+    use BinaryOperator::*;
+    // from the tightest binding level to the loosest
     recursive(|e| {
-        terminal(e)
-            .with_operation(token(Kind::BinaryOperator(BinaryOperator::Star)), |a, b| {
-                Expr::Primitive {
-                    primitive: Primitive::Mul,
-                    arguments: vec![a, b],
-                }
-            })
-            .with_operation(
-                token(Kind::BinaryOperator(BinaryOperator::Cross)),
-                |a, b| Expr::Primitive {
-                    primitive: Primitive::Add,
-                    arguments: vec![a, b],
-                },
-            )
-            .with_operation(token(Kind::BinaryOperator(BinaryOperator::Dash)), |a, b| {
-                Expr::Primitive {
-                    primitive: Primitive::Sub,
-                    arguments: vec![a, b],
-                }
-            })
+        prefix(e)
+            .with_operation(&[
+                (Star, Primitive::Mul),
+                (Slash, Primitive::Div),
+                (Percent, Primitive::Rem),
+            ])
+            .with_operation(&[(Cross, Primitive::Add), (Dash, Primitive::Sub)])
+            .with_operation(&[
+                (Less, Primitive::Less),
+                (LessEquals, Primitive::LessEq),
+                (Greater, Primitive::Greater),
+                (GreaterEquals, Primitive::GreaterEq),
+            ])
+            .with_operation(&[
+                (DoubleEquals, Primitive::Eq),
+                (BangEquals, Primitive::NotEq),
+            ])
+            .with_operation(&[(DoubleAmpersand, Primitive::And)])
+            .with_operation(&[(DoubleBar, Primitive::Or)])
     })
 }
 
@@ -190,24 +365,27 @@ fn pack_field<'src>(expr: parser!('src, Expr)) -> parser!('src, PackField) {
 }
 
 fn closure<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    closure_arguments()
+        .then_ignore(token(Kind::ThickArrow))
+        .then(expr)
+        .map_with_span(|(arguments, body), span| Expr::Closure {
+            arguments,
+            body: Box::new(body),
+            tag: Untagged,
+            span: Span::from(span),
+        })
+}
+
+fn closure_arguments<'src>() -> parser!('src, Vec<ClosureArgument>) {
     let closure_argument = ident()
         .then(token(Kind::Colon).ignore_then(typ()).or_not())
         .map(|(name, typ)| ClosureArgument { name, typ });
-    let arg_list = closure_argument
+    closure_argument
         .clone()
         .map(|arg| vec![arg])
         .or(token(Kind::LeftParen)
             .ignore_then(comma_list(closure_argument))
-            .then_ignore(token(Kind::RightParen)));
-
-    arg_list
-        .then_ignore(token(Kind::ThickArrow))
-        .then(expr)
-        .map(|(arguments, body)| Expr::Closure {
-            arguments,
-            body: Box::new(body),
-            tag: (),
-        })
+            .then_ignore(token(Kind::RightParen)))
 }
 
 fn struct_pack<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
@@ -215,10 +393,11 @@ fn struct_pack<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
         .then_ignore(token(Kind::LeftBrace))
         .then(comma_list(pack_field(expr.clone())))
         .then_ignore(token(Kind::RightBrace))
-        .map(|(name, fields)| Expr::StructPack {
+        .map_with_span(|(name, fields), span| Expr::StructPack {
             name,
             fields,
-            tag: (),
+            tag: Untagged,
+            span: Span::from(span),
         })
 }
 
@@ -229,76 +408,318 @@ fn if_expr<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
         .then(expr.clone())
         .then_ignore(token(Kind::Else))
         .then(expr.clone())
-        .map(|((predicate, true_branch), false_branch)| Expr::If {
+        .map_with_span(|((predicate, true_branch), false_branch), span| Expr::If {
             predicate: Box::new(predicate),
             true_branch: Box::new(true_branch),
             false_branch: Box::new(false_branch),
             tag: If,
+            span: Span::from(span),
+        })
+}
+
+/// a variant of an enum, with its payload if it has one: `Some(x)` or `None`
+fn variant_pack<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    named_type()
+        .then(
+            token(Kind::LeftParen)
+                .ignore_then(comma_list(expr))
+                .then_ignore(token(Kind::RightParen))
+                .or_not(),
+        )
+        .map_with_span(|(name, arguments), span| Expr::VariantPack {
+            name,
+            arguments: arguments.unwrap_or_default(),
+            tag: Untagged,
+            span: Span::from(span),
+        })
+}
+
+fn pattern<'src>() -> parser!('src, Pattern) {
+    recursive(|pattern| {
+        let literal = number()
+            .or(token(Kind::True).map(|_| Literal::Boolean(true)))
+            .or(token(Kind::False).map(|_| Literal::Boolean(false)))
+            .map_with_span(|literal, span| Pattern::Literal {
+                literal,
+                tag: Untagged,
+                span: Span::from(span),
+            });
+        token(Kind::Underscore)
+            .map_with_span(|_, span| Pattern::Wildcard {
+                tag: Untagged,
+                span: Span::from(span),
+            })
+            .or(ident().map_with_span(|name, span| Pattern::Binding {
+                name,
+                tag: Untagged,
+                span: Span::from(span),
+            }))
+            .or(literal)
+            .or(named_type()
+                .then(
+                    token(Kind::LeftParen)
+                        .ignore_then(comma_list(pattern))
+                        .then_ignore(token(Kind::RightParen))
+                        .or_not(),
+                )
+                .map_with_span(|(name, fields), span| Pattern::Variant {
+                    name,
+                    fields: fields.unwrap_or_default(),
+                    tag: Untagged,
+                    span: Span::from(span),
+                }))
+    })
+}
+
+fn arms<'src>(expr: parser!('src, Expr)) -> parser!('src, Vec<Arm>) {
+    token(Kind::SingleBar)
+        .ignore_then(pattern())
+        .then_ignore(token(Kind::ThickArrow))
+        .then(expr)
+        .map(|(pattern, body)| Arm { pattern, body })
+        .repeated()
+        .at_least(1)
+}
+
+fn case_expr<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    token(Kind::Case)
+        .ignore_then(expr.clone())
+        .then_ignore(token(Kind::Of))
+        .then(arms(expr))
+        .map_with_span(|(scrutinee, arms), span| Expr::Case {
+            scrutinee: Box::new(scrutinee),
+            arms,
+            tag: If,
+            span: Span::from(span),
+        })
+}
+
+/// `throw error` makes an error union that holds `error`
+fn throw_expr<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    token(Kind::Throw)
+        .ignore_then(expr)
+        .map_with_span(|error, span| Expr::VariantPack {
+            name: String::from(THROWN),
+            arguments: vec![error],
+            tag: Untagged,
+            span: Span::from(span),
+        })
+}
+
+/// `try value catch | error => handled` is the value of an error union, or what its arms make of the error it holds.
+/// it's a case expression over the union, whose first arm passes the value through
+fn try_expr<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    token(Kind::Try)
+        .ignore_then(expr.clone())
+        .then_ignore(token(Kind::Catch))
+        .then(arms(expr))
+        .map_with_span(|(scrutinee, catches), span| {
+            let span = Span::from(span);
+            let value = String::from("_value");
+            let mut arms = vec![Arm {
+                pattern: Pattern::Variant {
+                    name: String::from(OK),
+                    fields: vec![Pattern::Binding {
+                        name: value.clone(),
+                        tag: Untagged,
+                        span,
+                    }],
+                    tag: Untagged,
+                    span,
+                },
+                body: Expr::Variable {
+                    name: value,
+                    typ: Untagged,
+                    span,
+                },
+            }];
+            arms.extend(catches.into_iter().map(|arm| Arm {
+                pattern: Pattern::Variant {
+                    name: String::from(THROWN),
+                    span: arm.pattern.span(),
+                    fields: vec![arm.pattern],
+                    tag: Untagged,
+                },
+                body: arm.body,
+            }));
+            Expr::Case {
+                scrutinee: Box::new(scrutinee),
+                arms,
+                tag: If,
+                span,
+            }
         })
 }
 
 fn terminal<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
     literal_expr()
         .or(if_expr(expr.clone()))
+        .or(case_expr(expr.clone()))
+        .or(throw_expr(expr.clone()))
+        .or(try_expr(expr.clone()))
         .or(closure(expr.clone()))
         .or(boolean_literal())
         .or(struct_pack(expr.clone()))
-        .or(ident()
-            .then_ignore(token(Kind::LeftParen))
-            .then(comma_list(expr.clone()))
-            .then_ignore(token(Kind::RightParen))
-            .map(|(function, arguments)| Expr::CallDirect {
+        .or(variant_pack(expr.clone()))
+        .or(ident().then(call_arguments(expr.clone())).map_with_span(
+            |(function, arguments), span| Expr::CallDirect {
                 function,
                 arguments,
-                tag: (),
-            }))
-        .or(ident().map(|name| Expr::Variable { name, typ: () }))
+                tag: Untagged,
+                span: Span::from(span),
+            },
+        ))
+        .or(ident().map_with_span(|name, span| Expr::Variable {
+            name,
+            typ: Untagged,
+            span: Span::from(span),
+        }))
         .or(token(Kind::LeftParen)
             .ignore_then(expr.clone())
             .then_ignore(token(Kind::RightParen)))
         .or(block(expr).map(|block| Expr::Block(block)))
 }
 
+/// the arguments of a call. a closure in braces after the parenthesised arguments is one more argument, so `map(xs) { x => x + 1 }` is `map(xs, x => x + 1)`.
+/// its body is the rest of the braces, which can start with statements like a block's
+fn call_arguments<'src>(expr: parser!('src, Expr)) -> parser!('src, Vec<Expr>) {
+    let body = block_contents(expr.clone()).map_with_span(|(stmts, result), span| {
+        if stmts.is_empty() {
+            result
+        } else {
+            Expr::Block(Block {
+                stmts,
+                result: Box::new(result),
+                span: Span::from(span),
+            })
+        }
+    });
+    let trailing = token(Kind::LeftBrace)
+        .ignore_then(closure_arguments())
+        .then_ignore(token(Kind::ThickArrow))
+        .then(body)
+        .then_ignore(token(Kind::RightBrace))
+        .map_with_span(|(arguments, body), span| Expr::Closure {
+            arguments,
+            body: Box::new(body),
+            tag: Untagged,
+            span: Span::from(span),
+        });
+    token(Kind::LeftParen)
+        .ignore_then(comma_list(expr))
+        .then_ignore(token(Kind::RightParen))
+        .then(trailing.or_not())
+        .map(|(mut arguments, trailing)| {
+            arguments.extend(trailing);
+            arguments
+        })
+}
+
+#[derive(Clone)]
+enum Postfix {
+    Call(Vec<Expr>),
+    Field(String),
+}
+
+/// a terminal followed by any number of argument lists and field accesses, such as `mul_by(2)(3)` or `line.start.x`.
+/// a method call such as `map.get(key)` is a call through a field access, until the type checker decides whether it's sugar for `get(map, key)`
+fn call<'src>(expr: parser!('src, Expr)) -> parser!('src, Expr) {
+    let argument_list = call_arguments(expr.clone()).map(Postfix::Call);
+    let field = token(Kind::Dot).ignore_then(ident()).map(Postfix::Field);
+    terminal(expr)
+        .then(
+            argument_list
+                .or(field)
+                .map_with_span(|postfix, span| (postfix, Span::from(span)))
+                .repeated(),
+        )
+        .foldl(|value, (postfix, span)| {
+            let span = value.span().merge(span);
+            match postfix {
+                Postfix::Call(arguments) => Expr::CallIndirect {
+                    function: Box::new(value),
+                    arguments,
+                    tag: Untagged,
+                    span,
+                },
+                Postfix::Field(field) => Expr::FieldAccess {
+                    value: Box::new(value),
+                    field,
+                    tag: Untagged,
+                    span,
+                },
+            }
+        })
+}
+
 fn boolean_literal<'src>() -> parser!('src, Expr) {
     token(Kind::True)
         .map(|_| Literal::Boolean(true))
         .or(token(Kind::False).map(|_| Literal::Boolean(false)))
-        .map(|literal| Expr::Literal { literal })
+        .map_with_span(|literal, span| Expr::Literal {
+            literal,
+            typ: Untagged,
+            span: Span::from(span),
+        })
 }
 
-fn literal_expr<'src>() -> parser!('src, Expr) {
+/// numbers with a decimal point are floats, and the rest are integers
+fn number<'src>() -> parser!('src, Literal) {
     token_text(Kind::Number).try_map(|text, span| {
-        text.parse::<f64>()
-            .map_err(|err| Simple::custom(span, format!("{} is not a float: {:?}", text, err)))
-            .map(|float| Expr::Literal {
-                literal: Literal::Float(float),
+        if text.contains('.') {
+            text.parse::<f64>().map(Literal::Float).map_err(|err| {
+                Simple::custom(span.clone(), format!("{} is not a float: {:?}", text, err))
+            })
+        } else {
+            text.parse::<i64>().map(Literal::Integer).map_err(|err| {
+                Simple::custom(
+                    span.clone(),
+                    format!("{} is not an integer: {:?}", text, err),
+                )
             })
+        }
+    })
+}
+
+fn literal_expr<'src>() -> parser!('src, Expr) {
+    number().map_with_span(|literal, span| Expr::Literal {
+        literal,
+        typ: Untagged,
+        span: Span::from(span),
     })
 }
 
 fn block<'src>(expr: parser!('src, Expr)) -> parser!('src, Block) {
     token(Kind::LeftBrace)
-        .ignore_then(
-            statement(expr.clone())
-                .then_ignore(token(Kind::Semicolon))
-                .repeated(),
-        )
-        .then(expr)
-        .map(|(stmts, result)| Block {
+        .ignore_then(block_contents(expr))
+        .then_ignore(token(Kind::RightBrace))
+        .map_with_span(|(stmts, result), span| Block {
             stmts,
             result: Box::new(result),
+            span: Span::from(span),
         })
-        .then_ignore(token(Kind::RightBrace))
 }
 
+/// what goes between a block's braces: its statements, then the expression it evaluates to
+fn block_contents<'src>(expr: parser!('src, Expr)) -> parser!('src, (Vec<Statement>, Expr)) {
+    statement(expr.clone()).repeated().then(expr)
+}
+
+/// a `let` followed by a semicolon, or a function that can only be called from the rest of the block.
+/// a function ends where its body does, like one at the top level, so its semicolon can be left out
 fn statement<'src>(expr: parser!('src, Expr)) -> parser!('src, Statement) {
     token(Kind::Let)
         .ignore_then(ident())
         .then_ignore(token(Kind::SingleEquals))
-        .then(expr)
-        .map(|(name, value)| Statement::Let {
+        .then(expr.clone())
+        .map_with_span(|(name, value), span| Statement::Let {
             name,
-            typ: (),
+            typ: Untagged,
             value,
+            span: Span::from(span),
         })
+        .then_ignore(token(Kind::Semicolon))
+        .or(function(expr)
+            .then_ignore(token(Kind::Semicolon).or_not())
+            .map(Statement::Function))
 }
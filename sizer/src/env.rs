@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 use im::HashMap;
@@ -11,11 +12,17 @@ pub struct StructInstance {
     pub name: String,
 }
 
+/// the names of a union's variants, along with how many fields each one has
+pub type Union = Vec<(String, usize)>;
+
 #[derive(Clone, Default)]
 pub struct Env {
     variables: HashMap<String, Variable>,
     structs: HashMap<String, Struct>,
+    enums: HashMap<String, Enum>,
     instances: Rc<RefCell<HashMap<StructInstance, ()>>>,
+    /// the variants of each union, by the name of the enum that it is laid out as
+    unions: Rc<RefCell<BTreeMap<String, Union>>>,
 }
 
 impl Env {
@@ -44,7 +51,53 @@ impl Env {
         self.structs.get(name).unwrap().clone()
     }
 
+    pub fn define_enum(&mut self, name: String, definition: Enum) {
+        self.enums.insert(name, definition);
+    }
+
+    pub fn lookup_enum(&self, name: &String) -> Option<Enum> {
+        self.enums.get(name).cloned()
+    }
+
     pub fn witness_struct_instance(&self, instance: StructInstance, witness: ()) {
         self.instances.borrow_mut().insert(instance, witness);
     }
+
+    /// records every union that appears in `typ`, so that the enums they are laid out as get made
+    pub fn witness_unions(&self, typ: &Type) {
+        match typ.canonical() {
+            Type::Named { arguments, .. } => {
+                for argument in arguments {
+                    self.witness_unions(argument);
+                }
+            }
+            Type::Function { arguments, result } => {
+                for argument in arguments.iter().chain([result.as_ref()]) {
+                    self.witness_unions(argument);
+                }
+            }
+            Type::Union { .. } => {
+                let (variants, _) = typ.union_variants().unwrap();
+                for field in variants.iter().flat_map(|variant| &variant.fields) {
+                    self.witness_unions(field);
+                }
+                let Some(Type::Named { name, .. }) = typ.union_shape() else {
+                    unreachable!("unions are laid out as named types")
+                };
+                self.unions.borrow_mut().insert(
+                    name,
+                    variants
+                        .into_iter()
+                        .map(|variant| (variant.name, variant.fields.len()))
+                        .collect(),
+                );
+            }
+            Type::Generic { .. } | Type::Unification { .. } | Type::Existential { .. } => {}
+        }
+    }
+
+    /// the unions recorded so far
+    pub fn unions(&self) -> BTreeMap<String, Union> {
+        self.unions.borrow().clone()
+    }
 }
@@ -1,5 +1,7 @@
 use tree::sized::*;
 use tree::type_passing;
+use tree::type_passing::make_signature;
+use tree::{Span, String};
 
 use crate::env::Env;
 use crate::env::StructInstance;
@@ -7,40 +9,166 @@ use crate::env::StructInstance;
 pub fn program(to_size: &type_passing::Program) -> Program {
     let mut env = Env::default();
 
+    let enums: Vec<_> = to_size
+        .enums
+        .iter()
+        .map(|to_size| enumeration(&mut env, to_size))
+        .collect();
     let structs = to_size
         .structs
         .iter()
         .map(|to_size| strukt(&mut env, to_size))
         .collect();
+    let impls = to_size
+        .impls
+        .iter()
+        .map(|to_size| Impl {
+            protocol: to_size.protocol.clone(),
+            typ: to_size.typ.clone(),
+            functions: to_size
+                .functions
+                .iter()
+                .map(|func| function(&env, func))
+                .collect(),
+            span: to_size.span,
+        })
+        .collect();
     let functions = to_size
         .functions
         .iter()
         .map(|func| function(&env, func))
         .collect();
-    Program { structs, functions }
+    // every structural variant gets the same tag in every union, which is its position among all of them
+    let unions = env.unions();
+    let mut variants: Vec<_> = unions
+        .values()
+        .flatten()
+        .map(|(name, _)| name.clone())
+        .collect();
+    variants.sort();
+    variants.dedup();
+    let enums = enums
+        .into_iter()
+        .chain(
+            unions
+                .iter()
+                .map(|(name, union)| union_enum(name, union, &variants)),
+        )
+        .collect();
+    Program {
+        structs,
+        enums,
+        protocols: to_size.protocols.clone(),
+        impls,
+        functions,
+    }
+}
+
+fn enumeration(env: &mut Env, to_size: &type_passing::Enum) -> Enum {
+    for variant in &to_size.variants {
+        for field in &variant.fields {
+            env.witness_unions(field);
+        }
+    }
+    let variants = to_size
+        .tag
+        .variants
+        .iter()
+        .map(|fields| fields.iter().map(|field| expr(env, field)).collect())
+        .collect();
+    let sized = Enum {
+        name: to_size.name.clone(),
+        variants: to_size.variants.clone(),
+        tag: EnumMeta {
+            arguments: Vec::new(),
+            variants,
+        },
+        span: to_size.span,
+    };
+    env.define_enum(to_size.name.clone(), sized.clone());
+    sized
+}
+
+/// the enum that a union is laid out as. it has every one of `all` the structural variants in the program, so that each of them
+/// has the same tag in every union. the variants of the union hold its type arguments, and the others hold nothing
+fn union_enum(name: &String, union: &[(String, usize)], all: &[String]) -> Enum {
+    let count = union.iter().map(|(_, arity)| arity).sum();
+    let arguments: Vec<_> = (0..count)
+        .map(|i| Variable {
+            name: String::from(format!("_T{i}")),
+            witness: Witness::Type,
+        })
+        .collect();
+    let mut next = arguments.iter();
+    let (variants, witnesses) = all
+        .iter()
+        .map(|variant| {
+            let arity = union
+                .iter()
+                .find(|(other, _)| other == variant)
+                .map_or(0, |(_, arity)| *arity);
+            let fields: Vec<_> = next.by_ref().take(arity).collect();
+            let variant = Variant {
+                name: variant.clone(),
+                fields: fields
+                    .iter()
+                    .map(|field| Type::Generic {
+                        name: field.name.clone(),
+                    })
+                    .collect(),
+            };
+            let witnesses = fields
+                .into_iter()
+                .map(|field| Expr::Variable {
+                    name: field.clone(),
+                    typ: Type::typ(),
+                    span: Span::default(),
+                })
+                .collect();
+            (variant, witnesses)
+        })
+        .unzip();
+    Enum {
+        name: name.clone(),
+        variants,
+        tag: EnumMeta {
+            arguments,
+            variants: witnesses,
+        },
+        span: Span::default(),
+    }
 }
 
 fn strukt(env: &mut Env, to_size: &type_passing::Struct) -> Struct {
+    let mut inner_env = env.clone();
     let arguments = to_size
         .tag
         .arguments
         .iter()
-        .map(|arg| Variable {
-            name: arg.clone(),
-            witness: Witness::Type,
+        .map(|arg| {
+            inner_env.define_variable(arg.clone(), Witness::Type);
+            Variable {
+                name: arg.clone(),
+                witness: Witness::Type,
+            }
         })
         .collect();
+    for field in &to_size.fields {
+        env.witness_unions(&field.typ);
+    }
     let fields = to_size
         .tag
         .fields
         .iter()
-        .map(|field| expr(env, field))
+        .map(|field| expr(&inner_env, field))
         .collect();
     let tag = StructMeta { arguments, fields };
     let sized = Struct {
         name: to_size.name.clone(),
+        generics: to_size.generics.clone(),
         fields: to_size.fields.clone(),
         tag,
+        span: to_size.span,
     };
     env.define_struct(to_size.name.clone(), sized.clone());
     sized
@@ -68,25 +196,30 @@ fn function(env: &Env, to_size: &type_passing::Function) -> Function {
         arguments: args,
         result: to_size.result.clone(),
         body,
+        span: to_size.span,
     }
 }
 
 fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
     match to_size {
-        type_passing::Expr::Variable { name, typ } => {
+        type_passing::Expr::Variable { name, typ, span } => {
             let var = env.lookup_variable(name);
             Expr::Variable {
                 name: var,
                 typ: typ.clone(),
+                span: *span,
             }
         }
-        type_passing::Expr::Literal { literal } => Expr::Literal {
+        type_passing::Expr::Literal { literal, typ, span } => Expr::Literal {
             literal: literal.clone(),
+            typ: typ.clone(),
+            span: *span,
         },
         type_passing::Expr::CallDirect {
             function,
             arguments,
             tag,
+            span,
         } => {
             let sized_args = arguments.iter().map(|arg| expr(env, arg)).collect();
             Expr::CallDirect {
@@ -97,20 +230,46 @@ fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
                     witness: type_witness(env, &tag.result),
                     signature: tag.signature.clone(),
                 },
+                span: *span,
+            }
+        }
+        type_passing::Expr::CallIndirect {
+            function,
+            arguments,
+            tag,
+            span,
+        } => {
+            let sized_args = arguments.iter().map(|arg| expr(env, arg)).collect();
+            Expr::CallIndirect {
+                function: Box::new(expr(env, function)),
+                arguments: sized_args,
+                tag: Call {
+                    result: tag.result.clone(),
+                    witness: type_witness(env, &tag.result),
+                    signature: tag.signature.clone(),
+                },
+                span: *span,
             }
         }
         type_passing::Expr::Block(to_size) => Expr::Block(block(env, to_size)),
         type_passing::Expr::Primitive {
             primitive,
             arguments,
+            span,
         } => {
             let sized_args = arguments.iter().map(|arg| expr(env, arg)).collect();
             Expr::Primitive {
                 primitive: *primitive,
                 arguments: sized_args,
+                span: *span,
             }
         }
-        type_passing::Expr::StructPack { name, fields, tag } => {
+        type_passing::Expr::StructPack {
+            name,
+            fields,
+            tag,
+            span,
+        } => {
             let witness = type_witness(env, &tag.result);
             let sized_fields = fields
                 .iter()
@@ -125,13 +284,16 @@ fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
                 tag: StructPack {
                     result: tag.result.clone(),
                     witness,
+                    offset: None,
                 },
+                span: *span,
             }
         }
         type_passing::Expr::If {
             predicate,
             true_branch,
             false_branch,
+            span,
             ..
         } => {
             let sized_predicate = expr(env, &predicate);
@@ -144,12 +306,14 @@ fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
                 predicate: Box::new(sized_predicate),
                 true_branch: Box::new(sized_true),
                 false_branch: Box::new(sized_false),
+                span: *span,
             }
         }
         type_passing::Expr::Closure {
             arguments,
             body,
             tag,
+            span,
         } => {
             let sized_args: Vec<_> = arguments
                 .iter()
@@ -213,11 +377,13 @@ fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
                     .collect();
                 Struct {
                     name: tag.env.name.clone(),
+                    generics: tag.env.generics.clone(),
                     fields: tag.env.fields.clone(),
                     tag: StructMeta {
                         arguments: builder_args,
                         fields: builder_fields,
                     },
+                    span: tag.env.span,
                 }
             };
             let tag = Closure {
@@ -231,8 +397,135 @@ fn expr(env: &Env, to_size: &type_passing::Expr) -> Expr {
                 arguments: sized_args,
                 body: Box::new(sized_body),
                 tag,
+                span: *span,
             }
         }
+        type_passing::Expr::VariantPack {
+            name,
+            arguments,
+            tag,
+            span,
+        } => Expr::VariantPack {
+            name: name.clone(),
+            arguments: arguments.iter().map(|arg| expr(env, arg)).collect(),
+            tag: StructPack {
+                result: tag.result.clone(),
+                witness: type_witness(env, &tag.result),
+                offset: None,
+            },
+            span: *span,
+        },
+        type_passing::Expr::Case {
+            scrutinee,
+            arms,
+            span,
+            ..
+        } => {
+            let sized_scrutinee = expr(env, scrutinee);
+            let sized_arms: Vec<_> = arms
+                .iter()
+                .map(|arm| {
+                    let mut inner_env = env.clone();
+                    let sized_pattern = pattern(&mut inner_env, &arm.pattern);
+                    Arm {
+                        pattern: sized_pattern,
+                        body: expr(&inner_env, &arm.body),
+                    }
+                })
+                .collect();
+            Expr::Case {
+                tag: If {
+                    witness: type_witness(env, &sized_arms[0].body.get_type()),
+                },
+                scrutinee: Box::new(sized_scrutinee),
+                arms: sized_arms,
+                span: *span,
+            }
+        }
+        type_passing::Expr::FieldAccess {
+            value,
+            field,
+            tag,
+            span,
+        } => {
+            let sized_value = expr(env, value);
+            let offset = match sized_value.get_witness() {
+                Witness::Trivial { .. } => field_offset(env, &sized_value.get_type(), field),
+                _ => None,
+            };
+            Expr::FieldAccess {
+                value: Box::new(sized_value),
+                field: field.clone(),
+                tag: StructPack {
+                    result: tag.result.clone(),
+                    witness: type_witness(env, &tag.result),
+                    offset,
+                },
+                span: *span,
+            }
+        }
+        type_passing::Expr::Unwrap { value, tag, span } => Expr::Unwrap {
+            value: Box::new(expr(env, value)),
+            tag: StructPack {
+                result: tag.result.clone(),
+                witness: type_witness(env, &tag.result),
+                offset: None,
+            },
+            span: *span,
+        },
+        type_passing::Expr::ExistentialPack {
+            value,
+            protocol,
+            tag,
+            span,
+        } => Expr::ExistentialPack {
+            value: Box::new(expr(env, value)),
+            protocol: protocol.clone(),
+            tag: ExistentialPack {
+                witness: Box::new(expr(env, &tag.witness)),
+                dictionary: Box::new(expr(env, &tag.dictionary)),
+            },
+            span: *span,
+        },
+    }
+}
+
+/// sizes the pattern and defines the variables it binds in `env`
+fn pattern(env: &mut Env, to_size: &type_passing::Pattern) -> Pattern {
+    let meta = |env: &Env, typ: &Type| PatternMeta {
+        typ: typ.clone(),
+        witness: type_witness(env, typ),
+    };
+    match to_size {
+        type_passing::Pattern::Wildcard { tag, span } => Pattern::Wildcard {
+            tag: meta(env, tag),
+            span: *span,
+        },
+        type_passing::Pattern::Binding { name, tag, span } => {
+            let tag = meta(env, tag);
+            env.define_variable(name.clone(), tag.witness.clone());
+            Pattern::Binding {
+                name: name.clone(),
+                tag,
+                span: *span,
+            }
+        }
+        type_passing::Pattern::Literal { literal, tag, span } => Pattern::Literal {
+            literal: literal.clone(),
+            tag: meta(env, tag),
+            span: *span,
+        },
+        type_passing::Pattern::Variant {
+            name,
+            fields,
+            tag,
+            span,
+        } => Pattern::Variant {
+            name: name.clone(),
+            fields: fields.iter().map(|field| pattern(env, field)).collect(),
+            tag: meta(env, tag),
+            span: *span,
+        },
     }
 }
 
@@ -246,6 +539,7 @@ fn block(env: &Env, to_size: &type_passing::Block) -> Block {
                 name,
                 typ: let_type,
                 value,
+                span,
             } => {
                 env.define_variable(name.clone(), type_witness(&env, let_type));
                 Statement::Let {
@@ -255,29 +549,33 @@ fn block(env: &Env, to_size: &type_passing::Block) -> Block {
                     },
                     typ: let_type.clone(),
                     value: expr(&env, value),
+                    span: *span,
                 }
             }
+            type_passing::Statement::Function(_) => {
+                unreachable!("the type checker lifts local functions to the top level")
+            }
         })
         .collect();
     Block {
         stmts,
         result: Box::new(expr(&env, &to_size.result)),
+        span: to_size.span,
     }
 }
 
 fn type_witness(env: &Env, to_witness: &Type) -> Witness {
+    env.witness_unions(to_witness);
     match to_witness {
         Type::Named { name, arguments } => {
-            if !arguments.is_empty() {
-                unimplemented!("argument types");
-            }
-            Witness::Trivial {
-                size: match name.as_str() {
-                    "F64" => 8,
-                    "Bool" => 8,
-                    "Type" => return Witness::Type,
-                    _ => return struct_witness(env, &env.lookup_struct(&name)),
-                },
+            if name == "Type" {
+                Witness::Type
+            } else if let Some(size) = static_size(env, to_witness, &[]) {
+                Witness::Trivial { size }
+            } else if name == RESULT || env.lookup_enum(name).is_some() {
+                builder_witness(to_witness)
+            } else {
+                struct_witness(env, &env.lookup_struct(name), arguments)
             }
         }
         Type::Generic { name } => Witness::Dynamic {
@@ -287,29 +585,139 @@ fn type_witness(env: &Env, to_witness: &Type) -> Witness {
                     witness: Witness::Type,
                 },
                 typ: Type::typ(),
+                span: Span::default(),
             }),
         },
         Type::Function { .. } => Witness::closure(),
+        Type::Existential { .. } => Witness::Existential,
         Type::Unification { name, value } => type_witness(env, Type::unwrap(name, value)),
+        Type::Union { .. } => static_size(env, to_witness, &[]).map_or_else(
+            || builder_witness(to_witness),
+            |size| Witness::Trivial { size },
+        ),
     }
 }
 
-fn struct_witness(env: &Env, to_witness: &Struct) -> Witness {
+/// the size of values of `typ`, if it is known statically and they can be copied and destroyed without a witness table.
+/// that is true of numbers, and of the enums and non-generic structs that only hold them.
+/// `enclosing` are the types that `typ` is inside of, which a recursive type would need a pointer to hold
+fn static_size(env: &Env, typ: &Type, enclosing: &[&str]) -> Option<usize> {
+    // unions are laid out like the enums that are made for them, which only hold their variants' fields
+    if let Some((variants, _)) = typ.union_variants() {
+        let payloads = variants
+            .iter()
+            .map(|variant| {
+                variant
+                    .fields
+                    .iter()
+                    .map(|field| static_size(env, field, enclosing))
+                    .sum::<Option<usize>>()
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return Some(8 + payloads.into_iter().max().unwrap_or_default());
+    }
+    let Type::Named { name, arguments } = typ.canonical() else {
+        return None;
+    };
+    match name.as_str() {
+        // a dictionary is a pointer to one that is statically allocated
+        "F64" | "I64" | "Bool" | "_Dictionary" => Some(8),
+        "Type" => None,
+        // error unions are laid out like an enum whose variants hold the value and the error
+        RESULT => {
+            let value = static_size(env, &arguments[0], enclosing)?;
+            let error = static_size(env, &arguments[1], enclosing)?;
+            Some(8 + value.max(error))
+        }
+        _ if enclosing.contains(&name.as_str()) => None,
+        _ => {
+            let enclosing = [enclosing, &[name.as_str()]].concat();
+            let sum = |fields: &mut dyn Iterator<Item = &Type>| {
+                fields
+                    .map(|field| static_size(env, field, &enclosing))
+                    .sum::<Option<usize>>()
+            };
+            if let Some(enumeration) = env.lookup_enum(name) {
+                // enums are laid out as an 8 byte tag followed by the fields of the largest variant
+                let payloads = enumeration
+                    .variants
+                    .iter()
+                    .map(|variant| sum(&mut variant.fields.iter()))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(8 + payloads.into_iter().max().unwrap_or_default())
+            } else if arguments.is_empty() {
+                let strukt = env.lookup_struct(name);
+                sum(&mut strukt.fields.iter().map(|field| &field.typ))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// where `field` is in values of the struct type `typ`, if their layout is known statically
+fn field_offset(env: &Env, typ: &Type, field: &str) -> Option<usize> {
+    let Type::Named { name, .. } = typ.canonical() else {
+        return None;
+    };
+    let strukt = env.lookup_struct(name);
+    let index = strukt
+        .fields
+        .iter()
+        .position(|candidate| candidate.name == field)?;
+    strukt.fields[..index]
+        .iter()
+        .map(|field| static_size(env, &field.typ, &[]))
+        .sum()
+}
+
+fn struct_witness(env: &Env, to_witness: &Struct, arguments: &[Type]) -> Witness {
     env.witness_struct_instance(
         StructInstance {
             name: to_witness.name.clone(),
         },
         (),
     );
+    builder_witness(&Type::Named {
+        name: to_witness.name.clone(),
+        arguments: arguments.to_vec(),
+    })
+}
+
+/// the witness table made by calling the builder of the named type
+fn builder_witness(typ: &Type) -> Witness {
     Witness::Dynamic {
-        value: Box::new(Expr::CallDirect {
-            function: to_witness.name.clone(),
-            arguments: Vec::new(),
-            tag: Call {
-                result: Type::typ(),
+        value: Box::new(type_value(typ)),
+    }
+}
+
+/// an expression that makes the witness table of `typ`, passing the witness tables of its arguments to its builder
+fn type_value(typ: &Type) -> Expr {
+    let builder = |function: String, arguments: Vec<Expr>| Expr::CallDirect {
+        function,
+        tag: Call {
+            result: Type::typ(),
+            witness: Witness::Type,
+            signature: make_signature(arguments.len()),
+        },
+        arguments,
+        span: Span::default(),
+    };
+    match typ {
+        Type::Named { name, arguments } => {
+            builder(name.clone(), arguments.iter().map(type_value).collect())
+        }
+        Type::Generic { name } => Expr::Variable {
+            name: Variable {
+                name: name.clone(),
                 witness: Witness::Type,
-                signature: vec![Convention::Out],
             },
-        }),
+            typ: Type::typ(),
+            span: Span::default(),
+        },
+        Type::Function { .. } => builder(String::from("_Closure"), Vec::new()),
+        Type::Existential { .. } => builder(String::from("_Existential"), Vec::new()),
+        Type::Unification { name, value } => type_value(Type::unwrap(name, value)),
+        Type::Union { .. } => type_value(&typ.union_shape().unwrap()),
     }
 }
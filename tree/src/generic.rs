@@ -1,7 +1,8 @@
+use core::fmt::Write;
 use core::{fmt, hash};
 use std::{cell::OnceCell, rc::Rc};
 
-use crate::String;
+use crate::{Span, String};
 
 pub trait Stage {
     type Argument: Clone;
@@ -13,6 +14,9 @@ pub trait Stage {
     type StructMeta: Clone;
     type Closure: Clone;
     type ClosureArgument: Clone;
+    type EnumMeta: Clone;
+    type PatternMeta: Clone;
+    type ExistentialPack: Clone;
 }
 
 pub trait DisplayStage:
@@ -26,6 +30,9 @@ pub trait DisplayStage:
     StructMeta = <Self as DisplayStage>::StructMeta,
     Closure = <Self as DisplayStage>::Closure,
     ClosureArgument = <Self as DisplayStage>::ClosureArgument,
+    EnumMeta = <Self as DisplayStage>::EnumMeta,
+    PatternMeta = <Self as DisplayStage>::PatternMeta,
+    ExistentialPack = <Self as DisplayStage>::ExistentialPack,
 >
 {
     type Argument: Clone + fmt::Debug;
@@ -37,19 +44,67 @@ pub trait DisplayStage:
     type StructMeta: Clone + fmt::Display;
     type Closure: Clone + fmt::Display;
     type ClosureArgument: Clone + fmt::Debug;
+    type EnumMeta: Clone + fmt::Display;
+    type PatternMeta: Clone + fmt::Display;
+    type ExistentialPack: Clone + fmt::Display;
 }
 
 #[derive(Clone)]
 pub struct Program<S: Stage> {
     pub structs: Vec<Struct<S>>,
+    pub enums: Vec<Enum<S>>,
+    pub protocols: Vec<Protocol>,
+    pub impls: Vec<Impl<S>>,
     pub functions: Vec<Function<S>>,
 }
 
 #[derive(Clone)]
 pub struct Struct<S: Stage> {
     pub name: String,
+    pub generics: Vec<Generic>,
     pub fields: Vec<Field>,
     pub tag: S::StructMeta,
+    pub span: Span,
+}
+
+/// A tagged union: values of the enum are laid out as a tag followed by the payload of one variant.
+#[derive(Clone)]
+pub struct Enum<S: Stage> {
+    pub name: String,
+    pub variants: Vec<Variant>,
+    pub tag: S::EnumMeta,
+    pub span: Span,
+}
+
+/// A set of functions that a type can implement. The functions refer to the implementing type as `Self`.
+#[derive(Clone)]
+pub struct Protocol {
+    pub name: String,
+    pub functions: Vec<Signature>,
+    pub span: Span,
+}
+
+/// A protocol function, which only has a body in each of the protocol's impls
+#[derive(Clone)]
+pub struct Signature {
+    pub name: String,
+    pub arguments: Vec<Type>,
+    pub result: Type,
+}
+
+/// The functions that make the named type implement a protocol
+#[derive(Clone)]
+pub struct Impl<S: Stage> {
+    pub protocol: String,
+    pub typ: String,
+    pub functions: Vec<Function<S>>,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -82,11 +137,23 @@ pub enum Type {
         name: String,
         value: Rc<OnceCell<Type>>,
     },
+    /// a value of any type that implements the protocol, packed together with its witness table and dictionary
+    Existential {
+        protocol: String,
+    },
+    /// `A(T) | B`, a structurally typed sum of variants sorted by name. an open union can still gain variants through `rest`,
+    /// which is a unification variable until something decides what the other variants are
+    Union {
+        variants: Vec<Variant>,
+        rest: Option<Box<Type>>,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub struct Generic {
     pub name: String,
+    /// the protocols that the type filling in this generic has to implement
+    pub bounds: Vec<String>,
 }
 
 #[derive(Clone)]
@@ -96,20 +163,61 @@ pub struct Function<S: Stage> {
     pub arguments: Vec<S::Argument>,
     pub result: Type,
     pub body: Expr<S>,
+    pub span: Span,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Primitive {
     Add,
     Sub,
     Mul,
+    Div,
+    Rem,
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    /// only evaluates its second operand if the first is `True`
+    And,
+    /// only evaluates its second operand if the first is `False`
+    Or,
+    Neg,
+    Not,
+    /// converts an `I64` to the nearest `F64`
+    ToFloat,
+    /// converts an `F64` to an `I64`, rounding towards zero and saturating at the bounds of `I64`
+    ToInteger,
 }
 
 impl Primitive {
     pub fn arity(&self) -> Option<usize> {
         use Primitive::*;
         match self {
-            Add | Sub | Mul => Some(2),
+            Add | Sub | Mul | Div | Rem | Eq | NotEq | Less | LessEq | Greater | GreaterEq
+            | And | Or => Some(2),
+            Neg | Not | ToFloat | ToInteger => Some(1),
+        }
+    }
+
+    /// the primitive that a call to the builtin function `name` stands for
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "to_f64" => Some(Self::ToFloat),
+            "to_i64" => Some(Self::ToInteger),
+            _ => None,
+        }
+    }
+
+    /// the type of the result, given the type of the first argument
+    pub fn result_type(&self, first: Type) -> Type {
+        use Primitive::*;
+        match self {
+            Add | Sub | Mul | Div | Rem | Neg => first,
+            Eq | NotEq | Less | LessEq | Greater | GreaterEq | And | Or | Not => Type::bool(),
+            ToFloat => Type::float(),
+            ToInteger => Type::integer(),
         }
     }
 }
@@ -135,35 +243,114 @@ pub enum Expr<S: Stage> {
     Variable {
         name: S::Variable,
         typ: S::Type,
+        span: Span,
     },
+    /// integer literals can be either `I64` or `F64`, so their type is inferred along with everything else
     Literal {
         literal: Literal,
+        typ: S::Type,
+        span: Span,
     },
     CallDirect {
         function: String,
         arguments: Vec<Expr<S>>,
         tag: S::Call,
+        span: Span,
+    },
+    /// a call through a closure value, rather than to a statically known function
+    CallIndirect {
+        function: Box<Expr<S>>,
+        arguments: Vec<Expr<S>>,
+        tag: S::Call,
+        span: Span,
     },
     Primitive {
         primitive: Primitive,
         arguments: Vec<Expr<S>>,
+        span: Span,
     },
     Block(Block<S>),
     StructPack {
         name: String,
         fields: Vec<PackField<S>>,
         tag: S::StructPack,
+        span: Span,
     },
     If {
         predicate: Box<Expr<S>>,
         true_branch: Box<Expr<S>>,
         false_branch: Box<Expr<S>>,
         tag: S::If,
+        span: Span,
     },
     Closure {
         arguments: Vec<S::ClosureArgument>,
         body: Box<Expr<S>>,
         tag: S::Closure,
+        span: Span,
+    },
+    /// constructs a value of an enum from one of its variants
+    VariantPack {
+        name: String,
+        arguments: Vec<Expr<S>>,
+        tag: S::StructPack,
+        span: Span,
+    },
+    Case {
+        scrutinee: Box<Expr<S>>,
+        arms: Vec<Arm<S>>,
+        tag: S::If,
+        span: Span,
+    },
+    /// reads one field out of a struct value, as in `p.x`
+    FieldAccess {
+        value: Box<Expr<S>>,
+        field: String,
+        tag: S::StructPack,
+        span: Span,
+    },
+    /// hides the type of `value` behind an existential of `protocol`. the type checker inserts these wherever an existential is expected
+    ExistentialPack {
+        value: Box<Expr<S>>,
+        protocol: String,
+        tag: S::ExistentialPack,
+        span: Span,
+    },
+    /// the value of an error union, which development mode assumes wasn't thrown. the program crashes if it was
+    Unwrap {
+        value: Box<Expr<S>>,
+        tag: S::StructPack,
+        span: Span,
+    },
+}
+
+#[derive(Clone)]
+pub struct Arm<S: Stage> {
+    pub pattern: Pattern<S>,
+    pub body: Expr<S>,
+}
+
+#[derive(Clone)]
+pub enum Pattern<S: Stage> {
+    Wildcard {
+        tag: S::PatternMeta,
+        span: Span,
+    },
+    Binding {
+        name: String,
+        tag: S::PatternMeta,
+        span: Span,
+    },
+    Literal {
+        literal: Literal,
+        tag: S::PatternMeta,
+        span: Span,
+    },
+    Variant {
+        name: String,
+        fields: Vec<Pattern<S>>,
+        tag: S::PatternMeta,
+        span: Span,
     },
 }
 
@@ -179,27 +366,121 @@ pub enum Statement<S: Stage> {
         name: S::Variable,
         typ: S::Type,
         value: Expr<S>,
+        span: Span,
     },
+    /// a function that's only visible in the rest of its block. the type checker lifts these to the top level, so later stages never see one
+    Function(Function<S>),
 }
 
 #[derive(Clone)]
 pub struct Block<S: Stage> {
     pub stmts: Vec<Statement<S>>,
     pub result: Box<Expr<S>>,
+    pub span: Span,
+}
+
+impl<S: Stage> Expr<S> {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Variable { span, .. }
+            | Expr::Literal { span, .. }
+            | Expr::CallDirect { span, .. }
+            | Expr::CallIndirect { span, .. }
+            | Expr::Primitive { span, .. }
+            | Expr::StructPack { span, .. }
+            | Expr::If { span, .. }
+            | Expr::Closure { span, .. }
+            | Expr::VariantPack { span, .. }
+            | Expr::Case { span, .. }
+            | Expr::FieldAccess { span, .. }
+            | Expr::ExistentialPack { span, .. }
+            | Expr::Unwrap { span, .. } => *span,
+            Expr::Block(block) => block.span,
+        }
+    }
+}
+
+impl<S: Stage> Pattern<S> {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard { span, .. }
+            | Pattern::Binding { span, .. }
+            | Pattern::Literal { span, .. }
+            | Pattern::Variant { span, .. } => *span,
+        }
+    }
+
+    pub const fn tag(&self) -> &S::PatternMeta {
+        match self {
+            Pattern::Wildcard { tag, .. }
+            | Pattern::Binding { tag, .. }
+            | Pattern::Literal { tag, .. }
+            | Pattern::Variant { tag, .. } => tag,
+        }
+    }
+
+    /// the names of all variables bound by this pattern, in order
+    pub fn bindings(&self) -> Vec<String> {
+        match self {
+            Pattern::Wildcard { .. } | Pattern::Literal { .. } => Vec::new(),
+            Pattern::Binding { name, .. } => vec![name.clone()],
+            Pattern::Variant { fields, .. } => fields.iter().flat_map(Pattern::bindings).collect(),
+        }
+    }
+}
+
+impl<S: Stage> Statement<S> {
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::Let { span, .. } => *span,
+            Statement::Function(function) => function.span,
+        }
+    }
 }
 
 impl<S: Stage> Program<S> {
+    fn empty() -> Self {
+        Self {
+            structs: Vec::new(),
+            enums: Vec::new(),
+            protocols: Vec::new(),
+            impls: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
     pub fn from_struct(struct_def: Struct<S>) -> Self {
         Self {
             structs: vec![struct_def],
-            functions: Vec::new(),
+            ..Self::empty()
+        }
+    }
+
+    pub fn from_enum(enum_def: Enum<S>) -> Self {
+        Self {
+            enums: vec![enum_def],
+            ..Self::empty()
+        }
+    }
+
+    pub fn from_protocol(protocol_def: Protocol) -> Self {
+        Self {
+            protocols: vec![protocol_def],
+            ..Self::empty()
+        }
+    }
+
+    pub fn from_impl(impl_def: Impl<S>) -> Self {
+        Self {
+            impls: vec![impl_def],
+            ..Self::empty()
         }
     }
 
     pub fn from_function(function_def: Function<S>) -> Self {
         Self {
-            structs: Vec::new(),
             functions: vec![function_def],
+            ..Self::empty()
         }
     }
 }
@@ -229,6 +510,19 @@ impl PartialEq for Type {
                     value: value1,
                 },
             ) => name == name1 && value.get() == value1.get(),
+            (
+                Type::Existential { protocol },
+                Type::Existential {
+                    protocol: protocol1,
+                },
+            ) => protocol == protocol1,
+            (
+                Type::Union { variants, rest },
+                Type::Union {
+                    variants: variants1,
+                    rest: rest1,
+                },
+            ) => variants == variants1 && rest == rest1,
             _ => false,
         }
     }
@@ -254,10 +548,26 @@ impl hash::Hash for Type {
                 name.hash(state);
                 value.get().hash(state);
             }
+            Type::Existential { protocol } => {
+                protocol.hash(state);
+            }
+            Type::Union { variants, rest } => {
+                variants.hash(state);
+                rest.hash(state);
+            }
         }
     }
 }
 
+/// the builtin type of `T ! E`, whose arguments are the types of the value and of the error
+pub const RESULT: &str = "_Result";
+/// the variant of an error union that holds a value, which is its first variant
+pub const OK: &str = "_Ok";
+/// the variant of an error union that holds a thrown error, which is its second variant
+pub const THROWN: &str = "_Thrown";
+/// the start of the name of every type that a union of variants is laid out as
+pub const UNION: &str = "_Union";
+
 impl Type {
     pub fn typ() -> Self {
         Self::Named {
@@ -265,6 +575,13 @@ impl Type {
             arguments: Vec::new(),
         }
     }
+    /// a pointer to the functions that implement a protocol for some type
+    pub fn dictionary() -> Self {
+        Self::Named {
+            name: String::from("_Dictionary"),
+            arguments: Vec::new(),
+        }
+    }
     pub fn float() -> Self {
         Self::Named {
             name: String::from("F64"),
@@ -285,6 +602,24 @@ impl Type {
         }
     }
 
+    /// `value ! error`
+    pub fn result(value: Self, error: Self) -> Self {
+        Self::Named {
+            name: String::from(RESULT),
+            arguments: vec![value, error],
+        }
+    }
+
+    /// the types of the value and the error, if this is an error union
+    pub fn error_union(&self) -> Option<(&Self, &Self)> {
+        match self.canonical() {
+            Self::Named { name, arguments } if name == RESULT => {
+                Some((&arguments[0], &arguments[1]))
+            }
+            _ => None,
+        }
+    }
+
     pub fn unification(name: String) -> Self {
         Self::Unification {
             name,
@@ -300,11 +635,100 @@ impl Type {
 
     pub fn canonical(&self) -> &Type {
         if let Self::Unification { value, .. } = self {
-            value.get().unwrap_or(self)
+            value.get().map_or(self, Type::canonical)
         } else {
             self
         }
     }
+
+    /// whether the named type appears anywhere in this type
+    pub fn mentions(&self, named: &str) -> bool {
+        match self.canonical() {
+            Type::Named { name, arguments } => {
+                name == named || arguments.iter().any(|arg| arg.mentions(named))
+            }
+            Type::Function { arguments, result } => {
+                arguments.iter().any(|arg| arg.mentions(named)) || result.mentions(named)
+            }
+            Type::Union { variants, rest } => {
+                variants
+                    .iter()
+                    .flat_map(|variant| &variant.fields)
+                    .any(|field| field.mentions(named))
+                    || rest.as_ref().is_some_and(|rest| rest.mentions(named))
+            }
+            Type::Generic { .. } | Type::Unification { .. } | Type::Existential { .. } => false,
+        }
+    }
+
+    /// a union of `variants`, which can gain more through `rest` if it is given
+    pub fn union(mut variants: Vec<Variant>, rest: Option<Self>) -> Self {
+        variants.sort_by(|a, b| a.name.cmp(&b.name));
+        Self::Union {
+            variants,
+            rest: rest.map(Box::new),
+        }
+    }
+
+    /// every variant of a union, following its rest as far as it is known,
+    /// along with the unification variable that the rest ends in if the union is still open
+    pub fn union_variants(&self) -> Option<(Vec<Variant>, Option<Self>)> {
+        let Self::Union { variants, rest } = self.canonical() else {
+            return None;
+        };
+        let mut variants = variants.clone();
+        let rest = match rest.as_deref().map(Self::canonical) {
+            None => None,
+            Some(open @ Self::Unification { .. }) => Some(open.clone()),
+            Some(more) => {
+                let (more, rest) = more.union_variants()?;
+                variants.extend(more);
+                rest
+            }
+        };
+        variants.sort_by(|a, b| a.name.cmp(&b.name));
+        Some((variants, rest))
+    }
+
+    /// the named type that a closed union is laid out as. its name says which variants it has and how many fields each one holds,
+    /// and its arguments are the types of those fields, in order
+    #[must_use]
+    pub fn union_shape(&self) -> Option<Self> {
+        let (variants, _) = self.union_variants()?;
+        let mut name = std::string::String::from(UNION);
+        for variant in &variants {
+            write!(name, "_{}_{}", variant.name, variant.fields.len()).unwrap();
+        }
+        Some(Self::Named {
+            name: String::from(name),
+            arguments: variants
+                .into_iter()
+                .flat_map(|variant| variant.fields)
+                .collect(),
+        })
+    }
+}
+
+impl Signature {
+    /// the index of the argument that `Self` is passed in, if it is passed exactly once and mentioned nowhere else.
+    /// only these functions can be called on an existential, by unpacking that one argument
+    pub fn self_argument(&self) -> Option<usize> {
+        let is_self = |typ: &Type| matches!(typ, Type::Named { name, arguments } if name == "Self" && arguments.is_empty());
+        let mut positions = (0..self.arguments.len()).filter(|i| is_self(&self.arguments[*i]));
+        let index = positions.next()?;
+        let elsewhere = positions.next().is_some()
+            || self
+                .arguments
+                .iter()
+                .filter(|arg| !is_self(arg))
+                .chain(std::iter::once(&self.result))
+                .any(|typ| typ.mentions("Self"));
+        if elsewhere {
+            None
+        } else {
+            Some(index)
+        }
+    }
 }
 
 impl<S: DisplayStage> fmt::Display for Program<S> {
@@ -312,6 +736,15 @@ impl<S: DisplayStage> fmt::Display for Program<S> {
         for s in &self.structs {
             writeln!(f, "{}", s)?;
         }
+        for e in &self.enums {
+            writeln!(f, "{}", e)?;
+        }
+        for protocol in &self.protocols {
+            writeln!(f, "{}", protocol)?;
+        }
+        for i in &self.impls {
+            writeln!(f, "{}", i)?;
+        }
         for func in &self.functions {
             writeln!(f, "{}", func)?;
         }
@@ -319,6 +752,33 @@ impl<S: DisplayStage> fmt::Display for Program<S> {
     }
 }
 
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "protocol {} {{", self.name)?;
+        for function in &self.functions {
+            write!(f, "\tfunc {}(", function.name)?;
+            for (i, arg) in function.arguments.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{:?}", arg)?;
+            }
+            writeln!(f, "): {:?}", function.result)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl<S: DisplayStage> fmt::Display for Impl<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "impl {} for {} {{", self.protocol, self.typ)?;
+        for function in &self.functions {
+            writeln!(f, "{}", function)?;
+        }
+        write!(f, "}}")
+    }
+}
+
 impl<S: DisplayStage> fmt::Display for Function<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "func {}", self.name)?;
@@ -339,12 +799,28 @@ impl<S: DisplayStage> fmt::Display for Function<S> {
 impl<S: DisplayStage> fmt::Display for Expr<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Expr::Variable { name, typ } => write!(f, "({}: {})", name, typ),
-            Expr::Literal { literal } => write!(f, "{}", literal),
+            Expr::Variable { name, typ, .. } => write!(f, "({}: {})", name, typ),
+            Expr::Literal { literal, .. } => write!(f, "{}", literal),
             Expr::CallDirect {
                 function,
                 arguments,
                 tag,
+                ..
+            } => {
+                write!(f, "{}[{}](", function, tag)?;
+                for (i, arg) in arguments.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::CallIndirect {
+                function,
+                arguments,
+                tag,
+                ..
             } => {
                 write!(f, "{}[{}](", function, tag)?;
                 for (i, arg) in arguments.iter().enumerate() {
@@ -359,18 +835,17 @@ impl<S: DisplayStage> fmt::Display for Expr<S> {
             Expr::Primitive {
                 primitive,
                 arguments,
+                ..
             } => match primitive {
-                Primitive::Add => {
-                    write!(f, "({} + {})", &arguments[0], &arguments[1])
-                }
-                Primitive::Sub => {
-                    write!(f, "({} - {})", &arguments[0], &arguments[1])
-                }
-                Primitive::Mul => {
-                    write!(f, "({} * {})", &arguments[0], &arguments[1])
+                Primitive::ToFloat | Primitive::ToInteger => {
+                    write!(f, "{primitive}({})", &arguments[0])
                 }
+                Primitive::Neg | Primitive::Not => write!(f, "{primitive}{}", &arguments[0]),
+                _ => write!(f, "({} {primitive} {})", &arguments[0], &arguments[1]),
             },
-            Expr::StructPack { name, fields, tag } => {
+            Expr::StructPack {
+                name, fields, tag, ..
+            } => {
                 write!(f, "{}[{}]", name, tag)?;
                 let mut strukt = f.debug_struct("");
                 for field in fields {
@@ -392,6 +867,7 @@ impl<S: DisplayStage> fmt::Display for Expr<S> {
                 arguments,
                 body,
                 tag,
+                ..
             } => {
                 if arguments.len() == 1 {
                     write!(f, "{:?}", arguments[0])?;
@@ -404,10 +880,81 @@ impl<S: DisplayStage> fmt::Display for Expr<S> {
                 }
                 write!(f, " [{tag}] => {}", body.as_ref())
             }
+            Expr::VariantPack {
+                name,
+                arguments,
+                tag,
+                ..
+            } => {
+                write!(f, "{}[{}]", name, tag)?;
+                let mut tuple = f.debug_tuple("");
+                for arg in arguments {
+                    tuple.field(arg);
+                }
+                tuple.finish()
+            }
+            Expr::Case {
+                scrutinee,
+                arms,
+                tag,
+                ..
+            } => {
+                write!(f, "case {} of [{}]", scrutinee, tag)?;
+                for arm in arms {
+                    write!(f, "\n\t| {} => {}", arm.pattern, arm.body)?;
+                }
+                Ok(())
+            }
+            Expr::FieldAccess {
+                value, field, tag, ..
+            } => write!(f, "{value}.{field}[{tag}]"),
+            Expr::ExistentialPack {
+                value,
+                protocol,
+                tag,
+                ..
+            } => write!(f, "(any {protocol} {value})[{tag}]"),
+            Expr::Unwrap { value, tag, .. } => write!(f, "(unwrap {value})[{tag}]"),
         }
     }
 }
 
+impl<S: DisplayStage> fmt::Display for Pattern<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Wildcard { .. } => write!(f, "_"),
+            Pattern::Binding { name, tag, .. } => write!(f, "{name}@{tag}"),
+            Pattern::Literal { literal, .. } => write!(f, "{literal}"),
+            Pattern::Variant {
+                name, fields, tag, ..
+            } => {
+                write!(f, "{name}@{tag}")?;
+                if !fields.is_empty() {
+                    let mut tuple = f.debug_tuple("");
+                    for field in fields {
+                        tuple.field(&format_args!("{field}"));
+                    }
+                    tuple.finish()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<S: DisplayStage> fmt::Display for Enum<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "enum {} {{", self.name)?;
+        for variant in &self.variants {
+            write!(f, "\n\t{}", variant.name)?;
+            if !variant.fields.is_empty() {
+                f.debug_list().entries(&variant.fields).finish()?;
+            }
+        }
+        write!(f, "\n}} {}", self.tag)
+    }
+}
+
 impl<S: DisplayStage> fmt::Display for Block<S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{{")?;
@@ -421,9 +968,12 @@ impl<S: DisplayStage> fmt::Display for Block<S> {
 impl<S: DisplayStage> fmt::Display for Statement<S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::Let { name, typ, value } => {
+            Statement::Let {
+                name, typ, value, ..
+            } => {
                 write!(f, "let {name}: {typ} = {value}")
             }
+            Statement::Function(function) => write!(f, "{function}"),
         }
     }
 }
@@ -444,6 +994,20 @@ impl fmt::Display for Primitive {
             Primitive::Add => "+",
             Primitive::Sub => "-",
             Primitive::Mul => "*",
+            Primitive::Div => "/",
+            Primitive::Rem => "%",
+            Primitive::Eq => "==",
+            Primitive::NotEq => "!=",
+            Primitive::Less => "<",
+            Primitive::LessEq => "<=",
+            Primitive::Greater => ">",
+            Primitive::GreaterEq => ">=",
+            Primitive::And => "&&",
+            Primitive::Or => "||",
+            Primitive::Neg => "-",
+            Primitive::Not => "!",
+            Primitive::ToFloat => "to_f64",
+            Primitive::ToInteger => "to_i64",
         }
         .fmt(f)
     }
@@ -460,7 +1024,13 @@ where
 
 impl<S: DisplayStage> fmt::Display for Struct<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut s = f.debug_struct(&format!("struct {}", self.name));
+        let name = if self.generics.is_empty() {
+            format!("struct {}", self.name)
+        } else {
+            let generics: Vec<_> = self.generics.iter().map(|g| g.name.as_str()).collect();
+            format!("struct {}[{}]", self.name, generics.join(", "))
+        };
+        let mut s = f.debug_struct(&name);
         for field in &self.fields {
             s.field(&field.name, &field.typ);
         }
@@ -472,6 +1042,9 @@ impl<S: DisplayStage> fmt::Display for Struct<S> {
 impl fmt::Debug for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Type::Named { name, arguments } if name == RESULT => {
+                write!(f, "{:?} ! {:?}", arguments[0], arguments[1])
+            }
             Type::Named { name, arguments } => {
                 write!(f, "{}", name)?;
                 if !arguments.is_empty() {
@@ -500,6 +1073,29 @@ impl fmt::Debug for Type {
                     write!(f, "{name}?")
                 }
             }
+            Type::Existential { protocol } => write!(f, "any {protocol}"),
+            Type::Union { .. } => {
+                let (variants, rest) = self.union_variants().unwrap();
+                for (i, variant) in variants.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", variant.name)?;
+                    if !variant.fields.is_empty() {
+                        let mut tuple = f.debug_tuple("");
+                        for field in &variant.fields {
+                            tuple.field(field);
+                        }
+                        tuple.finish()?;
+                    }
+                }
+                match (variants.is_empty(), rest) {
+                    (true, None) => write!(f, "(no variants)"),
+                    (true, Some(_)) => write!(f, ".."),
+                    (false, Some(_)) => write!(f, " | .."),
+                    (false, None) => Ok(()),
+                }
+            }
         }
     }
 }
@@ -1,9 +1,11 @@
 use core::fmt;
 
-use crate::generic::{self, Stage};
+use crate::generic::{self, DisplayStage, Stage};
 use crate::String;
 
-pub use generic::{Field, Generic, Literal, Type};
+pub use generic::{
+    Field, Generic, Literal, Protocol, Signature, Type, Variant, OK, RESULT, THROWN,
+};
 
 #[derive(Clone)]
 pub struct Parsed;
@@ -23,16 +25,38 @@ pub struct ClosureArgument {
 #[derive(Copy, Clone)]
 pub struct If;
 
+/// the tag of nodes that a stage has nothing to say about yet
+#[derive(Copy, Clone)]
+pub struct Untagged;
+
 impl Stage for Parsed {
     type Variable = String;
     type Argument = Argument;
-    type Call = ();
-    type Type = ();
-    type StructPack = ();
+    type Call = Untagged;
+    type Type = Untagged;
+    type StructPack = Untagged;
     type If = If;
-    type StructMeta = ();
-    type Closure = ();
+    type StructMeta = Untagged;
+    type Closure = Untagged;
     type ClosureArgument = ClosureArgument;
+    type EnumMeta = Untagged;
+    type PatternMeta = Untagged;
+    type ExistentialPack = Untagged;
+}
+
+impl DisplayStage for Parsed {
+    type Variable = String;
+    type Argument = Argument;
+    type Call = Untagged;
+    type Type = Untagged;
+    type StructPack = Untagged;
+    type If = If;
+    type StructMeta = Untagged;
+    type Closure = Untagged;
+    type ClosureArgument = ClosureArgument;
+    type EnumMeta = Untagged;
+    type PatternMeta = Untagged;
+    type ExistentialPack = Untagged;
 }
 
 pub type Program = generic::Program<Parsed>;
@@ -42,9 +66,34 @@ pub type Statement = generic::Statement<Parsed>;
 pub type Block = generic::Block<Parsed>;
 pub type PackField = generic::PackField<Parsed>;
 pub type Struct = generic::Struct<Parsed>;
+pub type Enum = generic::Enum<Parsed>;
+pub type Impl = generic::Impl<Parsed>;
+pub type Arm = generic::Arm<Parsed>;
+pub type Pattern = generic::Pattern<Parsed>;
 
 impl fmt::Display for If {
     fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         Ok(())
     }
 }
+
+impl fmt::Display for Untagged {
+    fn fmt(&self, _: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Argument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.typ)
+    }
+}
+
+impl fmt::Debug for ClosureArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.typ {
+            Some(typ) => write!(f, "{}: {}", self.name, typ),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
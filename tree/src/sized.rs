@@ -1,9 +1,12 @@
 use core::fmt;
 
 use crate::generic::{self, DisplayStage, Stage};
-use crate::String;
+use crate::{Span, String};
 
-pub use generic::{Convention, Field, Generic, Literal, Primitive, Type};
+pub use generic::{
+    Convention, Field, Generic, Literal, Primitive, Protocol, Signature, Type, Variant, OK, RESULT,
+    THROWN, UNION,
+};
 
 #[derive(Clone)]
 pub struct Sized;
@@ -27,6 +30,8 @@ pub struct Call {
 pub struct StructPack {
     pub result: Type,
     pub witness: Witness,
+    /// where a field access finds its field, if the struct's layout is known statically
+    pub offset: Option<usize>,
 }
 
 #[derive(Clone)]
@@ -54,6 +59,20 @@ pub struct StructMeta {
     pub fields: Vec<Expr>,
 }
 
+#[derive(Clone)]
+pub struct EnumMeta {
+    /// the witness tables of the type arguments of the enums that unions are laid out as, which declared enums don't have
+    pub arguments: Vec<Variable>,
+    /// the witness tables of the fields of each variant
+    pub variants: Vec<Vec<Expr>>,
+}
+
+#[derive(Clone)]
+pub struct PatternMeta {
+    pub typ: Type,
+    pub witness: Witness,
+}
+
 #[derive(Clone)]
 pub struct Closure {
     pub value_captures: Vec<ClosureArgument>,
@@ -70,6 +89,14 @@ pub struct ClosureArgument {
     pub witness: Witness,
 }
 
+#[derive(Clone)]
+pub struct ExistentialPack {
+    /// the witness table of the packed value's type
+    pub witness: Box<Expr>,
+    /// the packed value's dictionary for the protocol
+    pub dictionary: Box<Expr>,
+}
+
 impl Stage for Sized {
     type Variable = Variable;
     type Argument = Argument;
@@ -80,6 +107,9 @@ impl Stage for Sized {
     type StructMeta = StructMeta;
     type Closure = Closure;
     type ClosureArgument = ClosureArgument;
+    type EnumMeta = EnumMeta;
+    type PatternMeta = PatternMeta;
+    type ExistentialPack = ExistentialPack;
 }
 
 impl DisplayStage for Sized {
@@ -92,6 +122,9 @@ impl DisplayStage for Sized {
     type StructMeta = StructMeta;
     type Closure = Closure;
     type ClosureArgument = ClosureArgument;
+    type EnumMeta = EnumMeta;
+    type PatternMeta = PatternMeta;
+    type ExistentialPack = ExistentialPack;
 }
 
 pub type Program = generic::Program<Sized>;
@@ -101,45 +134,55 @@ pub type Block = generic::Block<Sized>;
 pub type Statement = generic::Statement<Sized>;
 pub type PackField = generic::PackField<Sized>;
 pub type Struct = generic::Struct<Sized>;
+pub type Enum = generic::Enum<Sized>;
+pub type Impl = generic::Impl<Sized>;
+pub type Arm = generic::Arm<Sized>;
+pub type Pattern = generic::Pattern<Sized>;
 
 impl Expr {
     pub fn get_type(&self) -> Type {
         match self {
             generic::Expr::Variable { typ, .. } => typ.clone(),
-            generic::Expr::Literal { literal } => literal.get_type(),
-            generic::Expr::CallDirect { tag, .. } => tag.result.clone(),
+            generic::Expr::Literal { typ, .. } => typ.clone(),
+            generic::Expr::CallDirect { tag, .. } | generic::Expr::CallIndirect { tag, .. } => {
+                tag.result.clone()
+            }
             generic::Expr::Block(block) => block.result.get_type(),
             generic::Expr::Primitive {
                 primitive,
                 arguments,
-            } =>
-            {
-                #[allow(irrefutable_let_patterns)]
-                if let Primitive::Add | Primitive::Sub | Primitive::Mul = primitive {
-                    arguments[0].get_type()
-                } else {
-                    unimplemented!()
-                }
-            }
+                ..
+            } => primitive.result_type(arguments[0].get_type()),
             generic::Expr::StructPack { tag, .. } => tag.result.clone(),
             generic::Expr::If { true_branch, .. } => true_branch.get_type(),
             generic::Expr::Closure { tag, .. } => tag.result.clone(),
+            generic::Expr::VariantPack { tag, .. }
+            | generic::Expr::FieldAccess { tag, .. }
+            | generic::Expr::Unwrap { tag, .. } => tag.result.clone(),
+            generic::Expr::Case { arms, .. } => arms[0].body.get_type(),
+            generic::Expr::ExistentialPack { protocol, .. } => Type::Existential {
+                protocol: protocol.clone(),
+            },
         }
     }
 
     pub fn get_witness(&self) -> Witness {
         match self {
             generic::Expr::Variable { name, .. } => name.witness.clone(),
-            generic::Expr::Literal { literal } => literal_witness(literal),
-            generic::Expr::CallDirect { tag, .. } => tag.witness.clone(),
-            generic::Expr::Primitive {
-                primitive,
-                arguments,
-            } => arguments[0].get_witness(),
+            generic::Expr::Literal { literal, .. } => literal_witness(literal),
+            generic::Expr::CallDirect { tag, .. } | generic::Expr::CallIndirect { tag, .. } => {
+                tag.witness.clone()
+            }
+            generic::Expr::Primitive { arguments, .. } => arguments[0].get_witness(),
             generic::Expr::Block(block) => block.result.get_witness(),
             generic::Expr::StructPack { tag, .. } => tag.witness.clone(),
             generic::Expr::If { tag, .. } => tag.witness.clone(),
             generic::Expr::Closure { tag, .. } => tag.witness.clone(),
+            generic::Expr::VariantPack { tag, .. }
+            | generic::Expr::FieldAccess { tag, .. }
+            | generic::Expr::Unwrap { tag, .. } => tag.witness.clone(),
+            generic::Expr::Case { tag, .. } => tag.witness.clone(),
+            generic::Expr::ExistentialPack { .. } => Witness::Existential,
         }
     }
 }
@@ -164,6 +207,7 @@ impl Witness {
                     witness: Witness::Type,
                     signature: vec![Convention::Out],
                 },
+                span: Span::default(),
             }),
         }
     }
@@ -227,6 +271,31 @@ impl fmt::Display for StructMeta {
     }
 }
 
+impl fmt::Display for EnumMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.arguments.is_empty() {
+            let mut tuple = f.debug_tuple("");
+            for arg in &self.arguments {
+                tuple.field(arg);
+            }
+            tuple.finish()?;
+        }
+        f.debug_list().entries(&self.variants).finish()
+    }
+}
+
+impl fmt::Display for PatternMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.witness, self.typ)
+    }
+}
+
+impl fmt::Display for ExistentialPack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.witness, self.dictionary)
+    }
+}
+
 impl fmt::Display for Closure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut tuple = f.debug_tuple("");
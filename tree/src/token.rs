@@ -11,6 +11,17 @@ impl<'a> Span<'a> {
     pub const fn new(data: &'a str, offset: usize) -> Self {
         Span { data, offset }
     }
+
+    #[must_use]
+    pub const fn end(&self) -> usize {
+        self.offset + self.data.len()
+    }
+}
+
+impl From<Span<'_>> for crate::Span {
+    fn from(span: Span<'_>) -> Self {
+        Self::new(span.offset, span.end())
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -19,6 +30,15 @@ pub enum BinaryOperator {
     Dash,
     Star,
     Slash,
+    Percent,
+    DoubleEquals,
+    BangEquals,
+    Less,
+    LessEquals,
+    Greater,
+    GreaterEquals,
+    DoubleAmpersand,
+    DoubleBar,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -28,11 +48,19 @@ pub enum Kind {
     UpperIdentifier,
     Func,
     Struct,
+    Enum,
+    Protocol,
+    Impl,
+    For,
+    Any,
     Case,
     Of,
     If,
     Then,
     Else,
+    Throw,
+    Try,
+    Catch,
     Arrow,
     ThickArrow,
     Let,
@@ -48,6 +76,8 @@ pub enum Kind {
     SingleEquals,
     SingleBar,
     Dot,
+    Underscore,
+    Bang,
     True,
     False,
     BinaryOperator(BinaryOperator),
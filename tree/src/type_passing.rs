@@ -1,13 +1,14 @@
+use core::fmt;
+
+use crate::generic::{self, DisplayStage, Stage};
 pub use crate::parsed::Argument;
 use crate::parsed::If;
 use crate::typed::{self, StructPack};
 use crate::String;
-use crate::{
-    generic::{self, Stage},
-    sized::Primitive,
-};
 
-pub use generic::{Convention, Field, Generic, Literal, Type};
+pub use generic::{
+    Convention, Field, Generic, Literal, Protocol, Signature, Type, Variant, OK, RESULT, THROWN,
+};
 
 #[derive(Copy, Clone)]
 pub struct TypePassing;
@@ -24,6 +25,12 @@ pub struct StructMeta {
     pub fields: Vec<Expr>,
 }
 
+#[derive(Clone)]
+pub struct EnumMeta {
+    /// the witness tables of the fields of each variant
+    pub variants: Vec<Vec<Expr>>,
+}
+
 #[derive(Clone)]
 pub struct Closure {
     pub value_captures: Vec<typed::ClosureArgument>,
@@ -32,6 +39,14 @@ pub struct Closure {
     pub env: Struct,
 }
 
+#[derive(Clone)]
+pub struct ExistentialPack {
+    /// the witness table of the packed value's type
+    pub witness: Box<Expr>,
+    /// the packed value's dictionary for the protocol
+    pub dictionary: Box<Expr>,
+}
+
 impl Stage for TypePassing {
     type Variable = String;
     type Argument = Argument;
@@ -42,6 +57,24 @@ impl Stage for TypePassing {
     type StructMeta = StructMeta;
     type Closure = Closure;
     type ClosureArgument = typed::ClosureArgument;
+    type EnumMeta = EnumMeta;
+    type PatternMeta = Type;
+    type ExistentialPack = ExistentialPack;
+}
+
+impl DisplayStage for TypePassing {
+    type Variable = String;
+    type Argument = Argument;
+    type Call = Call;
+    type Type = Type;
+    type StructPack = StructPack;
+    type If = If;
+    type StructMeta = StructMeta;
+    type Closure = Closure;
+    type ClosureArgument = typed::ClosureArgument;
+    type EnumMeta = EnumMeta;
+    type PatternMeta = Type;
+    type ExistentialPack = ExistentialPack;
 }
 
 pub type Program = generic::Program<TypePassing>;
@@ -51,29 +84,35 @@ pub type Block = generic::Block<TypePassing>;
 pub type Statement = generic::Statement<TypePassing>;
 pub type PackField = generic::PackField<TypePassing>;
 pub type Struct = generic::Struct<TypePassing>;
+pub type Enum = generic::Enum<TypePassing>;
+pub type Impl = generic::Impl<TypePassing>;
+pub type Arm = generic::Arm<TypePassing>;
+pub type Pattern = generic::Pattern<TypePassing>;
 
 impl Expr {
     pub fn get_type(&self) -> Type {
         match self {
             generic::Expr::Variable { typ, .. } => typ.clone(),
-            generic::Expr::Literal { literal } => literal.get_type(),
-            generic::Expr::CallDirect { tag, .. } => tag.result.clone(),
+            generic::Expr::Literal { typ, .. } => typ.clone(),
+            generic::Expr::CallDirect { tag, .. } | generic::Expr::CallIndirect { tag, .. } => {
+                tag.result.clone()
+            }
             generic::Expr::Block(block) => block.result.get_type(),
             generic::Expr::Primitive {
                 primitive,
                 arguments,
-            } =>
-            {
-                #[allow(irrefutable_let_patterns)]
-                if let Primitive::Add | Primitive::Sub | Primitive::Mul = primitive {
-                    arguments[0].get_type()
-                } else {
-                    unimplemented!()
-                }
-            }
+                ..
+            } => primitive.result_type(arguments[0].get_type()),
             generic::Expr::StructPack { tag, .. } => tag.result.clone(),
             generic::Expr::If { true_branch, .. } => true_branch.get_type(),
             generic::Expr::Closure { tag, .. } => tag.result.clone(),
+            generic::Expr::VariantPack { tag, .. }
+            | generic::Expr::FieldAccess { tag, .. }
+            | generic::Expr::Unwrap { tag, .. } => tag.result.clone(),
+            generic::Expr::Case { arms, .. } => arms[0].body.get_type(),
+            generic::Expr::ExistentialPack { protocol, .. } => Type::Existential {
+                protocol: protocol.clone(),
+            },
         }
     }
 }
@@ -83,3 +122,64 @@ pub fn make_signature(arity: usize) -> Vec<Convention> {
     signature.extend(vec![Convention::In; arity]);
     signature
 }
+
+/// the type name that `any P`'s own implementation of `P` is generated under
+pub const EXISTENTIAL: &str = "any";
+
+/// the name of the function that implements `function` of `protocol` for the named type
+pub fn impl_function_name(protocol: &str, typ: &str, function: &str) -> String {
+    String::from(format!("_{protocol}_{typ}_{function}"))
+}
+
+/// the name of the function that makes the named type's dictionary for `protocol`
+pub fn dictionary_builder_name(protocol: &str, typ: &str) -> String {
+    String::from(format!("_{protocol}_{typ}"))
+}
+
+/// the name of the argument that holds a generic's dictionary for `protocol`
+pub fn dictionary_argument_name(generic: &str, protocol: &str) -> String {
+    String::from(format!("_{generic}_{protocol}"))
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.result)
+    }
+}
+
+impl fmt::Display for StructMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tuple = f.debug_tuple("");
+        for arg in &self.arguments {
+            tuple.field(arg);
+        }
+        tuple.finish()?;
+        f.debug_list().entries(&self.fields).finish()
+    }
+}
+
+impl fmt::Display for EnumMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.variants).finish()
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tuple = f.debug_tuple("");
+        for capture in &self.type_captures {
+            tuple.field(capture);
+        }
+        for capture in &self.value_captures {
+            tuple.field(capture);
+        }
+        tuple.finish()?;
+        write!(f, ": {}", self.result)
+    }
+}
+
+impl fmt::Display for ExistentialPack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, {}", self.witness, self.dictionary)
+    }
+}
@@ -1,14 +1,13 @@
 use core::fmt;
 
-pub use crate::parsed::Argument;
+use crate::generic::{self, DisplayStage, Stage};
 use crate::parsed::If;
+pub use crate::parsed::{Argument, Untagged};
 use crate::String;
-use crate::{
-    generic::{self, Stage},
-    sized::Primitive,
-};
 
-pub use generic::{Field, Generic, Literal, Type};
+pub use generic::{
+    Field, Generic, Literal, Protocol, Signature, Type, Variant, OK, RESULT, THROWN, UNION,
+};
 
 #[derive(Clone)]
 pub struct Typed;
@@ -44,9 +43,27 @@ impl Stage for Typed {
     type Type = Type;
     type StructPack = StructPack;
     type If = If;
-    type StructMeta = ();
+    type StructMeta = Untagged;
+    type Closure = Closure;
+    type ClosureArgument = ClosureArgument;
+    type EnumMeta = Untagged;
+    type PatternMeta = Type;
+    type ExistentialPack = Untagged;
+}
+
+impl DisplayStage for Typed {
+    type Variable = String;
+    type Argument = Argument;
+    type Call = Call;
+    type Type = Type;
+    type StructPack = StructPack;
+    type If = If;
+    type StructMeta = Untagged;
     type Closure = Closure;
     type ClosureArgument = ClosureArgument;
+    type EnumMeta = Untagged;
+    type PatternMeta = Type;
+    type ExistentialPack = Untagged;
 }
 
 pub type Program = generic::Program<Typed>;
@@ -56,29 +73,35 @@ pub type Block = generic::Block<Typed>;
 pub type Statement = generic::Statement<Typed>;
 pub type PackField = generic::PackField<Typed>;
 pub type Struct = generic::Struct<Typed>;
+pub type Enum = generic::Enum<Typed>;
+pub type Impl = generic::Impl<Typed>;
+pub type Arm = generic::Arm<Typed>;
+pub type Pattern = generic::Pattern<Typed>;
 
 impl Expr {
     pub fn get_type(&self) -> Type {
         match self {
             generic::Expr::Variable { typ, .. } => typ.clone(),
-            generic::Expr::Literal { literal } => literal.get_type(),
-            generic::Expr::CallDirect { tag, .. } => tag.result.clone(),
+            generic::Expr::Literal { typ, .. } => typ.clone(),
+            generic::Expr::CallDirect { tag, .. } | generic::Expr::CallIndirect { tag, .. } => {
+                tag.result.clone()
+            }
             generic::Expr::Block(block) => block.result.get_type(),
             generic::Expr::Primitive {
                 primitive,
                 arguments,
-            } =>
-            {
-                #[allow(irrefutable_let_patterns)]
-                if let Primitive::Add | Primitive::Sub | Primitive::Mul = primitive {
-                    arguments[0].get_type()
-                } else {
-                    unimplemented!()
-                }
-            }
+                ..
+            } => primitive.result_type(arguments[0].get_type()),
             generic::Expr::StructPack { tag, .. } => tag.result.clone(),
             generic::Expr::If { true_branch, .. } => true_branch.get_type(),
             generic::Expr::Closure { tag, .. } => tag.result.clone(),
+            generic::Expr::VariantPack { tag, .. }
+            | generic::Expr::FieldAccess { tag, .. }
+            | generic::Expr::Unwrap { tag, .. } => tag.result.clone(),
+            generic::Expr::Case { arms, .. } => arms[0].body.get_type(),
+            generic::Expr::ExistentialPack { protocol, .. } => Type::Existential {
+                protocol: protocol.clone(),
+            },
         }
     }
 }
@@ -88,3 +111,36 @@ impl fmt::Display for ClosureArgument {
         write!(f, "{}: {}", self.name, self.typ)
     }
 }
+
+impl fmt::Debug for ClosureArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.result)?;
+        if !self.generics.is_empty() {
+            f.debug_list().entries(&self.generics).finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for StructPack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.result)
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tuple = f.debug_tuple("");
+        for capture in &self.captures {
+            tuple.field(capture);
+        }
+        tuple.finish()?;
+        write!(f, ": {}", self.result)
+    }
+}
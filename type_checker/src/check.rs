@@ -4,12 +4,20 @@ use crate::infer::closure_captures;
 use crate::unify::unify;
 use tree::parsed;
 use tree::typed::*;
+use tree::String;
 
 pub fn expr(env: &Env, to_check: &parsed::Expr, want: &Type) -> Result<Expr> {
+    expr_inner(env, to_check, want).map_err(|error| error.at(to_check.span()))
+}
+
+fn expr_inner(env: &Env, to_check: &parsed::Expr, want: &Type) -> Result<Expr> {
     match (to_check, want.canonical()) {
         (
             parsed::Expr::Closure {
-                arguments, body, ..
+                arguments,
+                body,
+                span,
+                ..
             },
             Type::Function {
                 arguments: arg_types,
@@ -46,12 +54,176 @@ pub fn expr(env: &Env, to_check: &parsed::Expr, want: &Type) -> Result<Expr> {
                 arguments: typed_arguments,
                 body: Box::new(typed_body),
                 tag,
+                span: *span,
+            })
+        }
+        (
+            parsed::Expr::Case {
+                scrutinee,
+                arms,
+                tag,
+                span,
+            },
+            _,
+        ) => infer::case(env, scrutinee, arms, Some(want), *tag, *span),
+        // both branches are checked, so that either of them can be what turns a value into an error union
+        (
+            parsed::Expr::If {
+                predicate,
+                true_branch,
+                false_branch,
+                tag,
+                span,
+            },
+            _,
+        ) => Ok(Expr::If {
+            predicate: Box::new(expr(env, predicate, &Type::bool())?),
+            true_branch: Box::new(expr(env, true_branch, want)?),
+            false_branch: Box::new(expr(env, false_branch, want)?),
+            tag: *tag,
+            span: *span,
+        }),
+        (parsed::Expr::Block(block), _) => Ok(Expr::Block(infer::block(env, block, Some(want))?)),
+        _ => coerce(env, infer::expr(env, to_check)?, want),
+    }
+}
+
+/// makes an expression whose type has already been inferred into one of type `want`
+pub fn coerce(env: &Env, typed: Expr, want: &Type) -> Result<Expr> {
+    let span = typed.span();
+    match want.canonical() {
+        Type::Named { arguments, .. } if want.error_union().is_some() => {
+            let typ = typed.get_type();
+            if typ.error_union().is_some() {
+                unify(want, &typ)?;
+                return Ok(typed);
+            }
+            // anything that isn't already an error union is its value
+            unify(&arguments[0], &typ)?;
+            Ok(Expr::VariantPack {
+                name: String::from(OK),
+                arguments: vec![typed],
+                tag: StructPack {
+                    result: want.clone(),
+                    generics: arguments.clone(),
+                },
+                span,
             })
         }
+        Type::Existential { protocol } => {
+            let typ = typed.get_type();
+            // a number that nothing else decided the type of is an `I64`, which is packed like any other value
+            if env.is_numeric(&typ) {
+                unify(&Type::integer(), &typ)?;
+            }
+            if let Type::Existential { .. } | Type::Unification { .. } = typ.canonical() {
+                unify(want, &typ)?;
+                return Ok(typed);
+            }
+            // the packed value has to implement the protocol, just like the argument of a bounded generic
+            let generic = Generic {
+                name: String::from("Self"),
+                bounds: vec![protocol.clone()],
+            };
+            env.require_bounds(&generic, &typ, span);
+            Ok(Expr::ExistentialPack {
+                value: Box::new(typed),
+                protocol: protocol.clone(),
+                tag: Untagged,
+                span,
+            })
+        }
+        Type::Union { .. } => {
+            let typed = infer::unwrap(env, typed)?;
+            let typ = typed.get_type();
+            if let (Some((wanted, _)), Some((found, None))) =
+                (want.union_variants(), typ.union_variants())
+            {
+                let subset = found
+                    .iter()
+                    .all(|variant| wanted.iter().any(|other| other.name == variant.name));
+                if subset && found.len() < wanted.len() {
+                    return widen(typed, &found, &wanted, want);
+                }
+            }
+            unify(want, &typ)?;
+            Ok(typed)
+        }
+        Type::Unification { .. } => {
+            unify(want, &typed.get_type())?;
+            Ok(typed)
+        }
         _ => {
-            let typed = infer::expr(env, to_check)?;
+            // a value is wanted, so an error union has to be unwrapped to get one
+            let typed = infer::unwrap(env, typed)?;
             unify(want, &typed.get_type())?;
             Ok(typed)
         }
     }
 }
+
+/// turns a value of a closed union into a value of `want`, a union with more variants, by repacking whichever variant it holds
+fn widen(typed: Expr, found: &[Variant], wanted: &[Variant], want: &Type) -> Result<Expr> {
+    let span = typed.span();
+    let from = typed.get_type();
+    let arms = found
+        .iter()
+        .map(|variant| {
+            let other = wanted
+                .iter()
+                .find(|other| other.name == variant.name)
+                .unwrap();
+            if other.fields.len() != variant.fields.len() {
+                return Err(Error::TypeMismatch {
+                    expected: want.clone(),
+                    got: from.clone(),
+                });
+            }
+            for (expected, found) in other.fields.iter().zip(&variant.fields) {
+                unify(expected, found)?;
+            }
+            let names: Vec<_> = (0..variant.fields.len())
+                .map(|i| String::from(format!("_field{i}")))
+                .collect();
+            Ok(Arm {
+                pattern: Pattern::Variant {
+                    name: variant.name.clone(),
+                    fields: names
+                        .iter()
+                        .zip(&variant.fields)
+                        .map(|(name, typ)| Pattern::Binding {
+                            name: name.clone(),
+                            tag: typ.clone(),
+                            span,
+                        })
+                        .collect(),
+                    tag: from.clone(),
+                    span,
+                },
+                body: Expr::VariantPack {
+                    name: variant.name.clone(),
+                    arguments: names
+                        .iter()
+                        .zip(&variant.fields)
+                        .map(|(name, typ)| Expr::Variable {
+                            name: name.clone(),
+                            typ: typ.clone(),
+                            span,
+                        })
+                        .collect(),
+                    tag: StructPack {
+                        result: want.clone(),
+                        generics: Vec::new(),
+                    },
+                    span,
+                },
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Expr::Case {
+        scrutinee: Box::new(typed),
+        arms,
+        tag: parsed::If,
+        span,
+    })
+}
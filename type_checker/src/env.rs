@@ -1,7 +1,12 @@
+use core::fmt;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::result;
 
 use im::{HashMap, HashSet};
-use tree::{typed::*, String};
+use tree::{typed::*, Span, String};
+
+use crate::unify::close;
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -26,6 +31,213 @@ pub enum Error {
         generic: Generic,
     },
     UnknownStruct(String),
+    UnknownField {
+        name: String,
+        field: String,
+    },
+    MissingField {
+        name: String,
+        field: String,
+    },
+    DuplicateField {
+        name: String,
+        field: String,
+    },
+    NotAStruct(Type),
+    /// a struct or enum that holds a value of its own type, directly or through other types
+    RecursiveType(String),
+    UnknownVariant(String),
+    DuplicateVariant(String),
+    NonExhaustive {
+        /// a pattern that none of the arms match
+        missing: std::string::String,
+    },
+    RedundantPattern,
+    NotAFunction(Type),
+    NotNumeric(Type),
+    NotEquatable(Type),
+    UnknownProtocol(String),
+    NotImplemented {
+        typ: Type,
+        protocol: String,
+    },
+    DuplicateImpl {
+        protocol: String,
+        typ: String,
+    },
+    MissingImplFunction {
+        protocol: String,
+        function: String,
+    },
+    NotInProtocol {
+        protocol: String,
+        function: String,
+    },
+    /// `any P` can only implement `P` if each of its functions can be called through the dictionary of the packed value
+    ExistentialUnsafe {
+        protocol: String,
+        function: String,
+    },
+    WrongArity {
+        expected: usize,
+        got: usize,
+    },
+    /// an error union whose value is used without checking for an error, which production mode doesn't allow
+    Unchecked(Type),
+    /// an error in the function call that a method call is sugar for, such as `get(map, key)` for `map.get(key)`
+    Desugared {
+        call: std::string::String,
+        error: Box<Error>,
+    },
+    /// an error that has been attributed to the innermost expression that caused it
+    Located {
+        span: Span,
+        error: Box<Error>,
+    },
+}
+
+impl Error {
+    /// attaches `span` to the error, unless a more precise span has already been attached
+    #[must_use]
+    pub fn at(self, span: Span) -> Self {
+        if let Self::Located { .. } = self {
+            self
+        } else {
+            Self::Located {
+                span,
+                error: Box::new(self),
+            }
+        }
+    }
+
+    /// notes that the error happened in `call`, keeping the span of the innermost expression that caused it
+    #[must_use]
+    pub fn desugared(self, call: std::string::String, span: Span) -> Self {
+        let (span, error) = match self {
+            Self::Located { span, error } => (span, *error),
+            error => (span, error),
+        };
+        Self::Desugared {
+            call,
+            error: Box::new(error),
+        }
+        .at(span)
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        if let Self::Located { span, .. } = self {
+            Some(*span)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownVariable(name) => write!(f, "unknown variable `{name}`"),
+            Error::UnknownFunction(name) => write!(f, "unknown function `{name}`"),
+            Error::NamedTypeMismatch { expected, got } => {
+                write!(f, "expected type `{expected}`, got `{got}`")
+            }
+            Error::TypeMismatch { expected, got } => {
+                write!(f, "expected type `{expected}`, got `{got}`")
+            }
+            Error::GenericTypeMismatch {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "generic `{name}` is used as both `{first}` and `{second}`"
+            ),
+            Error::UnspecifiedGeneric { generic } => {
+                write!(f, "could not infer a type for generic `{}`", generic.name)
+            }
+            Error::UnknownStruct(name) => write!(f, "unknown struct `{name}`"),
+            Error::UnknownField { name, field } => {
+                write!(f, "struct `{name}` has no field `{field}`")
+            }
+            Error::MissingField { name, field } => {
+                write!(f, "struct `{name}` is missing field `{field}`")
+            }
+            Error::DuplicateField { name, field } => {
+                write!(f, "struct `{name}` is given field `{field}` more than once")
+            }
+            Error::NotAStruct(typ) => write!(f, "`{typ}` is not a struct type"),
+            Error::RecursiveType(name) => write!(
+                f,
+                "`{name}` holds a value of its own type, so its values would take up infinite space"
+            ),
+            Error::UnknownVariant(name) => write!(f, "unknown variant `{name}`"),
+            Error::DuplicateVariant(name) => {
+                write!(f, "variant `{name}` is defined more than once")
+            }
+            Error::NonExhaustive { missing } => {
+                write!(f, "non-exhaustive patterns: `{missing}` is not covered")
+            }
+            Error::RedundantPattern => write!(f, "unreachable pattern"),
+            Error::NotAFunction(typ) => write!(f, "`{typ}` is not a function type"),
+            Error::NotNumeric(typ) => write!(f, "expected `I64` or `F64`, got `{typ}`"),
+            Error::NotEquatable(typ) => {
+                write!(f, "expected `I64`, `F64` or `Bool`, got `{typ}`")
+            }
+            Error::UnknownProtocol(name) => write!(f, "unknown protocol `{name}`"),
+            Error::NotImplemented { typ, protocol } => {
+                write!(f, "`{typ}` does not implement `{protocol}`")
+            }
+            Error::DuplicateImpl { protocol, typ } => {
+                write!(f, "`{protocol}` is implemented more than once for `{typ}`")
+            }
+            Error::MissingImplFunction { protocol, function } => {
+                write!(f, "impl of `{protocol}` is missing function `{function}`")
+            }
+            Error::NotInProtocol { protocol, function } => {
+                write!(f, "`{function}` is not a function of protocol `{protocol}`")
+            }
+            Error::ExistentialUnsafe { protocol, function } => write!(
+                f,
+                "`any {protocol}` does not implement `{protocol}`, because `{function}` must take `Self` exactly once and mention it nowhere else"
+            ),
+            Error::WrongArity { expected, got } => {
+                write!(f, "expected {expected} arguments, got {got}")
+            }
+            Error::Unchecked(typ) => write!(
+                f,
+                "`{typ}` is used without checking for an error, which has to be caught with `try`"
+            ),
+            Error::Desugared { call, error } => write!(f, "{error}, in `{call}`"),
+            Error::Located { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+/// what to do with an error union whose value is used without checking for an error
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// unwrap it, crashing the program if it holds an error, and warn about it
+    #[default]
+    Development,
+    /// reject the program
+    Production,
+}
+
+/// an error union that development mode unwraps
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub typ: Type,
+    pub span: Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is used without checking for an error, so the program crashes if one was thrown",
+            self.typ
+        )
+    }
 }
 
 #[derive(Clone)]
@@ -37,35 +249,124 @@ pub struct FunctionScheme {
 
 #[derive(Clone)]
 pub struct StructScheme {
+    pub generics: Vec<Generic>,
     pub fields: HashMap<String, Type>,
     pub result: Type,
 }
 
+#[derive(Clone)]
+pub struct EnumScheme {
+    /// the names of the variants, in declaration order
+    pub variants: Vec<String>,
+    pub result: Type,
+}
+
+#[derive(Clone)]
+pub struct VariantScheme {
+    pub enum_name: String,
+    pub index: usize,
+    /// only the variants of error unions are generic
+    pub generics: Vec<Generic>,
+    pub fields: Vec<Type>,
+    pub result: Type,
+}
+
+#[derive(Clone)]
+pub struct ProtocolScheme {
+    /// the protocol's functions, with `Self` as their only generic
+    pub functions: HashMap<String, FunctionScheme>,
+    /// the functions as declared, with `Self` as a named type
+    pub signatures: Vec<Signature>,
+}
+
+/// a type that has to implement a protocol, which is checked once the whole function has been inferred
+#[derive(Clone)]
+pub struct Obligation {
+    pub generic: Generic,
+    pub typ: Type,
+    pub protocol: String,
+    pub span: Span,
+    /// the generics in scope where the obligation arose, which include those of any local function it's in
+    pub generics: HashMap<String, Vec<String>>,
+}
+
+/// a function defined in a block, which is called as the top level function it's lifted to
+#[derive(Clone)]
+pub struct Local {
+    /// the name of the lifted function
+    pub lifted: String,
+    /// the enclosing function's generics, which the lifted function takes first and are passed on as themselves
+    pub generics: Vec<Generic>,
+    /// the variables that hold copies of what the function captures, which are passed after its arguments
+    pub captures: Vec<String>,
+}
+
 #[derive(Clone)]
 pub struct Env {
     variables: HashMap<String, Type>,
     functions: HashMap<String, FunctionScheme>,
-    generics: HashSet<String>,
+    /// the functions defined in enclosing blocks, which are also in `functions`
+    locals: HashMap<String, Local>,
+    /// the local functions that have been lifted to the top level so far
+    lifted: Rc<RefCell<Vec<Function>>>,
+    /// how many local functions have been given a name so far
+    next_local: Rc<Cell<usize>>,
+    /// the generics in scope, along with the protocols they are bound by
+    generics: HashMap<String, Vec<String>>,
     structs: HashMap<String, StructScheme>,
+    enums: HashMap<String, EnumScheme>,
+    variants: HashMap<String, VariantScheme>,
+    protocols: HashMap<String, ProtocolScheme>,
+    /// pairs of protocol and type name
+    impls: HashSet<(String, String)>,
+    obligations: Rc<RefCell<Vec<Obligation>>>,
+    /// types that have to be `I64` or `F64`, along with the expression that needs them to be
+    numbers: Rc<RefCell<Vec<(Type, Span)>>>,
+    /// types that have to be compared with `==` or `!=`, along with the expression that compares them
+    equatable: Rc<RefCell<Vec<(Type, Span)>>>,
+    /// the rows of unions that can still gain variants, which are closed if nothing else decides what those are
+    rows: Rc<RefCell<Vec<Type>>>,
+    mode: Mode,
+    /// the error unions that development mode has unwrapped so far
+    warnings: Rc<RefCell<Vec<Warning>>>,
 }
 
 impl Env {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         variables: HashMap<String, Type>,
         functions: HashMap<String, FunctionScheme>,
-        generics: HashSet<String>,
+        generics: HashMap<String, Vec<String>>,
         structs: HashMap<String, StructScheme>,
+        enums: HashMap<String, EnumScheme>,
+        variants: HashMap<String, VariantScheme>,
+        protocols: HashMap<String, ProtocolScheme>,
+        impls: HashSet<(String, String)>,
+        mode: Mode,
     ) -> Self {
         Self {
             variables,
             functions,
+            locals: HashMap::new(),
+            lifted: Rc::default(),
+            next_local: Rc::default(),
             generics,
             structs,
+            enums,
+            variants,
+            protocols,
+            impls,
+            obligations: Rc::default(),
+            numbers: Rc::default(),
+            equatable: Rc::default(),
+            rows: Rc::default(),
+            mode,
+            warnings: Rc::default(),
         }
     }
     pub fn define_generics<'a>(&mut self, generics: impl Iterator<Item = &'a Generic>) {
         self.generics
-            .extend(generics.map(|generic| generic.name.clone()))
+            .extend(generics.map(|generic| (generic.name.clone(), generic.bounds.clone())));
     }
 
     pub fn define_arguments<'a>(&mut self, arguments: impl Iterator<Item = &'a Argument>) {
@@ -91,10 +392,204 @@ impl Env {
         self.variables.insert(name, typ);
     }
 
+    pub fn define_local(&mut self, name: String, scheme: FunctionScheme, local: Local) {
+        self.functions.insert(name.clone(), scheme);
+        self.locals.insert(name, local);
+    }
+
+    #[must_use]
+    pub fn lookup_local(&self, name: &String) -> Option<Local> {
+        self.locals.get(name).cloned()
+    }
+
+    /// a name for a local function's lifted version that no other function has, since identifiers can't start with `_`
+    #[must_use]
+    pub fn fresh_local(&self, name: &String) -> String {
+        let index = self.next_local.get();
+        self.next_local.set(index + 1);
+        String::from(format!("_local{index}_{name}"))
+    }
+
+    pub fn lift(&self, function: Function) {
+        self.lifted.borrow_mut().push(function);
+    }
+
+    #[must_use]
+    pub fn lifted(&self) -> Vec<Function> {
+        self.lifted.borrow().clone()
+    }
+
+    /// the generics in scope, ordered by name
+    #[must_use]
+    pub fn generics(&self) -> Vec<Generic> {
+        let mut generics: Vec<_> = self
+            .generics
+            .iter()
+            .map(|(name, bounds)| Generic {
+                name: name.clone(),
+                bounds: bounds.clone(),
+            })
+            .collect();
+        generics.sort_by(|a, b| a.name.cmp(&b.name));
+        generics
+    }
+
     pub fn lookup_struct(&self, name: &String) -> Result<StructScheme> {
         self.structs
             .get(name)
             .ok_or(Error::UnknownStruct(name.clone()))
             .cloned()
     }
+
+    pub fn lookup_enum(&self, name: &String) -> Option<EnumScheme> {
+        self.enums.get(name).cloned()
+    }
+
+    pub fn lookup_variant(&self, name: &String) -> Result<VariantScheme> {
+        self.variants
+            .get(name)
+            .ok_or(Error::UnknownVariant(name.clone()))
+            .cloned()
+    }
+
+    pub fn lookup_protocol(&self, name: &String) -> Result<ProtocolScheme> {
+        self.protocols
+            .get(name)
+            .ok_or(Error::UnknownProtocol(name.clone()))
+            .cloned()
+    }
+
+    /// records that `typ` has to implement every protocol that bounds `generic`
+    pub fn require_bounds(&self, generic: &Generic, typ: &Type, span: Span) {
+        self.obligations
+            .borrow_mut()
+            .extend(generic.bounds.iter().map(|protocol| Obligation {
+                generic: generic.clone(),
+                typ: typ.clone(),
+                protocol: protocol.clone(),
+                span,
+                generics: self.generics.clone(),
+            }));
+    }
+
+    /// records that `typ` has to be one of the numeric types
+    pub fn require_numeric(&self, typ: &Type, span: Span) {
+        self.numbers.borrow_mut().push((typ.clone(), span));
+    }
+
+    /// whether `typ` is still undecided but has to be one of the numeric types, like the type of an integer literal
+    #[must_use]
+    pub fn is_numeric(&self, typ: &Type) -> bool {
+        let Type::Unification { value, .. } = typ.canonical() else {
+            return false;
+        };
+        self.numbers.borrow().iter().any(|(number, _)| {
+            matches!(number.canonical(), Type::Unification { value: other, .. } if Rc::ptr_eq(value, other))
+        })
+    }
+
+    /// records that `typ` has to be one of the types whose values can be compared directly
+    pub fn require_equatable(&self, typ: &Type, span: Span) {
+        self.equatable.borrow_mut().push((typ.clone(), span));
+    }
+
+    /// a fresh row for the rest of a union, so that it can gain more variants
+    #[must_use]
+    pub fn open_row(&self) -> Type {
+        let row = Type::unification(String::from("variants"));
+        self.rows.borrow_mut().push(row.clone());
+        row
+    }
+
+    /// allows an error union of type `typ` to be used without checking for an error, if the mode does
+    pub fn unwrap_error(&self, typ: &Type, span: Span) -> Result<()> {
+        match self.mode {
+            Mode::Development => {
+                self.warnings.borrow_mut().push(Warning {
+                    typ: typ.clone(),
+                    span,
+                });
+                Ok(())
+            }
+            Mode::Production => Err(Error::Unchecked(typ.clone()).at(span)),
+        }
+    }
+
+    /// the error unions that have been unwrapped, in the order they were checked
+    #[must_use]
+    pub fn warnings(&self) -> Vec<Warning> {
+        self.warnings.borrow().clone()
+    }
+
+    /// checks every obligation recorded so far, now that inference has filled in their types
+    pub fn discharge_obligations(&self) -> Result<()> {
+        let numbers: Vec<_> = self.numbers.borrow_mut().drain(..).collect();
+        for (typ, span) in numbers {
+            match typ.canonical() {
+                // nothing else decided what kind of number this is
+                Type::Unification { value, .. } => value.set(Type::integer()).unwrap(),
+                Type::Named { name, arguments }
+                    if arguments.is_empty() && (name == "I64" || name == "F64") => {}
+                other => return Err(Error::NotNumeric(other.clone()).at(span)),
+            }
+        }
+        for row in self.rows.borrow_mut().drain(..) {
+            close(&row);
+        }
+        let equatable: Vec<_> = self.equatable.borrow_mut().drain(..).collect();
+        for (typ, span) in equatable {
+            match typ.canonical() {
+                Type::Named { name, arguments }
+                    if arguments.is_empty()
+                        && (name == "I64" || name == "F64" || name == "Bool") => {}
+                other => return Err(Error::NotEquatable(other.clone()).at(span)),
+            }
+        }
+        let obligations: Vec<_> = self.obligations.borrow_mut().drain(..).collect();
+        for obligation in obligations {
+            let implemented = match obligation.typ.canonical() {
+                Type::Named { name, .. } => self
+                    .impls
+                    .contains(&(obligation.protocol.clone(), name.clone())),
+                Type::Generic { name } => obligation
+                    .generics
+                    .get(name)
+                    .is_some_and(|bounds| bounds.contains(&obligation.protocol)),
+                Type::Existential { protocol } if *protocol == obligation.protocol => {
+                    self.check_existential_safe(protocol)
+                        .map_err(|error| error.at(obligation.span))?;
+                    true
+                }
+                Type::Function { .. } | Type::Existential { .. } | Type::Union { .. } => false,
+                Type::Unification { .. } => {
+                    return Err(Error::UnspecifiedGeneric {
+                        generic: obligation.generic,
+                    }
+                    .at(obligation.span))
+                }
+            };
+            if !implemented {
+                return Err(Error::NotImplemented {
+                    typ: obligation.typ,
+                    protocol: obligation.protocol,
+                }
+                .at(obligation.span));
+            }
+        }
+        Ok(())
+    }
+
+    /// checks that every function of the protocol can be called on `any P`
+    fn check_existential_safe(&self, protocol: &String) -> Result<()> {
+        let scheme = self.lookup_protocol(protocol)?;
+        for signature in &scheme.signatures {
+            if signature.self_argument().is_none() {
+                return Err(Error::ExistentialUnsafe {
+                    protocol: protocol.clone(),
+                    function: signature.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
 }
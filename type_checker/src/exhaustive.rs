@@ -0,0 +1,270 @@
+//! exhaustiveness and redundancy checking for `case` expressions, using the usefulness algorithm from Maranget's "Warnings for pattern matching"
+
+use core::fmt;
+
+use tree::typed::*;
+use tree::String;
+
+use crate::env::*;
+
+#[derive(Clone, PartialEq)]
+enum Constructor {
+    Variant(String),
+    /// a variant of a union, which has no declaration to find the others in. they are listed here instead,
+    /// along with how many fields each one has, unless the union is open and there is no telling what they are
+    Structural {
+        name: String,
+        arity: usize,
+        union: Option<Vec<(String, usize)>>,
+    },
+    Boolean(bool),
+    /// numbers have too many values to ever be covered by literals alone
+    Number(std::string::String),
+}
+
+/// a pattern with the bindings erased, since they match everything just like a wildcard
+#[derive(Clone)]
+enum Space {
+    Wildcard,
+    Constructor(Constructor, Vec<Space>),
+}
+
+pub fn check(env: &Env, arms: &[Arm]) -> Result<()> {
+    let mut rows: Vec<Vec<Space>> = Vec::new();
+    for arm in arms {
+        let row = vec![space(&arm.pattern)];
+        if !useful(env, &rows, &row) {
+            return Err(Error::RedundantPattern.at(arm.pattern.span()));
+        }
+        rows.push(row);
+    }
+    if let Some(missing) = missing(env, &rows, 1) {
+        return Err(Error::NonExhaustive {
+            missing: missing[0].to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn space(pattern: &Pattern) -> Space {
+    match pattern {
+        Pattern::Wildcard { .. } | Pattern::Binding { .. } => Space::Wildcard,
+        Pattern::Literal { literal, .. } => {
+            let constructor = match literal {
+                Literal::Boolean(boolean) => Constructor::Boolean(*boolean),
+                Literal::Float(_) | Literal::Integer(_) => Constructor::Number(literal.to_string()),
+            };
+            Space::Constructor(constructor, Vec::new())
+        }
+        Pattern::Variant {
+            name, fields, tag, ..
+        } => {
+            let constructor = match tag.union_variants() {
+                Some((variants, rest)) => Constructor::Structural {
+                    name: name.clone(),
+                    arity: fields.len(),
+                    union: rest.is_none().then(|| {
+                        variants
+                            .into_iter()
+                            .map(|variant| (variant.name, variant.fields.len()))
+                            .collect()
+                    }),
+                },
+                None => Constructor::Variant(name.clone()),
+            };
+            Space::Constructor(constructor, fields.iter().map(space).collect())
+        }
+    }
+}
+
+/// whether some value matched by `vector` is not matched by any of `rows`
+fn useful(env: &Env, rows: &[Vec<Space>], vector: &[Space]) -> bool {
+    let Some((head, tail)) = vector.split_first() else {
+        return rows.is_empty();
+    };
+    match head {
+        Space::Constructor(constructor, fields) => useful(
+            env,
+            &specialize(env, rows, constructor),
+            &[fields.as_slice(), tail].concat(),
+        ),
+        Space::Wildcard => match complete_signature(env, rows) {
+            Some(universe) => universe.iter().any(|constructor| {
+                let wildcards = vec![Space::Wildcard; arity(env, constructor)];
+                useful(
+                    env,
+                    &specialize(env, rows, constructor),
+                    &[wildcards.as_slice(), tail].concat(),
+                )
+            }),
+            None => useful(env, &default(rows), tail),
+        },
+    }
+}
+
+/// finds `width` patterns that together describe values that none of `rows` match
+fn missing(env: &Env, rows: &[Vec<Space>], width: usize) -> Option<Vec<Space>> {
+    if width == 0 {
+        return rows.is_empty().then(Vec::new);
+    }
+    let heads = head_constructors(rows);
+    let universe = heads
+        .first()
+        .and_then(|constructor| universe(env, constructor));
+    if let Some(universe) = &universe {
+        if universe
+            .iter()
+            .all(|constructor| heads.contains(constructor))
+        {
+            return universe.iter().find_map(|constructor| {
+                let arity = arity(env, constructor);
+                let mut fields =
+                    missing(env, &specialize(env, rows, constructor), arity + width - 1)?;
+                let rest = fields.split_off(arity);
+                let mut found = vec![Space::Constructor(constructor.clone(), fields)];
+                found.extend(rest);
+                Some(found)
+            });
+        }
+    }
+    let mut found = missing(env, &default(rows), width - 1)?;
+    let uncovered = universe
+        .into_iter()
+        .flatten()
+        .find(|constructor| !heads.contains(constructor));
+    let head = match uncovered {
+        Some(constructor) => {
+            let wildcards = vec![Space::Wildcard; arity(env, &constructor)];
+            Space::Constructor(constructor, wildcards)
+        }
+        None => Space::Wildcard,
+    };
+    found.insert(0, head);
+    Some(found)
+}
+
+fn head_constructors(rows: &[Vec<Space>]) -> Vec<Constructor> {
+    let mut heads = Vec::new();
+    for row in rows {
+        if let Space::Constructor(constructor, _) = &row[0] {
+            if !heads.contains(constructor) {
+                heads.push(constructor.clone());
+            }
+        }
+    }
+    heads
+}
+
+/// every constructor of the first column's type, if the rows mention all of them
+fn complete_signature(env: &Env, rows: &[Vec<Space>]) -> Option<Vec<Constructor>> {
+    let heads = head_constructors(rows);
+    let universe = universe(env, heads.first()?)?;
+    universe
+        .iter()
+        .all(|constructor| heads.contains(constructor))
+        .then_some(universe)
+}
+
+/// every constructor of the type that `constructor` belongs to, or `None` if there are too many to list
+fn universe(env: &Env, constructor: &Constructor) -> Option<Vec<Constructor>> {
+    match constructor {
+        Constructor::Variant(name) => {
+            let variant = env.lookup_variant(name).ok()?;
+            let scheme = env.lookup_enum(&variant.enum_name)?;
+            Some(
+                scheme
+                    .variants
+                    .into_iter()
+                    .map(Constructor::Variant)
+                    .collect(),
+            )
+        }
+        Constructor::Structural { union, .. } => Some(
+            union
+                .clone()?
+                .into_iter()
+                .map(|(name, arity)| Constructor::Structural {
+                    name,
+                    arity,
+                    union: union.clone(),
+                })
+                .collect(),
+        ),
+        Constructor::Boolean(_) => Some(vec![
+            Constructor::Boolean(true),
+            Constructor::Boolean(false),
+        ]),
+        Constructor::Number(_) => None,
+    }
+}
+
+fn arity(env: &Env, constructor: &Constructor) -> usize {
+    match constructor {
+        Constructor::Variant(name) => env
+            .lookup_variant(name)
+            .map_or(0, |variant| variant.fields.len()),
+        Constructor::Structural { arity, .. } => *arity,
+        Constructor::Boolean(_) | Constructor::Number(_) => 0,
+    }
+}
+
+/// the rows that match `constructor`, with its fields spliced in place of the first column
+fn specialize(env: &Env, rows: &[Vec<Space>], constructor: &Constructor) -> Vec<Vec<Space>> {
+    rows.iter()
+        .filter_map(|row| {
+            let (head, tail) = row.split_first()?;
+            match head {
+                Space::Constructor(other, fields) if other == constructor => {
+                    Some([fields.as_slice(), tail].concat())
+                }
+                Space::Constructor(..) => None,
+                Space::Wildcard => {
+                    let wildcards = vec![Space::Wildcard; arity(env, constructor)];
+                    Some([wildcards.as_slice(), tail].concat())
+                }
+            }
+        })
+        .collect()
+}
+
+/// the rows that match any constructor not in the first column, without the first column
+fn default(rows: &[Vec<Space>]) -> Vec<Vec<Space>> {
+    rows.iter()
+        .filter_map(|row| match row.split_first()? {
+            (Space::Wildcard, tail) => Some(tail.to_vec()),
+            (Space::Constructor(..), _) => None,
+        })
+        .collect()
+}
+
+impl fmt::Display for Space {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Space::Wildcard => write!(f, "_"),
+            // `try` matches errors by the pattern that the variant holds them in
+            Space::Constructor(Constructor::Variant(name), fields) if name == THROWN => {
+                write!(f, "throw {}", fields[0])
+            }
+            Space::Constructor(
+                Constructor::Variant(name) | Constructor::Structural { name, .. },
+                fields,
+            ) => {
+                write!(f, "{name}")?;
+                if !fields.is_empty() {
+                    write!(f, "(")?;
+                    for (i, field) in fields.iter().enumerate() {
+                        if i != 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{field}")?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Space::Constructor(Constructor::Boolean(true), _) => write!(f, "True"),
+            Space::Constructor(Constructor::Boolean(false), _) => write!(f, "False"),
+            Space::Constructor(Constructor::Number(number), _) => write!(f, "{number}"),
+        }
+    }
+}
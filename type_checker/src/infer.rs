@@ -1,15 +1,21 @@
 use crate::check;
 use crate::env::*;
+use crate::exhaustive;
 use crate::specialize::apply;
 use crate::specialize::make_specialization;
+use crate::specialize::Spec;
+use crate::unify::{close, unify};
 use im::HashMap;
 use im::HashSet;
+use im::Vector;
+use std::iter;
 use tree::parsed;
+use tree::sized::Primitive;
 use tree::typed::*;
-use tree::String;
-use unzip3::Unzip3;
+use tree::{Span, String};
 
-pub fn program(program: &parsed::Program) -> Result<Program> {
+/// checks a whole program, along with the error unions that `mode` allowed to be used without checking for an error
+pub fn program(program: &parsed::Program, mode: Mode) -> Result<(Program, Vec<Warning>)> {
     let struct_signatures = program
         .structs
         .iter()
@@ -22,16 +28,60 @@ pub fn program(program: &parsed::Program) -> Result<Program> {
             (
                 strukt.name.clone(),
                 StructScheme {
+                    generics: strukt.generics.clone(),
                     fields,
                     result: Type::Named {
                         name: strukt.name.clone(),
-                        arguments: Vec::new(),
+                        arguments: strukt
+                            .generics
+                            .iter()
+                            .map(|generic| Type::Generic {
+                                name: generic.name.clone(),
+                            })
+                            .collect(),
                     },
                 },
             )
         })
         .collect();
-    let functions_signatures = program
+    let mut enum_signatures = HashMap::new();
+    let mut variant_signatures = HashMap::new();
+    for enumeration in &program.enums {
+        let result = Type::Named {
+            name: enumeration.name.clone(),
+            arguments: Vec::new(),
+        };
+        for (index, variant) in enumeration.variants.iter().enumerate() {
+            let scheme = VariantScheme {
+                enum_name: enumeration.name.clone(),
+                index,
+                generics: Vec::new(),
+                fields: variant.fields.clone(),
+                result: result.clone(),
+            };
+            // variants are referred to without their enum's name, so they must be unique across all enums
+            if variant_signatures
+                .insert(variant.name.clone(), scheme)
+                .is_some()
+            {
+                return Err(Error::DuplicateVariant(variant.name.clone()).at(enumeration.span));
+            }
+        }
+        enum_signatures.insert(
+            enumeration.name.clone(),
+            EnumScheme {
+                variants: enumeration
+                    .variants
+                    .iter()
+                    .map(|variant| variant.name.clone())
+                    .collect(),
+                result,
+            },
+        );
+    }
+    define_error_union(&mut enum_signatures, &mut variant_signatures);
+    check_finite(program)?;
+    let mut functions_signatures: HashMap<_, _> = program
         .functions
         .iter()
         .map(|func| {
@@ -45,19 +95,81 @@ pub fn program(program: &parsed::Program) -> Result<Program> {
             )
         })
         .collect();
+    let mut protocol_signatures = HashMap::new();
+    for protocol in &program.protocols {
+        // protocol functions are called like any other function, with `Self` bound by the protocol
+        let self_generic = Generic {
+            name: String::from("Self"),
+            bounds: vec![protocol.name.clone()],
+        };
+        let self_type = Type::Generic {
+            name: self_generic.name.clone(),
+        };
+        let functions: HashMap<_, _> = protocol
+            .functions
+            .iter()
+            .map(|signature| {
+                (
+                    signature.name.clone(),
+                    FunctionScheme {
+                        generics: vec![self_generic.clone()],
+                        arguments: signature
+                            .arguments
+                            .iter()
+                            .map(|arg| replace_self(arg, &self_type))
+                            .collect(),
+                        result: replace_self(&signature.result, &self_type),
+                    },
+                )
+            })
+            .collect();
+        functions_signatures.extend(functions.clone());
+        protocol_signatures.insert(
+            protocol.name.clone(),
+            ProtocolScheme {
+                functions,
+                signatures: protocol.functions.clone(),
+            },
+        );
+    }
+    let mut impl_signatures = HashSet::new();
+    for to_check in &program.impls {
+        if impl_signatures
+            .insert((to_check.protocol.clone(), to_check.typ.clone()))
+            .is_some()
+        {
+            return Err(Error::DuplicateImpl {
+                protocol: to_check.protocol.clone(),
+                typ: to_check.typ.clone(),
+            }
+            .at(to_check.span));
+        }
+    }
 
     let env = Env::new(
         HashMap::new(),
         functions_signatures,
-        HashSet::new(),
+        HashMap::new(),
         struct_signatures,
+        enum_signatures,
+        variant_signatures,
+        protocol_signatures,
+        impl_signatures,
+        mode,
     );
 
-    let functions = program
+    let impls = program
+        .impls
+        .iter()
+        .map(|to_check| implementation(&env, to_check))
+        .collect::<Result<_>>()?;
+
+    let mut functions: Vec<_> = program
         .functions
         .iter()
         .map(|func| function(env.clone(), func))
         .collect::<Result<_>>()?;
+    functions.extend(env.lifted());
 
     let structs = program
         .structs
@@ -65,14 +177,271 @@ pub fn program(program: &parsed::Program) -> Result<Program> {
         .map(|to_infer| strukt(to_infer))
         .collect();
 
-    Ok(Program { structs, functions })
+    let enums = program
+        .enums
+        .iter()
+        .map(|to_infer| enumeration(to_infer))
+        .collect();
+
+    Ok((
+        Program {
+            structs,
+            enums,
+            protocols: program.protocols.clone(),
+            impls,
+            functions,
+        },
+        env.warnings(),
+    ))
+}
+
+/// error unions are an enum whose variants hold either the value or the error
+fn define_error_union(
+    enum_signatures: &mut HashMap<String, EnumScheme>,
+    variant_signatures: &mut HashMap<String, VariantScheme>,
+) {
+    let generics: Vec<_> = ["Value", "Error"]
+        .into_iter()
+        .map(|name| Generic {
+            name: String::from(name),
+            bounds: Vec::new(),
+        })
+        .collect();
+    let fields: Vec<_> = generics
+        .iter()
+        .map(|generic| Type::Generic {
+            name: generic.name.clone(),
+        })
+        .collect();
+    let result = Type::result(fields[0].clone(), fields[1].clone());
+    for (index, (name, field)) in [OK, THROWN].into_iter().zip(fields).enumerate() {
+        variant_signatures.insert(
+            String::from(name),
+            VariantScheme {
+                enum_name: String::from(RESULT),
+                index,
+                generics: generics.clone(),
+                fields: vec![field],
+                result: result.clone(),
+            },
+        );
+    }
+    enum_signatures.insert(
+        String::from(RESULT),
+        EnumScheme {
+            variants: vec![String::from(OK), String::from(THROWN)],
+            result,
+        },
+    );
+}
+
+/// makes sure that no struct or enum holds a value of its own type, since such values would need infinite space
+fn check_finite(program: &parsed::Program) -> Result<()> {
+    let mut layouts: HashMap<String, (Vec<Generic>, Vec<Type>, Span)> = HashMap::new();
+    for strukt in &program.structs {
+        let fields = strukt
+            .fields
+            .iter()
+            .map(|field| field.typ.clone())
+            .collect();
+        layouts.insert(
+            strukt.name.clone(),
+            (strukt.generics.clone(), fields, strukt.span),
+        );
+    }
+    for enumeration in &program.enums {
+        let fields = enumeration
+            .variants
+            .iter()
+            .flat_map(|variant| variant.fields.iter().cloned())
+            .collect();
+        layouts.insert(
+            enumeration.name.clone(),
+            (Vec::new(), fields, enumeration.span),
+        );
+    }
+    for name in program
+        .structs
+        .iter()
+        .map(|strukt| &strukt.name)
+        .chain(program.enums.iter().map(|enumeration| &enumeration.name))
+    {
+        let typ = Type::Named {
+            name: name.clone(),
+            arguments: layouts[name]
+                .0
+                .iter()
+                .map(|generic| Type::Generic {
+                    name: generic.name.clone(),
+                })
+                .collect(),
+        };
+        if let Some(recursive) = held_twice(&layouts, &typ, &Vector::new())? {
+            return Err(Error::RecursiveType(recursive.clone()).at(layouts[&recursive].2));
+        }
+    }
+    Ok(())
+}
+
+/// the first struct or enum that a value of type `typ` holds inside a value of the same struct or enum,
+/// where `path` are the types that are already holding it. closures and existentials hold their contents behind a pointer
+fn held_twice(
+    layouts: &HashMap<String, (Vec<Generic>, Vec<Type>, Span)>,
+    typ: &Type,
+    path: &Vector<String>,
+) -> Result<Option<String>> {
+    let fields = match typ.canonical() {
+        Type::Named { name, .. } if path.contains(name) => return Ok(Some(name.clone())),
+        Type::Named { name, arguments } => match layouts.get(name) {
+            Some((generics, fields, _)) => {
+                let spec = generics
+                    .iter()
+                    .map(|generic| generic.name.clone())
+                    .zip(arguments.iter().cloned())
+                    .collect();
+                let mut path = path.clone();
+                path.push_back(name.clone());
+                for field in fields {
+                    if let Some(recursive) = held_twice(layouts, &apply(field, &spec)?, &path)? {
+                        return Ok(Some(recursive));
+                    }
+                }
+                return Ok(None);
+            }
+            // error unions hold either of their arguments
+            None => arguments.clone(),
+        },
+        Type::Union { .. } => typ
+            .union_variants()
+            .map(|(variants, _)| {
+                variants
+                    .into_iter()
+                    .flat_map(|variant| variant.fields)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Type::Generic { .. }
+        | Type::Function { .. }
+        | Type::Unification { .. }
+        | Type::Existential { .. } => Vec::new(),
+    };
+    for field in &fields {
+        if let Some(recursive) = held_twice(layouts, field, path)? {
+            return Ok(Some(recursive));
+        }
+    }
+    Ok(None)
+}
+
+/// substitutes `with` for every `Self` in a protocol function's signature
+fn replace_self(typ: &Type, with: &Type) -> Type {
+    match typ {
+        Type::Named { name, arguments } if name == "Self" && arguments.is_empty() => with.clone(),
+        Type::Named { name, arguments } => Type::Named {
+            name: name.clone(),
+            arguments: arguments
+                .iter()
+                .map(|arg| replace_self(arg, with))
+                .collect(),
+        },
+        Type::Function { arguments, result } => Type::Function {
+            arguments: arguments
+                .iter()
+                .map(|arg| replace_self(arg, with))
+                .collect(),
+            result: Box::new(replace_self(result, with)),
+        },
+        Type::Union { variants, rest } => Type::Union {
+            variants: variants
+                .iter()
+                .map(|variant| Variant {
+                    name: variant.name.clone(),
+                    fields: variant
+                        .fields
+                        .iter()
+                        .map(|field| replace_self(field, with))
+                        .collect(),
+                })
+                .collect(),
+            rest: rest.clone(),
+        },
+        Type::Generic { .. } | Type::Unification { .. } | Type::Existential { .. } => typ.clone(),
+    }
+}
+
+/// checks that an impl provides exactly the functions of its protocol, with `Self` replaced by the implementing type
+fn implementation(env: &Env, to_check: &parsed::Impl) -> Result<Impl> {
+    let protocol = env
+        .lookup_protocol(&to_check.protocol)
+        .map_err(|error| error.at(to_check.span))?;
+    let implementor = Type::Named {
+        name: to_check.typ.clone(),
+        arguments: Vec::new(),
+    };
+    for name in protocol.functions.keys() {
+        if !to_check.functions.iter().any(|func| &func.name == name) {
+            return Err(Error::MissingImplFunction {
+                protocol: to_check.protocol.clone(),
+                function: name.clone(),
+            }
+            .at(to_check.span));
+        }
+    }
+    let functions = to_check
+        .functions
+        .iter()
+        .map(|func| {
+            let scheme = protocol
+                .functions
+                .get(&func.name)
+                .ok_or_else(|| Error::NotInProtocol {
+                    protocol: to_check.protocol.clone(),
+                    function: func.name.clone(),
+                })
+                .map_err(|error| error.at(func.span))?;
+            let spec = HashMap::unit(String::from("Self"), implementor.clone());
+            let expected = Type::Function {
+                arguments: scheme
+                    .arguments
+                    .iter()
+                    .map(|arg| apply(arg, &spec))
+                    .collect::<Result<_>>()?,
+                result: Box::new(apply(&scheme.result, &spec)?),
+            };
+            let got = Type::Function {
+                arguments: func.arguments.iter().map(|arg| arg.typ.clone()).collect(),
+                result: Box::new(func.result.clone()),
+            };
+            if !func.generics.is_empty() || expected != got {
+                return Err(Error::TypeMismatch { expected, got }.at(func.span));
+            }
+            function(env.clone(), func)
+        })
+        .collect::<Result<_>>()?;
+    Ok(Impl {
+        protocol: to_check.protocol.clone(),
+        typ: to_check.typ.clone(),
+        functions,
+        span: to_check.span,
+    })
+}
+
+fn enumeration(to_infer: &parsed::Enum) -> Enum {
+    Enum {
+        name: to_infer.name.clone(),
+        variants: to_infer.variants.clone(),
+        tag: Untagged,
+        span: to_infer.span,
+    }
 }
 
 fn strukt(to_infer: &parsed::Struct) -> Struct {
     Struct {
         name: to_infer.name.clone(),
+        generics: to_infer.generics.clone(),
         fields: to_infer.fields.clone(),
-        tag: (),
+        tag: Untagged,
+        span: to_infer.span,
     }
 }
 
@@ -80,89 +449,102 @@ pub fn function(mut env: Env, function: &parsed::Function) -> Result<Function> {
     env.define_generics(function.generics.iter());
     env.define_arguments(function.arguments.iter());
     let body = check::expr(&env, &function.body, &function.result)?;
+    env.discharge_obligations()?;
     Ok(Function {
         name: function.name.clone(),
         generics: function.generics.clone(),
         arguments: function.arguments.clone(),
         result: function.result.clone(),
         body,
+        span: function.span,
     })
 }
 
 pub fn expr(env: &Env, to_infer: &parsed::Expr) -> Result<Expr> {
+    expr_inner(env, to_infer).map_err(|error| error.at(to_infer.span()))
+}
+
+fn expr_inner(env: &Env, to_infer: &parsed::Expr) -> Result<Expr> {
     match to_infer {
-        parsed::Expr::Variable { name, .. } => {
+        parsed::Expr::Variable { name, span, .. } => {
             let typ = env.lookup_variable(name)?;
             Ok(Expr::Variable {
                 name: name.clone(),
                 typ,
+                span: *span,
             })
         }
-        parsed::Expr::Literal { literal } => Ok(Expr::Literal {
+        parsed::Expr::Literal { literal, span, .. } => Ok(Expr::Literal {
             literal: literal.clone(),
+            typ: literal_type(env, literal, *span),
+            span: *span,
         }),
         parsed::Expr::CallDirect {
             function,
             arguments,
+            span,
+            ..
+        } => call_direct(env, function, parsed_arguments(arguments), *span),
+        parsed::Expr::CallIndirect {
+            function,
+            arguments,
+            span,
             ..
         } => {
-            let scheme = env.lookup_function(function)?;
-
-            let spec = make_specialization(&scheme.generics);
-
-            let arg_types = scheme
-                .arguments
-                .iter()
-                .map(|arg| apply(arg, &spec))
-                .collect::<Result<Vec<_>>>()?;
-
-            let typed_arguments = arguments
-                .into_iter()
-                .zip(arg_types)
-                .map(|(arg, typ)| check::expr(env, arg, &typ))
-                .collect::<Result<Vec<_>>>()?;
-
-            let result = apply(&scheme.result, &spec)?;
-            let generics = scheme
-                .generics
-                .into_iter()
-                .map(|generic| {
-                    spec.get(&generic.name)
-                        .ok_or(Error::UnspecifiedGeneric { generic })
-                        .cloned()
-                })
-                .collect::<Result<_>>()?;
-
-            Ok(Expr::CallDirect {
-                function: function.clone(),
-                arguments: typed_arguments,
-                tag: Call { result, generics },
-            })
+            if let parsed::Expr::FieldAccess { value, field, .. } = function.as_ref() {
+                return method_call(env, value, field, arguments, *span);
+            }
+            let typed_function = expr(env, function)?;
+            call_indirect(env, typed_function, parsed_arguments(arguments), *span)
         }
         parsed::Expr::Block(b) => {
-            let typed_block = block(env, b)?;
+            let typed_block = block(env, b, None)?;
             Ok(Expr::Block(typed_block))
         }
         parsed::Expr::Primitive {
             primitive,
             arguments,
+            span,
+        } => self::primitive(env, *primitive, parsed_arguments(arguments), *span),
+        parsed::Expr::StructPack {
+            name, fields, span, ..
         } => {
-            let typed_arguments = arguments
-                .into_iter()
-                .map(|arg| expr(env, arg))
-                .collect::<Result<Vec<_>>>()?;
-            Ok(Expr::Primitive {
-                primitive: *primitive,
-                arguments: typed_arguments,
-            })
-        }
-        parsed::Expr::StructPack { name, fields, .. } => {
             let scheme = env.lookup_struct(&name)?;
-            let spec = make_specialization(&[]);
+            // every field has to be given exactly once
+            let mut given = HashSet::new();
+            for field in fields {
+                if given.insert(field.name.clone()).is_some() {
+                    return Err(Error::DuplicateField {
+                        name: name.clone(),
+                        field: field.name.clone(),
+                    });
+                }
+            }
+            let mut missing: Vec<_> = scheme
+                .fields
+                .keys()
+                .filter(|field| !given.contains(*field))
+                .collect();
+            missing.sort();
+            if let Some(field) = missing.first() {
+                return Err(Error::MissingField {
+                    name: name.clone(),
+                    field: (*field).clone(),
+                });
+            }
+            let spec = make_specialization(&scheme.generics);
             let fields = fields
                 .iter()
                 .map(|field| {
-                    let want = apply(scheme.fields.get(&field.name).unwrap(), &spec)?;
+                    let declared =
+                        scheme
+                            .fields
+                            .get(&field.name)
+                            .ok_or_else(|| Error::UnknownField {
+                                name: name.clone(),
+                                field: field.name.clone(),
+                            })?;
+                    let want = apply(declared, &spec)?;
                     let typed = check::expr(env, &field.value, &want)?;
                     Ok(PackField {
                         name: field.name.clone(),
@@ -171,13 +553,20 @@ pub fn expr(env: &Env, to_infer: &parsed::Expr) -> Result<Expr> {
                 })
                 .collect::<Result<Vec<_>>>()?;
             let result = apply(&scheme.result, &spec)?;
+            let generics = scheme
+                .generics
+                .into_iter()
+                .map(|generic| {
+                    spec.get(&generic.name)
+                        .ok_or(Error::UnspecifiedGeneric { generic })
+                        .cloned()
+                })
+                .collect::<Result<_>>()?;
             Ok(Expr::StructPack {
                 name: name.clone(),
                 fields,
-                tag: StructPack {
-                    result,
-                    generics: Vec::new(),
-                },
+                tag: StructPack { result, generics },
+                span: *span,
             })
         }
         parsed::Expr::If {
@@ -185,31 +574,24 @@ pub fn expr(env: &Env, to_infer: &parsed::Expr) -> Result<Expr> {
             true_branch,
             false_branch,
             tag,
+            span,
         } => {
-            let typed_predicate = expr(env, &predicate)?;
+            let typed_predicate = check::expr(env, &predicate, &Type::bool())?;
             let typed_true = expr(env, &true_branch)?;
-            let typed_false = expr(env, &false_branch)?;
-            if typed_predicate.get_type() != Type::bool() {
-                return Err(Error::TypeMismatch {
-                    expected: Type::bool(),
-                    got: typed_predicate.get_type(),
-                });
-            }
-            if typed_true.get_type() != typed_false.get_type() {
-                return Err(Error::TypeMismatch {
-                    expected: typed_true.get_type(),
-                    got: typed_false.get_type(),
-                });
-            }
+            let typed_false = check::expr(env, &false_branch, &typed_true.get_type())?;
             Ok(Expr::If {
                 predicate: Box::new(typed_predicate),
                 true_branch: Box::new(typed_true),
                 false_branch: Box::new(typed_false),
                 tag: *tag,
+                span: *span,
             })
         }
         parsed::Expr::Closure {
-            arguments, body, ..
+            arguments,
+            body,
+            span,
+            ..
         } => {
             let mut inner_env = env.clone();
             let typed_arguments: Vec<_> = arguments
@@ -243,9 +625,683 @@ pub fn expr(env: &Env, to_infer: &parsed::Expr) -> Result<Expr> {
                 arguments: typed_arguments,
                 body: Box::new(typed_body),
                 tag,
+                span: *span,
+            })
+        }
+        parsed::Expr::VariantPack {
+            name,
+            arguments,
+            span,
+            ..
+        } => {
+            let Ok(scheme) = env.lookup_variant(name) else {
+                // no enum declares the variant, so it is structurally typed and belongs to any union that has it
+                let typed_arguments = arguments
+                    .iter()
+                    .map(|arg| expr(env, arg))
+                    .collect::<Result<Vec<_>>>()?;
+                let variant = Variant {
+                    name: name.clone(),
+                    fields: typed_arguments.iter().map(Expr::get_type).collect(),
+                };
+                return Ok(Expr::VariantPack {
+                    name: name.clone(),
+                    arguments: typed_arguments,
+                    tag: StructPack {
+                        result: Type::union(vec![variant], Some(env.open_row())),
+                        generics: Vec::new(),
+                    },
+                    span: *span,
+                });
+            };
+            if scheme.fields.len() != arguments.len() {
+                return Err(Error::WrongArity {
+                    expected: scheme.fields.len(),
+                    got: arguments.len(),
+                });
+            }
+            let spec = make_specialization(&scheme.generics);
+            let typed_arguments = arguments
+                .iter()
+                .zip(&scheme.fields)
+                .map(|(arg, typ)| check::expr(env, arg, &apply(typ, &spec)?))
+                .collect::<Result<Vec<_>>>()?;
+            let generics = scheme
+                .generics
+                .iter()
+                .map(|generic| spec[&generic.name].clone())
+                .collect();
+            Ok(Expr::VariantPack {
+                name: name.clone(),
+                arguments: typed_arguments,
+                tag: StructPack {
+                    result: apply(&scheme.result, &spec)?,
+                    generics,
+                },
+                span: *span,
             })
         }
+        parsed::Expr::Case {
+            scrutinee,
+            arms,
+            tag,
+            span,
+        } => case(env, scrutinee, arms, None, *tag, *span),
+        parsed::Expr::FieldAccess {
+            value, field, span, ..
+        } => field_access(env, unwrap(env, expr(env, value)?)?, field, *span),
+        parsed::Expr::Unwrap { value, .. } => unwrap(env, expr(env, value)?),
+        parsed::Expr::ExistentialPack {
+            value, protocol, ..
+        } => check::expr(
+            env,
+            value,
+            &Type::Existential {
+                protocol: protocol.clone(),
+            },
+        ),
+    }
+}
+
+/// integer literals can be used as either kind of number, which is decided by how they are used
+fn literal_type(env: &Env, literal: &Literal, span: Span) -> Type {
+    if let Literal::Integer(_) = literal {
+        let typ = Type::unification(String::from("number"));
+        env.require_numeric(&typ, span);
+        typ
+    } else {
+        literal.get_type()
+    }
+}
+
+fn primitive(
+    env: &Env,
+    primitive: Primitive,
+    arguments: Vec<Argument>,
+    span: Span,
+) -> Result<Expr> {
+    let typed_arguments = match primitive {
+        Primitive::Add
+        | Primitive::Sub
+        | Primitive::Mul
+        | Primitive::Div
+        | Primitive::Rem
+        | Primitive::Neg
+        | Primitive::Less
+        | Primitive::LessEq
+        | Primitive::Greater
+        | Primitive::GreaterEq => {
+            // the operands have to be the same kind of number, so the first one decides for the rest
+            let typed_arguments = same_type(env, arguments)?;
+            env.require_numeric(&typed_arguments[0].get_type(), span);
+            typed_arguments
+        }
+        Primitive::Eq | Primitive::NotEq => {
+            let typed_arguments = same_type(env, arguments)?;
+            env.require_equatable(&typed_arguments[0].get_type(), span);
+            typed_arguments
+        }
+        Primitive::And | Primitive::Or | Primitive::Not => arguments
+            .into_iter()
+            .map(|arg| arg.check(env, &Type::bool()))
+            .collect::<Result<_>>()?,
+        Primitive::ToFloat => arguments
+            .into_iter()
+            .map(|arg| arg.check(env, &Type::integer()))
+            .collect::<Result<_>>()?,
+        Primitive::ToInteger => arguments
+            .into_iter()
+            .map(|arg| arg.check(env, &Type::float()))
+            .collect::<Result<_>>()?,
+    };
+    Ok(Expr::Primitive {
+        primitive,
+        arguments: typed_arguments,
+        span,
+    })
+}
+
+/// infers the type of the first argument, then checks the rest against it
+fn same_type(env: &Env, arguments: Vec<Argument>) -> Result<Vec<Expr>> {
+    let mut arguments = arguments.into_iter();
+    let first = unwrap(env, arguments.next().unwrap().infer(env)?)?;
+    let typ = first.get_type();
+    let mut typed_arguments = vec![first];
+    for arg in arguments {
+        typed_arguments.push(arg.check(env, &typ)?);
+    }
+    Ok(typed_arguments)
+}
+
+/// uses the value of an error union without checking for an error, if the mode allows it.
+/// anything else is already a value, so it's left alone
+pub fn unwrap(env: &Env, typed: Expr) -> Result<Expr> {
+    let typ = typed.get_type();
+    let Some((value, _)) = typ.error_union() else {
+        return Ok(typed);
+    };
+    let span = typed.span();
+    env.unwrap_error(&typ, span)?;
+    Ok(Expr::Unwrap {
+        tag: StructPack {
+            result: value.clone(),
+            generics: Vec::new(),
+        },
+        value: Box::new(typed),
+        span,
+    })
+}
+
+/// checks every arm of a case expression, then makes sure that together they cover every value of the scrutinee exactly once
+pub fn case(
+    env: &Env,
+    scrutinee: &parsed::Expr,
+    arms: &[parsed::Arm],
+    want: Option<&Type>,
+    tag: parsed::If,
+    span: Span,
+) -> Result<Expr> {
+    let typed_scrutinee = expr(env, scrutinee)?;
+    let scrutinee_type = typed_scrutinee.get_type();
+    let mut result = want.cloned();
+    let typed_arms = arms
+        .iter()
+        .map(|arm| {
+            let mut inner_env = env.clone();
+            let typed_pattern = pattern(&mut inner_env, &arm.pattern, &scrutinee_type)?;
+            let typed_body = if let Some(want) = &result {
+                check::expr(&inner_env, &arm.body, want)?
+            } else {
+                let typed_body = expr(&inner_env, &arm.body)?;
+                result = Some(typed_body.get_type());
+                typed_body
+            };
+            Ok(Arm {
+                pattern: typed_pattern,
+                body: typed_body,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // without a catch-all arm, a union that could still gain variants has exactly the ones that the arms handle
+    let catch_all = typed_arms.iter().any(|arm| {
+        matches!(
+            arm.pattern,
+            Pattern::Wildcard { .. } | Pattern::Binding { .. }
+        )
+    });
+    if !catch_all && scrutinee_type.union_variants().is_some() {
+        close(&scrutinee_type);
     }
+    exhaustive::check(env, &typed_arms)?;
+    Ok(Expr::Case {
+        scrutinee: Box::new(typed_scrutinee),
+        arms: typed_arms,
+        tag,
+        span,
+    })
+}
+
+/// checks that `to_infer` can match values of type `want`, defining the variables it binds in `env`
+fn pattern(env: &mut Env, to_infer: &parsed::Pattern, want: &Type) -> Result<Pattern> {
+    pattern_inner(env, to_infer, want).map_err(|error| error.at(to_infer.span()))
+}
+
+fn pattern_inner(env: &mut Env, to_infer: &parsed::Pattern, want: &Type) -> Result<Pattern> {
+    match to_infer {
+        parsed::Pattern::Wildcard { span, .. } => Ok(Pattern::Wildcard {
+            tag: want.clone(),
+            span: *span,
+        }),
+        parsed::Pattern::Binding { name, span, .. } => {
+            env.define_variable(name.clone(), want.clone());
+            Ok(Pattern::Binding {
+                name: name.clone(),
+                tag: want.clone(),
+                span: *span,
+            })
+        }
+        parsed::Pattern::Literal { literal, span, .. } => {
+            unify(want, &literal_type(env, literal, *span))?;
+            Ok(Pattern::Literal {
+                literal: literal.clone(),
+                tag: want.clone(),
+                span: *span,
+            })
+        }
+        parsed::Pattern::Variant {
+            name, fields, span, ..
+        } => {
+            let Ok(scheme) = env.lookup_variant(name) else {
+                let variant = Variant {
+                    name: name.clone(),
+                    fields: (0..fields.len())
+                        .map(|i| Type::unification(String::from(format!("field {i} of {name}"))))
+                        .collect(),
+                };
+                let result = Type::union(vec![variant.clone()], Some(env.open_row()));
+                unify(want, &result)?;
+                let typed_fields = fields
+                    .iter()
+                    .zip(&variant.fields)
+                    .map(|(field, typ)| pattern(env, field, typ))
+                    .collect::<Result<Vec<_>>>()?;
+                return Ok(Pattern::Variant {
+                    name: name.clone(),
+                    fields: typed_fields,
+                    tag: result,
+                    span: *span,
+                });
+            };
+            let spec = make_specialization(&scheme.generics);
+            let result = apply(&scheme.result, &spec)?;
+            unify(want, &result)?;
+            if scheme.fields.len() != fields.len() {
+                return Err(Error::WrongArity {
+                    expected: scheme.fields.len(),
+                    got: fields.len(),
+                });
+            }
+            let typed_fields = fields
+                .iter()
+                .zip(&scheme.fields)
+                .map(|(field, typ)| pattern(env, field, &apply(typ, &spec)?))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Pattern::Variant {
+                name: name.clone(),
+                fields: typed_fields,
+                tag: result,
+                span: *span,
+            })
+        }
+    }
+}
+
+/// the type of `field` in a value of type `typ`, which has to be a struct
+fn field_access(env: &Env, typed_value: Expr, field: &String, span: Span) -> Result<Expr> {
+    let value_type = typed_value.get_type();
+    let Type::Named { name, arguments } = value_type.canonical() else {
+        return Err(Error::NotAStruct(value_type));
+    };
+    let scheme = env
+        .lookup_struct(name)
+        .map_err(|_| Error::NotAStruct(value_type.clone()))?;
+    // the declared field type is in terms of the struct's generics, so substitute the value's type arguments
+    let spec = scheme
+        .generics
+        .iter()
+        .map(|generic| generic.name.clone())
+        .zip(arguments.iter().cloned())
+        .collect();
+    let declared = scheme
+        .fields
+        .get(field)
+        .ok_or_else(|| Error::UnknownField {
+            name: name.clone(),
+            field: field.clone(),
+        })?;
+    let result = apply(declared, &spec)?;
+    Ok(Expr::FieldAccess {
+        value: Box::new(typed_value),
+        field: field.clone(),
+        tag: StructPack {
+            result,
+            generics: Vec::new(),
+        },
+        span,
+    })
+}
+
+/// whether values of type `typ`, or the values that it holds if it's an error union, are structs with a field called `field`
+fn has_field(env: &Env, typ: &Type, field: &String) -> bool {
+    let typ = typ.error_union().map_or(typ, |(value, _)| value);
+    let Type::Named { name, .. } = typ.canonical() else {
+        return false;
+    };
+    env.lookup_struct(name)
+        .is_ok_and(|scheme| scheme.fields.contains_key(field))
+}
+
+/// `value.name(arguments)` calls the field `name` of `value` if it's a struct with such a field,
+/// and is otherwise sugar for `name(value, arguments)`. only the type of `value` decides, so a field always wins over a function of the same name
+fn method_call(
+    env: &Env,
+    value: &parsed::Expr,
+    name: &String,
+    arguments: &[parsed::Expr],
+    span: Span,
+) -> Result<Expr> {
+    let receiver = expr(env, value)?;
+    if has_field(env, &receiver.get_type(), name) {
+        let function = field_access(env, unwrap(env, receiver)?, name, span)?;
+        return call_indirect(env, function, parsed_arguments(arguments), span);
+    }
+    let desugared = iter::once(Argument::Typed(receiver))
+        .chain(arguments.iter().map(Argument::Parsed))
+        .collect();
+    call_direct(env, name, desugared, span).map_err(|error| {
+        let call = iter::once(value)
+            .chain(arguments)
+            .map(sketch)
+            .collect::<Vec<_>>()
+            .join(", ");
+        error.desugared(format!("{name}({call})"), span)
+    })
+}
+
+/// `expr` as it would be written, for error messages that show what a method call was desugared to
+fn sketch(expr: &parsed::Expr) -> std::string::String {
+    let list = |exprs: &[parsed::Expr]| exprs.iter().map(sketch).collect::<Vec<_>>().join(", ");
+    match expr {
+        parsed::Expr::Variable { name, .. } => name.to_string(),
+        parsed::Expr::Literal { literal, .. } => sketch_literal(literal),
+        parsed::Expr::CallDirect {
+            function,
+            arguments,
+            ..
+        } => format!("{function}({})", list(arguments)),
+        parsed::Expr::CallIndirect {
+            function,
+            arguments,
+            ..
+        } => format!("{}({})", sketch(function), list(arguments)),
+        parsed::Expr::Primitive {
+            primitive,
+            arguments,
+            ..
+        } => match (primitive, arguments.as_slice()) {
+            (Primitive::Neg | Primitive::Not, [operand]) => {
+                format!("{primitive}{}", sketch_operand(operand))
+            }
+            (Primitive::ToFloat | Primitive::ToInteger, _) => {
+                format!("{primitive}({})", list(arguments))
+            }
+            _ => arguments
+                .iter()
+                .map(sketch_operand)
+                .collect::<Vec<_>>()
+                .join(&format!(" {primitive} ")),
+        },
+        parsed::Expr::Block(block) => {
+            let stmts = block.stmts.iter().map(|stmt| match stmt {
+                parsed::Statement::Let { name, value, .. } => {
+                    format!("let {name} = {};", sketch(value))
+                }
+                parsed::Statement::Function(function) => {
+                    format!("func {} = {};", function.name, sketch(&function.body))
+                }
+            });
+            let result = iter::once(sketch(&block.result));
+            format!(
+                "{{ {} }}",
+                stmts.chain(result).collect::<Vec<_>>().join(" ")
+            )
+        }
+        parsed::Expr::StructPack { name, fields, .. } => {
+            let fields = fields
+                .iter()
+                .map(|field| format!("{}: {}", field.name, sketch(&field.value)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("{name} {{ {fields} }}")
+        }
+        parsed::Expr::If {
+            predicate,
+            true_branch,
+            false_branch,
+            ..
+        } => format!(
+            "if {} then {} else {}",
+            sketch(predicate),
+            sketch(true_branch),
+            sketch(false_branch)
+        ),
+        parsed::Expr::Closure {
+            arguments, body, ..
+        } if matches!(
+            arguments.as_slice(),
+            [parsed::ClosureArgument { typ: None, .. }]
+        ) =>
+        {
+            format!("{} => {}", arguments[0].name, sketch(body))
+        }
+        parsed::Expr::Closure {
+            arguments, body, ..
+        } => {
+            let arguments = arguments
+                .iter()
+                .map(|argument| {
+                    argument.typ.as_ref().map_or_else(
+                        || argument.name.to_string(),
+                        |typ| format!("{}: {typ}", argument.name),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("({arguments}) => {}", sketch(body))
+        }
+        parsed::Expr::VariantPack {
+            name, arguments, ..
+        } if arguments.is_empty() => name.to_string(),
+        parsed::Expr::VariantPack {
+            name, arguments, ..
+        } => format!("{name}({})", list(arguments)),
+        parsed::Expr::Case {
+            scrutinee, arms, ..
+        } => {
+            let arms = arms
+                .iter()
+                .map(|arm| {
+                    format!(
+                        " | {} => {}",
+                        sketch_pattern(&arm.pattern),
+                        sketch(&arm.body)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .concat();
+            format!("case {} of{arms}", sketch(scrutinee))
+        }
+        parsed::Expr::FieldAccess { value, field, .. } => {
+            format!("{}.{field}", sketch_operand(value))
+        }
+        parsed::Expr::Unwrap { value, .. } | parsed::Expr::ExistentialPack { value, .. } => {
+            sketch(value)
+        }
+    }
+}
+
+/// `expr` in parentheses if it has to be, to be an operand or to have a field accessed
+fn sketch_operand(expr: &parsed::Expr) -> std::string::String {
+    match expr {
+        parsed::Expr::Primitive { .. }
+        | parsed::Expr::If { .. }
+        | parsed::Expr::Closure { .. }
+        | parsed::Expr::Case { .. } => format!("({})", sketch(expr)),
+        _ => sketch(expr),
+    }
+}
+
+fn sketch_pattern(pattern: &parsed::Pattern) -> std::string::String {
+    match pattern {
+        parsed::Pattern::Wildcard { .. } => std::string::String::from("_"),
+        parsed::Pattern::Binding { name, .. } => name.to_string(),
+        parsed::Pattern::Literal { literal, .. } => sketch_literal(literal),
+        parsed::Pattern::Variant { name, fields, .. } if fields.is_empty() => name.to_string(),
+        parsed::Pattern::Variant { name, fields, .. } => {
+            let fields = fields.iter().map(sketch_pattern).collect::<Vec<_>>();
+            format!("{name}({})", fields.join(", "))
+        }
+    }
+}
+
+fn sketch_literal(literal: &Literal) -> std::string::String {
+    match literal {
+        Literal::Boolean(true) => std::string::String::from("True"),
+        Literal::Boolean(false) => std::string::String::from("False"),
+        literal => literal.to_string(),
+    }
+}
+
+/// an argument of a call. the receiver of a method call is the only one that's already typed,
+/// since its type decides which function is called
+enum Argument<'a> {
+    Parsed(&'a parsed::Expr),
+    Typed(Expr),
+}
+
+impl Argument<'_> {
+    fn infer(self, env: &Env) -> Result<Expr> {
+        match self {
+            Argument::Parsed(parsed) => expr(env, parsed),
+            Argument::Typed(typed) => Ok(typed),
+        }
+    }
+
+    fn check(self, env: &Env, want: &Type) -> Result<Expr> {
+        match self {
+            Argument::Parsed(parsed) => check::expr(env, parsed, want),
+            Argument::Typed(typed) => check::coerce(env, typed, want),
+        }
+    }
+}
+
+fn parsed_arguments(arguments: &[parsed::Expr]) -> Vec<Argument<'_>> {
+    arguments.iter().map(Argument::Parsed).collect()
+}
+
+fn call_direct(env: &Env, function: &String, arguments: Vec<Argument>, span: Span) -> Result<Expr> {
+    if let Ok(typ) = env.lookup_variable(function) {
+        // local variables shadow top level functions, so this is a call through a closure
+        let function = Expr::Variable {
+            name: function.clone(),
+            typ,
+            span,
+        };
+        return call_indirect(env, function, arguments, span);
+    }
+    let scheme = match (env.lookup_function(function), Primitive::builtin(function)) {
+        (Ok(scheme), _) => scheme,
+        // builtins only apply when no function of the same name has been defined
+        (Err(_), Some(primitive)) => {
+            let expected = primitive.arity().unwrap();
+            if expected != arguments.len() {
+                return Err(Error::WrongArity {
+                    expected,
+                    got: arguments.len(),
+                });
+            }
+            return self::primitive(env, primitive, arguments, span);
+        }
+        (Err(error), None) => return Err(error),
+    };
+    let local = env.lookup_local(function);
+    let captures = local.as_ref().map_or(0, |local| local.captures.len());
+    if scheme.arguments.len() != arguments.len() + captures {
+        return Err(Error::WrongArity {
+            expected: scheme.arguments.len() - captures,
+            got: arguments.len(),
+        });
+    }
+
+    let spec = make_specialization(&scheme.generics);
+    let mut arguments = arguments;
+    let function = match local {
+        Some(local) => {
+            // the enclosing function's generics are the same ones wherever the local function can be called
+            for generic in &local.generics {
+                unify(
+                    &spec[&generic.name],
+                    &Type::Generic {
+                        name: generic.name.clone(),
+                    },
+                )?;
+            }
+            for name in local.captures {
+                let typ = env.lookup_variable(&name)?;
+                arguments.push(Argument::Typed(Expr::Variable { name, typ, span }));
+            }
+            local.lifted
+        }
+        None => function.clone(),
+    };
+
+    let arg_types = scheme
+        .arguments
+        .iter()
+        .map(|arg| apply(arg, &spec))
+        .collect::<Result<Vec<_>>>()?;
+
+    let typed_arguments = arguments
+        .into_iter()
+        .zip(arg_types)
+        .map(|(arg, typ)| arg.check(env, &typ))
+        .collect::<Result<Vec<_>>>()?;
+
+    let result = apply(&scheme.result, &spec)?;
+    let generics = scheme
+        .generics
+        .into_iter()
+        .map(|generic| {
+            let typ = spec
+                .get(&generic.name)
+                .ok_or(Error::UnspecifiedGeneric {
+                    generic: generic.clone(),
+                })?
+                .clone();
+            env.require_bounds(&generic, &typ, span);
+            Ok(typ)
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Expr::CallDirect {
+        function: function.clone(),
+        arguments: typed_arguments,
+        tag: Call { result, generics },
+        span,
+    })
+}
+
+fn call_indirect(env: &Env, function: Expr, arguments: Vec<Argument>, span: Span) -> Result<Expr> {
+    let function_type = function.get_type();
+    let (arg_types, result) = match function_type.canonical() {
+        Type::Function { arguments, result } => (arguments.clone(), result.as_ref().clone()),
+        Type::Unification { name, .. } => {
+            // the closure's type isn't known yet, so assume it takes exactly these arguments
+            let arg_types: Vec<_> = (0..arguments.len())
+                .map(|i| Type::unification(String::from(format!("argument {i} of {name}"))))
+                .collect();
+            let result = Type::unification(String::from(format!("result of {name}")));
+            unify(
+                &function_type,
+                &Type::Function {
+                    arguments: arg_types.clone(),
+                    result: Box::new(result.clone()),
+                },
+            )?;
+            (arg_types, result)
+        }
+        other => return Err(Error::NotAFunction(other.clone())),
+    };
+    if arg_types.len() != arguments.len() {
+        return Err(Error::WrongArity {
+            expected: arg_types.len(),
+            got: arguments.len(),
+        });
+    }
+    let typed_arguments = arguments
+        .into_iter()
+        .zip(&arg_types)
+        .map(|(arg, typ)| arg.check(env, typ))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Expr::CallIndirect {
+        function: Box::new(function),
+        arguments: typed_arguments,
+        tag: Call {
+            result,
+            generics: Vec::new(),
+        },
+        span,
+    })
 }
 
 pub fn closure_captures(
@@ -260,10 +1316,24 @@ pub fn closure_captures(
             .map(|arg| arg.name.clone())
             .collect(),
     );
+    Ok(captures(env, vars))
+}
+
+/// the variables that code with the free names `vars` uses from its surroundings.
+/// calling a local function uses the copies of what it captures
+fn captures(env: &Env, vars: HashSet<String>) -> Vec<ClosureArgument> {
+    let vars: HashSet<_> = vars
+        .into_iter()
+        .flat_map(|name| match env.lookup_local(&name) {
+            Some(local) => local.captures,
+            None => vec![name],
+        })
+        .collect();
+    // free names that aren't variables are either top level functions or unknown variables, which are reported when the body is checked
     vars.into_iter()
-        .map(|name| {
-            let typ = env.lookup_variable(&name)?;
-            Ok(ClosureArgument { name, typ })
+        .filter_map(|name| {
+            let typ = env.lookup_variable(&name).ok()?;
+            Some(ClosureArgument { name, typ })
         })
         .collect()
 }
@@ -272,8 +1342,30 @@ fn free_variables(expr: &parsed::Expr) -> HashSet<String> {
     match expr {
         parsed::Expr::Variable { name, .. } => HashSet::unit(name.clone()),
         parsed::Expr::Literal { .. } => HashSet::new(),
-        parsed::Expr::CallDirect { arguments, .. } => {
-            arguments.iter().flat_map(free_variables).collect()
+        parsed::Expr::CallDirect {
+            function,
+            arguments,
+            ..
+        } => arguments
+            .iter()
+            .flat_map(free_variables)
+            .chain(iter::once(function.clone()))
+            .collect(),
+        parsed::Expr::CallIndirect {
+            function,
+            arguments,
+            ..
+        } => {
+            let free: HashSet<_> = arguments
+                .iter()
+                .flat_map(free_variables)
+                .chain(free_variables(function))
+                .collect();
+            // a method call might be sugar for a call through a closure in a local variable of the method's name
+            match function.as_ref() {
+                parsed::Expr::FieldAccess { field, .. } => free.update(field.clone()),
+                _ => free,
+            }
         }
         parsed::Expr::Primitive { arguments, .. } => {
             arguments.iter().flat_map(free_variables).collect()
@@ -297,6 +1389,22 @@ fn free_variables(expr: &parsed::Expr) -> HashSet<String> {
             let without = arguments.iter().map(|arg| arg.name.clone()).collect();
             free_variables(&body).relative_complement(without)
         }
+        parsed::Expr::VariantPack { arguments, .. } => {
+            arguments.iter().flat_map(free_variables).collect()
+        }
+        parsed::Expr::Case {
+            scrutinee, arms, ..
+        } => arms
+            .iter()
+            .flat_map(|arm| {
+                let without = arm.pattern.bindings().into_iter().collect();
+                free_variables(&arm.body).relative_complement(without)
+            })
+            .chain(free_variables(scrutinee))
+            .collect(),
+        parsed::Expr::FieldAccess { value, .. }
+        | parsed::Expr::Unwrap { value, .. }
+        | parsed::Expr::ExistentialPack { value, .. } => free_variables(value),
     }
 }
 
@@ -309,31 +1417,259 @@ fn free_variables_block(block: &parsed::Block) -> HashSet<String> {
                 free.extend(free_variables(value).relative_complement(without.clone()));
                 without.insert(name.clone());
             }
+            parsed::Statement::Function(function) => {
+                let arguments = function.arguments.iter().map(|arg| arg.name.clone());
+                free.extend(
+                    free_variables(&function.body)
+                        .relative_complement(without.clone().union(arguments.collect())),
+                );
+            }
         }
     }
+    free.extend(free_variables(&block.result).relative_complement(without));
     free
 }
 
-fn block(env: &Env, block: &parsed::Block) -> Result<Block> {
+/// infers the type of a block, or checks its result against `want`
+pub fn block(env: &Env, block: &parsed::Block, want: Option<&Type>) -> Result<Block> {
     let mut env = env.clone();
-    let typed_stmts = block
-        .stmts
-        .iter()
-        .map(|stmt: &parsed::Statement| match stmt {
-            parsed::Statement::Let { name, value, .. } => {
+    let mut typed_stmts = Vec::new();
+    for stmt in &block.stmts {
+        match stmt {
+            parsed::Statement::Let {
+                name, value, span, ..
+            } => {
                 let typed_value = expr(&env, value)?;
                 env.define_variable(name.clone(), typed_value.get_type());
-                Ok(Statement::Let {
+                typed_stmts.push(Statement::Let {
                     name: name.clone(),
                     typ: typed_value.get_type(),
                     value: typed_value,
-                })
+                    span: *span,
+                });
             }
-        })
-        .collect::<Result<_>>()?;
-    let typed_result = expr(&env, &block.result)?;
+            parsed::Statement::Function(function) => {
+                typed_stmts.extend(local_function(&mut env, function)?);
+            }
+        }
+    }
+    let typed_result = match want {
+        Some(want) => check::expr(&env, &block.result, want)?,
+        None => expr(&env, &block.result)?,
+    };
     Ok(Block {
         stmts: typed_stmts,
         result: Box::new(typed_result),
+        span: block.span,
     })
 }
+
+/// lifts a function defined in a block to the top level, where it takes the enclosing function's generics
+/// and the variables it captures as extra arguments, so that it can be generic like any other function.
+/// what it captures is copied into variables that nothing can shadow, which are the statements this returns
+fn local_function(env: &mut Env, function: &parsed::Function) -> Result<Vec<Statement>> {
+    let enclosing = env.generics();
+    let lifted = env.fresh_local(&function.name);
+    // the lifted function takes the enclosing generics too, so its own generics that shadow them get new names
+    let renames: Spec = function
+        .generics
+        .iter()
+        .filter(|generic| enclosing.iter().any(|other| other.name == generic.name))
+        .map(|generic| {
+            let name = String::from(format!("{lifted}_{}", generic.name));
+            (generic.name.clone(), Type::Generic { name })
+        })
+        .collect();
+    let shadowing;
+    let function = if renames.is_empty() {
+        function
+    } else {
+        shadowing = rename_generics(function, &renames)?;
+        &shadowing
+    };
+    let arguments = function.arguments.iter().map(|arg| arg.name.clone());
+    let free = free_variables(&function.body)
+        .relative_complement(arguments.collect())
+        .without(&function.name);
+    let mut captured = captures(env, free);
+    captured.sort_by(|a, b| a.name.cmp(&b.name));
+    let hidden: Vec<_> = captured
+        .iter()
+        .map(|capture| String::from(format!("{lifted}_{}", capture.name)))
+        .collect();
+
+    let generics: Vec<_> = enclosing
+        .iter()
+        .chain(&function.generics)
+        .cloned()
+        .collect();
+    let scheme = FunctionScheme {
+        generics: generics.clone(),
+        arguments: function
+            .arguments
+            .iter()
+            .map(|arg| arg.typ.clone())
+            .chain(captured.iter().map(|capture| capture.typ.clone()))
+            .collect(),
+        result: function.result.clone(),
+    };
+    let copies = captured
+        .iter()
+        .zip(&hidden)
+        .map(|(capture, hidden)| copy(hidden, &capture.name, &capture.typ, function.span))
+        .collect();
+    for (capture, hidden) in captured.iter().zip(&hidden) {
+        env.define_variable(hidden.clone(), capture.typ.clone());
+    }
+    env.define_local(
+        function.name.clone(),
+        scheme,
+        Local {
+            lifted: lifted.clone(),
+            generics: enclosing,
+            captures: hidden.clone(),
+        },
+    );
+
+    // the body sees the captures under their own names, as copies of the arguments that hold them
+    let mut inner = env.clone();
+    inner.define_generics(function.generics.iter());
+    inner.define_arguments(function.arguments.iter());
+    let typed_body = check::expr(&inner, &function.body, &function.result)?;
+    let span = function.body.span();
+    let body = Expr::Block(Block {
+        stmts: captured
+            .iter()
+            .zip(&hidden)
+            .map(|(capture, hidden)| copy(&capture.name, hidden, &capture.typ, span))
+            .collect(),
+        result: Box::new(typed_body),
+        span,
+    });
+    env.lift(Function {
+        name: lifted,
+        generics,
+        arguments: function
+            .arguments
+            .iter()
+            .cloned()
+            .chain(
+                captured
+                    .iter()
+                    .zip(hidden)
+                    .map(|(capture, name)| parsed::Argument {
+                        name,
+                        typ: capture.typ.clone(),
+                    }),
+            )
+            .collect(),
+        result: function.result.clone(),
+        body,
+        span: function.span,
+    });
+    Ok(copies)
+}
+
+/// `function` with the generics in `renames` replaced, except where a local function shadows them
+fn rename_generics(function: &parsed::Function, renames: &Spec) -> Result<parsed::Function> {
+    let mut lifted = function.clone();
+    for generic in &mut lifted.generics {
+        if let Some(Type::Generic { name }) = renames.get(&generic.name) {
+            generic.name = name.clone();
+        }
+    }
+    for argument in &mut lifted.arguments {
+        argument.typ = apply(&argument.typ, renames)?;
+    }
+    lifted.result = apply(&lifted.result, renames)?;
+    rename_generics_expr(&mut lifted.body, renames)?;
+    Ok(lifted)
+}
+
+fn rename_generics_expr(expr: &mut parsed::Expr, renames: &Spec) -> Result<()> {
+    match expr {
+        parsed::Expr::Variable { .. } | parsed::Expr::Literal { .. } => {}
+        parsed::Expr::CallDirect { arguments, .. }
+        | parsed::Expr::Primitive { arguments, .. }
+        | parsed::Expr::VariantPack { arguments, .. } => {
+            for argument in arguments {
+                rename_generics_expr(argument, renames)?;
+            }
+        }
+        parsed::Expr::CallIndirect {
+            function,
+            arguments,
+            ..
+        } => {
+            rename_generics_expr(function, renames)?;
+            for argument in arguments {
+                rename_generics_expr(argument, renames)?;
+            }
+        }
+        parsed::Expr::Block(block) => {
+            for stmt in &mut block.stmts {
+                match stmt {
+                    parsed::Statement::Let { value, .. } => rename_generics_expr(value, renames)?,
+                    parsed::Statement::Function(function) => {
+                        let shadowed = function.generics.iter().map(|generic| &generic.name);
+                        let unshadowed =
+                            shadowed.fold(renames.clone(), |renames, name| renames.without(name));
+                        *function = rename_generics(function, &unshadowed)?;
+                    }
+                }
+            }
+            rename_generics_expr(&mut block.result, renames)?;
+        }
+        parsed::Expr::StructPack { fields, .. } => {
+            for field in fields {
+                rename_generics_expr(&mut field.value, renames)?;
+            }
+        }
+        parsed::Expr::If {
+            predicate,
+            true_branch,
+            false_branch,
+            ..
+        } => {
+            rename_generics_expr(predicate, renames)?;
+            rename_generics_expr(true_branch, renames)?;
+            rename_generics_expr(false_branch, renames)?;
+        }
+        parsed::Expr::Closure {
+            arguments, body, ..
+        } => {
+            for argument in arguments {
+                if let Some(typ) = &argument.typ {
+                    argument.typ = Some(apply(typ, renames)?);
+                }
+            }
+            rename_generics_expr(body, renames)?;
+        }
+        parsed::Expr::Case {
+            scrutinee, arms, ..
+        } => {
+            rename_generics_expr(scrutinee, renames)?;
+            for arm in arms {
+                rename_generics_expr(&mut arm.body, renames)?;
+            }
+        }
+        parsed::Expr::FieldAccess { value, .. }
+        | parsed::Expr::Unwrap { value, .. }
+        | parsed::Expr::ExistentialPack { value, .. } => rename_generics_expr(value, renames)?,
+    }
+    Ok(())
+}
+
+/// `let name = from`
+fn copy(name: &String, from: &String, typ: &Type, span: Span) -> Statement {
+    Statement::Let {
+        name: name.clone(),
+        typ: typ.clone(),
+        value: Expr::Variable {
+            name: from.clone(),
+            typ: typ.clone(),
+            span,
+        },
+        span,
+    }
+}
@@ -3,6 +3,7 @@
 
 pub mod check;
 pub mod env;
+pub mod exhaustive;
 pub mod infer;
 pub mod specialize;
 pub mod unify;
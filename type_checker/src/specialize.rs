@@ -47,5 +47,27 @@ pub fn apply(typ: &Type, spec: &Spec) -> Result<Type> {
             result: Box::new(apply(result, spec)?),
         }),
         Type::Unification { name, value } => apply(Type::unwrap(name, value), spec),
+        Type::Existential { .. } => Ok(typ.clone()),
+        Type::Union { variants, rest } => Ok(Type::Union {
+            variants: variants
+                .iter()
+                .map(|variant| {
+                    Ok(Variant {
+                        name: variant.name.clone(),
+                        fields: variant
+                            .fields
+                            .iter()
+                            .map(|field| apply(field, spec))
+                            .collect::<Result<_>>()?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            // a row that is still open has nothing to specialize yet
+            rest: match rest.as_deref().map(Type::canonical) {
+                Some(open @ Type::Unification { .. }) => Some(Box::new(open.clone())),
+                Some(more) => Some(Box::new(apply(more, spec)?)),
+                None => None,
+            },
+        }),
     }
 }
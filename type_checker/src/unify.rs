@@ -1,7 +1,17 @@
+use std::rc::Rc;
+
 use crate::env::{Error, Result};
-use tree::typed::Type;
+use tree::typed::{Type, Variant};
 
 pub fn unify(expected: &Type, found: &Type) -> Result<()> {
+    if let (Type::Unification { value, .. }, Type::Unification { value: other, .. }) =
+        (expected.canonical(), found.canonical())
+    {
+        // binding a variable to itself would make it its own value
+        if Rc::ptr_eq(value, other) {
+            return Ok(());
+        }
+    }
     match (expected, found) {
         (
             Type::Named {
@@ -56,6 +66,11 @@ pub fn unify(expected: &Type, found: &Type) -> Result<()> {
             }
             unify(&expected_res, &found_res)
         }
+        (Type::Existential { protocol: expected }, Type::Existential { protocol: found })
+            if expected == found =>
+        {
+            Ok(())
+        }
         (Type::Generic { name: expected }, Type::Generic { name: found }) => {
             if expected == found {
                 Ok(())
@@ -66,9 +81,92 @@ pub fn unify(expected: &Type, found: &Type) -> Result<()> {
                 })
             }
         }
+        (Type::Union { .. }, Type::Union { .. }) => unify_unions(expected, found),
         _ => Err(Error::TypeMismatch {
             expected: expected.clone(),
             got: found.clone(),
         }),
     }
 }
+
+/// unifies the variants that both unions have, then gives each open union the variants that only the other one has.
+/// a closed union can't gain variants, so the other union must not have any that it lacks
+fn unify_unions(expected: &Type, found: &Type) -> Result<()> {
+    let mismatch = || Error::TypeMismatch {
+        expected: expected.clone(),
+        got: found.clone(),
+    };
+    let (expected_variants, expected_rest) = expected.union_variants().ok_or_else(mismatch)?;
+    let (found_variants, found_rest) = found.union_variants().ok_or_else(mismatch)?;
+    for expected_variant in &expected_variants {
+        let Some(found_variant) = found_variants
+            .iter()
+            .find(|variant| variant.name == expected_variant.name)
+        else {
+            continue;
+        };
+        if expected_variant.fields.len() != found_variant.fields.len() {
+            return Err(mismatch());
+        }
+        for (expected, found) in expected_variant.fields.iter().zip(&found_variant.fields) {
+            unify(expected, found)?;
+        }
+    }
+    let only_in = |variants: &[Variant], other: &[Variant]| -> Vec<Variant> {
+        variants
+            .iter()
+            .filter(|variant| !other.iter().any(|candidate| candidate.name == variant.name))
+            .cloned()
+            .collect()
+    };
+    let only_expected = only_in(&expected_variants, &found_variants);
+    let only_found = only_in(&found_variants, &expected_variants);
+    match (expected_rest, found_rest) {
+        (Some(expected_rest), Some(found_rest)) => {
+            if let (Type::Unification { value, .. }, Type::Unification { value: other, .. }) =
+                (&expected_rest, &found_rest)
+            {
+                if Rc::ptr_eq(value, other) {
+                    return if only_expected.is_empty() && only_found.is_empty() {
+                        Ok(())
+                    } else {
+                        Err(mismatch())
+                    };
+                }
+            }
+            // both unions end up with every variant of either, and whatever else they gain later is shared
+            let shared = Type::unification(tree::String::from("variants"));
+            unify(&expected_rest, &extend(only_found, shared.clone()))?;
+            unify(&found_rest, &extend(only_expected, shared))
+        }
+        (Some(expected_rest), None) if only_expected.is_empty() => {
+            unify(&expected_rest, &Type::union(only_found, None))
+        }
+        (None, Some(found_rest)) if only_found.is_empty() => {
+            unify(&found_rest, &Type::union(only_expected, None))
+        }
+        (None, None) if only_expected.is_empty() && only_found.is_empty() => Ok(()),
+        _ => Err(mismatch()),
+    }
+}
+
+/// makes a union that could still gain variants have exactly the ones it has so far
+pub fn close(typ: &Type) {
+    let open = match typ.canonical() {
+        open @ Type::Unification { .. } => Some(open.clone()),
+        union @ Type::Union { .. } => union.union_variants().and_then(|(_, rest)| rest),
+        _ => None,
+    };
+    if let Some(Type::Unification { value, .. }) = open {
+        value.set(Type::union(Vec::new(), None)).unwrap();
+    }
+}
+
+/// the row that holds `variants` followed by whatever `rest` turns out to hold
+fn extend(variants: Vec<Variant>, rest: Type) -> Type {
+    if variants.is_empty() {
+        rest
+    } else {
+        Type::union(variants, Some(rest))
+    }
+}
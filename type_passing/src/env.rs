@@ -1,2 +1,40 @@
-#[derive(Default)]
-pub struct Env {}
+use im::HashMap;
+use tree::type_passing::Generic;
+use tree::String;
+
+#[derive(Clone, Default)]
+pub struct Env {
+    /// the generics of every function, whose bounds decide which dictionaries a call passes
+    functions: HashMap<String, Vec<Generic>>,
+    /// the protocol that each protocol function belongs to
+    protocol_functions: HashMap<String, String>,
+    /// the protocols that bound each generic in scope
+    bounds: HashMap<String, Vec<String>>,
+}
+
+impl Env {
+    pub fn define_function(&mut self, name: String, generics: Vec<Generic>) {
+        self.functions.insert(name, generics);
+    }
+
+    pub fn lookup_function(&self, name: &String) -> Vec<Generic> {
+        self.functions.get(name).cloned().unwrap_or_default()
+    }
+
+    pub fn define_protocol_function(&mut self, name: String, protocol: String) {
+        self.protocol_functions.insert(name, protocol);
+    }
+
+    pub fn lookup_protocol_function(&self, name: &String) -> Option<String> {
+        self.protocol_functions.get(name).cloned()
+    }
+
+    pub fn define_generics<'a>(&mut self, generics: impl Iterator<Item = &'a Generic>) {
+        self.bounds
+            .extend(generics.map(|generic| (generic.name.clone(), generic.bounds.clone())));
+    }
+
+    pub fn lookup_bounds(&self, generic: &String) -> Vec<String> {
+        self.bounds.get(generic).cloned().unwrap_or_default()
+    }
+}
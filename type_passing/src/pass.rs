@@ -4,39 +4,103 @@ use im::HashSet;
 use tree::type_passing::*;
 use tree::typed;
 use tree::typed::ClosureArgument;
-use tree::String;
+use tree::{Span, String};
 
 use crate::env::Env;
 
 pub fn program(to_pass: &typed::Program) -> Program {
-    let env = Env::default();
+    let mut env = Env::default();
+    for func in &to_pass.functions {
+        env.define_function(func.name.clone(), func.generics.clone());
+    }
+    for protocol in &to_pass.protocols {
+        for signature in &protocol.functions {
+            let self_generic = Generic {
+                name: String::from("Self"),
+                bounds: vec![protocol.name.clone()],
+            };
+            env.define_function(signature.name.clone(), vec![self_generic]);
+            env.define_protocol_function(signature.name.clone(), protocol.name.clone());
+        }
+    }
     let structs = to_pass
         .structs
         .iter()
         .map(|to_pass| strukt(&env, to_pass))
         .collect();
+    let enums = to_pass
+        .enums
+        .iter()
+        .map(|to_pass| enumeration(&env, to_pass))
+        .collect();
+    let impls = to_pass
+        .impls
+        .iter()
+        .map(|to_pass| Impl {
+            protocol: to_pass.protocol.clone(),
+            typ: to_pass.typ.clone(),
+            functions: to_pass
+                .functions
+                .iter()
+                .map(|func| function(&env, func))
+                .collect(),
+            span: to_pass.span,
+        })
+        .collect();
     let functions = to_pass
         .functions
         .iter()
         .map(|func| function(&env, func))
         .collect();
-    Program { structs, functions }
+    Program {
+        structs,
+        enums,
+        protocols: to_pass.protocols.clone(),
+        impls,
+        functions,
+    }
+}
+
+fn enumeration(env: &Env, to_pass: &typed::Enum) -> Enum {
+    let variants = to_pass
+        .variants
+        .iter()
+        .map(|variant| {
+            variant
+                .fields
+                .iter()
+                .map(|field| typ(env, field, to_pass.span))
+                .collect()
+        })
+        .collect();
+    Enum {
+        name: to_pass.name.clone(),
+        variants: to_pass.variants.clone(),
+        tag: EnumMeta { variants },
+        span: to_pass.span,
+    }
 }
 
 fn strukt(env: &Env, to_pass: &typed::Struct) -> Struct {
     let fields = to_pass
         .fields
         .iter()
-        .map(|field| typ(env, &field.typ))
+        .map(|field| typ(env, &field.typ, to_pass.span))
         .collect();
     let tag = StructMeta {
-        arguments: Vec::new(),
+        arguments: to_pass
+            .generics
+            .iter()
+            .map(|generic| generic.name.clone())
+            .collect(),
         fields,
     };
     Struct {
         name: to_pass.name.clone(),
+        generics: to_pass.generics.clone(),
         fields: to_pass.fields.clone(),
         tag,
+        span: to_pass.span,
     }
 }
 
@@ -54,35 +118,76 @@ fn function(env: &Env, to_pass: &typed::Function) -> Function {
             typ: Type::typ(),
         });
     }
+    // bound generics also get a dictionary for each of their protocols, after all the witness tables
+    for generic in &to_pass.generics {
+        for protocol in &generic.bounds {
+            arguments.push(Argument {
+                name: dictionary_argument_name(&generic.name, protocol),
+                typ: Type::dictionary(),
+            });
+        }
+    }
+    let mut env = env.clone();
+    env.define_generics(to_pass.generics.iter());
     Function {
         name: to_pass.name.clone(),
         generics: Vec::new(),
         arguments,
         result: to_pass.result.clone(),
-        body: expr(env, &to_pass.body),
+        body: expr(&env, &to_pass.body),
+        span: to_pass.span,
     }
 }
 
 fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
     match to_pass {
-        typed::Expr::Variable { name, typ } => Expr::Variable {
+        typed::Expr::Variable { name, typ, span } => Expr::Variable {
             name: name.clone(),
             typ: typ.clone(),
+            span: *span,
         },
-        typed::Expr::Literal { literal } => Expr::Literal {
+        typed::Expr::Literal { literal, typ, span } => Expr::Literal {
             literal: literal.clone(),
+            typ: typ.clone(),
+            span: *span,
         },
         typed::Expr::CallDirect {
             function,
             arguments,
             tag,
+            span,
         } => {
             let mut passed_args = Vec::new();
             for arg in arguments {
                 passed_args.push(expr(env, arg));
             }
+            if let Some(protocol) = env.lookup_protocol_function(function) {
+                let implementor = match tag.generics[0].canonical() {
+                    Type::Named { name, .. } => Some(name.as_str()),
+                    Type::Existential { .. } => Some(EXISTENTIAL),
+                    _ => None,
+                };
+                if let Some(implementor) = implementor {
+                    // the implementation is known statically, so it can be called without going through a dictionary
+                    return Expr::CallDirect {
+                        function: impl_function_name(&protocol, implementor, function),
+                        tag: Call {
+                            result: tag.result.clone(),
+                            signature: make_signature(passed_args.len()),
+                        },
+                        arguments: passed_args,
+                        span: *span,
+                    };
+                }
+            }
             for arg in &tag.generics {
-                passed_args.push(typ(env, arg));
+                passed_args.push(typ(env, arg, *span));
+            }
+            let generics = env.lookup_function(function);
+            for (generic, arg) in generics.iter().zip(&tag.generics) {
+                for protocol in &generic.bounds {
+                    passed_args.push(dictionary(arg, protocol, *span));
+                }
             }
             Expr::CallDirect {
                 function: function.clone(),
@@ -91,20 +196,46 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                     signature: make_signature(passed_args.len()),
                 },
                 arguments: passed_args,
+                span: *span,
+            }
+        }
+        typed::Expr::CallIndirect {
+            function,
+            arguments,
+            tag,
+            span,
+        } => {
+            // closures carry their own type information, so no witness tables need to be passed
+            let passed_args: Vec<_> = arguments.iter().map(|arg| expr(env, arg)).collect();
+            Expr::CallIndirect {
+                function: Box::new(expr(env, function)),
+                tag: Call {
+                    result: tag.result.clone(),
+                    signature: make_signature(passed_args.len()),
+                },
+                arguments: passed_args,
+                span: *span,
             }
         }
         typed::Expr::Block(to_pass) => Expr::Block(block(env, to_pass)),
         typed::Expr::Primitive {
             primitive,
             arguments,
+            span,
         } => {
             let lowered_args = arguments.iter().map(|arg| expr(env, arg)).collect();
             Expr::Primitive {
                 primitive: *primitive,
                 arguments: lowered_args,
+                span: *span,
             }
         }
-        typed::Expr::StructPack { name, fields, tag } => {
+        typed::Expr::StructPack {
+            name,
+            fields,
+            tag,
+            span,
+        } => {
             let passed_fields = fields
                 .iter()
                 .map(|field| PackField {
@@ -116,6 +247,7 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                 name: name.clone(),
                 fields: passed_fields,
                 tag: tag.clone(),
+                span: *span,
             }
         }
         typed::Expr::If {
@@ -123,6 +255,7 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
             true_branch,
             false_branch,
             tag,
+            span,
         } => {
             let passed_predicate = expr(env, &predicate);
             let passed_true = expr(env, &true_branch);
@@ -132,15 +265,17 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                 true_branch: Box::new(passed_true),
                 false_branch: Box::new(passed_false),
                 tag: *tag,
+                span: *span,
             }
         }
         typed::Expr::Closure {
             arguments,
             body,
             tag,
+            span,
         } => {
             let passed_body = expr(env, &body);
-            let value_captures = tag.captures.clone();
+            let mut value_captures = tag.captures.clone();
             let type_captures: Vec<_> = {
                 let generics: HashSet<_> = arguments
                     .iter()
@@ -157,6 +292,15 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                     })
                     .collect()
             };
+            // dictionaries don't need reference counting, so they are captured like ordinary values
+            for capture in &type_captures {
+                for protocol in env.lookup_bounds(&capture.name) {
+                    value_captures.push(ClosureArgument {
+                        name: dictionary_argument_name(&capture.name, &protocol),
+                        typ: Type::dictionary(),
+                    });
+                }
+            }
 
             let env_struct = {
                 let env_fields: Vec<_> = value_captures
@@ -168,7 +312,7 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                     .collect();
                 let builder_fields = env_fields
                     .iter()
-                    .map(|field| typ(env, &field.typ))
+                    .map(|field| typ(env, &field.typ, *span))
                     .collect();
                 let builder_args = type_captures
                     .iter()
@@ -180,8 +324,16 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                 };
                 Struct {
                     name: String::new(),
+                    generics: type_captures
+                        .iter()
+                        .map(|capture| Generic {
+                            name: capture.name.clone(),
+                            bounds: Vec::new(),
+                        })
+                        .collect(),
                     fields: env_fields,
                     tag: env_tag,
+                    span: *span,
                 }
             };
 
@@ -195,26 +347,128 @@ fn expr(env: &Env, to_pass: &typed::Expr) -> Expr {
                 arguments: arguments.clone(),
                 body: Box::new(passed_body),
                 tag,
+                span: *span,
+            }
+        }
+        typed::Expr::VariantPack {
+            name,
+            arguments,
+            tag,
+            span,
+        } => Expr::VariantPack {
+            name: name.clone(),
+            arguments: arguments.iter().map(|arg| expr(env, arg)).collect(),
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Expr::Case {
+            scrutinee,
+            arms,
+            tag,
+            span,
+        } => Expr::Case {
+            scrutinee: Box::new(expr(env, scrutinee)),
+            arms: arms
+                .iter()
+                .map(|arm| Arm {
+                    pattern: pattern(&arm.pattern),
+                    body: expr(env, &arm.body),
+                })
+                .collect(),
+            tag: *tag,
+            span: *span,
+        },
+        typed::Expr::FieldAccess {
+            value,
+            field,
+            tag,
+            span,
+        } => Expr::FieldAccess {
+            value: Box::new(expr(env, value)),
+            field: field.clone(),
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Expr::Unwrap { value, tag, span } => Expr::Unwrap {
+            value: Box::new(expr(env, value)),
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Expr::ExistentialPack {
+            value,
+            protocol,
+            span,
+            ..
+        } => {
+            let value_type = value.get_type();
+            Expr::ExistentialPack {
+                value: Box::new(expr(env, value)),
+                protocol: protocol.clone(),
+                tag: ExistentialPack {
+                    witness: Box::new(typ(env, &value_type, *span)),
+                    dictionary: Box::new(dictionary(&value_type, protocol, *span)),
+                },
+                span: *span,
             }
         }
     }
 }
 
+fn pattern(to_pass: &typed::Pattern) -> Pattern {
+    match to_pass {
+        typed::Pattern::Wildcard { tag, span } => Pattern::Wildcard {
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Pattern::Binding { name, tag, span } => Pattern::Binding {
+            name: name.clone(),
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Pattern::Literal { literal, tag, span } => Pattern::Literal {
+            literal: literal.clone(),
+            tag: tag.clone(),
+            span: *span,
+        },
+        typed::Pattern::Variant {
+            name,
+            fields,
+            tag,
+            span,
+        } => Pattern::Variant {
+            name: name.clone(),
+            fields: fields.iter().map(pattern).collect(),
+            tag: tag.clone(),
+            span: *span,
+        },
+    }
+}
+
 fn block(env: &Env, to_pass: &typed::Block) -> Block {
     let mut passed_stmts = Vec::new();
     for stmt in &to_pass.stmts {
         let passed_stmt = match stmt {
-            typed::Statement::Let { name, typ, value } => Statement::Let {
+            typed::Statement::Let {
+                name,
+                typ,
+                value,
+                span,
+            } => Statement::Let {
                 name: name.clone(),
                 typ: typ.clone(),
                 value: expr(env, value),
+                span: *span,
             },
+            typed::Statement::Function(_) => {
+                unreachable!("the type checker lifts local functions to the top level")
+            }
         };
         passed_stmts.push(passed_stmt);
     }
     Block {
         stmts: passed_stmts,
         result: Box::new(expr(env, &to_pass.result)),
+        span: to_pass.span,
     }
 }
 
@@ -228,13 +482,54 @@ fn generics(typ: &Type) -> HashSet<String> {
             .chain(generics(&result))
             .collect(),
         Type::Unification { name, value } => generics(Type::unwrap(name, &value)),
+        Type::Existential { .. } => HashSet::new(),
+        Type::Union { variants, rest } => variants
+            .iter()
+            .flat_map(|variant| &variant.fields)
+            .flat_map(generics)
+            .chain(rest.iter().flat_map(|rest| generics(rest)))
+            .collect(),
     }
 }
 
-fn typ(env: &Env, to_pass: &Type) -> Expr {
+/// builds the expression that finds the dictionary of `to_pass` for `protocol`
+fn dictionary(to_pass: &Type, protocol: &str, span: Span) -> Expr {
+    match to_pass {
+        Type::Named { name, .. } => Expr::CallDirect {
+            function: dictionary_builder_name(protocol, name),
+            arguments: Vec::new(),
+            tag: Call {
+                result: Type::dictionary(),
+                signature: make_signature(0),
+            },
+            span,
+        },
+        Type::Generic { name } => Expr::Variable {
+            name: dictionary_argument_name(name, protocol),
+            typ: Type::dictionary(),
+            span,
+        },
+        Type::Existential { .. } => Expr::CallDirect {
+            function: dictionary_builder_name(protocol, EXISTENTIAL),
+            arguments: Vec::new(),
+            tag: Call {
+                result: Type::dictionary(),
+                signature: make_signature(0),
+            },
+            span,
+        },
+        Type::Function { .. } | Type::Union { .. } => {
+            unreachable!("functions and unions can't implement protocols")
+        }
+        Type::Unification { name, value } => dictionary(Type::unwrap(name, value), protocol, span),
+    }
+}
+
+/// builds the expression that computes the witness table for `to_pass`, attributed to the expression at `span` that needed it
+fn typ(env: &Env, to_pass: &Type, span: Span) -> Expr {
     match to_pass {
         Type::Named { name, arguments } => {
-            let passed_args = arguments.iter().map(|arg| typ(env, arg)).collect();
+            let passed_args = arguments.iter().map(|arg| typ(env, arg, span)).collect();
             Expr::CallDirect {
                 function: name.clone(),
                 arguments: passed_args,
@@ -242,29 +537,35 @@ fn typ(env: &Env, to_pass: &Type) -> Expr {
                     result: Type::typ(),
                     signature: make_signature(arguments.len()),
                 },
+                span,
             }
         }
         Type::Generic { name } => Expr::Variable {
             name: name.clone(),
             typ: Type::typ(),
+            span,
         },
-        Type::Function { arguments, result } => {
-            let mut passed_args = Vec::new();
-            passed_args.extend(arguments.iter().map(|arg| typ(env, arg)));
-            passed_args.push(typ(env, result));
-            Expr::CallDirect {
-                function: n_function(arguments.len()),
-                arguments: passed_args,
-                tag: Call {
-                    result: Type::typ(),
-                    signature: make_signature(arguments.len() + 1),
-                },
-            }
-        }
-        Type::Unification { name, value } => typ(env, Type::unwrap(name, &value)),
+        // every closure has the same layout, no matter what it takes or returns
+        Type::Function { .. } => Expr::CallDirect {
+            function: String::from("_Closure"),
+            arguments: Vec::new(),
+            tag: Call {
+                result: Type::typ(),
+                signature: make_signature(0),
+            },
+            span,
+        },
+        // like closures, every existential has the same layout
+        Type::Existential { .. } => Expr::CallDirect {
+            function: String::from("_Existential"),
+            arguments: Vec::new(),
+            tag: Call {
+                result: Type::typ(),
+                signature: make_signature(0),
+            },
+            span,
+        },
+        Type::Unification { name, value } => typ(env, Type::unwrap(name, &value), span),
+        Type::Union { .. } => typ(env, &to_pass.union_shape().unwrap(), span),
     }
 }
-
-fn n_function(arity: usize) -> String {
-    String::from(format!("{}function", arity))
-}